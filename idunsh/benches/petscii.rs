@@ -0,0 +1,34 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+// Copyright (C) 2026 Brian Holdsworth
+use criterion::{criterion_group, criterion_main, Criterion, Throughput};
+use std::hint::black_box;
+use bstr::BString;
+
+use idun_client::util::{self, PetString};
+
+// A 16KB block of catalog-like PETSCII text
+fn sample() -> Vec<u8> {
+    let line = b"  12 \"\xc7\xc1\xcd\xc5 \xc4\xc9\xd3\xcb\"       PRG\r";
+    line.iter().cycle().take(16 * 1024).copied().collect()
+}
+
+fn conversion(c: &mut Criterion) {
+    let pet = sample();
+    let asc = util::pet_to_ascii(&pet).into_owned();
+    let text = String::from_utf8_lossy(&asc).into_owned();
+
+    let mut g = c.benchmark_group("petscii");
+    g.throughput(Throughput::Bytes(pet.len() as u64));
+    g.bench_function("pet_to_ascii", |b| b.iter(|| util::pet_to_ascii(black_box(&pet)).len()));
+    g.bench_function("ascii_to_pet", |b| b.iter(|| util::ascii_to_pet(black_box(&asc)).len()));
+    g.bench_function("PetString->String", |b| b.iter(|| {
+        String::from(PetString::new(&BString::new(black_box(&pet).clone()))).len()
+    }));
+    g.bench_function("&str->PetString", |b| b.iter(|| {
+        PetString::from(black_box(text.as_str())).as_slice().len()
+    }));
+    g.finish();
+}
+
+criterion_group!(benches, conversion);
+criterion_main!(benches);
@@ -0,0 +1,378 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+// Copyright (C) 2026 Brian Holdsworth
+
+//! BASIC V2 programs to and from listings, as petcat does.
+//!
+//! A program is its load address, then lines of a link to the next line,
+//! the line number, and the tokenized text ending with a zero byte. Two
+//! zero bytes stand for the link after the last line. Listings are UTF-8
+//! text with control codes inside strings written as names in braces,
+//! see `petscii`.
+//!
+//! While a program runs or after it stops, BASIC's zero page pointers
+//! tell where its variables are: simple variables of seven bytes each
+//! follow the program, then the arrays, and the strings are stored
+//! downwards from the top of BASIC memory or point into the program.
+use std::fs;
+use std::io::{self, Read, Write, stdout};
+use std::path::Path;
+use std::result;
+use clap::{Subcommand, ValueEnum};
+use idun_client::petscii::{self, Charset, Controls, Decoder};
+use crate::formats::FileInfo;
+use crate::target::Memory;
+
+// Simpler error handling
+type Result<T> = result::Result<T, failure::Error>;
+
+// Keywords by token, from $80
+const KEYWORDS: [&[u8]; 76] = [
+    b"END", b"FOR", b"NEXT", b"DATA", b"INPUT#", b"INPUT", b"DIM", b"READ",
+    b"LET", b"GOTO", b"RUN", b"IF", b"RESTORE", b"GOSUB", b"RETURN", b"REM",
+    b"STOP", b"ON", b"WAIT", b"LOAD", b"SAVE", b"VERIFY", b"DEF", b"POKE",
+    b"PRINT#", b"PRINT", b"CONT", b"LIST", b"CLR", b"CMD", b"SYS", b"OPEN",
+    b"CLOSE", b"GET", b"NEW", b"TAB(", b"TO", b"FN", b"SPC(", b"THEN",
+    b"NOT", b"STEP", b"+", b"-", b"*", b"/", b"^", b"AND",
+    b"OR", b">", b"=", b"<", b"SGN", b"INT", b"ABS", b"USR",
+    b"FRE", b"POS", b"SQR", b"RND", b"LOG", b"EXP", b"COS", b"SIN",
+    b"TAN", b"ATN", b"PEEK", b"LEN", b"STR$", b"VAL", b"ASC", b"CHR$",
+    b"LEFT$", b"RIGHT$", b"MID$", b"GO",
+];
+const DATA: u8 = 0x83;
+const REM: u8 = 0x8f;
+const PRINT: u8 = 0x99;
+
+/// The listing of a program, one line of text per BASIC line.
+pub fn list(prg: &[u8], charset: Charset) -> Result<String> {
+    let mut decoder = Decoder::new(charset, Controls::Names);
+    let mut text = String::new();
+    let mut rest = prg.get(2..).unwrap_or_default();
+    loop {
+        match rest {
+            [0, 0, ..] | [] => break,
+            [_, _, lo, hi, body @ ..] => {
+                let end = body.iter().position(|b| *b == 0)
+                    .ok_or_else(|| format_err!("BASIC line {} has no end", u16::from_le_bytes([*lo, *hi])))?;
+                let mut line = format!("{} ", u16::from_le_bytes([*lo, *hi])).into_bytes();
+                let mut quoted = false;
+                for b in &body[..end] {
+                    match *b {
+                        b'"' => quoted = !quoted,
+                        0x80..=0xcb if !quoted => {
+                            line.extend_from_slice(KEYWORDS[*b as usize - 0x80]);
+                            continue
+                        },
+                        _ => (),
+                    }
+                    line.push(*b);
+                }
+                text.push_str(&decoder.decode(&line));
+                text.push('\n');
+                rest = &body[end + 1..];
+            },
+            _ => bail!("The program ends in the middle of a line"),
+        }
+    }
+    Ok(text)
+}
+
+/// The program for a listing, to be loaded at `load`.
+pub fn tokenize(text: &str, charset: Charset, load: u16) -> Result<Vec<u8>> {
+    let pet = petscii::encode(text, charset, true)?;
+    let mut prg = load.to_le_bytes().to_vec();
+    let mut addr = load;
+    for line in pet.split(|b| *b == 0x0d) {
+        let line = line.trim_ascii();
+        if line.is_empty() {
+            continue
+        }
+        let digits = line.iter().take_while(|b| b.is_ascii_digit()).count();
+        let number: u16 = std::str::from_utf8(&line[..digits]).unwrap_or_default().parse().ok()
+            .filter(|n| *n < 64000)
+            .ok_or_else(|| format_err!("{:?} doesn't start with a line number", String::from_utf8_lossy(line)))?;
+        let body = crunch(line[digits..].trim_ascii_start());
+        addr = addr.wrapping_add(body.len() as u16 + 5);
+        prg.extend_from_slice(&addr.to_le_bytes());
+        prg.extend_from_slice(&number.to_le_bytes());
+        prg.extend_from_slice(&body);
+        prg.push(0);
+    }
+    prg.extend_from_slice(&[0, 0]);
+    Ok(prg)
+}
+
+// Replaces keywords with tokens as the Commodore does when a line is
+// entered: the first keyword in token order that matches wins, and
+// nothing is replaced in strings, after REM, or in DATA statements
+fn crunch(line: &[u8]) -> Vec<u8> {
+    let mut body = Vec::with_capacity(line.len());
+    let (mut quoted, mut data, mut rem) = (false, false, false);
+    let mut i = 0;
+    while i < line.len() {
+        let b = line[i];
+        i += 1;
+        match b {
+            b'"' => quoted = !quoted,
+            _ if quoted || rem => (),
+            b':' => data = false,
+            _ if data || b >= 0x80 || b.is_ascii_digit() || b == b';' || b == b' ' => (),
+            b'?' => {
+                body.push(PRINT);
+                continue
+            },
+            _ => if let Some(t) = KEYWORDS.iter().position(|k| line[i - 1..].starts_with(k)) {
+                let token = 0x80 + t as u8;
+                data = token == DATA;
+                rem = token == REM;
+                body.push(token);
+                i += KEYWORDS[t].len() - 1;
+                continue
+            },
+        }
+        body.push(b);
+    }
+    body
+}
+
+/// Where BASIC keeps a program and its variables, from the zero page.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Pointers {
+    pub program: u16,
+    pub variables: u16,
+    pub arrays: u16,
+    pub arrays_end: u16,
+    pub strings: u16,
+    pub top: u16,
+}
+
+impl Pointers {
+    /// The pointers in `zp`, the memory from $0000 to at least $0038.
+    pub fn read(zp: &[u8]) -> Result<Pointers> {
+        let word = |a: usize| zp.get(a..a + 2).map(|w| u16::from_le_bytes([w[0], w[1]]))
+            .ok_or_else(|| format_err!("The zero page is too short"));
+        Ok(Pointers {
+            program: word(0x2b)?,
+            variables: word(0x2d)?,
+            arrays: word(0x2f)?,
+            arrays_end: word(0x31)?,
+            strings: word(0x33)?,
+            top: word(0x37)?,
+        })
+    }
+    /// The memory map, one area per line.
+    pub fn map(&self) -> String {
+        let area = |name: &str, start: u16, end: u16|
+            format!("{:<10}${:04X}-${:04X} {:>6} bytes\n", name, start, end, end.saturating_sub(start));
+        [
+            area("program", self.program, self.variables),
+            area("variables", self.variables, self.arrays),
+            area("arrays", self.arrays, self.arrays_end),
+            area("free", self.arrays_end, self.strings),
+            area("strings", self.strings, self.top),
+        ].concat()
+    }
+}
+
+/// The variables and arrays of the program in `mem`, the memory from
+/// $0000 up to the top of BASIC, as names with their values in the
+/// form BASIC prints them.
+pub fn variables(mem: &[u8], p: &Pointers, charset: Charset) -> Result<Vec<(String, String)>> {
+    let bytes = |start: u16, len: usize| mem.get(start as usize..start as usize + len)
+        .ok_or_else(|| format_err!("${:04X} is beyond the memory read", start));
+    let mut vars = Vec::new();
+    let mut addr = p.variables;
+    while addr.saturating_add(7) <= p.arrays {
+        let v = bytes(addr, 7)?;
+        let (name, kind) = variable_name(v[0], v[1]);
+        let value = match kind {
+            Kind::Float => float(&v[2..7]),
+            Kind::Integer => i16::from_be_bytes([v[2], v[3]]).to_string(),
+            Kind::String => string(mem, &v[2..5], charset)?,
+            Kind::Function => String::from("FN"),
+        };
+        vars.push((name, value));
+        addr += 7;
+    }
+    while addr.saturating_add(5) <= p.arrays_end {
+        let header = bytes(addr, 5)?;
+        let (name, kind) = variable_name(header[0], header[1]);
+        let size = u16::from_le_bytes([header[2], header[3]]);
+        let dims = header[4] as usize;
+        // Dimensions are stored last first, as the number of elements
+        let counts: Vec<u16> = bytes(addr + 5, dims * 2)?.chunks(2)
+            .map(|c| u16::from_be_bytes([c[0], c[1]])).rev().collect();
+        let width = match kind {
+            Kind::Integer => 2,
+            Kind::String => 3,
+            _ => 5,
+        };
+        let n: usize = counts.iter().map(|c| *c as usize).product();
+        let elements = bytes(addr + 5 + dims as u16 * 2, n * width)?;
+        let values = elements.chunks(width).map(|e| Ok(match kind {
+            Kind::Integer => i16::from_be_bytes([e[0], e[1]]).to_string(),
+            Kind::String => string(mem, e, charset)?,
+            _ => float(e),
+        })).collect::<Result<Vec<_>>>()?;
+        let bounds: Vec<String> = counts.iter().map(|c| c.saturating_sub(1).to_string()).collect();
+        vars.push((format!("{}({})", name, bounds.join(",")), values.join(", ")));
+        if size == 0 {
+            bail!("The array {} at ${:04X} has no size", name, addr)
+        }
+        addr = addr.saturating_add(size);
+    }
+    Ok(vars)
+}
+
+// What a variable holds, told by the high bits of its name
+enum Kind {
+    Float,
+    Integer,
+    String,
+    Function,
+}
+
+fn variable_name(a: u8, b: u8) -> (String, Kind) {
+    let kind = match (a & 0x80 != 0, b & 0x80 != 0) {
+        (false, false) => Kind::Float,
+        (true, true) => Kind::Integer,
+        (false, true) => Kind::String,
+        (true, false) => Kind::Function,
+    };
+    let mut name: String = [a & 0x7f, b & 0x7f].iter().filter(|c| **c != 0).map(|c| *c as char).collect();
+    match kind {
+        Kind::Integer => name.push('%'),
+        Kind::String => name.push('$'),
+        Kind::Function => name.insert_str(0, "FN "),
+        Kind::Float => (),
+    }
+    (name, kind)
+}
+
+// A string descriptor: its length, then where it is
+fn string(mem: &[u8], descriptor: &[u8], charset: Charset) -> Result<String> {
+    let start = u16::from_le_bytes([descriptor[1], descriptor[2]]) as usize;
+    let text = mem.get(start..start + descriptor[0] as usize)
+        .ok_or_else(|| format_err!("A string at ${:04X} is beyond the memory read", start))?;
+    Ok(format!("\"{}\"", petscii::decode(text, charset, Controls::Names)))
+}
+
+// A number in the five byte floating point format
+fn float(f: &[u8]) -> String {
+    if f[0] == 0 {
+        return String::from("0")
+    }
+    let mantissa = u32::from_be_bytes([f[1] | 0x80, f[2], f[3], f[4]]) as f64 / 4294967296.0;
+    let value = mantissa * 2f64.powi(f[0] as i32 - 128);
+    let value = if f[1] & 0x80 != 0 { -value } else { value };
+    // BASIC shows nine significant digits, switching to an exponent
+    // below 0.01 and from 1E9
+    let magnitude = value.abs();
+    if !(0.01..1e9).contains(&magnitude) {
+        let text = format!("{:.8E}", value);
+        let (digits, exp) = text.split_once('E').unwrap_or((&text, "0"));
+        let digits = digits.trim_end_matches('0').trim_end_matches('.');
+        let exp: i32 = exp.parse().unwrap_or_default();
+        return format!("{}E{}{:02}", digits, if exp < 0 { '-' } else { '+' }, exp.abs())
+    }
+    let decimals = 8usize.saturating_sub(magnitude.log10().floor().max(0.0) as usize);
+    let text = format!("{:.*}", decimals, value);
+    let text = match text.contains('.') {
+        true => text.trim_end_matches('0').trim_end_matches('.'),
+        false => &text,
+    };
+    // Without the zero before the point, as in .5
+    text.replacen("0.", ".", usize::from(text.trim_start_matches('-').starts_with("0.")))
+}
+
+#[derive(Subcommand)]
+pub enum BasicCommands {
+    /// Show the variables and arrays of a running or stopped program
+    Vars,
+    /// Show where the program, its variables and its strings are
+    Map,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum ConvertTo {
+    /// UTF-8 text, or a listing for a BASIC program
+    Text,
+    /// PETSCII text
+    Seq,
+    /// Tokenized BASIC program
+    Prg,
+}
+
+// Shows the BASIC program in memory: where its parts are, or the values
+// of its variables
+pub fn run(memory: &dyn Memory, cmd: BasicCommands, charset: Charset) -> Result<()> {
+    let pointers = Pointers::read(&memory.read(0, 0x40)?)?;
+    match cmd {
+        BasicCommands::Map => print!("{}", pointers.map()),
+        BasicCommands::Vars => {
+            let mem = memory.read(0, pointers.top as usize)?;
+            for (name, value) in variables(&mem, &pointers, charset)? {
+                println!("{} = {}", name, value);
+            }
+        },
+    }
+    Ok(())
+}
+
+// Converts a PETSCII file to text, or text to PETSCII; - is stdin or stdout
+pub fn convert(input: &str, output: &str, to: Option<ConvertTo>, load: u16, charset: Charset) -> Result<()> {
+    let data = match input {
+        "-" => {
+            let mut data = vec![];
+            io::stdin().read_to_end(&mut data)?;
+            data
+        },
+        _ => fs::read(input).map_err(|e| format_err!("{}: {}", input, e))?,
+    };
+    let ext = Path::new(output).extension().map(|e| e.to_string_lossy().to_lowercase());
+    let to = to.unwrap_or(match ext.as_deref() {
+        Some("prg") => ConvertTo::Prg,
+        Some("seq") => ConvertTo::Seq,
+        _ => ConvertTo::Text,
+    });
+    let text = || str::from_utf8(&data).map_err(|_| format_err!("{} is not UTF-8 text", input));
+    let converted = match to {
+        ConvertTo::Text if FileInfo::identify(input, &data).basic_line.is_some() =>
+            list(&data, charset)?.into_bytes(),
+        ConvertTo::Text => petscii::decode(&data, charset, Controls::Names).into_bytes(),
+        ConvertTo::Seq => petscii::encode(text()?, charset, true)?,
+        ConvertTo::Prg => tokenize(text()?, charset, load)?,
+    };
+    match output {
+        "-" => stdout().write_all(&converted)?,
+        _ => fs::write(output, converted).map_err(|e| format_err!("{}: {}", output, e))?,
+    }
+    Ok(())
+}
+
+#[test]
+fn basic_listing() {
+    let text = "10 print \"{clr}hello\"\n20 goto10\n";
+    let prg = tokenize(text, Charset::Lower, 0x0801).unwrap();
+    assert_eq!(prg, b"\x01\x08\x10\x08\x0a\x00\x99 \"\x93\x48\x45\x4c\x4c\x4f\"\x00\x18\x08\x14\x00\x8910\x00\x00\x00");
+    assert_eq!(list(&prg, Charset::Lower).unwrap(), "10 print \"{clr}hello\"\n20 goto10\n");
+    assert_eq!(list(&prg, Charset::Upper).unwrap(), "10 PRINT \"{clr}HELLO\"\n20 GOTO10\n");
+    assert_eq!(crunch(b"DATA TO,1:REM TO"), b"\x83 TO,1:\x8f TO");
+    assert!(tokenize("print", Charset::Lower, 0x0801).is_err());
+}
+
+#[test]
+fn basic_variables() {
+    assert_eq!(float(&[0x81, 0x00, 0, 0, 0]), "1");
+    assert_eq!(float(&[0x82, 0xc0, 0, 0, 0]), "-3");
+    assert_eq!(float(&[0x7d, 0x4c, 0xcc, 0xcc, 0xcd]), ".1");
+    assert_eq!(float(&[0x77, 0x03, 0x12, 0x6e, 0x98]), "1E-03");
+    let mut mem = vec![0u8; 0x1000];
+    let p = Pointers { program: 0x0801, variables: 0x0900, arrays: 0x0915, arrays_end: 0x0924, strings: 0x0ffe, top: 0x1000 };
+    mem[0x0900..0x0915].copy_from_slice(b"A\x00\x81\x00\x00\x00\x00\xc9\x80\xff\xfe\x00\x00\x00N\x80\x02\xfe\x0f\x00\x00");
+    mem[0x0915..0x0924].copy_from_slice(b"\xc2\x80\x0f\x00\x01\x00\x03\x00\x01\x00\x02\x00\x03\x00\x00");
+    mem[0x0ffe..].copy_from_slice(b"HI");
+    let vars = variables(&mem, &p, Charset::Upper).unwrap();
+    assert_eq!(vars, [("A".into(), "1".into()), ("I%".into(), "-2".into()), ("N$".into(), "\"HI\"".into()),
+        ("B%(2)".into(), "1, 2, 3".into())]);
+    assert!(p.map().starts_with("program   $0801-$0900    255 bytes\n"));
+}
@@ -1,16 +1,24 @@
 // SPDX-License-Identifier: GPL-3.0-or-later
 // Copyright (C) 2026 Brian Holdsworth
 use std::result;
-use std::net::{UdpSocket, SocketAddr};
-use std::time::Duration;
+use std::net::SocketAddr;
+use tokio::net::UdpSocket;
+use tokio::task::JoinHandle;
+use std::time::{Duration, Instant};
+use std::thread;
 use std::path::Path;
 use std::fs;
 use std::io;
 use std::io::Read;
-use std::collections::HashMap;
-use serde;
+use std::collections::BTreeMap;
+use std::sync::OnceLock;
 use serde::Deserialize;
-use ureq;
+use sha2::{Digest, Sha256};
+use idun_client::client::Timeouts;
+use crate::ftp::Ftp;
+use crate::progress::Progress;
+use crate::runtime;
+use crate::target::{Capabilities, Memory, Target};
 
 // Simpler error handling
 type Result<T> = result::Result<T, failure::Error>;
@@ -27,14 +35,156 @@ pub struct Device {
     pub image_file: Option<String>,
     pub image_path: Option<String>,
 }
+impl Device {
+    /// Full path of the mounted image on the Ultimate's file system
+    pub fn mounted_image(&self) -> Option<String> {
+        let file = self.image_file.as_deref().filter(|f| !f.is_empty())?;
+        match self.image_path.as_deref() {
+            Some(path) if path.ends_with(file) => Some(path.to_string()),
+            Some(path) => Some(format!("{}/{}", path.trim_end_matches('/'), file)),
+            None => Some(file.to_string()),
+        }
+    }
+}
+/// Drive slots the Ultimate reports, in the order it reports them
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum DriveSlot {
+    A,
+    B,
+    Iec,
+    Printer,
+    Other(String),
+}
+impl DriveSlot {
+    fn from_name(name: &str) -> Self {
+        match name {
+            "a" => DriveSlot::A,
+            "b" => DriveSlot::B,
+            "IEC Drive" => DriveSlot::Iec,
+            "Printer Emulation" => DriveSlot::Printer,
+            _ => DriveSlot::Other(name.to_string()),
+        }
+    }
+    /// Name as used in the REST API, e.g. "a" in /v1/drives/a:mount
+    pub fn name(&self) -> &str {
+        match self {
+            DriveSlot::A => "a",
+            DriveSlot::B => "b",
+            DriveSlot::Iec => "IEC Drive",
+            DriveSlot::Printer => "Printer Emulation",
+            DriveSlot::Other(name) => name,
+        }
+    }
+    /// True for the emulated floppy drives that can mount images
+    pub fn is_floppy(&self) -> bool {
+        matches!(self, DriveSlot::A | DriveSlot::B)
+    }
+}
+/// One drive slot and its settings. The API sends each one as a single
+/// entry object, e.g. `{"a": {...}}`.
 #[derive(Deserialize)]
-pub struct DriveEntry {
-    #[serde(flatten)]
-    pub devices: HashMap<String, Device>,
+#[serde(try_from = "BTreeMap<String, Device>")]
+pub struct NamedDevice {
+    pub slot: DriveSlot,
+    pub device: Device,
+}
+impl TryFrom<BTreeMap<String, Device>> for NamedDevice {
+    type Error = String;
+
+    fn try_from(map: BTreeMap<String, Device>) -> result::Result<Self, String> {
+        if map.len() != 1 {
+            return Err(format!("expected one drive per entry, got {}", map.len()));
+        }
+        let (name, device) = map.into_iter().next().unwrap();
+        Ok(NamedDevice { slot: DriveSlot::from_name(&name), device })
+    }
 }
 #[derive(Deserialize)]
 pub struct UltiDrives {
-    pub drives: Vec<DriveEntry>,
+    pub drives: Vec<NamedDevice>,
+}
+impl UltiDrives {
+    /// Settings for a drive slot, if the Ultimate has it
+    pub fn get(&self, slot: &DriveSlot) -> Option<&Device> {
+        self.drives.iter().find(|d| d.slot == *slot).map(|d| &d.device)
+    }
+}
+
+/// The Ultimate's network data streams
+#[derive(Clone, Copy, Debug, clap::ValueEnum)]
+pub enum Stream {
+    Video,
+    Audio,
+    Debug,
+}
+impl Stream {
+    fn name(&self) -> &'static str {
+        match self {
+            Stream::Video => "video",
+            Stream::Audio => "audio",
+            Stream::Debug => "debug",
+        }
+    }
+}
+
+/// Extensions of the firmware update files of the Ultimate 64, Elite II,
+/// Ultimate II+ and Ultimate II
+const UPDATE_TYPES: [&str; 4] = ["u64", "ue2", "u2p", "u2u"];
+
+/// Reads a firmware update file, checking it is one by its name and
+/// size, and with `sha256` that it is the one published.
+pub fn read_update(file: &str, sha256: Option<&str>) -> Result<Vec<u8>> {
+    let ext = Path::new(file).extension().and_then(|e| e.to_str()).map(|e| e.to_lowercase());
+    if !ext.as_deref().is_some_and(|e| UPDATE_TYPES.contains(&e)) {
+        bail!("{} isn't a firmware update; their names end in .{}", file, UPDATE_TYPES.join(", ."))
+    }
+    let data = fs::read(file).map_err(|e| format_err!("{}: {}", file, e))?;
+    if data.len() < 0x10000 || data.len() > 0x2000000 {
+        bail!("{} is {} bytes, too {} for a firmware update", file, data.len(),
+            if data.len() < 0x10000 { "small" } else { "large" })
+    }
+    if let Some(expected) = sha256 {
+        let digest: String = Sha256::digest(&data).iter().map(|b| format!("{:02x}", b)).collect();
+        if !digest.eq_ignore_ascii_case(expected.trim()) {
+            bail!("{} has SHA-256 {}, not the {} expected", file, digest, expected)
+        }
+    }
+    Ok(data)
+}
+
+/// Default location of the C64 text screen
+const SCREEN_RAM: u16 = 0x0400;
+/// "READY." and "LOADING" as they appear in screen codes
+const SC_READY: &[u8] = &[0x12, 0x05, 0x01, 0x04, 0x19, 0x2e];
+const SC_LOADING: &[u8] = &[0x0c, 0x0f, 0x01, 0x04, 0x09, 0x0e, 0x07];
+const SC_SPACE: u8 = 0x20;
+/// The KERNAL's cursor row, 0 to 24
+const CURSOR_ROW: u16 = 0x00d6;
+/// The CLR key, which clears the screen
+const PETSCII_CLEAR: u8 = 0x93;
+/// The SID's volume, in the low nibble, and filter mode register
+const SID_VOLUME: u16 = 0xd418;
+
+/// Number of times `word` appears on the screen
+fn count(scr: &[u8], word: &[u8]) -> usize {
+    scr.windows(word.len()).filter(|w| *w == word).count()
+}
+
+/// Size and load address of a content file, as checked by `meta()`
+struct Meta {
+    size: u64,
+    load_addr: Option<u16>,
+}
+
+/// A search for the C64U under way; see `C64Ultimate::discover()`.
+pub struct Discovery(JoinHandle<Option<String>>, Timeouts);
+
+impl Discovery {
+    /// Waits for the search to end.
+    pub fn connect(self) -> C64Ultimate {
+        let service_ip = runtime::block_on(self.0).ok().flatten();
+        C64Ultimate { service_ip, progress: Progress::default(), timeouts: self.1, version: OnceLock::new() }
+    }
 }
 
 /// Access to a C64U on the LAN using its network service API.
@@ -42,65 +192,69 @@ pub struct UltiDrives {
 /// "Ident Service" must be enabled in the C64U configuration.
 pub struct C64Ultimate {
     service_ip: Option<String>,
+    progress: Progress,
+    timeouts: Timeouts,
+    // The firmware version, once asked
+    version: OnceLock<String>,
 }
 
 impl C64Ultimate {
-    /// If the "C64_ULTIMATE_IP" env var is set, then it is assumed that
-    /// a C64U has been previously detected and available at that IP.
-    /// Otherwise, attempt to detect a C64U on the LAN.
-    pub fn new() -> Self {
-        match std::env::var("C64_ULTIMATE_IP") {
-            Ok(v) => C64Ultimate { service_ip: Some(v) },
-            Err(_) => if let Some(detect) = Self::detect() {
-                C64Ultimate { service_ip: Some(detect) }
-            } else {
-                C64Ultimate { service_ip: None }
-            }
+    /// If an IP is given, from "C64_ULTIMATE_IP" or the config file,
+    /// then it is assumed that a C64U has been previously detected and
+    /// available at that IP. Otherwise, attempt to detect a C64U on the
+    /// LAN. The search runs in the background, so other work can go on
+    /// until `connect()` needs the answer. Requests then wait as long as
+    /// `timeouts` allows.
+    pub fn discover(ip: Option<String>, timeouts: Timeouts) -> Discovery {
+        match ip {
+            Some(v) => Discovery(runtime::spawn(async { Some(v) }), timeouts),
+            None => Discovery(runtime::spawn(Self::detect(timeouts)), timeouts),
         }
     }
+    /// Reports uploads and mounts to `progress`.
+    pub fn with_progress(mut self, progress: Progress) -> Self {
+        self.progress = progress;
+        self
+    }
     /// Returns the IP of the C64U as a String, or None if it is
     /// not detected.
     pub fn ip(&self) -> &Option<String> {
         &self.service_ip
     }
     /// Loads content file using network service. Currently supports
-    /// PRG, CRT, SID, and MOD files.
-    pub fn load(&self, filenm: &String) -> Result<()> {
-        let url: Option<String>;
+    /// PRG, CRT, SID, and MOD files. Disk images are mounted on drive a
+    /// and started with `autostart()`. Any `options` are passed on to the
+    /// runner as query parameters.
+    pub fn load(&self, filenm: &str, options: &[(&str, String)]) -> Result<()> {
         let lcase = filenm.to_lowercase();
         let ext = Path::new(&lcase)
                                 .extension()
                                 .and_then(|s| s.to_str());
-        let (size, start) = Self::meta(filenm)?;
-
-        if ext == None {
-            if (size + (start as u64)) < 65536 {
-                url = Some(String::from("/v1/runners:run_prg"));
-            } else {
-                bail!("PRG file is too large")
+        if let Some("d64" | "g64" | "d71" | "g71" | "d81") = ext {
+            return self.autostart(filenm);
+        }
+        let url = match ext {
+            None | Some("prg") => Some(String::from("/v1/runners:run_prg")),
+            Some("crt") => Some(String::from("/v1/runners:run_crt")),
+            Some("sid") => Some(String::from("/v1/runners:sidplay")),
+            Some("mod") => Some(String::from("/v1/runners:modplay")),
+            _ => None,
+        };
+        if let Some(mut u) = url {
+            for (i, (key, value)) in options.iter().enumerate() {
+                u.push(if i == 0 { '?' } else { '&' });
+                u.push_str(&format!("{}={}", key, value));
             }
-        } else {
-            url = match ext.unwrap() {
-                "crt" => Some(String::from("/v1/runners:run_crt")),
-                "sid" => Some(String::from("/v1/runners:sidplay")),
-                "mod" => Some(String::from("/v1/runners:modplay")),
-                "prg" => if (size + (start as u64)) < 65536 {
-                    Some(String::from("/v1/runners:run_prg"))
-                } else {
-                    bail!("PRG file is too large")
-                }
-                _ => None,
-            };
-        }
-        
-        if let Some(u) = url {
-            match self.post(&u, filenm) {
-                Ok(_) => Ok(()),
-                Err(e) => {
-                    println!("Error: {}", e);
-                    bail!("C64 Ultimate web request fail: {}", u)
+            let meta = Self::meta(filenm, ext)?;
+            if let Some(start) = meta.load_addr {
+                if (start as u64) + meta.size - 2 > 0x10000 {
+                    bail!("{}: PRG file is too large to load at ${:04X}", filenm, start)
                 }
             }
+            let size = self.post(&u, filenm)
+                .map_err(|e| format_err!("C64 Ultimate web request fail: {}: {}", u, e))?;
+            self.progress.report("load", size, size);
+            Ok(())
         } else {
             bail!("File extension not recognized")
         }
@@ -108,7 +262,7 @@ impl C64Ultimate {
     /// Mounts disk image file to selected floppy device [a | b]. Supports
     /// most disk image types and the C64U will change the drive type based
     /// on the filename extension.
-    pub fn mount(&self, device: &String, dimage: &String) -> Result<()> {
+    pub fn mount(&self, device: &str, dimage: &str) -> Result<()> {
         let lcase = dimage.to_lowercase();
         let ext = Path::new(&lcase)
                                 .extension()
@@ -123,45 +277,291 @@ impl C64Ultimate {
             _ => bail!("Unrecognized disk image file type/")
         };
 
-        match self.post(&url, dimage) {
-            Ok(_) => Ok(()),
-            Err(e) => {
-                println!("Error: {}", e);
-                bail!("C64 Ultimate web request fail: {}", &url)
+        let size = self.post(&url, dimage)
+            .map_err(|e| format_err!("C64 Ultimate web request fail: {}: {}", url, e))?;
+        self.progress.report("mount", size, size);
+        Ok(())
+    }
+    /// Mounts a disk image on drive a, resets the machine and types the
+    /// usual `LOAD"*",8,1` and `RUN`, the same as starting the disk by hand.
+    fn autostart(&self, dimage: &str) -> Result<()> {
+        const BOOT_TIMEOUT: Duration = Duration::from_secs(10);
+        const LOAD_TIMEOUT: Duration = Duration::from_secs(120);
+
+        self.mount("a:", dimage)?;
+        // The screen is blanked first, so a READY left on it from before
+        // the reset isn't taken for the new one
+        self.writemem(SCREEN_RAM, &[SC_SPACE; 1000])
+            .map_err(|e| format_err!("C64 Ultimate screen write fail: {}", e))?;
+        self.reset()?;
+
+        // Wait for the BASIC start-up screen before typing anything
+        self.wait_screen(BOOT_TIMEOUT, |scr| self.at_prompt(scr))?;
+
+        // Clear the screen, so that any READY after the LOAD is its own
+        self.type_keys(&[PETSCII_CLEAR])?;
+        self.wait_screen(BOOT_TIMEOUT, |scr| count(scr, SC_READY) == 0)?;
+
+        // lO"*",8,1 is the abbreviated LOAD, so it fits in the 10 byte
+        // keyboard buffer along with the RETURN.
+        self.type_keys(b"L\xcf\"*\",8,1\r")?;
+
+        // Loading is over when BASIC says READY, or when the LOADING
+        // message vanishes because the program started itself.
+        let mut loading = false;
+        let mut ready = false;
+        self.wait_screen(LOAD_TIMEOUT, |scr| {
+            ready = count(scr, SC_READY) >= 1 && self.at_prompt(scr);
+            let now = count(scr, SC_LOADING) > 0;
+            let started = loading && !now;
+            loading |= now;
+            ready || started
+        })?;
+        if ready {
+            self.type_keys(b"RUN\r")?;
+        }
+        Ok(())
+    }
+    // Whether BASIC waits for a command: READY is on the line just above
+    // the cursor, not merely somewhere on the screen
+    fn at_prompt(&self, scr: &[u8]) -> bool {
+        let row = match self.readmem(CURSOR_ROW, 1) {
+            Ok(row) => row.first().copied().unwrap_or_default() as usize,
+            Err(_) => return false,
+        };
+        row > 0 && scr.get((row - 1) * 40..).is_some_and(|line| line.starts_with(SC_READY))
+    }
+    /// Resets the C64, keeping mounted images and cartridge in place.
+    pub fn reset(&self) -> Result<()> {
+        self.put("/v1/machine:reset")
+            .map_err(|e| format_err!("C64 Ultimate reset fail: {}", e))
+    }
+    /// Stops the runner's player or program. The REST API has no stop for
+    /// the runners, so the C64 is reset to BASIC; the mounts and the
+    /// Ultimate itself are left alone.
+    pub fn stop(&self) -> Result<()> {
+        self.reset()
+    }
+    /// Lets the C64 carry on after a pause.
+    pub fn resume(&self) -> Result<()> {
+        self.put("/v1/machine:resume")
+            .map_err(|e| format_err!("C64 Ultimate resume fail: {}", e))
+    }
+    /// Puts a file on the Ultimate's file system, over FTP.
+    pub fn ftp_put(&self, path: &str, data: &[u8]) -> Result<()> {
+        let ultimate = self.service_ip.as_deref().unwrap_or_default();
+        let host = ultimate.rsplit_once(':').map_or(ultimate, |(host, _)| host);
+        self.progress.report("upload", 0, data.len() as u64);
+        Ftp::connect(host)?.put(path, data).map_err(|e| format_err!("C64 Ultimate upload of {} fail: {}", path, e))?;
+        self.progress.report("upload", data.len() as u64, data.len() as u64);
+        Ok(())
+    }
+    /// Waits for the Ultimate to stop answering, then to answer again,
+    /// giving its API version then.
+    pub fn wait_restart(&self, timeout: Duration) -> Result<String> {
+        const POLL: Duration = Duration::from_secs(2);
+        let start = Instant::now();
+        let mut down = false;
+        loop {
+            match self.version() {
+                Ok(version) if down => return Ok(version),
+                Ok(_) => (),
+                Err(_) => down = true,
+            }
+            if start.elapsed() > timeout {
+                bail!("The C64 Ultimate didn't {} within {}s", if down { "come back" } else { "restart" }, timeout.as_secs())
+            }
+            thread::sleep(POLL);
+        }
+    }
+    /// Shuts the machine down, as its power-off menu item does.
+    pub fn poweroff(&self) -> Result<()> {
+        self.put("/v1/machine:poweroff")
+            .map_err(|e| format_err!("C64 Ultimate power off fail: {}", e))
+    }
+    /// Lets the current content play for `duration`, then stops it with a
+    /// reset. With `fade`, the SID volume is stepped down from full over the
+    /// last part of the duration. That is best effort, because tunes that
+    /// rewrite the volume register every frame will override it.
+    pub fn stop_after(&self, duration: Duration, fade: Option<Duration>) -> Result<()> {
+        let fade = fade.unwrap_or_default().min(duration);
+
+        thread::sleep(duration - fade);
+        if !fade.is_zero() {
+            let step = fade / 15;
+            for vol in (0..15u8).rev() {
+                self.set_volume(vol)?;
+                thread::sleep(step);
+            }
+        }
+        self.reset()
+    }
+    // Sets the SID's volume, 0 to 15. The register is write-only, so the
+    // filter mode in its high nibble can't be kept and is cleared.
+    fn set_volume(&self, vol: u8) -> Result<()> {
+        self.writemem(SID_VOLUME, &[vol & 0x0f])
+            .map_err(|e| format_err!("C64 Ultimate volume write fail: {}", e))
+    }
+    /// Where the Ultimate can send a stream to us on `port`: the address
+    /// we reach it from.
+    pub fn stream_dest(&self, port: u16) -> Result<String> {
+        let ultimate = self.service_ip.as_deref().unwrap_or_default();
+        let host = ultimate.rsplit_once(':').map_or(ultimate, |(host, _)| host);
+        let probe = std::net::UdpSocket::bind("0.0.0.0:0")?;
+        probe.connect((host, 80)).map_err(|e| format_err!("{}: {}", host, e))?;
+        Ok(format!("{}:{}", probe.local_addr()?.ip(), port))
+    }
+    /// Starts sending a data stream to `dest` (host or host:port). The
+    /// Ultimate only sends; capturing the stream is up to the receiver.
+    pub fn stream_start(&self, stream: Stream, dest: &str) -> Result<()> {
+        let url = format!("/v1/streams/{}:start?ip={}", stream.name(), dest);
+        self.put(&url)
+            .map_err(|e| format_err!("C64 Ultimate {} stream start fail: {}", stream.name(), e))
+    }
+    /// Stops sending a data stream.
+    pub fn stream_stop(&self, stream: Stream) -> Result<()> {
+        let url = format!("/v1/streams/{}:stop", stream.name());
+        self.put(&url)
+            .map_err(|e| format_err!("C64 Ultimate {} stream stop fail: {}", stream.name(), e))
+    }
+    /// Polls the text screen until `done` is satisfied, returning the
+    /// final screen contents.
+    fn wait_screen<F>(&self, timeout: Duration, mut done: F) -> Result<Vec<u8>>
+    where F: FnMut(&[u8]) -> bool {
+        const POLL: Duration = Duration::from_millis(250);
+        let start = Instant::now();
+        loop {
+            let scr = self.readmem(SCREEN_RAM, 1000)
+                .map_err(|e| format_err!("C64 Ultimate screen read fail: {}", e))?;
+            if done(&scr) {
+                return Ok(scr);
             }
+            if start.elapsed() > timeout {
+                bail!("Timed out waiting for the C64 Ultimate screen to change")
+            }
+            thread::sleep(POLL);
+        }
+    }
+    /// Puts PETSCII keys in the keyboard buffer, as if they were typed.
+    fn type_keys(&self, keys: &[u8]) -> Result<()> {
+        if keys.len() > 10 {
+            bail!("At most 10 keys fit in the keyboard buffer")
         }
+        self.writemem(0x0277, keys)
+            .and_then(|_| self.writemem(0x00c6, &[keys.len() as u8]))
+            .map_err(|e| format_err!("C64 Ultimate keyboard buffer write fail: {}", e))
+    }
+    /// Types any number of PETSCII keys, ten at a time, waiting for the
+    /// C64 to take each lot from the keyboard buffer.
+    pub fn type_text(&self, keys: &[u8]) -> Result<()> {
+        const POLL: Duration = Duration::from_millis(50);
+        const TIMEOUT: Duration = Duration::from_secs(5);
+        for chunk in keys.chunks(10) {
+            self.type_keys(chunk)?;
+            let start = Instant::now();
+            let pending = || self.readmem(0x00c6, 1)
+                .map(|b| b.first().is_some_and(|n| *n != 0))
+                .map_err(|e| format_err!("C64 Ultimate memory read fail: {}", e));
+            while pending()? {
+                if start.elapsed() > TIMEOUT {
+                    bail!("The C64 stopped reading the keyboard")
+                }
+                thread::sleep(POLL);
+            }
+        }
+        Ok(())
+    }
+    /// Reads `len` bytes of C64 memory starting at `addr`.
+    pub fn readmem(&self, addr: u16, len: usize) -> io::Result<Vec<u8>> {
+        let url = format!("http://{}/v1/machine:readmem?address={:04X}&length={}",
+            self.service_ip.as_ref().unwrap(), addr, len);
+        let mut resp = self.agent(false).get(&url)
+            .call()
+            .map_err(|e| io::Error::other(e.to_string()))?;
+        resp.body_mut()
+            .read_to_vec()
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e.to_string()))
+    }
+    /// Writes bytes into C64 memory starting at `addr`.
+    pub fn writemem(&self, addr: u16, data: &[u8]) -> io::Result<()> {
+        let hex: String = data.iter().map(|b| format!("{:02X}", b)).collect();
+        self.put(&format!("/v1/machine:writemem?address={:04X}&data={}", addr, hex))
+    }
+    /// Exchanges the disk images in drives a and b. If only one drive
+    /// has an image, it moves to the other drive.
+    pub fn swap(&self) -> Result<()> {
+        let ultid = self.getdrv(&None)
+            .map_err(|e| format_err!("C64 Ultimate drive settings Error: {}", e))?;
+        let a = ultid.get(&DriveSlot::A).and_then(|d| d.mounted_image());
+        let b = ultid.get(&DriveSlot::B).and_then(|d| d.mounted_image());
+        if a.is_none() && b.is_none() {
+            bail!("No disk images mounted in drive a or b")
+        }
+        self.remount("a:", &b)?;
+        self.remount("b:", &a)
+    }
+    /// Mounts an image already on the Ultimate's file system, or empties
+    /// the drive when there is no image.
+    fn remount(&self, device: &str, image: &Option<String>) -> Result<()> {
+        let r = match image {
+            Some(path) => self.put_query(&format!("/v1/drives/{}mount", device), &[("image", path)]),
+            None => self.put(&format!("/v1/drives/{}remove", device)),
+        };
+        r.map_err(|e| format_err!("C64 Ultimate drive {} mount fail: {}", device, e))
     }
     /// Get the vital information about the available IEC devices
     pub fn getdrv(&self, _device: &Option<String>) -> io::Result<UltiDrives> {
         let url = format!("http://{}/v1/drives", self.service_ip.as_ref().unwrap());
-        let mut resp = ureq::get(&url)
+        let mut resp = self.agent(false).get(&url)
             .call()
-            .map_err(|e| io::Error::new(io::ErrorKind::Other, e.to_string()))?;
+            .map_err(|e| io::Error::other(e.to_string()))?;
         resp.body_mut()
             .read_json::<UltiDrives>()
             .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e.to_string()))
     }
+    /// Returns the version of the REST API, e.g. "0.1". It's asked for
+    /// once per connection.
+    pub fn version(&self) -> Result<String> {
+        if let Some(v) = self.version.get() {
+            return Ok(v.clone())
+        }
+        #[derive(Deserialize)]
+        struct Version {
+            version: String,
+        }
+        let url = format!("http://{}/v1/version", self.service_ip.as_ref().unwrap());
+        let mut resp = self.agent(false).get(&url)
+            .call()
+            .map_err(|e| format_err!("C64 Ultimate web request fail: {}", e))?;
+        let v = resp.body_mut()
+            .read_json::<Version>()
+            .map_err(|e| format_err!("C64 Ultimate version: {}", e))?;
+        Ok(self.version.get_or_init(|| v.version).clone())
+    }
     /// Detect if there is a C64 Ultimate on the LAN and return its IP address.
-    fn detect() -> Option<String> {
+    /// The packet is sent again as often as `timeouts` retries.
+    async fn detect(timeouts: Timeouts) -> Option<String> {
         const MESSAGE: &[u8] = b"ping";
         const BROADCAST_ADDR: &str = "255.255.255.255:64";
-        const TIMEOUT: Duration = Duration::from_millis(500);
 
         // Bind to an ephemeral local port
-        let socket = UdpSocket::bind("0.0.0.0:0").ok()?;
+        let socket = UdpSocket::bind("0.0.0.0:0").await.ok()?;
 
         // Enable broadcast (best effort)
         let _ = socket.set_broadcast(true);
 
-        // Set receive timeout
-        socket.set_read_timeout(Some(TIMEOUT)).ok()?;
-
-        // Send discovery packet
-        socket.send_to(MESSAGE, BROADCAST_ADDR).ok()?;
-
-        // Receive exactly one response
+        // Receive exactly one response, giving up after the timeout
         let mut buf = [0u8; 2048];
-        let (len, src): (usize, SocketAddr) = socket.recv_from(&mut buf).ok()?;
+        let mut tries = 0;
+        let (len, src): (usize, SocketAddr) = loop {
+            // Send discovery packet
+            socket.send_to(MESSAGE, BROADCAST_ADDR).await.ok()?;
+            match tokio::time::timeout(timeouts.discovery, socket.recv_from(&mut buf)).await {
+                Ok(received) => break received.ok()?,
+                Err(_) if tries < timeouts.retries => tries += 1,
+                Err(_) => return None,
+            }
+        };
 
         let payload = std::str::from_utf8(&buf[..len]).ok()?;
 
@@ -181,36 +581,121 @@ impl C64Ultimate {
             None
         }
     }
-    fn post(&self, url: &String, file: &String) -> io::Result<()> {
+    // An HTTP agent with our timeouts; an upload gets the one for
+    // transfers instead of the one for commands
+    fn agent(&self, upload: bool) -> ureq::Agent {
+        ureq::Agent::config_builder()
+            .timeout_connect(Some(self.timeouts.connect))
+            .timeout_global(if upload { self.timeouts.transfer } else { self.timeouts.command })
+            .build()
+            .into()
+    }
+    /// Uploads a file, returning its size. The body is sent in one piece
+    /// with a Content-Length, so progress is only known before and after.
+    /// Ctrl-C abandons the upload.
+    fn post(&self, url: &str, file: &str) -> io::Result<u64> {
         let path = Path::new(file);
         let mut buf: Vec<u8> = vec![];
         fs::File::open(path)?.read_to_end(&mut buf)?;
-        
+        let size = buf.len() as u64;
+
         let mut req = String::from("http://");
         req.push_str(self.service_ip.as_ref().unwrap().as_str());
         req.push_str(url);
 
-        ureq::post(req)
-            .send(buf)
-            .map(|_| ())
-            .map_err(|e| io::Error::new(io::ErrorKind::Other, e.to_string()))
+        self.progress.report("upload", 0, size);
+        let agent = self.agent(true);
+        runtime::interruptible(move || agent.post(req).send(buf).map(|_| ()))
+            .map_err(|e| io::Error::new(io::ErrorKind::Interrupted, e.to_string()))?
+            .map_err(|e| io::Error::other(e.to_string()))?;
+        self.progress.report("upload", size, size);
+        Ok(size)
     }
-    fn meta(filename: &str) -> io::Result<(u64, u16)> {
-        let path = Path::new(filename);
-
-        // Open file
-        let mut file = fs::File::open(path)?;
+    fn put(&self, url: &str) -> io::Result<()> {
+        self.put_query(url, &[])
+    }
+    fn put_query(&self, url: &str, query: &[(&str, &str)]) -> io::Result<()> {
+        let mut req = String::from("http://");
+        req.push_str(self.service_ip.as_ref().unwrap().as_str());
+        req.push_str(url);
 
-        // Get file size
+        self.agent(false).put(req)
+            .query_pairs(query.iter().copied())
+            .send_empty()
+            .map(|_| ())
+            .map_err(|e| io::Error::other(e.to_string()))
+    }
+    /// Reads just enough of a content file to check that it really is
+    /// what its extension claims. Only PRG files (with or without the
+    /// extension) have a load address.
+    fn meta(filename: &str, ext: Option<&str>) -> Result<Meta> {
+        let file = fs::File::open(filename)
+            .map_err(|e| format_err!("{}: {}", filename, e))?;
         let size = file.metadata()?.len();
 
-        // Read first 2 bytes
-        let mut buf = [0u8; 2];
-        file.read_exact(&mut buf)?;
+        // The longest signature we check is the CRT header
+        let mut head = Vec::with_capacity(16);
+        file.take(16).read_to_end(&mut head)?;
+
+        let mut load_addr = None;
+        match ext {
+            None | Some("prg") => {
+                if head.len() < 2 {
+                    bail!("{}: not a PRG: missing load address", filename)
+                }
+                if size == 2 {
+                    bail!("{}: not a PRG: no data after load address", filename)
+                }
+                load_addr = Some(u16::from_le_bytes([head[0], head[1]]));
+            },
+            Some("crt") => if !head.starts_with(b"C64 CARTRIDGE") {
+                bail!("{}: not a CRT: missing \"C64 CARTRIDGE\" header", filename)
+            },
+            Some("sid") => if !(head.starts_with(b"PSID") || head.starts_with(b"RSID")) {
+                bail!("{}: not a SID: missing PSID/RSID header", filename)
+            },
+            _ => if size == 0 {
+                bail!("{}: file is empty", filename)
+            },
+        }
+        Ok(Meta { size, load_addr })
+    }
+}
 
-        // Convert to little-endian u16
-        let addr = u16::from_le_bytes(buf);
+impl Target for C64Ultimate {
+    fn name(&self) -> &str {
+        "C64 Ultimate"
+    }
+    /// Asks the Ultimate for its API version; every version so far has
+    /// the drive and memory endpoints, but nothing reports completion.
+    fn capabilities(&self) -> Result<Capabilities> {
+        self.version()?;
+        Ok(Capabilities {
+            mount_types: Some(vec!["d64", "g64", "d71", "g71", "d81"]),
+            max_transfer: None,
+            memory_access: true,
+            events: false,
+            subdirectories: false,
+        })
+    }
+}
 
-        Ok((size, addr))
+impl Memory for C64Ultimate {
+    fn read(&self, addr: u16, len: usize) -> Result<Vec<u8>> {
+        self.readmem(addr, len).map_err(|e| format_err!("C64 Ultimate memory read fail: {}", e))
+    }
+    fn write(&self, addr: u16, data: &[u8]) -> Result<()> {
+        self.writemem(addr, data).map_err(|e| format_err!("C64 Ultimate memory write fail: {}", e))
+    }
+    /// Types SYS at the BASIC prompt; there's no way to set the PC.
+    fn call(&self, addr: u16) -> Result<()> {
+        self.type_keys(format!("SYS{}\r", addr).as_bytes())
+    }
+    fn freeze(&self) -> Result<bool> {
+        self.put("/v1/machine:pause").map_err(|e| format_err!("C64 Ultimate pause fail: {}", e))?;
+        Ok(true)
+    }
+    fn thaw(&self) -> Result<()> {
+        self.resume()
     }
 }
@@ -0,0 +1,221 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+// Copyright (C) 2026 Brian Holdsworth
+
+//! Downloaded files, kept in `~/.cache/idunsh`.
+//!
+//! Files are stored by content, as `objects/<sha256>.<ext>`, so the same
+//! image fetched from two places is kept once. `urls/` maps each URL to
+//! its object. Every change happens under an exclusive lock on the
+//! `lock` file, so parallel idunsh runs can share the cache. The oldest
+//! objects are evicted once the cache grows beyond its size limit.
+use std::fs::{self, File};
+use std::io::{self, Read, Write};
+use std::path::{Path, PathBuf};
+use std::process;
+use std::result;
+use std::time::SystemTime;
+use clap::Subcommand;
+use sha2::{Digest, Sha256};
+use crate::cleanup::TempPath;
+use crate::confirm::confirm;
+use crate::lock::FileLock;
+
+// Simpler error handling
+type Result<T> = result::Result<T, failure::Error>;
+
+/// Size the cache is trimmed to after each download
+pub const DEFAULT_LIMIT: u64 = 256 * 1024 * 1024;
+
+pub struct Cache {
+    dir: PathBuf,
+    limit: u64,
+}
+
+/// One cached download, as shown by `cache ls`.
+#[derive(Debug)]
+pub struct CacheEntry {
+    pub url: String,
+    pub path: PathBuf,
+    pub size: u64,
+    pub modified: SystemTime,
+}
+
+/// True for arguments naming something to download rather than a file.
+pub fn is_url(s: &str) -> bool {
+    s.starts_with("http://") || s.starts_with("https://")
+}
+
+impl Cache {
+    pub fn open() -> Result<Cache> {
+        let dir = dirs::cache_dir()
+            .ok_or_else(|| format_err!("No cache directory"))?
+            .join("idunsh");
+        Self::at(dir, DEFAULT_LIMIT)
+    }
+    fn at(dir: PathBuf, limit: u64) -> Result<Cache> {
+        fs::create_dir_all(dir.join("objects"))?;
+        fs::create_dir_all(dir.join("urls"))?;
+        Ok(Cache { dir, limit })
+    }
+    fn lock(&self) -> Result<FileLock> {
+        FileLock::exclusive(&self.dir.join("lock"))
+    }
+    /// Returns a local copy of `url`, downloading it unless it's cached.
+    pub fn fetch(&self, url: &str) -> Result<PathBuf> {
+        let _lock = self.lock()?;
+        if let Some(path) = self.lookup(url) {
+            // Mark it recently used, so eviction keeps it
+            File::options().append(true).open(&path)?.set_modified(SystemTime::now())?;
+            return Ok(path);
+        }
+        let resp = ureq::get(url)
+            .call()
+            .map_err(|e| format_err!("{}: {}", url, e))?;
+        self.store(url, resp.into_body().into_reader())
+    }
+    // Copies a download into the cache, naming it by its hash
+    fn store<R: Read>(&self, url: &str, mut body: R) -> Result<PathBuf> {
+        let tmp = TempPath::new(self.dir.join(format!("objects/.tmp-{}", process::id())));
+        let mut out = File::create(tmp.path())?;
+        let mut hasher = Sha256::new();
+        let mut buf = [0u8; 8192];
+        loop {
+            match body.read(&mut buf)? {
+                0 => break,
+                n => {
+                    hasher.update(&buf[..n]);
+                    out.write_all(&buf[..n])?;
+                }
+            }
+        }
+        out.sync_all()?;
+        let mut name = hex(&hasher.finalize());
+        if let Some(ext) = Path::new(url.split(['?', '#']).next().unwrap_or(url)).extension() {
+            name.push('.');
+            name.push_str(&ext.to_string_lossy().to_lowercase());
+        }
+        let path = self.dir.join("objects").join(&name);
+        fs::rename(tmp.path(), &path)?;
+        fs::write(self.url_file(url), format!("{}\n{}\n", name, url))?;
+        self.evict(&path)?;
+        Ok(path)
+    }
+    fn url_file(&self, url: &str) -> PathBuf {
+        self.dir.join("urls").join(hex(&Sha256::digest(url.as_bytes())))
+    }
+    // The cached object for a URL, if it's still there
+    fn lookup(&self, url: &str) -> Option<PathBuf> {
+        let text = fs::read_to_string(self.url_file(url)).ok()?;
+        let path = self.dir.join("objects").join(text.lines().next()?);
+        path.exists().then_some(path)
+    }
+    /// Everything in the cache, oldest first.
+    pub fn entries(&self) -> Result<Vec<CacheEntry>> {
+        let mut entries = vec![];
+        for f in fs::read_dir(self.dir.join("urls"))? {
+            let text = fs::read_to_string(f?.path())?;
+            let mut lines = text.lines();
+            let (Some(name), Some(url)) = (lines.next(), lines.next()) else {
+                continue;
+            };
+            let path = self.dir.join("objects").join(name);
+            if let Ok(meta) = fs::metadata(&path) {
+                entries.push(CacheEntry {
+                    url: url.to_string(),
+                    path,
+                    size: meta.len(),
+                    modified: meta.modified()?,
+                });
+            }
+        }
+        entries.sort_by_key(|e| e.modified);
+        Ok(entries)
+    }
+    /// Removes everything from the cache.
+    pub fn clean(&self) -> Result<()> {
+        let _lock = self.lock()?;
+        for sub in ["objects", "urls"] {
+            for f in fs::read_dir(self.dir.join(sub))? {
+                fs::remove_file(f?.path())?;
+            }
+        }
+        Ok(())
+    }
+    // Removes the least recently used objects, other than `keep`, until
+    // the cache fits its limit. URL entries whose object is gone are
+    // dropped along the way.
+    fn evict(&self, keep: &Path) -> Result<()> {
+        let mut objects = vec![];
+        for f in fs::read_dir(self.dir.join("objects"))? {
+            let f = f?;
+            let meta = f.metadata()?;
+            objects.push((meta.modified()?, meta.len(), f.path()));
+        }
+        objects.sort();
+        let mut total: u64 = objects.iter().map(|o| o.1).sum();
+        for (_, size, path) in objects {
+            if total <= self.limit {
+                break;
+            }
+            if path == keep {
+                continue;
+            }
+            match fs::remove_file(&path) {
+                Ok(()) => total -= size,
+                Err(e) if e.kind() == io::ErrorKind::NotFound => (),
+                Err(e) => return Err(e.into()),
+            }
+        }
+        for f in fs::read_dir(self.dir.join("urls"))? {
+            let f = f?.path();
+            let text = fs::read_to_string(&f)?;
+            if !text.lines().next().is_some_and(|name| self.dir.join("objects").join(name).exists()) {
+                fs::remove_file(f)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+#[derive(Subcommand)]
+pub enum CacheCommands {
+    /// Show the cached files, least recently used first
+    Ls,
+    /// Remove all cached files
+    Clean,
+}
+
+pub fn run(cmd: CacheCommands, yes: bool) -> Result<()> {
+    let cache = Cache::open()?;
+    match cmd {
+        CacheCommands::Ls => {
+            for e in cache.entries()? {
+                println!("{:>10}  {}  {}", e.size, e.path.display(), e.url);
+            }
+            Ok(())
+        },
+        CacheCommands::Clean => {
+            confirm("Remove all cached files?", yes)?;
+            cache.clean()
+        },
+    }
+}
+
+#[test]
+fn cache_store() {
+    let dir = std::env::temp_dir().join(format!("idunsh-cache-{}", process::id()));
+    let cache = Cache::at(dir.clone(), 10).unwrap();
+    let a = cache.store("http://x/a.d64?dl=1", &b"12345678"[..]).unwrap();
+    assert_eq!(a.extension().unwrap(), "d64");
+    assert_eq!(cache.lookup("http://x/a.d64?dl=1"), Some(a.clone()));
+    // The second download pushes the cache over its limit, evicting the first
+    std::thread::sleep(std::time::Duration::from_millis(10));
+    cache.store("http://x/b.prg", &b"abcdefgh"[..]).unwrap();
+    assert_eq!(cache.lookup("http://x/a.d64?dl=1"), None);
+    assert_eq!(cache.entries().unwrap().len(), 1);
+    fs::remove_dir_all(dir).unwrap();
+}
@@ -0,0 +1,213 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+// Copyright (C) 2026 Brian Holdsworth
+
+//! Directory listings kept for a while, so Tab completion, `find` and
+//! wildcards in `get` don't ask the drive each time.
+//!
+//! Listings are kept by directory, such as `c:` or `c:games/`, in
+//! `~/.cache/idunsh/catalogs.json`, and used for `MAX_AGE`. Mounting or
+//! assigning a drive drops its listings, as does a `mount` or `assign`
+//! event from the daemon; so does changing the files on it from here. In
+//! interactive mode a thread lists the directories used again before
+//! they get too old, while the prompt waits for input.
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::PathBuf;
+use std::result;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::RecvTimeoutError;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use serde::{Deserialize, Serialize};
+use idun_client::client::CATALOG_CMD;
+use idun_client::listing::Listing;
+use crate::capture_shell;
+use crate::events::{Event, EventChannel};
+use crate::files;
+use crate::lock::FileLock;
+use crate::saves;
+use crate::store;
+use crate::subscribe_events;
+
+// Simpler error handling
+type Result<T> = result::Result<T, failure::Error>;
+
+/// How long a listing is used for
+pub const MAX_AGE: Duration = Duration::from_secs(60);
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+struct Cached {
+    /// When it was listed, in seconds since the epoch
+    listed: u64,
+    names: Vec<String>,
+}
+
+#[derive(Default)]
+pub struct Catalogs {
+    path: Option<PathBuf>,
+    dirs: BTreeMap<String, Cached>,
+}
+
+fn now() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or_default()
+}
+
+impl Catalogs {
+    /// The listings kept, or none if they can't be read.
+    pub fn open() -> Catalogs {
+        let path = dirs::cache_dir().map(|d| d.join("idunsh").join("catalogs.json"));
+        let dirs = path.as_ref()
+            .and_then(|p| fs::read(p).ok())
+            .and_then(|data| serde_json::from_slice(&data).ok())
+            .unwrap_or_default();
+        Catalogs { path, dirs }
+    }
+    /// The names in `dir`, if it was listed less than `MAX_AGE` ago.
+    pub fn get(&self, dir: &str) -> Option<&[String]> {
+        self.dirs.get(dir)
+            .filter(|c| now().saturating_sub(c.listed) < MAX_AGE.as_secs())
+            .map(|c| c.names.as_slice())
+    }
+    pub fn insert(&mut self, dir: &str, names: Vec<String>) {
+        self.dirs.insert(dir.to_string(), Cached { listed: now(), names });
+    }
+    /// Drops the listings of the drive `dev`, e.g. "c:".
+    pub fn invalidate(&mut self, dev: &str) {
+        let dev = dev.to_ascii_lowercase();
+        self.dirs.retain(|dir, _| !dir.to_ascii_lowercase().starts_with(&dev));
+    }
+    /// The directories whose listings are more than half `MAX_AGE` old,
+    /// and those too old to use.
+    pub fn stale(&self) -> Vec<String> {
+        let (now, age) = (now(), MAX_AGE.as_secs());
+        self.dirs.iter()
+            .filter(|(_, c)| now.saturating_sub(c.listed) >= age / 2)
+            .map(|(dir, _)| dir.clone())
+            .collect()
+    }
+    /// Keeps the listings for other idunsh runs; those too old go.
+    pub fn save(&mut self) -> Result<()> {
+        let Some(path) = &self.path else { return Ok(()) };
+        let (now, age) = (now(), MAX_AGE.as_secs());
+        self.dirs.retain(|_, c| now.saturating_sub(c.listed) < age);
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        let _lock = FileLock::exclusive(&path.with_extension("lock"))?;
+        store::write(path, &serde_json::to_vec(&self.dirs)?)
+    }
+}
+
+/// The names of the entries of a listing, subdirectories ending in '/'.
+pub fn names(listing: &Listing) -> Vec<String> {
+    listing.entries.iter()
+        .map(|e| if e.is_dir() { format!("{}/", e.name) } else { e.name.clone() })
+        .collect()
+}
+
+/// A path split after its directory, e.g. "c:games/" and "ta*".
+pub fn split_dir(path: &str) -> (&str, &str) {
+    let at = path.rfind(['/', ':']).map_or(0, |i| i + 1);
+    path.split_at(at)
+}
+
+/// True if a name has wildcards, `*` or `?`.
+pub fn has_wildcards(name: &str) -> bool {
+    name.contains(['*', '?'])
+}
+
+static CATALOGS: Mutex<Option<Catalogs>> = Mutex::new(None);
+
+// Directories are listed one at a time, from the prompt or the refresher
+static LISTING: Mutex<()> = Mutex::new(());
+
+fn with_catalogs<T>(f: impl FnOnce(&mut Catalogs) -> T) -> T {
+    let mut catalogs = CATALOGS.lock().unwrap_or_else(|e| e.into_inner());
+    f(catalogs.get_or_insert_with(Catalogs::open))
+}
+
+// Lists a directory such as "c:games/" afresh, keeping the names
+fn list_names(dir: &str) -> Result<Vec<String>> {
+    let _listing = LISTING.lock().unwrap_or_else(|e| e.into_inner());
+    let names = names(&Listing::parse(&String::from(capture_shell(CATALOG_CMD, dir)?)));
+    with_catalogs(|c| {
+        c.insert(dir, names.clone());
+        // The cache only saves time, so it may fail
+        let _ = c.save();
+    });
+    Ok(names)
+}
+
+// The names in a directory, as listed lately or else afresh
+pub fn cached_names(dir: &str) -> Result<Vec<String>> {
+    match with_catalogs(|c| c.get(dir).map(<[String]>::to_vec)) {
+        Some(names) => Ok(names),
+        None => list_names(dir),
+    }
+}
+
+// Drops the listings of the drive `path` is on, once its files change
+pub fn forget(path: &str) {
+    if let Ok((dev, _)) = files::split_device(path) {
+        with_catalogs(|c| {
+            c.invalidate(&dev);
+            let _ = c.save();
+        });
+    }
+}
+
+// The paths below `dir` whose names match `pattern`
+pub fn find(dir: &str, pattern: &str, depth: usize) -> Result<Vec<String>> {
+    const MAX_DEPTH: usize = 16;
+    let mut found = vec![];
+    for name in cached_names(dir)? {
+        let path = format!("{}{}", dir, name);
+        if saves::matches(pattern, name.trim_end_matches('/')) {
+            found.push(path.clone());
+        }
+        if name.ends_with('/') && depth < MAX_DEPTH {
+            found.extend(find(&path, pattern, depth + 1)?);
+        }
+    }
+    Ok(found)
+}
+
+// Lists the directories in use again before they get too old, while the
+// prompt waits, and drops those of drives the daemon says changed
+pub fn refresh(waiting: Arc<AtomicBool>) {
+    const TICK: Duration = Duration::from_secs(5);
+    thread::spawn(move || {
+        let mut events = subscribe_events().ok().map(EventChannel::forward);
+        loop {
+            match events.as_ref().map(|rx| rx.recv_timeout(TICK)) {
+                Some(Ok(Event::Changed(dev))) => forget(&dev),
+                Some(Ok(_)) | Some(Err(RecvTimeoutError::Timeout)) => (),
+                Some(Err(RecvTimeoutError::Disconnected)) => events = None,
+                None => thread::sleep(TICK),
+            }
+            for dir in with_catalogs(|c| c.stale()) {
+                if !waiting.load(Ordering::Relaxed) || list_names(&dir).is_err() {
+                    break
+                }
+            }
+        }
+    });
+}
+
+#[test]
+fn catalog_cache() {
+    let mut catalogs = Catalogs::default();
+    catalogs.insert("c:", vec!["game".into(), "games/".into()]);
+    catalogs.insert("c:games/", vec!["tetris".into()]);
+    catalogs.insert("d:", vec!["demo".into()]);
+    assert_eq!(catalogs.get("c:games/").unwrap(), ["tetris"]);
+    assert!(catalogs.stale().is_empty());
+    catalogs.invalidate("C:");
+    assert_eq!((catalogs.get("c:"), catalogs.get("c:games/")), (None, None));
+    catalogs.dirs.get_mut("d:").unwrap().listed -= MAX_AGE.as_secs();
+    assert_eq!((catalogs.get("d:"), catalogs.stale()), (None, vec!["d:".to_string()]));
+    assert_eq!(split_dir("c:games/ta*"), ("c:games/", "ta*"));
+    assert_eq!(split_dir("c:"), ("c:", ""));
+    assert!(has_wildcards("*.prg") && !has_wildcards("game"));
+}
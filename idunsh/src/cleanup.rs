@@ -0,0 +1,120 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+// Copyright (C) 2026 Brian Holdsworth
+
+//! Removal of temporary files and sockets, however idunsh ends.
+//!
+//! A `TempPath` removes its file when dropped. Every live `TempPath` is
+//! also registered here, so the files still go away when idunsh panics,
+//! is interrupted, or exits from another thread.
+//!
+//! While something waits on `interrupted`, SIGINT wakes it instead of
+//! ending idunsh, so that work such as an upload can be abandoned and
+//! reported like any other error.
+use std::fs;
+use std::panic;
+use std::path::{Path, PathBuf};
+use std::process;
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::thread;
+use nix::sys::signal::{SigSet, Signal};
+use tokio::sync::Notify;
+
+static REGISTERED: Mutex<Vec<PathBuf>> = Mutex::new(Vec::new());
+// What waits for SIGINT, and how many are waiting
+static INTERRUPT: Notify = Notify::const_new();
+static WAITING: AtomicUsize = AtomicUsize::new(0);
+
+/// A temporary file or socket, removed when it goes out of scope.
+#[derive(Debug)]
+pub struct TempPath {
+    path: PathBuf,
+}
+
+impl TempPath {
+    pub fn new<P: Into<PathBuf>>(path: P) -> TempPath {
+        let path = path.into();
+        if let Ok(mut reg) = REGISTERED.lock() {
+            reg.push(path.clone());
+        }
+        TempPath { path }
+    }
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+}
+
+impl Drop for TempPath {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.path);
+        if let Ok(mut reg) = REGISTERED.lock() {
+            reg.retain(|p| *p != self.path);
+        }
+    }
+}
+
+/// Removes every registered file now.
+pub fn remove_all() {
+    if let Ok(mut reg) = REGISTERED.lock() {
+        for path in reg.drain(..) {
+            let _ = fs::remove_file(path);
+        }
+    }
+}
+
+/// Completes when SIGINT arrives. Until then, SIGINT doesn't end idunsh.
+pub async fn interrupted() {
+    let notified = INTERRUPT.notified();
+    tokio::pin!(notified);
+    notified.as_mut().enable();
+    // Counted until it completes or is dropped
+    struct Waiting;
+    impl Drop for Waiting {
+        fn drop(&mut self) {
+            WAITING.fetch_sub(1, Ordering::SeqCst);
+        }
+    }
+    WAITING.fetch_add(1, Ordering::SeqCst);
+    let _waiting = Waiting;
+    notified.await;
+}
+
+/// Cleans up on panics and on SIGINT, SIGTERM and SIGHUP. Call this
+/// before starting any threads: the signals are blocked in every thread
+/// and handled by one that waits for them.
+pub fn install() {
+    let hook = panic::take_hook();
+    panic::set_hook(Box::new(move |info| {
+        remove_all();
+        hook(info);
+    }));
+
+    let mut signals = SigSet::empty();
+    signals.add(Signal::SIGINT);
+    signals.add(Signal::SIGTERM);
+    signals.add(Signal::SIGHUP);
+    if signals.thread_block().is_ok() {
+        thread::spawn(move || {
+            while let Ok(sig) = signals.wait() {
+                if sig == Signal::SIGINT && WAITING.load(Ordering::SeqCst) > 0 {
+                    INTERRUPT.notify_waiters();
+                    continue
+                }
+                remove_all();
+                process::exit(128 + sig as i32);
+            }
+        });
+    }
+}
+
+#[test]
+fn temp_path_removed() {
+    let path = std::env::temp_dir().join(format!("idunsh-test-{}", process::id()));
+    fs::write(&path, b"x").unwrap();
+    let temp = TempPath::new(&path);
+    remove_all();
+    assert!(!path.exists());
+    fs::write(&path, b"x").unwrap();
+    drop(temp);
+    assert!(!path.exists());
+}
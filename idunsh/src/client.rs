@@ -0,0 +1,481 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+// Copyright (C) 2026 Brian Holdsworth
+
+//! A client for the idun daemon, for tools that control the cartridge
+//! without running `idunsh`.
+//!
+//! ```no_run
+//! use idun_client::client::IdunClient;
+//!
+//! let idun = IdunClient::new();
+//! idun.mount("d:", "/home/idun/disks/work.d64")?;
+//! for file in idun.dir("d:")?.entries {
+//!     println!("{} {}", file.name, file.ftype);
+//! }
+//! # Ok::<(), failure::Error>(())
+//! ```
+//!
+//! Commands are sent over the daemon's Lua socket; see `protocol` for
+//! their encoding. Output a command redirects comes back on a socket of
+//! our own in the run directory, `/run/user/<uid>` unless changed, named
+//! by the number passed with it.
+//!
+//! How long the daemon is waited for, and how often it's tried again
+//! when it can't be reached, is set with `with_timeouts`. Only connecting
+//! is tried again, as a command sent may already have been run.
+//!
+//! Requests wait their turn in a queue shared by everything in the
+//! process, e.g. the interactive mode's commands and its listing refresh,
+//! or the clients of a bridge. Each is numbered, they go out in the order
+//! they came, a few at a time, and once many are waiting further ones are
+//! refused rather than left to pile up behind a daemon that has stopped
+//! answering.
+//!
+//! A socket named `serial://...` is a serial line to the cartridge's
+//! UART instead; see `serial`.
+//!
+//! Links other than the daemon's socket may need the bytes carried
+//! another way; `with_encoding` picks one of those in `encoding` for
+//! calls, answers and redirected output alike.
+use std::future::Future;
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::{Path, PathBuf};
+use std::process;
+use std::result;
+use std::sync::{Arc, OnceLock};
+use std::sync::atomic::{AtomicU32, AtomicU64, AtomicUsize, Ordering};
+use std::time::Duration;
+use bstr::BString;
+use nix::unistd;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::sync::Semaphore;
+use crate::cleanup::TempPath;
+use crate::encoding::{self, Encoding, Raw};
+use crate::listing::{Listing, Mount};
+use crate::protocol::{self, RemoteError};
+use crate::runtime;
+use crate::serial::{self, Port};
+use crate::util::PetString;
+
+// Simpler error handling
+type Result<T> = result::Result<T, failure::Error>;
+
+pub const LUAPORT: &str = "/tmp/idunmm-lua";
+
+// Supported shell command constants
+pub const EXEC_CMD: u8      = 0;
+pub const GO_CMD: u8        = 1;
+pub const LOAD_CMD: u8      = 2;
+pub const DIR_CMD: u8       = 3;
+pub const CATALOG_CMD: u8   = 4;
+pub const DRIVES_CMD: u8    = 5;
+pub const MOUNT_CMD: u8     = 6;
+pub const ASSIGN_CMD: u8    = 7;
+pub const MKDIR_CMD: u8     = 8;
+pub const RMDIR_CMD: u8     = 9;
+pub const DOS_CMD: u8       = 10;
+pub const BLOCK_READ_CMD: u8  = 11;
+pub const BLOCK_WRITE_CMD: u8 = 12;
+pub const IMAGE_RIP_CMD: u8   = 13;
+pub const IMAGE_BURN_CMD: u8  = 14;
+pub const FILE_GET_CMD: u8    = 15;
+pub const FILE_PUT_CMD: u8    = 16;
+pub const IMAGE_HASH_CMD: u8  = 17;
+
+// Each request opens its own connection to the daemon, so requests never
+// share a socket. Bursts, e.g. dir on many devices, wait for one of these
+// slots rather than all connecting at once.
+const MAX_REQUESTS: usize = 4;
+// Requests waiting or being sent, beyond which more are refused
+const QUEUE_DEPTH: usize = 64;
+static REQUESTS: Queue = Queue::new(MAX_REQUESTS, QUEUE_DEPTH);
+
+// Requests for the daemon, taken in the order they come
+struct Queue {
+    slots: Semaphore,
+    depth: usize,
+    queued: AtomicUsize,
+    next: AtomicU64,
+}
+
+// A request's place in the queue, given up when dropped
+struct Ticket<'a> {
+    id: u64,
+    queue: &'a Queue,
+}
+
+impl Queue {
+    const fn new(slots: usize, depth: usize) -> Queue {
+        Queue { slots: Semaphore::const_new(slots), depth, queued: AtomicUsize::new(0), next: AtomicU64::new(1) }
+    }
+    // Numbers a request and queues it, unless the queue is full
+    fn enter(&self) -> Result<Ticket<'_>> {
+        let queued = self.queued.fetch_add(1, Ordering::AcqRel);
+        let ticket = Ticket { id: self.next.fetch_add(1, Ordering::Relaxed), queue: self };
+        if queued >= self.depth {
+            bail!("{} requests are already waiting for the daemon; request {} was not sent", queued, ticket.id)
+        }
+        Ok(ticket)
+    }
+}
+
+impl Ticket<'_> {
+    // Waits until fewer than the most requests at a time are being sent;
+    // the semaphore is fair, so requests go out in the order they came
+    async fn turn(&self) -> Result<tokio::sync::SemaphorePermit<'_>> {
+        Ok(self.queue.slots.acquire().await?)
+    }
+}
+
+impl Drop for Ticket<'_> {
+    fn drop(&mut self) {
+        self.queue.queued.fetch_sub(1, Ordering::AcqRel);
+    }
+}
+
+/// How long to wait for the daemon and the C64U, and how often to try
+/// reaching them again.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Timeouts {
+    /// Connecting to the daemon's socket, or to the C64U
+    pub connect: Duration,
+    /// The answer to a command
+    pub command: Option<Duration>,
+    /// Redirected output and file transfers, each as a whole
+    pub transfer: Option<Duration>,
+    /// The search for a C64U on the LAN
+    pub discovery: Duration,
+    /// Times connecting is tried again after failing
+    pub retries: u32,
+}
+
+impl Default for Timeouts {
+    fn default() -> Self {
+        Timeouts {
+            connect: Duration::from_secs(5),
+            command: None,
+            transfer: None,
+            discovery: Duration::from_millis(500),
+            retries: 0,
+        }
+    }
+}
+
+// The wait before trying to connect again grows by this each time
+const RETRY_DELAY: Duration = Duration::from_millis(250);
+
+/// Shell commands run in order, stopping at the first that fails; sent as
+/// one call where the daemon has `sys.batch` (see `protocol`).
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Batch {
+    cmds: Vec<(u8, String)>,
+}
+
+impl Batch {
+    pub fn new() -> Batch {
+        Batch::default()
+    }
+    /// Adds a shell command and its argument string.
+    pub fn shell(mut self, cmd: u8, args: &str) -> Batch {
+        self.cmds.push((cmd, args.to_string()));
+        self
+    }
+    pub fn mount(self, dev: &str, image: &str) -> Batch {
+        self.shell(MOUNT_CMD, &protocol::join_args(&[dev, image]))
+    }
+    pub fn assign(self, dev: &str, path: &str) -> Batch {
+        self.shell(ASSIGN_CMD, &protocol::join_args(&[dev, path]))
+    }
+    pub fn load(self, file: &str) -> Batch {
+        self.shell(LOAD_CMD, file)
+    }
+    pub fn is_empty(&self) -> bool {
+        self.cmds.is_empty()
+    }
+}
+
+/// The daemon's Lua socket. Calls block until the daemon has answered.
+#[derive(Clone, Debug)]
+pub struct IdunClient {
+    socket: PathBuf,
+    run_dir: PathBuf,
+    timeouts: Timeouts,
+    encoding: Arc<dyn Encoding>,
+    // The proposed protocol features the daemon has, once asked
+    features: Arc<OnceLock<Vec<String>>>,
+}
+
+impl Default for IdunClient {
+    fn default() -> Self {
+        IdunClient::new()
+    }
+}
+
+impl IdunClient {
+    /// The daemon on this machine, at its usual socket.
+    pub fn new() -> IdunClient {
+        IdunClient::with_socket(LUAPORT)
+    }
+    pub fn with_socket(socket: impl AsRef<Path>) -> IdunClient {
+        let run_dir = PathBuf::from(format!("/run/user/{}", unistd::getuid()));
+        IdunClient {
+            socket: socket.as_ref().to_path_buf(),
+            run_dir,
+            timeouts: Timeouts::default(),
+            encoding: Arc::new(Raw),
+            features: Arc::new(OnceLock::new()),
+        }
+    }
+    /// Where our sockets for redirected output are made. The daemon has
+    /// to see the same directory.
+    pub fn with_run_dir(mut self, run_dir: impl AsRef<Path>) -> IdunClient {
+        self.run_dir = run_dir.as_ref().to_path_buf();
+        self
+    }
+    pub fn run_dir(&self) -> &Path {
+        &self.run_dir
+    }
+    pub fn with_timeouts(mut self, timeouts: Timeouts) -> IdunClient {
+        self.timeouts = timeouts;
+        self
+    }
+    pub fn timeouts(&self) -> Timeouts {
+        self.timeouts
+    }
+    /// How bytes are carried to and from the daemon; raw unless changed.
+    pub fn with_encoding(mut self, encoding: Arc<dyn Encoding>) -> IdunClient {
+        self.encoding = encoding;
+        self
+    }
+    pub fn encoding(&self) -> Arc<dyn Encoding> {
+        self.encoding.clone()
+    }
+    /// True if the daemon accepts connections.
+    pub fn reachable(&self) -> bool {
+        match Port::parse(&self.socket.to_string_lossy()) {
+            Ok(Some(port)) => port.path.exists(),
+            Ok(None) => UnixStream::connect(&self.socket).is_ok(),
+            Err(_) => false,
+        }
+    }
+    /// Sends one Lua call, e.g. `sys.shell(3, "c:", 0)`, and checks the
+    /// daemon's answer.
+    pub fn send(&self, message: String) -> Result<()> {
+        runtime::block_on(self.request(message))
+    }
+    /// `send` for use inside tasks on the runtime.
+    pub async fn request(&self, message: String) -> Result<()> {
+        let ticket = REQUESTS.enter()?;
+        let _slot = ticket.turn().await?;
+        let mut s = self.connect().await?;
+        let mut r: Vec<u8> = Vec::new();
+
+        within(self.timeouts.command, "The daemon didn't answer", async {
+            s.write_all(&self.encoding.encode(format!("{}\n", message).as_bytes())).await?;
+            s.read_to_end(&mut r).await?;
+            Ok(())
+        }).await.map_err(|e| format_err!("{} (request {})", e, ticket.id))?;
+        let r = encoding::decode(&*self.encoding, &r)?;
+        match RemoteError::from_reply(&r) {
+            Some(e) => Err(e.into()),
+            None => Ok(()),
+        }
+    }
+    // Connects to the daemon's socket, trying again as often as allowed
+    async fn connect(&self) -> Result<tokio::net::UnixStream> {
+        if let Some(port) = Port::parse(&self.socket.to_string_lossy())? {
+            let s = serial::relay(&port, &self.run_dir)?.connect()?;
+            s.set_nonblocking(true)?;
+            return Ok(tokio::net::UnixStream::from_std(s)?)
+        }
+        let mut tries = 0;
+        loop {
+            let e = match tokio::time::timeout(self.timeouts.connect, tokio::net::UnixStream::connect(&self.socket)).await {
+                Ok(Ok(s)) => return Ok(s),
+                Ok(Err(e)) => e.to_string(),
+                Err(_) => format!("no answer within {:?}", self.timeouts.connect),
+            };
+            if tries == self.timeouts.retries {
+                bail!("Can't reach the daemon at {}: {}", self.socket.display(), e)
+            }
+            tries += 1;
+            tokio::time::sleep(RETRY_DELAY * tries).await;
+        }
+    }
+    /// Runs a shell command, redirecting its output to `proc`, or leaving
+    /// it on the Commodore's screen if `proc` is 0.
+    pub fn shell(&self, cmd: u8, args: &str, proc: u32) -> Result<()> {
+        self.send(shell_call(cmd, args, proc))
+    }
+    /// Runs the commands of a batch, as a single call when there's more
+    /// than one and the daemon has `sys.batch`, or else one by one.
+    /// Commands before one that fails stay done.
+    pub fn batch(&self, batch: &Batch, proc: u32) -> Result<()> {
+        if batch.cmds.len() > 1 && self.has("batch")? {
+            return self.send(batch_call(batch, proc))
+        }
+        for (cmd, args) in &batch.cmds {
+            self.shell(*cmd, args, proc)?;
+        }
+        Ok(())
+    }
+    /// Whether the daemon has a proposed protocol feature, e.g. "batch".
+    /// The daemon is asked once, on first use; see `protocol` for
+    /// `sys.features`.
+    pub fn has(&self, feature: &str) -> Result<bool> {
+        if let Some(features) = self.features.get() {
+            return Ok(features.iter().any(|f| f == feature))
+        }
+        let features = match self.capture(|id| self.send(format!("sys.features({})", id))) {
+            Ok(list) => String::from_utf8_lossy(&list).split_whitespace().map(String::from).collect(),
+            // Released daemons don't know sys.features, or anything it names
+            Err(e) if e.downcast_ref::<RemoteError>().is_some() => vec![],
+            Err(e) => return Err(e),
+        };
+        Ok(self.features.get_or_init(|| features).iter().any(|f| f == feature))
+    }
+    /// Sends a command through `send`, given the redirect id, and
+    /// collects the raw redirected output.
+    pub fn capture(&self, send: impl FnOnce(u32) -> Result<()>) -> Result<Vec<u8>> {
+        let (resport, respath, id) = self.response_listener()?;
+        let reader = runtime::spawn(within(self.timeouts.transfer, "The output didn't all come back",
+            read_redirect(resport, self.encoding.clone())));
+        let output = match send(id) {
+            Ok(_) => runtime::join(reader),
+            Err(e) => {
+                reader.abort();
+                Err(e)
+            },
+        };
+        drop(respath);
+        output
+    }
+    /// Runs a shell command and collects all of its redirected output.
+    pub fn capture_shell(&self, cmd: u8, args: &str) -> Result<PetString> {
+        let output = self.capture(|id| self.shell(cmd, args, id))?;
+        Ok(PetString::new(&BString::from(output)))
+    }
+    /// Runs an idun program or command, e.g. `exec("ls", &["-l"])`, and
+    /// gives what it printed.
+    pub fn exec<S: AsRef<str>>(&self, cmd: &str, args: &[S]) -> Result<String> {
+        // The program and its arguments, each quoted as need be
+        let words: Vec<&str> = std::iter::once(cmd).chain(args.iter().map(AsRef::as_ref)).collect();
+        let line = protocol::join_args(&words);
+        Ok(String::from(self.capture_shell(EXEC_CMD, &line)?))
+    }
+    /// The catalog of a drive or directory, e.g. `c:` or `c:games/`.
+    pub fn dir(&self, dev: &str) -> Result<Listing> {
+        Ok(Listing::parse(&String::from(self.capture_shell(CATALOG_CMD, dev)?)))
+    }
+    /// The drives and what each is assigned to.
+    pub fn drives(&self) -> Result<Vec<Mount>> {
+        Ok(Mount::parse_all(&String::from(self.capture_shell(DRIVES_CMD, "")?)))
+    }
+    /// Mounts a disk image on this machine on a drive.
+    pub fn mount(&self, dev: &str, image: &str) -> Result<()> {
+        self.shell(MOUNT_CMD, &protocol::join_args(&[dev, image]), 0)
+    }
+    /// Assigns a drive to a directory on this machine.
+    pub fn assign(&self, dev: &str, path: &str) -> Result<()> {
+        self.shell(ASSIGN_CMD, &protocol::join_args(&[dev, path]), 0)
+    }
+    /// Listening socket the remote shell connects to for redirected
+    /// output, with the number that names it. The first is the process
+    /// id; sockets for concurrent captures get further numbers derived
+    /// from it.
+    pub fn response_listener(&self) -> Result<(UnixListener, TempPath, u32)> {
+        static NEXT: AtomicU32 = AtomicU32::new(0);
+        let id = match NEXT.fetch_add(1, Ordering::Relaxed) {
+            0 => process::id(),
+            n => process::id().wrapping_mul(1000).wrapping_add(n),
+        };
+        let respath = self.run_dir.join(id.to_string());
+        let resport = UnixListener::bind(&respath)
+            .map_err(|e| format_err!("Can't listen for output at {}: {}", respath.display(), e))?;
+        Ok((resport, TempPath::new(respath), id))
+    }
+}
+
+/// The Lua call that runs a shell command.
+pub fn shell_call(cmd: u8, args: &str, proc: u32) -> String {
+    format!("sys.shell({}, {}, {})", cmd, protocol::lua_string(args), proc)
+}
+
+/// `shell_call` asking for the redirected output as frames of its
+/// output, error and status streams.
+pub fn streams_call(cmd: u8, args: &str, proc: u32) -> String {
+    format!("sys.shell({}, {}, {}, \"streams\")", cmd, protocol::lua_string(args), proc)
+}
+
+/// `streams_call` also asking for a CRC of the output at its end.
+pub fn crc_call(cmd: u8, args: &str, proc: u32) -> String {
+    format!("sys.shell({}, {}, {}, \"streams,crc\")", cmd, protocol::lua_string(args), proc)
+}
+
+/// The Lua call that runs the commands of a batch.
+pub fn batch_call(batch: &Batch, proc: u32) -> String {
+    let cmds: Vec<String> = batch.cmds.iter()
+        .map(|(cmd, args)| format!("{{{}, {}}}", cmd, protocol::lua_string(args)))
+        .collect();
+    format!("sys.batch({{{}}}, {})", cmds.join(", "), proc)
+}
+
+/// Runs `future`, giving up after `limit` if there is one with `what`
+/// went wrong.
+pub async fn within<T>(limit: Option<Duration>, what: &'static str, future: impl Future<Output = Result<T>>) -> Result<T> {
+    match limit {
+        Some(limit) => tokio::time::timeout(limit, future).await
+            .map_err(|_| format_err!("{} within {:?}", what, limit))?,
+        None => future.await,
+    }
+}
+
+/// Waits on the runtime for the remote shell to connect to a redirect
+/// socket.
+pub async fn accept_redirect(listener: UnixListener) -> Result<tokio::net::UnixStream> {
+    listener.set_nonblocking(true)?;
+    let (s, _) = tokio::net::UnixListener::from_std(listener)?.accept().await?;
+    Ok(s)
+}
+
+/// Collects everything redirected to a socket, decoded.
+pub async fn read_redirect(listener: UnixListener, encoding: Arc<dyn Encoding>) -> Result<Vec<u8>> {
+    let mut s = accept_redirect(listener).await?;
+    let mut buf = vec![];
+    s.read_to_end(&mut buf).await?;
+    encoding::decode(&*encoding, &buf)
+}
+
+#[test]
+fn shell_calls() {
+    assert_eq!(shell_call(MOUNT_CMD, "d: \"my disk.d64\"", 0), r#"sys.shell(6, "d: \"my disk.d64\"", 0)"#);
+    assert_eq!(streams_call(EXEC_CMD, "ls", 42), r#"sys.shell(0, "ls", 42, "streams")"#);
+    assert_eq!(crc_call(DIR_CMD, "c:", 42), r#"sys.shell(3, "c:", 42, "streams,crc")"#);
+    let batch = Batch::new().mount("d:", "my disk.d64").load("demo");
+    assert_eq!(batch_call(&batch, 0), r#"sys.batch({{6, "d: \"my disk.d64\""}, {2, "demo"}}, 0)"#);
+    assert!(!IdunClient::with_socket("/nonexistent/idun").reachable());
+    let timeouts = Timeouts { retries: 2, connect: Duration::from_millis(10), ..Default::default() };
+    let idun = IdunClient::with_socket("/nonexistent/idun").with_timeouts(timeouts);
+    assert!(idun.send("sys.keys(\"\")".into()).is_err());
+    let late = runtime::block_on(within(Some(Duration::from_millis(1)), "Too slow", async {
+        tokio::time::sleep(Duration::from_secs(1)).await;
+        Ok(())
+    }));
+    assert_eq!(late.unwrap_err().to_string(), "Too slow within 1ms");
+}
+
+#[test]
+fn request_queue() {
+    let queue = Queue::new(1, 2);
+    let (first, second) = (queue.enter().unwrap(), queue.enter().unwrap());
+    assert_eq!((first.id, second.id), (1, 2));
+    let refused = queue.enter().err().unwrap().to_string();
+    assert_eq!(refused, "2 requests are already waiting for the daemon; request 3 was not sent");
+    // A place given up is free again, and numbers keep counting
+    drop(first);
+    assert_eq!(queue.enter().unwrap().id, 4);
+    let slot = runtime::block_on(second.turn()).unwrap();
+    assert_eq!(queue.slots.available_permits(), 0);
+    drop(slot);
+    assert_eq!(queue.slots.available_permits(), 1);
+}
@@ -0,0 +1,136 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+// Copyright (C) 2026 Brian Holdsworth
+
+//! The Commodore's clocks, for `idunsh clock`.
+//!
+//! TI$ is kept by the KERNAL as a jiffy count at $a0-$a2, highest byte
+//! first, sixty jiffies a second since midnight. The CIA's time of day
+//! clock at $dc08-$dc0b holds tenths, seconds, minutes and hours in BCD,
+//! on a 12 hour dial with bit 7 of the hours for PM. Writing the hours
+//! stops it until the tenths are written; reading the hours holds the
+//! other registers until the tenths are read.
+use std::process::Command;
+use std::result;
+use std::time::{SystemTime, UNIX_EPOCH};
+use clap::Subcommand;
+use crate::target::Memory;
+
+// Simpler error handling
+type Result<T> = result::Result<T, failure::Error>;
+
+const TI: u16 = 0x00a0;
+const TOD: u16 = 0xdc08;
+const DAY: u64 = 86400;
+
+/// Seconds since midnight on this machine's clock, in local time unless
+/// `utc`.
+pub fn now(utc: bool) -> Result<u64> {
+    let secs = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs() as i64;
+    let offset = if utc { 0 } else { local_offset() };
+    Ok((secs + offset).rem_euclid(DAY as i64) as u64)
+}
+
+// The local time zone's offset from UTC in seconds, as date gives it,
+// or 0 if it can't be told
+fn local_offset() -> i64 {
+    Command::new("date").arg("+%z").output().ok()
+        .and_then(|out| parse_offset(String::from_utf8_lossy(&out.stdout).trim()))
+        .unwrap_or(0)
+}
+
+// An offset such as +0200 or -0530
+fn parse_offset(s: &str) -> Option<i64> {
+    let (sign, digits) = match s.split_at_checked(1)? {
+        ("+", digits) => (1, digits),
+        ("-", digits) => (-1, digits),
+        _ => return None,
+    };
+    if digits.len() != 4 || !digits.bytes().all(|b| b.is_ascii_digit()) {
+        return None
+    }
+    let (hours, minutes): (i64, i64) = (digits[..2].parse().ok()?, digits[2..].parse().ok()?);
+    Some(sign * (hours * 3600 + minutes * 60))
+}
+
+/// A time of day as HH:MM:SS.
+pub fn format(secs: u64) -> String {
+    format!("{:02}:{:02}:{:02}", secs / 3600 % 24, secs / 60 % 60, secs % 60)
+}
+
+/// The jiffy count of TI for `secs` after midnight.
+pub fn ti_bytes(secs: u64) -> [u8; 3] {
+    let jiffies = (secs % DAY * 60) as u32;
+    let b = jiffies.to_be_bytes();
+    [b[1], b[2], b[3]]
+}
+
+fn bcd(n: u64) -> u8 {
+    (n / 10 * 16 + n % 10) as u8
+}
+fn from_bcd(b: u8) -> u64 {
+    (b >> 4) as u64 * 10 + (b & 0xf) as u64
+}
+
+/// The time of day registers for `secs` after midnight: tenths,
+/// seconds, minutes and hours.
+pub fn tod_bytes(secs: u64) -> [u8; 4] {
+    let hours = secs / 3600 % 24;
+    let dial = match hours % 12 {
+        0 => 12,
+        h => h,
+    };
+    let pm = if hours >= 12 { 0x80 } else { 0 };
+    [0, bcd(secs % 60), bcd(secs / 60 % 60), bcd(dial) | pm]
+}
+
+/// Seconds after midnight shown by the time of day registers.
+pub fn tod_secs(regs: [u8; 4]) -> u64 {
+    let hours = from_bcd(regs[3] & 0x1f) % 12 + if regs[3] & 0x80 != 0 { 12 } else { 0 };
+    hours * 3600 + from_bcd(regs[2] & 0x7f) * 60 + from_bcd(regs[1] & 0x7f)
+}
+
+/// Sets TI$ and the time of day clock to `secs` after midnight.
+pub fn set(memory: &dyn Memory, secs: u64) -> Result<()> {
+    memory.write(TI, &ti_bytes(secs))?;
+    // Hours first and tenths last, so the clock starts at the time set
+    for (i, b) in tod_bytes(secs).iter().enumerate().rev() {
+        memory.write(TOD + i as u16, &[*b])?;
+    }
+    Ok(())
+}
+
+/// The times TI$ and the time of day clock show.
+pub fn read(memory: &dyn Memory) -> Result<(u64, u64)> {
+    let ti = memory.read(TI, 3)?;
+    let jiffies = u32::from_be_bytes([0, ti[0], ti[1], ti[2]]) as u64;
+    let hours = memory.read(TOD + 3, 1)?[0];
+    let rest = memory.read(TOD, 3)?;
+    Ok((jiffies / 60, tod_secs([rest[0], rest[1], rest[2], hours])))
+}
+
+#[derive(Subcommand)]
+pub enum ClockCommands {
+    /// Set TI$ and the CIA time of day to the time here
+    Sync {
+        #[arg(long)]
+        /// Use UTC rather than the local time
+        utc: bool,
+    },
+    /// Show the time of TI$ and the CIA time of day (C64U or VICE)
+    Show,
+}
+
+#[test]
+fn clock_registers() {
+    let secs = 13 * 3600 + 45 * 60 + 30;
+    assert_eq!(format(secs), "13:45:30");
+    assert_eq!(ti_bytes(secs), [0x2d, 0x58, 0x98]);
+    assert_eq!(tod_bytes(secs), [0, 0x30, 0x45, 0x81]);
+    assert_eq!(tod_secs(tod_bytes(secs)), secs);
+    assert_eq!(tod_bytes(30), [0, 0x30, 0, 0x12]);
+    assert_eq!(tod_secs(tod_bytes(30)), 30);
+    assert_eq!(tod_secs(tod_bytes(12 * 3600)), 12 * 3600);
+    assert_eq!(parse_offset("+0200"), Some(7200));
+    assert_eq!(parse_offset("-0530"), Some(-19800));
+    assert_eq!(parse_offset("UTC"), None);
+}
@@ -0,0 +1,360 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+// Copyright (C) 2026 Brian Holdsworth
+
+//! An index of a collection of images and programs, for `idunsh
+//! collection`.
+//!
+//! `collection scan` walks a directory and notes every file whose
+//! format is known (see `formats`): its path, format, title, size and
+//! CRC-32, and for a D64, D71 or D81 image the files in its directory,
+//! each with its own CRC-32. The index is kept in `collection.json` in
+//! the state directory; scanning a directory again replaces what was
+//! noted under it and keeps the rest.
+//!
+//! `collection search` finds items by words that must all be in the
+//! title, the file name or the name of a file on the disk.
+//! `collection export` describes the disk images found the same way as
+//! a FILE_ID.DIZ each (see `diz`). `collection dupes` groups items that are the same: by checksum, the
+//! same size and CRC-32, or by content, where disk images also count as
+//! the same when they hold the same files, by name, type and CRC-32,
+//! though the sectors they're in differ, as they do with another
+//! interleave or the files copied in another order. `mount`,
+//! `load` and `run` take an item as `@` and its title or file name,
+//! e.g. `idunsh run @commando`.
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::result;
+use bstr::BString;
+use clap::{Subcommand, ValueEnum};
+use serde::{Deserialize, Serialize};
+use crate::disk::Disk;
+use crate::diz;
+use crate::formats::{FileInfo, Format};
+use crate::store;
+use crate::util::{self, PetString};
+
+// Simpler error handling
+type Result<T> = result::Result<T, failure::Error>;
+
+const INDEX: &str = "collection.json";
+// Anything larger is no image or program for a C64
+const LARGEST: u64 = 16 << 20;
+
+/// A file of the collection.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Item {
+    pub path: String,
+    pub format: String,
+    pub title: String,
+    pub size: usize,
+    pub crc32: u32,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub load_addr: Option<u16>,
+    /// The files on a disk image
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub files: Vec<DiskFile>,
+}
+
+/// A file on a disk image of the collection.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DiskFile {
+    pub name: String,
+    #[serde(rename = "type")]
+    pub ftype: String,
+    pub blocks: u16,
+    pub crc32: u32,
+}
+
+/// What makes two items the same, for `dupes`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum Likeness {
+    Checksum,
+    Content,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct Collection {
+    pub items: Vec<Item>,
+}
+
+impl Item {
+    /// The item for the file at `path`, or None if its format isn't known.
+    pub fn of_file(path: &Path) -> Result<Option<Item>> {
+        let data = fs::read(path).map_err(|e| format_err!("{}: {}", path.display(), e))?;
+        let name = path.to_string_lossy().into_owned();
+        let info = FileInfo::identify(&name, &data);
+        if info.format == Format::Unknown {
+            return Ok(None)
+        }
+        let stem = path.file_stem().map(|s| s.to_string_lossy().into_owned()).unwrap_or_default();
+        let files = match info.format {
+            // A disk whose directory can't be read is still indexed
+            Format::D64 | Format::D71 | Format::D81 => disk_files(&name, data).unwrap_or_default(),
+            _ => vec![],
+        };
+        Ok(Some(Item {
+            path: name,
+            format: info.format.to_string(),
+            title: info.title.filter(|t| !t.is_empty()).unwrap_or(stem),
+            size: info.size,
+            crc32: info.crc32,
+            load_addr: info.load_addr,
+            files,
+        }))
+    }
+    // True if every word is in the title, the file name or a disk file's name
+    fn matches(&self, words: &[String]) -> bool {
+        let file_name = Path::new(&self.path).file_name().map(|n| n.to_string_lossy().to_lowercase()).unwrap_or_default();
+        let names: Vec<String> = [self.title.to_lowercase(), file_name].into_iter()
+            .chain(self.files.iter().map(|f| f.name.to_lowercase()))
+            .collect();
+        words.iter().all(|w| names.iter().any(|n| n.contains(w.as_str())))
+    }
+    /// The item's FILE_ID.DIZ, if it's a disk image.
+    pub fn diz(&self, ascii: bool) -> Option<Vec<String>> {
+        if !["D64", "D71", "D81"].contains(&self.format.as_str()) {
+            return None
+        }
+        let files: Vec<diz::File> = self.files.iter()
+            .map(|f| diz::File { name: f.name.clone(), ftype: f.ftype.clone() })
+            .collect();
+        Some(diz::describe(&self.title, &files, None, ascii))
+    }
+    // What the items the same as this one by `likeness` have in common
+    fn key(&self, likeness: Likeness) -> String {
+        match likeness {
+            Likeness::Content if !self.files.is_empty() => {
+                let mut files: Vec<String> = self.files.iter()
+                    .map(|f| format!("{},{},{:08x}", f.name, f.ftype, f.crc32))
+                    .collect();
+                files.sort();
+                files.join("\n")
+            },
+            _ => format!("{:08x} {}", self.crc32, self.size),
+        }
+    }
+    /// The item as `collection search` lists it.
+    pub fn line(&self) -> String {
+        format!("{:<4} {:<24} {}", self.format, self.title, self.path)
+    }
+}
+
+fn disk_files(name: &str, data: Vec<u8>) -> Result<Vec<DiskFile>> {
+    let disk = Disk::open(name, data)?;
+    disk.entries()?.into_iter().map(|entry| Ok(DiskFile {
+        name: String::from(PetString::new(&BString::from(entry.name.as_slice()))),
+        ftype: entry.type_name().to_string(),
+        blocks: entry.blocks,
+        crc32: util::crc32(&disk.read(&entry).unwrap_or_default()),
+    })).collect()
+}
+
+impl Collection {
+    fn path() -> Result<PathBuf> {
+        Ok(store::dir()?.join(INDEX))
+    }
+    /// The index, empty if nothing was scanned yet.
+    pub fn load() -> Result<Collection> {
+        let path = Collection::path()?;
+        match fs::read(&path) {
+            Ok(data) => serde_json::from_slice(&data).map_err(|e| format_err!("{}: {}", path.display(), e)),
+            Err(_) => Ok(Collection::default()),
+        }
+    }
+    pub fn save(&self) -> Result<()> {
+        store::write(&Collection::path()?, &serde_json::to_vec_pretty(self)?)
+    }
+    /// Notes the files under `dir` in place of those noted before, and
+    /// tells how many were noted and how many couldn't be read.
+    pub fn scan(&mut self, dir: &Path) -> Result<(usize, usize)> {
+        let dir = dir.canonicalize().map_err(|e| format_err!("{}: {}", dir.display(), e))?;
+        self.items.retain(|item| !Path::new(&item.path).starts_with(&dir));
+        let (mut noted, mut unreadable) = (0, 0);
+        let mut pending = vec![dir];
+        while let Some(d) = pending.pop() {
+            let mut entries: Vec<_> = fs::read_dir(&d)
+                .map_err(|e| format_err!("{}: {}", d.display(), e))?
+                .filter_map(|e| e.ok())
+                .collect();
+            entries.sort_by_key(|e| e.file_name());
+            for entry in entries {
+                let path = entry.path();
+                // Linked directories aren't followed, so there are no loops
+                if entry.file_type().is_ok_and(|t| t.is_dir()) {
+                    pending.push(path);
+                    continue
+                }
+                if !fs::metadata(&path).is_ok_and(|m| m.is_file() && m.len() <= LARGEST) {
+                    continue
+                }
+                match Item::of_file(&path) {
+                    Ok(Some(item)) => {
+                        self.items.push(item);
+                        noted += 1;
+                    },
+                    Ok(None) => (),
+                    Err(_) => unreadable += 1,
+                }
+            }
+        }
+        self.items.sort_by(|a, b| a.path.cmp(&b.path));
+        Ok((noted, unreadable))
+    }
+    /// The items matching all of `words`, of `format` if given.
+    pub fn search(&self, words: &[String], format: Option<&str>) -> Vec<&Item> {
+        let words: Vec<String> = words.iter().map(|w| w.to_lowercase()).collect();
+        self.items.iter()
+            .filter(|item| format.is_none_or(|f| item.format.eq_ignore_ascii_case(f)))
+            .filter(|item| item.matches(&words))
+            .collect()
+    }
+    /// The groups of items that are the same by `likeness`, each in the
+    /// order of their paths.
+    pub fn dupes(&self, likeness: Likeness) -> Vec<Vec<&Item>> {
+        let mut groups: BTreeMap<String, Vec<&Item>> = BTreeMap::new();
+        for item in &self.items {
+            groups.entry(item.key(likeness)).or_default().push(item);
+        }
+        let mut dupes: Vec<Vec<&Item>> = groups.into_values().filter(|g| g.len() > 1).collect();
+        for group in &mut dupes {
+            group.sort_by(|a, b| a.path.cmp(&b.path));
+        }
+        dupes.sort_by(|a, b| a[0].path.cmp(&b[0].path));
+        dupes
+    }
+    /// The one item `query` names: by its title or file name, or else
+    /// as the only one a search for it finds.
+    pub fn find(&self, query: &str) -> Result<&Item> {
+        let named = |item: &&Item| {
+            let stem = Path::new(&item.path).file_stem().map(|s| s.to_string_lossy().into_owned()).unwrap_or_default();
+            item.title.eq_ignore_ascii_case(query) || stem.eq_ignore_ascii_case(query)
+        };
+        let mut found: Vec<&Item> = self.items.iter().filter(named).collect();
+        if found.is_empty() {
+            let words: Vec<String> = query.split_whitespace().map(String::from).collect();
+            found = self.search(&words, None);
+        }
+        match found[..] {
+            [item] => Ok(item),
+            [] => bail!("Nothing in the collection is called {:?}; see collection search", query),
+            _ => bail!("{} items in the collection match {:?}, e.g. {} and {}",
+                found.len(), query, found[0].path, found[1].path),
+        }
+    }
+}
+
+#[derive(Subcommand)]
+pub enum CollectionCommands {
+    /// Note the images and programs under a directory, e.g. collection
+    /// scan ~/c64
+    Scan { dir: String },
+    /// List the items whose title or file names hold all the words
+    Search {
+        words: Vec<String>,
+        #[arg(long, value_name="format")]
+        /// Only items of this format, e.g. d64
+        format: Option<String>,
+    },
+    /// Describe the disk images whose title or file names hold all the
+    /// words, or all of them, as a FILE_ID.DIZ each
+    Export {
+        words: Vec<String>,
+        #[arg(long)]
+        /// Draw PETSCII graphics with ASCII characters
+        ascii: bool,
+    },
+    /// List the items that are the same, a group at a time
+    Dupes {
+        #[arg(long, value_enum, default_value_t=Likeness::Checksum)]
+        /// Same by checksum, or also disks holding the same files (content)
+        by: Likeness,
+    },
+}
+
+pub fn run(cmd: CollectionCommands, json: bool) -> Result<()> {
+    let mut collection = Collection::load()?;
+    match cmd {
+        CollectionCommands::Scan { dir } => {
+            let (noted, unreadable) = collection.scan(Path::new(&dir))?;
+            collection.save()?;
+            eprintln!("{} item(s) noted under {}", noted, dir);
+            if unreadable > 0 {
+                eprintln!("{} file(s) couldn't be read", unreadable);
+            }
+        },
+        CollectionCommands::Search { words, format } => {
+            let found = collection.search(&words, format.as_deref());
+            match json {
+                true => println!("{}", serde_json::to_string_pretty(&found)?),
+                false => found.iter().for_each(|item| println!("{}", item.line())),
+            }
+        },
+        CollectionCommands::Export { words, ascii } => {
+            let described = collection.search(&words, None).into_iter().filter_map(|item| Some((item, item.diz(ascii)?)));
+            for (i, (item, diz)) in described.enumerate() {
+                if i > 0 {
+                    println!();
+                }
+                println!("{}\n", item.path);
+                diz.iter().for_each(|line| println!("{}", line));
+            }
+        },
+        CollectionCommands::Dupes { by } => {
+            let dupes = collection.dupes(by);
+            if json {
+                println!("{}", serde_json::to_string_pretty(&dupes)?);
+                return Ok(())
+            }
+            for (i, group) in dupes.iter().enumerate() {
+                if i > 0 {
+                    println!();
+                }
+                group.iter().for_each(|item| println!("{}", item.line()));
+            }
+            // All but one of each group could go
+            let spare: Vec<&&Item> = dupes.iter().flat_map(|g| &g[1..]).collect();
+            eprintln!("{} group(s); {} item(s), {} bytes, are spare",
+                dupes.len(), spare.len(), spare.iter().map(|i| i.size).sum::<usize>());
+        },
+    }
+    Ok(())
+}
+
+#[test]
+fn collection_index() {
+    let dir = std::env::temp_dir().join(format!("idunsh-collection-{}", std::process::id()));
+    fs::create_dir_all(dir.join("games")).unwrap();
+    let mut disk = Disk::format(Format::D64, "JUMPMAN", "JM").unwrap();
+    disk.add(PetString::from("jumpman").as_slice(), 2, b"\x01\x08game").unwrap();
+    fs::write(dir.join("games/jump.d64"), &disk.data).unwrap();
+    fs::write(dir.join("games/edge.prg"), b"\x01\x08\x00\x00").unwrap();
+    fs::write(dir.join("notes.txt"), b"not for the C64").unwrap();
+    let mut collection = Collection::default();
+    assert_eq!(collection.scan(&dir).unwrap(), (2, 0));
+    let jumpman = collection.find("jumpman").unwrap().clone();
+    assert_eq!((jumpman.format.as_str(), jumpman.files.len()), ("D64", 1));
+    assert_eq!((jumpman.files[0].name.as_str(), jumpman.files[0].crc32), ("jumpman", util::crc32(b"\x01\x08game")));
+    assert_eq!(collection.find("EDGE").unwrap().title, "edge");
+    assert_eq!(jumpman.diz(false).unwrap()[2], "jumpman          PRG");
+    assert_eq!(collection.find("EDGE").unwrap().diz(false), None);
+    assert_eq!(collection.search(&["jump".into()], Some("prg")).len(), 0);
+    assert!(collection.find("pitstop").is_err());
+    // The same files in other sectors, and a copy of the game
+    let mut moved = jumpman;
+    (moved.path, moved.crc32) = ("/c64/jumpman-interleave-6.d64".into(), 7);
+    let mut copy = collection.find("edge").unwrap().clone();
+    copy.path = "/c64/edge-copy.prg".into();
+    collection.items.extend([moved, copy]);
+    let names = |dupes: Vec<Vec<&Item>>| -> Vec<Vec<String>> {
+        dupes.iter().map(|g| g.iter().map(|i| i.path.rsplit('/').next().unwrap().to_string()).collect()).collect()
+    };
+    assert_eq!(names(collection.dupes(Likeness::Checksum)), [["edge-copy.prg", "edge.prg"]]);
+    assert_eq!(names(collection.dupes(Likeness::Content)).len(), 2);
+    collection.items.truncate(2);
+    // Scanning again replaces what's under the directory
+    fs::remove_file(dir.join("games/edge.prg")).unwrap();
+    collection.scan(&dir.join("games")).unwrap();
+    assert_eq!(collection.items.len(), 1);
+    fs::remove_dir_all(&dir).unwrap();
+}
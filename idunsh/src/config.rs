@@ -0,0 +1,449 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+// Copyright (C) 2026 Brian Holdsworth
+
+//! User settings read from `~/.config/idunsh/config.toml`, of which
+//! `idunsh init` writes a first one; see `init`.
+//!
+//! ```toml
+//! yes = true
+//! device = "c:"
+//! c64u_ip = "192.168.1.64"
+//! keyboard = "de"
+//! output_timeout = "30s"
+//!
+//! [power]
+//! on = "http://plug.local/relay/0?turn=on"
+//! off = "http://plug.local/relay/0?turn=off"
+//!
+//! [hooks]
+//! post_load = "notify-send \"$IDUNSH_FILE loaded\""
+//!
+//! [pkg]
+//! index = "https://example.com/idun/index.toml"
+//! drive = "e:"
+//!
+//! [timeouts]
+//! connect = "10s"
+//! command = "1m"
+//! retries = 3
+//!
+//! [timeouts.put]
+//! transfer = "20m"
+//!
+//! [xargs]
+//! catalog = ["l"]
+//! xlink = ["device=9", "/verbose"]
+//!
+//! [aliases]
+//! d81 = "mount d:"
+//!
+//! [keys]
+//! F5 = "load build/game.prg"
+//!
+//! [exec.xlink]
+//! args = "--device {dev} {file}"
+//!
+//! [profiles.attic]
+//! socket = "/tmp/attic-lua"
+//! c64u_ip = "192.168.1.65"
+//! ```
+//!
+//! `socket` is the daemon's Lua socket, or the serial line to its UART
+//! as `serial:///dev/ttyUSB0?baud=115200`, `run_dir` where the daemon finds
+//! our sockets for redirected output and events, `device` the drive used
+//! when a command that can do without one is given none, and `c64u_ip`
+//! the C64 Ultimate's address, found on the LAN otherwise. `encoding`
+//! is how bytes go over the link to the daemon, `raw` unless the link
+//! isn't 8-bit clean; see `encoding`. A profile under `profiles`
+//! changes any of these for another cartridge, picked with
+//! `--config-profile` or `$IDUNSH_PROFILE`. `$IDUNSH_SOCKET`,
+//! `$IDUNSH_RUN_DIR`, `$IDUNSH_DEVICE` and `$C64_ULTIMATE_IP` override
+//! both.
+//!
+//! `aliases` name command lines: `idunsh d81 work.d81` runs
+//! `idunsh mount d: work.d81`. Sub-commands can't be renamed this way.
+//!
+//! `exec` holds argument templates for remote programs: `idunsh x xlink
+//! game.prg` runs `exec xlink --device c: game.prg`. `{dev}` is the
+//! device used when none is given, other names in braces take the
+//! values given in turn, and values left over follow. `program` runs
+//! another program than the template's name.
+//!
+//! `keys` binds the function keys F1 to F12 in the interactive mode to
+//! command lines, run as soon as the key is pressed.
+//!
+//! `xargs` holds default `-x` flags per sub-command. For `exec` the key is
+//! the name of the remote program instead. `parsers` picks how the output
+//! of a remote program is turned into JSON; see `parsers`. `yes` skips
+//! the questions asked before destructive commands, like `--yes`.
+//! `theme` sets the colors of listings; see `theme`. `charset` is the
+//! character set, `upper` or `lower`, redirected output and `convert`
+//! use without `--charset`. `keyboard` is the layout of a German
+//! (`de`), Swedish (`se`) or Danish (`dk`) machine, whose own letters
+//! text typed on it may use; `us` by default. `output_timeout` is the
+//! default of `--output-timeout`.
+//!
+//! `power` holds the webhooks of a smart plug the C64 hangs off, posted
+//! to by `power on`, and by `power off` once the Ultimate has shut down.
+//!
+//! `hooks` holds shell commands run before a mount (`pre_mount`), after
+//! a load (`post_load`) and when a command fails (`on_error`); see
+//! `hooks`.
+//!
+//! Files in `~/.config/idunsh/config.d`, such as those `unpack` puts
+//! there, add `aliases`, `keys`, `exec` and `xargs` of their own; the
+//! config file wins where both name the same one. They may also set
+//! `yes`, `charset`, `keyboard` and `output_timeout`, which replace the
+//! config file's, as `state.toml` from `state import` does.
+//!
+//! `pkg` holds the URL or path of the package index `pkg` installs from,
+//! and the drive whose directory the applications go to, `e:` by
+//! default; see `pkg`.
+//!
+//! `timeouts` sets how long the daemon and the C64U are waited for:
+//! `connect` to reach them, 5s by default, `command` for the daemon to
+//! answer a command, or to connect for redirected output, which is given
+//! 30s otherwise, `transfer` for redirected output or a file to come or
+//! go whole, and `discovery` for a C64U to answer the search on the
+//! LAN, 500ms by default. `retries` is how often connecting is tried
+//! again, none by default. A table under it named for a sub-command,
+//! such as `put`, changes any of these for that command. A link over
+//! SSH wants them longer than the local socket.
+use std::collections::BTreeMap;
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::result;
+use std::time::Duration;
+use serde::Deserialize;
+use idun_client::client::Timeouts;
+use idun_client::util;
+use crate::parsers::Parser;
+use idun_client::petscii::{Charset, Layout};
+use crate::theme::ThemeConfig;
+
+// Simpler error handling
+type Result<T> = result::Result<T, failure::Error>;
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+pub struct Config {
+    pub yes: bool,
+    pub xargs: BTreeMap<String, Vec<String>>,
+    pub parsers: BTreeMap<String, Parser>,
+    pub theme: ThemeConfig,
+    pub charset: Option<Charset>,
+    pub keyboard: Layout,
+    pub output_timeout: Option<String>,
+    pub power: Power,
+    pub hooks: Hooks,
+    pub pkg: Pkg,
+    pub timeouts: TimeoutConfig,
+    pub aliases: BTreeMap<String, String>,
+    pub exec: BTreeMap<String, ExecTemplate>,
+    pub keys: BTreeMap<String, String>,
+    #[serde(flatten)]
+    pub connection: Connection,
+    pub profiles: BTreeMap<String, Connection>,
+}
+
+/// Where the cartridge and the C64U are found; unset fields keep the
+/// built-in defaults.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Deserialize)]
+#[serde(default)]
+pub struct Connection {
+    pub socket: Option<String>,
+    pub run_dir: Option<String>,
+    pub device: Option<String>,
+    pub c64u_ip: Option<String>,
+    pub encoding: Option<String>,
+}
+
+/// Smart plug webhooks for `power`.
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(default)]
+pub struct Power {
+    pub on: Option<String>,
+    pub off: Option<String>,
+}
+
+/// Shell commands run around idunsh's own.
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(default)]
+pub struct Hooks {
+    pub pre_mount: Option<String>,
+    pub post_load: Option<String>,
+    pub on_error: Option<String>,
+}
+
+/// The package index for `pkg`.
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(default)]
+pub struct Pkg {
+    pub index: Option<String>,
+    pub drive: Option<String>,
+}
+
+/// Timeouts and retries, with those of the sub-commands that differ.
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(default)]
+pub struct TimeoutConfig {
+    #[serde(flatten)]
+    pub policy: Policy,
+    #[serde(flatten)]
+    pub commands: BTreeMap<String, Policy>,
+}
+
+/// Timeouts as durations, e.g. "30s", and retries; unset ones are
+/// those of the table above.
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(default)]
+pub struct Policy {
+    pub connect: Option<String>,
+    pub command: Option<String>,
+    pub transfer: Option<String>,
+    pub discovery: Option<String>,
+    pub retries: Option<u32>,
+}
+
+/// The settings a file in `config.d` may add.
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Fragment {
+    pub xargs: BTreeMap<String, Vec<String>>,
+    pub aliases: BTreeMap<String, String>,
+    pub exec: BTreeMap<String, ExecTemplate>,
+    pub keys: BTreeMap<String, String>,
+    pub yes: Option<bool>,
+    pub charset: Option<Charset>,
+    pub keyboard: Option<Layout>,
+    pub output_timeout: Option<String>,
+}
+
+/// The arguments of a remote program, with names in braces for values.
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(default)]
+pub struct ExecTemplate {
+    pub program: Option<String>,
+    pub args: String,
+}
+
+impl ExecTemplate {
+    /// The arguments with `{dev}` replaced by `dev()`, and each other
+    /// name by the next of `values`.
+    pub fn expand<F>(&self, values: &[String], dev: F) -> Result<Vec<String>>
+    where F: FnOnce() -> Result<String> {
+        let mut words = shell_words::split(&self.args).map_err(|e| format_err!("{:?}: {}", self.args, e))?;
+        let mut dev = Some(dev);
+        let mut device = None;
+        let mut values = values.iter();
+        for word in &mut words {
+            let mut filled = String::new();
+            let mut rest = word.as_str();
+            while let Some((before, after)) = rest.split_once('{') {
+                let (name, after) = after.split_once('}')
+                    .ok_or_else(|| format_err!("{:?} has an unclosed {{", self.args))?;
+                filled.push_str(before);
+                match name {
+                    "dev" => {
+                        if let Some(f) = dev.take() {
+                            device = Some(f()?);
+                        }
+                        filled.push_str(device.as_deref().unwrap_or_default());
+                    },
+                    _ => filled.push_str(values.next()
+                        .ok_or_else(|| format_err!("No value given for {{{}}}", name))?),
+                }
+                rest = after;
+            }
+            filled.push_str(rest);
+            *word = filled;
+        }
+        words.extend(values.cloned());
+        Ok(words)
+    }
+}
+
+impl Fragment {
+    pub fn load(path: &Path) -> Result<Fragment> {
+        let text = fs::read_to_string(path).map_err(|e| format_err!("{}: {}", path.display(), e))?;
+        toml::from_str(&text).map_err(|e| format_err!("{}: {}", path.display(), e))
+    }
+}
+
+impl Connection {
+    // Fields set in `other` replace ours
+    fn merge(&mut self, other: Connection) {
+        for (field, value) in [
+            (&mut self.socket, other.socket), (&mut self.run_dir, other.run_dir),
+            (&mut self.device, other.device), (&mut self.c64u_ip, other.c64u_ip),
+            (&mut self.encoding, other.encoding),
+        ] {
+            if value.is_some() {
+                *field = value;
+            }
+        }
+    }
+}
+
+impl Config {
+    pub fn path() -> Option<PathBuf> {
+        dirs::config_dir().map(|d| d.join("idunsh").join("config.toml"))
+    }
+    /// Where files adding to the config file are kept.
+    pub fn fragment_dir() -> Option<PathBuf> {
+        dirs::config_dir().map(|d| d.join("idunsh").join("config.d"))
+    }
+    /// Reads the config file, or gives the defaults if there is none,
+    /// with what the files in `config.d` add.
+    pub fn load() -> Result<Config> {
+        let mut config: Config = match Config::path() {
+            Some(path) if path.exists() => {
+                let text = fs::read_to_string(&path)?;
+                toml::from_str(&text).map_err(|e| format_err!("{}: {}", path.display(), e))?
+            }
+            _ => Config::default(),
+        };
+        if let Some(Ok(dir)) = Config::fragment_dir().map(fs::read_dir) {
+            let mut paths: Vec<PathBuf> = dir.filter_map(|f| Some(f.ok()?.path()))
+                .filter(|p| p.extension().is_some_and(|e| e == "toml"))
+                .collect();
+            paths.sort();
+            for path in paths {
+                config.add(Fragment::load(&path)?);
+            }
+        }
+        Ok(config)
+    }
+    // Takes the settings of a fragment the config doesn't have already,
+    // and its toggles
+    fn add(&mut self, fragment: Fragment) {
+        fn merge<V>(ours: &mut BTreeMap<String, V>, theirs: BTreeMap<String, V>) {
+            for (k, v) in theirs {
+                ours.entry(k).or_insert(v);
+            }
+        }
+        merge(&mut self.xargs, fragment.xargs);
+        merge(&mut self.aliases, fragment.aliases);
+        merge(&mut self.exec, fragment.exec);
+        merge(&mut self.keys, fragment.keys);
+        self.yes = fragment.yes.unwrap_or(self.yes);
+        self.charset = fragment.charset.or(self.charset);
+        self.keyboard = fragment.keyboard.unwrap_or(self.keyboard);
+        self.output_timeout = fragment.output_timeout.or(self.output_timeout.take());
+    }
+    /// The connection settings of `profile`, or `$IDUNSH_PROFILE` if
+    /// none is given, with the environment's overrides.
+    pub fn connection(&self, profile: Option<&str>) -> Result<Connection> {
+        let mut connection = self.connection.clone();
+        let profile = profile.map(String::from).or_else(|| env::var("IDUNSH_PROFILE").ok());
+        if let Some(name) = profile {
+            let p = self.profiles.get(&name)
+                .ok_or_else(|| format_err!("There is no profile {:?} in the config file", name))?;
+            connection.merge(p.clone());
+        }
+        connection.merge(Connection {
+            socket: env::var("IDUNSH_SOCKET").ok(),
+            run_dir: env::var("IDUNSH_RUN_DIR").ok(),
+            device: env::var("IDUNSH_DEVICE").ok(),
+            c64u_ip: env::var("C64_ULTIMATE_IP").ok(),
+            encoding: None,
+        });
+        Ok(connection)
+    }
+    /// Replaces an alias at the start of `argv` (sub-command and its
+    /// arguments) with the command line it stands for.
+    pub fn expand_alias(&self, argv: Vec<String>) -> Result<Vec<String>> {
+        match argv.first().and_then(|a| self.aliases.get(a)) {
+            Some(line) => {
+                let mut expanded = shell_words::split(line)
+                    .map_err(|e| format_err!("Alias {:?}: {}", argv[0], e))?;
+                expanded.extend(argv.into_iter().skip(1));
+                Ok(expanded)
+            },
+            None => Ok(argv),
+        }
+    }
+    /// How long redirected output may be silent without `--output-timeout`.
+    pub fn output_timeout(&self) -> Result<Option<Duration>> {
+        self.output_timeout.as_deref()
+            .map(|t| util::parse_duration(t).map_err(|e| format_err!("output_timeout: {}", e)))
+            .transpose()
+    }
+    /// The timeouts and retries for the sub-command `cmd`.
+    pub fn timeouts(&self, cmd: &str) -> Result<Timeouts> {
+        let policies = [self.timeouts.commands.get(cmd), Some(&self.timeouts.policy)];
+        let duration = |name: &str, field: fn(&Policy) -> &Option<String>| {
+            policies.iter().flatten().find_map(|p| field(p).as_deref())
+                .map(|t| util::parse_duration(t).map_err(|e| format_err!("timeouts {}: {}", name, e)))
+                .transpose()
+        };
+        let default = Timeouts::default();
+        Ok(Timeouts {
+            connect: duration("connect", |p| &p.connect)?.unwrap_or(default.connect),
+            command: duration("command", |p| &p.command)?.or(default.command),
+            transfer: duration("transfer", |p| &p.transfer)?.or(default.transfer),
+            discovery: duration("discovery", |p| &p.discovery)?.unwrap_or(default.discovery),
+            retries: policies.iter().flatten().find_map(|p| p.retries).unwrap_or(default.retries),
+        })
+    }
+    /// Default `-x` flags for a command.
+    pub fn xargs(&self, cmd: &str) -> &[String] {
+        self.xargs.get(cmd).map(Vec::as_slice).unwrap_or_default()
+    }
+}
+
+#[test]
+fn parse_config() {
+    let config: Config = toml::from_str("yes = true\n[xargs]\ndir = [\"l\"]\n[parsers]\ndiskinfo = \"key-value\"\n").unwrap();
+    assert!(config.yes);
+    assert_eq!(config.xargs("dir"), ["l"]);
+    assert_eq!(config.parsers.get("diskinfo"), Some(&Parser::KeyValue));
+    assert!(config.xargs("exec").is_empty());
+    assert_eq!(config.keyboard, Layout::Us);
+    let config: Config = toml::from_str("keyboard = \"se\"\n").unwrap();
+    assert_eq!(config.keyboard, Layout::Se);
+    let config: Config = toml::from_str("output_timeout = \"1m\"\n").unwrap();
+    assert_eq!(config.output_timeout().unwrap(), Some(Duration::from_secs(60)));
+    let config: Config = toml::from_str("[power]\non = \"http://plug/on\"\n").unwrap();
+    assert_eq!((config.power.on.as_deref(), config.power.off), (Some("http://plug/on"), None));
+    let config: Config = toml::from_str("[pkg]\nindex = \"http://idun/index.toml\"\n").unwrap();
+    assert_eq!((config.pkg.index.as_deref(), config.pkg.drive), (Some("http://idun/index.toml"), None));
+    let config: Config = toml::from_str("[timeouts]\ncommand = \"1m\"\nretries = 2\n[timeouts.put]\nretries = 0\ntransfer = \"5m\"\n").unwrap();
+    let put = config.timeouts("put").unwrap();
+    assert_eq!((put.command, put.transfer, put.retries), (Some(Duration::from_secs(60)), Some(Duration::from_secs(300)), 0));
+    assert_eq!((config.timeouts("dir").unwrap().retries, config.timeouts("dir").unwrap().connect), (2, Duration::from_secs(5)));
+    let config: Config = toml::from_str("[timeouts.get]\ncommand = \"soon\"\n").unwrap();
+    assert!(config.timeouts("get").is_err() && config.timeouts("put").is_ok());
+    let config: Config = toml::from_str("[theme]\npreset = \"c64-blue\"\ndir = \"yellow\"\n").unwrap();
+    assert_eq!(config.theme.preset, Some(crate::theme::Preset::C64Blue));
+}
+
+#[test]
+fn profiles_and_aliases() {
+    let text = "socket = \"/tmp/a\"\ndevice = \"c:\"\n[aliases]\nd81 = \"mount d:\"\n[profiles.attic]\nsocket = \"/tmp/b\"\nencoding = \"slip\"\n";
+    let config: Config = toml::from_str(text).unwrap();
+    let attic = config.connection(Some("attic")).unwrap();
+    assert_eq!((attic.socket.as_deref(), attic.device.as_deref()), (Some("/tmp/b"), Some("c:")));
+    assert_eq!(attic.encoding.as_deref(), Some("slip"));
+    assert!(config.connection(Some("none")).is_err());
+    assert_eq!(config.expand_alias(vec!["d81".into(), "work.d81".into()]).unwrap(), ["mount", "d:", "work.d81"]);
+    assert_eq!(config.expand_alias(vec!["dir".into()]).unwrap(), ["dir"]);
+    let mut config = config;
+    config.add(toml::from_str("[aliases]\nd81 = \"mount e:\"\nd64 = \"mount d:\"\n").unwrap());
+    assert_eq!((config.aliases["d81"].as_str(), config.aliases["d64"].as_str()), ("mount d:", "mount d:"));
+    assert!(toml::from_str::<Fragment>("socket = \"/tmp/c\"\n").is_err());
+    // A fragment's toggles replace the config file's
+    config.add(toml::from_str("yes = true\noutput_timeout = \"5s\"\n").unwrap());
+    assert!(config.yes);
+    assert_eq!(config.output_timeout().unwrap(), Some(Duration::from_secs(5)));
+}
+
+#[test]
+fn exec_templates() {
+    let config: Config = toml::from_str("[exec.xlink]\nargs = \"--device {dev} {file} 'a b'\"\n").unwrap();
+    let xlink = &config.exec["xlink"];
+    let args = xlink.expand(&["game.prg".into(), "-v".into()], || Ok("c:".into())).unwrap();
+    assert_eq!(args, ["--device", "c:", "game.prg", "a b", "-v"]);
+    assert!(xlink.expand(&[], || Ok("c:".into())).is_err());
+}
@@ -0,0 +1,27 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+// Copyright (C) 2026 Brian Holdsworth
+
+//! Asking before commands that destroy data.
+//!
+//! `--yes`, or `yes = true` in the config file, answers every question
+//! with yes, for scripts. Without a terminal to ask on, a destructive
+//! command fails unless told yes.
+use std::io::{self, IsTerminal};
+use std::result;
+
+// Simpler error handling
+type Result<T> = result::Result<T, failure::Error>;
+
+/// Asks `question`, failing the command unless the answer is yes.
+pub fn confirm(question: &str, yes: bool) -> Result<()> {
+    if yes {
+        return Ok(())
+    }
+    if !io::stdin().is_terminal() || !io::stderr().is_terminal() {
+        bail!("{} Add --yes to go ahead without asking", question)
+    }
+    match dialoguer::Confirm::new().with_prompt(question).default(false).interact()? {
+        true => Ok(()),
+        false => bail!("Cancelled"),
+    }
+}
@@ -0,0 +1,331 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+// Copyright (C) 2026 Brian Holdsworth
+
+//! Directory listings for `dir`, `catalog`, `drives` and `find`.
+//!
+//! Listings are printed as they come through the redirect, unless they
+//! have to be parsed first: to page them, color them, walk their
+//! subdirectories or print them as CSV, TSV or JSON.
+use std::result;
+use std::time::Duration;
+use bstr::BString;
+use clap::Args;
+use idun_client::client::{CATALOG_CMD, DIR_CMD, DRIVES_CMD, read_redirect, shell_call};
+use idun_client::listing::{Entry, Listing, Mount};
+use idun_client::petscii::Charset;
+use idun_client::runtime;
+use idun_client::util::{self, PetString};
+use crate::Cli;
+use crate::Syscommands;
+use crate::c64ultimate::{C64Ultimate, NamedDevice};
+use crate::capture_shell;
+use crate::catalogs;
+use crate::diz;
+use crate::errors::ExitStatus;
+use crate::events;
+use crate::idun;
+use crate::output;
+use crate::theme::Theme;
+use crate::ult;
+use crate::watch;
+
+// Simpler error handling
+type Result<T> = result::Result<T, failure::Error>;
+
+// How parsed listings are printed, chosen by --csv, --tsv or --json
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum ListFormat {
+    Csv,
+    Tsv,
+    Json,
+}
+
+impl ListFormat {
+    pub fn of(cli: &Cli, csv: bool) -> Option<ListFormat> {
+        match (csv, cli.tsv, cli.json) {
+            (_, _, true) => Some(ListFormat::Json),
+            (_, true, _) => Some(ListFormat::Tsv),
+            (true, _, _) => Some(ListFormat::Csv),
+            _ => None,
+        }
+    }
+    pub fn entries(self, entries: Vec<Entry>) -> Result<String> {
+        match self {
+            ListFormat::Json => Ok(serde_json::to_string_pretty(&Entry::json_array(&entries))? + "\n"),
+            _ => self.listing(&Listing { entries, ..Default::default() }),
+        }
+    }
+    pub fn listing(self, listing: &Listing) -> Result<String> {
+        Ok(match self {
+            ListFormat::Csv => listing.csv(),
+            ListFormat::Tsv => listing.tsv(),
+            ListFormat::Json => serde_json::to_string_pretty(&listing.json())? + "\n",
+        })
+    }
+    pub fn print_listing(self, listing: &Listing) -> Result<()> {
+        print!("{}", self.listing(listing)?);
+        Ok(())
+    }
+    pub fn print_mounts(self, mounts: &[Mount]) -> Result<()> {
+        match self {
+            ListFormat::Json => {
+                let mounts: Vec<_> = mounts.iter().map(Mount::json).collect();
+                println!("{}", serde_json::to_string_pretty(&mounts)?);
+            },
+            _ => {
+                let sep = if self == ListFormat::Csv { ',' } else { '\t' };
+                println!("device{}target", sep);
+                for m in mounts {
+                    println!("{}{}{}", m.device, sep, m.target);
+                }
+            },
+        }
+        Ok(())
+    }
+    // The C64U's floppy drives, with their bus ids and types
+    pub fn print_ult_drives(self, c64u: &C64Ultimate, dev: &Option<String>) -> Result<()> {
+        let ultid = c64u.getdrv(dev)
+            .map_err(|e| format_err!("C64 Ultimate drive settings Error: {}", e))?;
+        let drives: Vec<_> = ultid.drives.into_iter()
+            .filter(|d| d.slot.is_floppy())
+            .map(|NamedDevice { slot, device }| serde_json::json!({
+                "device": format!("{}:", slot.name()),
+                "bus_id": device.bus_id,
+                "type": device.device_type,
+                "enabled": device.enabled,
+                "image": device.mounted_image(),
+            }))
+            .collect();
+        if self == ListFormat::Json {
+            println!("{}", serde_json::to_string_pretty(&drives)?);
+            return Ok(())
+        }
+        let sep = if self == ListFormat::Csv { "," } else { "\t" };
+        println!("{}", ["device", "bus_id", "type", "enabled", "image"].join(sep));
+        for d in drives {
+            let fields: Vec<String> = ["device", "bus_id", "type", "enabled", "image"].iter()
+                .map(|k| match &d[k] {
+                    serde_json::Value::String(s) => s.clone(),
+                    serde_json::Value::Null => String::new(),
+                    v => v.to_string(),
+                })
+                .collect();
+            println!("{}", fields.join(sep));
+        }
+        Ok(())
+    }
+}
+
+/// Prints the listings that are parsed before they're printed, for
+/// `find`, `dir`, `catalog` and `drives`. Gives false for a listing that's
+/// to be printed as it comes through the redirect instead. Listings of
+/// `dir` go where redirected output would, e.g. to the -O file.
+pub fn run(cli: &Cli, cmd: &Syscommands, xargs: &str, charset: Charset, theme: &Theme) -> Result<bool> {
+    let format = match cmd {
+        Syscommands::Dir { csv, .. } | Syscommands::Catalog { csv, .. } => ListFormat::of(cli, *csv),
+        _ => ListFormat::of(cli, false),
+    };
+    // Colored listings are collected like paged and structured ones
+    let themed = cli.output && theme.is_active();
+    match (cmd, format) {
+        (Syscommands::Find { pattern, devs }, _) => {
+            let mut found = false;
+            for dev in devs {
+                for path in catalogs::find(dev, pattern, 0)? {
+                    println!("{}", path);
+                    found = true;
+                }
+            }
+            // Like grep, finding nothing is a failure
+            if !found {
+                return Err(ExitStatus(1).into())
+            }
+        },
+        // Recursive listings walk the parsed catalog of each subdirectory
+        (Syscommands::Dir { devs, recursive: true, .. }, format) => {
+            let mut entries = vec![];
+            for dev in devs {
+                entries.extend(recursive(dev, 0)?);
+            }
+            let mut out = output::Sink::new(cli, charset, output::controls())?;
+            match format {
+                Some(format) => out.text(&format.entries(entries)?)?,
+                None => for entry in entries {
+                    out.text(&format!("{} {}{}\n", util::right_aligned(entry.blocks, 4), entry.name, entry.attrs()))?;
+                },
+            }
+        },
+        (Syscommands::Dir { devs, .. }, Some(format)) => {
+            let mut entries = vec![];
+            for (dev, data) in devs.iter().zip(capture_devices(CATALOG_CMD, devs, xargs)?) {
+                let mut listed = Listing::parse(&String::from(PetString::new(&BString::from(data)))).entries;
+                // Several devices are told apart by prefixing the names
+                if devs.len() > 1 {
+                    listed.iter_mut().for_each(|e| e.name.insert_str(0, dev));
+                }
+                entries.extend(listed);
+            }
+            output::Sink::new(cli, charset, output::controls())?.text(&format.entries(entries)?)?
+        },
+        // Listings of several devices are fetched together, then printed in
+        // turn, each under its device
+        (Syscommands::Dir { devs, .. }, None) if devs.len() > 1 && cli.output => {
+            let mut out = output::Sink::new(cli, charset, output::controls())?;
+            for (i, (dev, data)) in devs.iter().zip(capture_devices(DIR_CMD, devs, xargs)?).enumerate() {
+                if i > 0 {
+                    out.text("\n")?;
+                }
+                out.text(&format!("{}\n", dev))?;
+                out.write(&data)?;
+            }
+            out.finish()?
+        },
+        (Syscommands::Catalog { dev, diz: true, ascii, page, .. }, _) => {
+            let mut listing = catalog(&format!("{}{}", xargs, dev))?;
+            page.apply(&mut listing);
+            diz::of_listing(&listing, *ascii).iter().for_each(|line| println!("{}", line));
+        },
+        (Syscommands::Catalog { dev, page, .. }, format) if format.is_some() || page.is_set() || themed => {
+            let mut listing = catalog(&format!("{}{}", xargs, dev))?;
+            page.apply(&mut listing);
+            match format {
+                Some(format) => format.print_listing(&listing)?,
+                None => for line in theme.listing(&listing) {
+                    println!("{}", line);
+                },
+            }
+        },
+        // Live-refresh of the drives listing collects the output itself
+        (Syscommands::Drives { dev, watch: true, interval }, _) => {
+            let argstr = dev.clone().unwrap_or_default();
+            watch::watch(*interval, theme, || {
+                events::pace();
+                let text = String::from(capture_shell(DRIVES_CMD, &argstr)?);
+                Ok(text.split(['\r', '\n']).map(String::from).collect())
+            })?
+        },
+        (Syscommands::Drives { dev, .. }, format) if format.is_some() || themed => {
+            let text = String::from(capture_shell(DRIVES_CMD, &dev.clone().unwrap_or_default())?);
+            match format {
+                Some(format) => format.print_mounts(&Mount::parse_all(&text))?,
+                None => for line in text.split(['\r', '\n']).filter(|l| !l.is_empty()) {
+                    println!("{}", theme.drive(line));
+                },
+            }
+        },
+        _ => return Ok(false),
+    }
+    Ok(true)
+}
+
+/// The C64U's floppy drives, shown again every `interval` if given.
+pub fn ult_drives(c64u: &C64Ultimate, dev: &Option<String>, interval: Option<Duration>,
+        format: Option<ListFormat>, theme: &Theme) -> Result<()> {
+    if let Some(interval) = interval {
+        return watch::watch(interval, theme, || ult::drives(c64u, dev))
+    }
+    if let Some(format) = format {
+        return format.print_ult_drives(c64u, dev)
+    }
+    for line in ult::drives(c64u, dev)? {
+        println!("{}", theme.drive(&line));
+    }
+    Ok(())
+}
+
+// The parsed catalog of a device or directory
+fn catalog(args: &str) -> Result<Listing> {
+    Ok(Listing::parse(&String::from(capture_shell(CATALOG_CMD, args)?)))
+}
+
+// Lists every file below `dir` (e.g. "c:" or "c:games/"), each entry
+// named by its full path. Subdirectories end with '/'.
+fn recursive(dir: &str, depth: usize) -> Result<Vec<Entry>> {
+    const MAX_DEPTH: usize = 16;
+    let listing = catalog(dir)?;
+    let mut entries = vec![];
+    for mut entry in listing.entries {
+        if entry.is_dir() {
+            let sub = format!("{}{}/", dir, entry.name);
+            entry.name = sub.clone();
+            entries.push(entry);
+            if depth < MAX_DEPTH {
+                entries.extend(recursive(&sub, depth + 1)?);
+            }
+        } else {
+            entry.name = format!("{}{}", dir, entry.name);
+            entries.push(entry);
+        }
+    }
+    Ok(entries)
+}
+
+// Runs a shell command once per device, all at the same time, and
+// collects the output of each in device order, in PETSCII
+fn capture_devices(cmd: u8, devs: &[String], xargs: &str) -> Result<Vec<Vec<u8>>> {
+    let fetches = devs.iter()
+        .map(|dev| {
+            let client = idun();
+            let (resport, respath, id) = client.response_listener()?;
+            let message = shell_call(cmd, &format!("{}{}", xargs, dev), id);
+            let fetch = runtime::spawn(async move {
+                let reader = tokio::spawn(read_redirect(resport, client.encoding()));
+                if let Err(e) = client.request(message).await {
+                    reader.abort();
+                    return Err(e)
+                }
+                reader.await.map_err(|e| format_err!("Failed receiving redirected output E:{:?}", e))?
+            });
+            Ok((fetch, respath))
+        })
+        .collect::<Result<Vec<_>>>()?;
+    fetches.into_iter()
+        .map(|(fetch, _respath)| runtime::join(fetch))
+        .collect()
+}
+
+/// Selects part of a long directory listing
+#[derive(Args)]
+pub struct PageOpts {
+    #[arg(long, value_name="n", conflicts_with_all=["head", "tail"])]
+    /// Show at most this many entries
+    limit: Option<usize>,
+    #[arg(long, value_name="n", default_value_t=0)]
+    /// Skip this many entries first
+    offset: usize,
+    #[arg(long, value_name="n", conflicts_with="tail")]
+    /// Show only the first n entries
+    head: Option<usize>,
+    #[arg(long, value_name="n")]
+    /// Show only the last n entries
+    tail: Option<usize>,
+}
+
+impl PageOpts {
+    pub fn is_set(&self) -> bool {
+        self.limit.is_some() || self.offset > 0 || self.head.is_some() || self.tail.is_some()
+    }
+    pub fn apply(&self, listing: &mut Listing) {
+        let limit = self.limit.or(self.head).or(self.tail);
+        listing.paginate(self.offset, limit, self.tail.is_some());
+    }
+}
+
+#[test]
+fn page_windows() {
+    let text = "0 \"disk\" 2a\r1 \"a\" prg\r1 \"b\" prg\r1 \"c\" prg\r1 \"d\" prg\r1 \"e\" prg\r";
+    let page = |limit, offset, head, tail| {
+        let opts = PageOpts { limit, offset, head, tail };
+        let mut listing = Listing::parse(text);
+        opts.apply(&mut listing);
+        (opts.is_set(), listing.entries.iter().map(|e| e.name.as_str()).collect::<String>())
+    };
+    assert_eq!(page(None, 0, None, None), (false, "abcde".into()));
+    assert_eq!(page(Some(2), 1, None, None), (true, "bc".into()));
+    assert_eq!(page(None, 0, Some(2), None), (true, "ab".into()));
+    // The offset of a tail counts back from the end
+    assert_eq!(page(None, 1, None, Some(2)), (true, "cd".into()));
+    assert_eq!(page(None, 4, Some(3), None), (true, "e".into()));
+    assert_eq!(page(None, 9, None, Some(2)), (true, "".into()));
+    assert_eq!(page(Some(0), 0, None, None), (true, "".into()));
+}
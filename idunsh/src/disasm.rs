@@ -0,0 +1,212 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+// Copyright (C) 2026 Brian Holdsworth
+
+//! Disassembly and assembly of single 6502 instructions, with the
+//! documented opcodes only.
+//!
+//! Numbers are hexadecimal, with or without a `$`, and any operand can
+//! be a label name instead. Operands below $100 use zero page addressing
+//! where the instruction has it.
+use std::result;
+use crate::labels::Labels;
+
+// Simpler error handling
+type Result<T> = result::Result<T, failure::Error>;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Mode {
+    Implied,
+    Accumulator,
+    Immediate,
+    ZeroPage,
+    ZeroPageX,
+    ZeroPageY,
+    Absolute,
+    AbsoluteX,
+    AbsoluteY,
+    Indirect,
+    IndirectX,
+    IndirectY,
+    Relative,
+}
+
+impl Mode {
+    fn len(self) -> u16 {
+        match self {
+            Mode::Implied | Mode::Accumulator => 1,
+            Mode::Absolute | Mode::AbsoluteX | Mode::AbsoluteY | Mode::Indirect => 3,
+            _ => 2,
+        }
+    }
+}
+
+use Mode::*;
+
+const OPCODES: [(u8, &str, Mode); 151] = [
+    (0x69, "adc", Immediate), (0x65, "adc", ZeroPage), (0x75, "adc", ZeroPageX), (0x6d, "adc", Absolute),
+    (0x7d, "adc", AbsoluteX), (0x79, "adc", AbsoluteY), (0x61, "adc", IndirectX), (0x71, "adc", IndirectY),
+    (0x29, "and", Immediate), (0x25, "and", ZeroPage), (0x35, "and", ZeroPageX), (0x2d, "and", Absolute),
+    (0x3d, "and", AbsoluteX), (0x39, "and", AbsoluteY), (0x21, "and", IndirectX), (0x31, "and", IndirectY),
+    (0x0a, "asl", Accumulator), (0x06, "asl", ZeroPage), (0x16, "asl", ZeroPageX), (0x0e, "asl", Absolute),
+    (0x1e, "asl", AbsoluteX), (0x90, "bcc", Relative), (0xb0, "bcs", Relative), (0xf0, "beq", Relative),
+    (0x24, "bit", ZeroPage), (0x2c, "bit", Absolute), (0x30, "bmi", Relative), (0xd0, "bne", Relative),
+    (0x10, "bpl", Relative), (0x00, "brk", Implied), (0x50, "bvc", Relative), (0x70, "bvs", Relative),
+    (0x18, "clc", Implied), (0xd8, "cld", Implied), (0x58, "cli", Implied), (0xb8, "clv", Implied),
+    (0xc9, "cmp", Immediate), (0xc5, "cmp", ZeroPage), (0xd5, "cmp", ZeroPageX), (0xcd, "cmp", Absolute),
+    (0xdd, "cmp", AbsoluteX), (0xd9, "cmp", AbsoluteY), (0xc1, "cmp", IndirectX), (0xd1, "cmp", IndirectY),
+    (0xe0, "cpx", Immediate), (0xe4, "cpx", ZeroPage), (0xec, "cpx", Absolute), (0xc0, "cpy", Immediate),
+    (0xc4, "cpy", ZeroPage), (0xcc, "cpy", Absolute), (0xc6, "dec", ZeroPage), (0xd6, "dec", ZeroPageX),
+    (0xce, "dec", Absolute), (0xde, "dec", AbsoluteX), (0xca, "dex", Implied), (0x88, "dey", Implied),
+    (0x49, "eor", Immediate), (0x45, "eor", ZeroPage), (0x55, "eor", ZeroPageX), (0x4d, "eor", Absolute),
+    (0x5d, "eor", AbsoluteX), (0x59, "eor", AbsoluteY), (0x41, "eor", IndirectX), (0x51, "eor", IndirectY),
+    (0xe6, "inc", ZeroPage), (0xf6, "inc", ZeroPageX), (0xee, "inc", Absolute), (0xfe, "inc", AbsoluteX),
+    (0xe8, "inx", Implied), (0xc8, "iny", Implied), (0x4c, "jmp", Absolute), (0x6c, "jmp", Indirect),
+    (0x20, "jsr", Absolute), (0xa9, "lda", Immediate), (0xa5, "lda", ZeroPage), (0xb5, "lda", ZeroPageX),
+    (0xad, "lda", Absolute), (0xbd, "lda", AbsoluteX), (0xb9, "lda", AbsoluteY), (0xa1, "lda", IndirectX),
+    (0xb1, "lda", IndirectY), (0xa2, "ldx", Immediate), (0xa6, "ldx", ZeroPage), (0xb6, "ldx", ZeroPageY),
+    (0xae, "ldx", Absolute), (0xbe, "ldx", AbsoluteY), (0xa0, "ldy", Immediate), (0xa4, "ldy", ZeroPage),
+    (0xb4, "ldy", ZeroPageX), (0xac, "ldy", Absolute), (0xbc, "ldy", AbsoluteX), (0x4a, "lsr", Accumulator),
+    (0x46, "lsr", ZeroPage), (0x56, "lsr", ZeroPageX), (0x4e, "lsr", Absolute), (0x5e, "lsr", AbsoluteX),
+    (0xea, "nop", Implied), (0x09, "ora", Immediate), (0x05, "ora", ZeroPage), (0x15, "ora", ZeroPageX),
+    (0x0d, "ora", Absolute), (0x1d, "ora", AbsoluteX), (0x19, "ora", AbsoluteY), (0x01, "ora", IndirectX),
+    (0x11, "ora", IndirectY), (0x48, "pha", Implied), (0x08, "php", Implied), (0x68, "pla", Implied),
+    (0x28, "plp", Implied), (0x2a, "rol", Accumulator), (0x26, "rol", ZeroPage), (0x36, "rol", ZeroPageX),
+    (0x2e, "rol", Absolute), (0x3e, "rol", AbsoluteX), (0x6a, "ror", Accumulator), (0x66, "ror", ZeroPage),
+    (0x76, "ror", ZeroPageX), (0x6e, "ror", Absolute), (0x7e, "ror", AbsoluteX), (0x40, "rti", Implied),
+    (0x60, "rts", Implied), (0xe9, "sbc", Immediate), (0xe5, "sbc", ZeroPage), (0xf5, "sbc", ZeroPageX),
+    (0xed, "sbc", Absolute), (0xfd, "sbc", AbsoluteX), (0xf9, "sbc", AbsoluteY), (0xe1, "sbc", IndirectX),
+    (0xf1, "sbc", IndirectY), (0x38, "sec", Implied), (0xf8, "sed", Implied), (0x78, "sei", Implied),
+    (0x85, "sta", ZeroPage), (0x95, "sta", ZeroPageX), (0x8d, "sta", Absolute), (0x9d, "sta", AbsoluteX),
+    (0x99, "sta", AbsoluteY), (0x81, "sta", IndirectX), (0x91, "sta", IndirectY), (0x86, "stx", ZeroPage),
+    (0x96, "stx", ZeroPageY), (0x8e, "stx", Absolute), (0x84, "sty", ZeroPage), (0x94, "sty", ZeroPageX),
+    (0x8c, "sty", Absolute), (0xaa, "tax", Implied), (0xa8, "tay", Implied), (0xba, "tsx", Implied),
+    (0x8a, "txa", Implied), (0x9a, "txs", Implied), (0x98, "tya", Implied),
+];
+
+fn opcode(b: u8) -> Option<(&'static str, Mode)> {
+    OPCODES.iter().find(|(op, _, _)| *op == b).map(|(_, name, mode)| (*name, *mode))
+}
+
+/// The instruction at `addr`, the start of `bytes`: its length and its
+/// text, e.g. `c000  a9 01     lda #$01`. Addresses with a label are
+/// shown by name. Bytes that aren't an instruction show as `???`.
+pub fn disassemble(addr: u16, bytes: &[u8], labels: &Labels) -> (u16, String) {
+    let (name, mode) = match bytes.first().and_then(|b| opcode(*b)) {
+        Some((name, mode)) if bytes.len() >= mode.len() as usize => (name, mode),
+        _ => return (1, format!("{:04x}  {:<9} ???", addr, format!("{:02x}", bytes.first().unwrap_or(&0)))),
+    };
+    let len = mode.len();
+    let word = match len {
+        3 => u16::from_le_bytes([bytes[1], bytes[2]]),
+        2 => bytes[1] as u16,
+        _ => 0,
+    };
+    let target = |a: u16, digits: usize| match labels.at(a).first() {
+        Some(label) => label.clone(),
+        None => format!("${:0digits$x}", a, digits = digits),
+    };
+    let operand = match mode {
+        Implied => String::new(),
+        Accumulator => String::from("a"),
+        Immediate => format!("#${:02x}", word),
+        ZeroPage => target(word, 2),
+        ZeroPageX => format!("{},x", target(word, 2)),
+        ZeroPageY => format!("{},y", target(word, 2)),
+        Absolute => target(word, 4),
+        AbsoluteX => format!("{},x", target(word, 4)),
+        AbsoluteY => format!("{},y", target(word, 4)),
+        Indirect => format!("({})", target(word, 4)),
+        IndirectX => format!("({},x)", target(word, 2)),
+        IndirectY => format!("({}),y", target(word, 2)),
+        Relative => target(addr.wrapping_add(2).wrapping_add(bytes[1] as i8 as u16), 4),
+    };
+    let hex: Vec<String> = bytes[..len as usize].iter().map(|b| format!("{:02x}", b)).collect();
+    (len, format!("{:04x}  {:<9} {} {}", addr, hex.join(" "), name, operand).trim_end().to_string())
+}
+
+/// A number in hex, with or without `$`, or the address of a label.
+pub fn parse_value(text: &str, labels: &Labels) -> Result<u16> {
+    let text = text.trim();
+    if let Some(addr) = labels.lookup(text) {
+        return Ok(addr)
+    }
+    u16::from_str_radix(text.trim_start_matches('$'), 16)
+        .map_err(|_| format_err!("{:?} is neither a hex number nor a label", text))
+}
+
+/// The bytes of the instruction `text`, e.g. `lda ($fb),y`, placed at
+/// `addr`.
+pub fn assemble(addr: u16, text: &str, labels: &Labels) -> Result<Vec<u8>> {
+    let text = text.trim();
+    let (name, operand) = text.split_once(char::is_whitespace).unwrap_or((text, ""));
+    let name = name.to_ascii_lowercase();
+    // Labels keep their case, index registers don't
+    let operand: String = operand.chars().filter(|c| !c.is_whitespace()).collect();
+    let operand = operand.replace(",X", ",x").replace(",Y", ",y");
+    let find = |mode: Mode| OPCODES.iter().find(|(_, n, m)| *n == name && *m == mode).map(|(op, _, _)| *op);
+    if !OPCODES.iter().any(|(_, n, _)| *n == name) {
+        bail!("{:?} is not a 6502 instruction", name)
+    }
+    let value = |s: &str| parse_value(s, labels);
+    // The zero page form if the value fits and the instruction has one
+    let sized = |v: u16, zp: Mode, abs: Mode| match (v < 0x100, find(zp)) {
+        (true, Some(op)) => Some(vec![op, v as u8]),
+        _ => find(abs).map(|op| vec![op, v as u8, (v >> 8) as u8]),
+    };
+    let bytes = if operand.is_empty() || operand.eq_ignore_ascii_case("a") {
+        find(Implied).or_else(|| find(Accumulator)).map(|op| vec![op])
+    } else if let Some(v) = operand.strip_prefix('#') {
+        let v = value(v)?;
+        if v > 0xff {
+            bail!("#${:x} doesn't fit in a byte", v)
+        }
+        find(Immediate).map(|op| vec![op, v as u8])
+    } else if let Some(op) = find(Relative) {
+        let offset = value(&operand)? as i32 - (addr as i32 + 2);
+        if !(-128..=127).contains(&offset) {
+            bail!("{} is out of branch range", operand)
+        }
+        Some(vec![op, offset as i8 as u8])
+    } else if let Some(v) = operand.strip_prefix('(').and_then(|o| o.strip_suffix(",x)")) {
+        let v = value(v)?;
+        find(IndirectX).filter(|_| v < 0x100).map(|op| vec![op, v as u8])
+    } else if let Some(v) = operand.strip_prefix('(').and_then(|o| o.strip_suffix("),y")) {
+        let v = value(v)?;
+        find(IndirectY).filter(|_| v < 0x100).map(|op| vec![op, v as u8])
+    } else if let Some(v) = operand.strip_prefix('(').and_then(|o| o.strip_suffix(')')) {
+        let v = value(v)?;
+        find(Indirect).map(|op| vec![op, v as u8, (v >> 8) as u8])
+    } else if let Some(v) = operand.strip_suffix(",x") {
+        sized(value(v)?, ZeroPageX, AbsoluteX)
+    } else if let Some(v) = operand.strip_suffix(",y") {
+        sized(value(v)?, ZeroPageY, AbsoluteY)
+    } else {
+        sized(value(&operand)?, ZeroPage, Absolute)
+    };
+    bytes.ok_or_else(|| format_err!("{} can't take the operand {}", name, operand))
+}
+
+#[test]
+fn assemble_disassemble() {
+    let labels = Labels::parse("al C:d020 .border\n");
+    let code = [0xa9, 0x01, 0x8d, 0x20, 0xd0, 0xb1, 0xfb, 0xd0, 0xfe, 0x6c, 0xfc, 0xff, 0x0a, 0x02];
+    let mut addr = 0xc000;
+    let mut lines = Vec::new();
+    while (addr as usize - 0xc000) < code.len() {
+        let (len, text) = disassemble(addr, &code[addr as usize - 0xc000..], &labels);
+        lines.push(text);
+        addr += len;
+    }
+    assert_eq!(lines, ["c000  a9 01     lda #$01", "c002  8d 20 d0  sta border", "c005  b1 fb     lda ($fb),y",
+        "c007  d0 fe     bne $c007", "c009  6c fc ff  jmp ($fffc)", "c00c  0a        asl a", "c00d  02        ???"]);
+    assert_eq!(assemble(0xc000, "LDA #$01", &labels).unwrap(), [0xa9, 0x01]);
+    assert_eq!(assemble(0xc002, "sta border", &labels).unwrap(), [0x8d, 0x20, 0xd0]);
+    assert_eq!(assemble(0xc005, "lda ($fb), y", &labels).unwrap(), [0xb1, 0xfb]);
+    assert_eq!(assemble(0xc007, "bne c007", &labels).unwrap(), [0xd0, 0xfe]);
+    assert_eq!(assemble(0xc000, "stx 10,y", &labels).unwrap(), [0x96, 0x10]);
+    assert_eq!(assemble(0xc000, "lda 10,y", &labels).unwrap(), [0xb9, 0x10, 0x00]);
+    assert_eq!(assemble(0xc000, "asl", &labels).unwrap(), [0x0a]);
+    assert!(assemble(0xc000, "lda ($1234),y", &labels).is_err());
+    assert!(assemble(0xc000, "bne 1000", &labels).is_err());
+    assert!(assemble(0xc000, "foo", &labels).is_err());
+}
@@ -0,0 +1,425 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+// Copyright (C) 2026 Brian Holdsworth
+
+//! Files in D64, D71 and D81 disk images, as CBM DOS lays them out.
+//!
+//! A file is a chain of sectors, each starting with the track and sector
+//! of the next; the last has track 0 and, for sector, the position of its
+//! last byte. The directory is such a chain too, of sectors holding eight
+//! 32 byte entries, and starts on the directory track after the header.
+//! The BAM (block availability map) keeps a free count and a bitmap of
+//! free sectors for every track: on the header sector of a D64, spread
+//! over tracks 18 and 53 on a D71, and in two sectors after the header
+//! on a D81. Sectors of the directory track are only used by the
+//! directory, and don't count as free.
+use std::result;
+use bstr::BString;
+use crate::formats::Format;
+use crate::image::{Geometry, SECTOR_SIZE};
+use crate::util::PetString;
+
+// Simpler error handling
+type Result<T> = result::Result<T, failure::Error>;
+
+const ENTRY_SIZE: usize = 32;
+// Names are padded with shifted spaces
+const PAD: u8 = 0xa0;
+const FILE_TYPES: [&str; 7] = ["DEL", "SEQ", "PRG", "USR", "REL", "CBM", "DIR"];
+
+/// A file in the directory of an image.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct DirEntry {
+    /// PETSCII name, without padding
+    pub name: BString,
+    /// File type code, 0 (DEL) to 6 (DIR)
+    pub ftype: u8,
+    pub locked: bool,
+    /// The file was closed properly; a splat file otherwise
+    pub closed: bool,
+    pub start: (u8, u8),
+    pub blocks: u16,
+}
+
+impl DirEntry {
+    pub fn type_name(&self) -> &'static str {
+        FILE_TYPES.get(self.ftype as usize).copied().unwrap_or("???")
+    }
+    /// The entry as a directory listing shows it, in PETSCII
+    pub fn line(&self) -> BString {
+        let mut line = format!("{:<5}", self.blocks).into_bytes();
+        let mut name = vec![b'"'];
+        name.extend_from_slice(&self.name);
+        name.push(b'"');
+        name.resize(18.max(name.len()), b' ');
+        line.extend(name);
+        line.push(if self.closed { b' ' } else { b'*' });
+        line.extend_from_slice(self.type_name().as_bytes());
+        if self.locked {
+            line.push(b'<');
+        }
+        line.into()
+    }
+}
+
+/// A disk image in memory. Changes are made to the sectors and have to
+/// be written back with the rest of `data`.
+pub struct Disk {
+    pub geometry: Geometry,
+    pub data: Vec<u8>,
+}
+
+impl Disk {
+    /// A freshly formatted image, named `label` with the disk id `id`.
+    pub fn format(format: Format, label: &str, id: &str) -> Result<Disk> {
+        let geometry = Geometry::of(format, 0)
+            .ok_or_else(|| format_err!("Only D64, D71 and D81 images can be created, not {}", format))?;
+        let mut disk = Disk { geometry, data: vec![0; geometry.total_sectors() * SECTOR_SIZE] };
+        for track in 1..=geometry.tracks {
+            for sector in 0..geometry.sectors(track) {
+                disk.set_free(track, sector, true)?;
+            }
+        }
+        let (dir_track, header) = (disk.dir_track(), disk.header());
+        let mut reserved = vec![header, disk.first_dir()];
+        match format {
+            Format::D71 => reserved.extend((0..geometry.sectors(53)).map(|s| (53, s))),
+            Format::D81 => reserved.extend([(dir_track, 1), (dir_track, 2)]),
+            _ => (),
+        }
+        for (track, sector) in reserved {
+            disk.set_free(track, sector, false)?;
+        }
+        let name_at = disk.name_offset();
+        let first_dir = disk.first_dir();
+        let id: [u8; 2] = PetString::from(id).as_slice().try_into()
+            .map_err(|_| format_err!("A disk id has two characters, not {:?}", id))?;
+        let h = disk.sector_mut(header)?;
+        h[0..2].copy_from_slice(&[first_dir.0, first_dir.1]);
+        h[name_at..name_at + 25].fill(PAD);
+        h[name_at..name_at + 16].copy_from_slice(&padded_name(PetString::from(label).as_slice())?);
+        h[name_at + 18..name_at + 20].copy_from_slice(&id);
+        match format {
+            Format::D81 => {
+                h[2] = b'D';
+                h[name_at + 21..name_at + 23].copy_from_slice(b"3D");
+                for (sector, link) in [(1, [dir_track, 2]), (2, [0, 0xff])] {
+                    let bam = disk.sector_mut((dir_track, sector))?;
+                    bam[0..2].copy_from_slice(&link);
+                    bam[2..4].copy_from_slice(&[b'D', !b'D']);
+                    bam[4..6].copy_from_slice(&id);
+                    bam[6] = 0xc0;
+                }
+            },
+            _ => {
+                h[2] = b'A';
+                h[3] = if format == Format::D71 { 0x80 } else { 0 };
+                h[name_at + 21..name_at + 23].copy_from_slice(b"2A");
+                h[name_at + 23..name_at + 27].fill(PAD);
+            },
+        }
+        disk.sector_mut(first_dir)?[0..2].copy_from_slice(&[0, 0xff]);
+        Ok(disk)
+    }
+    /// An image read from a file named `name`, whose size tells its
+    /// format.
+    pub fn open(name: &str, data: Vec<u8>) -> Result<Disk> {
+        let format = crate::formats::FileInfo::identify(name, &data).format;
+        let geometry = Geometry::of(format, data.len())
+            .ok_or_else(|| format_err!("{} is not a D64, D71 or D81 disk image", name))?;
+        Ok(Disk { geometry, data })
+    }
+    /// The disk name and id from the header, in PETSCII
+    pub fn label(&self) -> Result<(BString, BString)> {
+        let at = self.name_offset();
+        let h = self.sector(self.header())?;
+        Ok((BString::from(unpadded(&h[at..at + 16])), BString::from(&h[at + 18..at + 23])))
+    }
+    /// The files in the directory, in their order there
+    pub fn entries(&self) -> Result<Vec<DirEntry>> {
+        let mut entries = vec![];
+        for (ts, _) in self.dir_chain()? {
+            let sector = self.sector(ts)?;
+            for e in sector.chunks(ENTRY_SIZE).filter(|e| e[2] != 0) {
+                entries.push(DirEntry {
+                    name: BString::from(unpadded(&e[5..21])),
+                    ftype: e[2] & 0x0f,
+                    locked: e[2] & 0x40 != 0,
+                    closed: e[2] & 0x80 != 0,
+                    start: (e[3], e[4]),
+                    blocks: u16::from_le_bytes([e[30], e[31]]),
+                });
+            }
+        }
+        Ok(entries)
+    }
+    /// Blocks free for files, as the listing counts them
+    pub fn blocks_free(&self) -> Result<u32> {
+        let mut free = 0;
+        for track in (1..=self.geometry.tracks).filter(|t| *t != self.dir_track()) {
+            for sector in 0..self.geometry.sectors(track) {
+                free += self.is_free(track, sector)? as u32;
+            }
+        }
+        Ok(free)
+    }
+    /// The directory as the drive's `$` listing prints it, in PETSCII
+    pub fn listing(&self) -> Result<BString> {
+        let (name, id) = self.label()?;
+        let mut text = BString::from("0 \x12\"");
+        text.extend_from_slice(&name);
+        text.extend(std::iter::repeat_n(b' ', 16usize.saturating_sub(name.len())));
+        text.extend_from_slice(b"\" ");
+        text.extend(id.iter().map(|c| if *c == PAD { b' ' } else { *c }));
+        text.push(b'\r');
+        for e in self.entries()? {
+            text.extend_from_slice(&e.line());
+            text.push(b'\r');
+        }
+        text.extend_from_slice(format!("{} BLOCKS FREE.\r", self.blocks_free()?).as_bytes());
+        Ok(text)
+    }
+    /// The file named `name`, in PETSCII, if there is one
+    pub fn find(&self, name: &[u8]) -> Result<Option<DirEntry>> {
+        Ok(self.entries()?.into_iter().find(|e| e.name == name))
+    }
+    /// The contents of a file.
+    pub fn read(&self, entry: &DirEntry) -> Result<Vec<u8>> {
+        let mut data = vec![];
+        for (ts, len) in self.chain(entry.start)? {
+            data.extend_from_slice(&self.sector(ts)?[2..2 + len]);
+        }
+        Ok(data)
+    }
+    /// Stores `data` as a new file of type `ftype`, e.g. 2 for PRG.
+    pub fn add(&mut self, name: &[u8], ftype: u8, data: &[u8]) -> Result<()> {
+        if self.find(name)?.is_some() {
+            bail!("There is already a file named {} on the disk", String::from(PetString::new(&BString::from(name))))
+        }
+        let name = padded_name(name)?;
+        let blocks = data.len().div_ceil(SECTOR_SIZE - 2).max(1);
+        if blocks > self.blocks_free()? as usize {
+            bail!("The file needs {} blocks, but the disk has only {} free", blocks, self.blocks_free()?)
+        }
+        let slot = self.free_entry()?;
+        let mut sectors = vec![];
+        let mut last = None;
+        for _ in 0..blocks {
+            let ts = self.allocate(last, false)?;
+            sectors.push(ts);
+            last = Some(ts);
+        }
+        let chunks: Vec<&[u8]> = match data.is_empty() {
+            true => vec![&[]],
+            false => data.chunks(SECTOR_SIZE - 2).collect(),
+        };
+        for (i, (ts, chunk)) in sectors.iter().zip(chunks).enumerate() {
+            let link = match sectors.get(i + 1) {
+                Some(next) => [next.0, next.1],
+                None => [0, chunk.len() as u8 + 1],
+            };
+            let sector = self.sector_mut(*ts)?;
+            sector.fill(0);
+            sector[0..2].copy_from_slice(&link);
+            sector[2..2 + chunk.len()].copy_from_slice(chunk);
+        }
+        let (ts, at) = slot;
+        let e = &mut self.sector_mut(ts)?[at..at + ENTRY_SIZE];
+        e[2] = 0x80 | ftype;
+        e[3..5].copy_from_slice(&[sectors[0].0, sectors[0].1]);
+        e[5..21].copy_from_slice(&name);
+        e[21..30].fill(0);
+        e[30..32].copy_from_slice(&(blocks as u16).to_le_bytes());
+        Ok(())
+    }
+
+    // Where the directory and its header are
+    fn dir_track(&self) -> u8 {
+        match self.geometry.format {
+            Format::D81 => 40,
+            _ => 18,
+        }
+    }
+    fn header(&self) -> (u8, u8) {
+        (self.dir_track(), 0)
+    }
+    fn first_dir(&self) -> (u8, u8) {
+        match self.geometry.format {
+            Format::D81 => (40, 3),
+            _ => (18, 1),
+        }
+    }
+    // Disk name in the header; the id follows two bytes after it
+    fn name_offset(&self) -> usize {
+        match self.geometry.format {
+            Format::D81 => 0x04,
+            _ => 0x90,
+        }
+    }
+    // Sectors between one of a file and the next, for the drive to be
+    // ready for it as the disk turns
+    fn interleave(&self, dir: bool) -> u8 {
+        match (self.geometry.format, dir) {
+            (Format::D81, _) => 1,
+            (_, true) => 3,
+            (Format::D71, false) => 6,
+            _ => 10,
+        }
+    }
+    fn sector(&self, (track, sector): (u8, u8)) -> Result<&[u8]> {
+        let at = self.geometry.offset(track, sector)?;
+        Ok(&self.data[at..at + SECTOR_SIZE])
+    }
+    fn sector_mut(&mut self, (track, sector): (u8, u8)) -> Result<&mut [u8]> {
+        let at = self.geometry.offset(track, sector)?;
+        Ok(&mut self.data[at..at + SECTOR_SIZE])
+    }
+    // The sectors of a chain, with the number of bytes used in each
+    fn chain(&self, start: (u8, u8)) -> Result<Vec<((u8, u8), usize)>> {
+        let mut chain = vec![];
+        let mut ts = start;
+        loop {
+            if chain.len() > self.geometry.total_sectors() {
+                bail!("The chain from track {} sector {} runs in a loop", start.0, start.1)
+            }
+            let link = self.sector(ts)?;
+            let (next, len) = match link[0] {
+                0 => (None, (link[1] as usize).saturating_sub(1)),
+                t => (Some((t, link[1])), SECTOR_SIZE - 2),
+            };
+            chain.push((ts, len));
+            match next {
+                Some(next) => ts = next,
+                None => return Ok(chain),
+            }
+        }
+    }
+    fn dir_chain(&self) -> Result<Vec<((u8, u8), usize)>> {
+        self.chain(self.first_dir())
+    }
+    // The sector and offset of an unused directory entry, adding a
+    // sector to the directory when it's full
+    fn free_entry(&mut self) -> Result<((u8, u8), usize)> {
+        let chain = self.dir_chain()?;
+        for (ts, _) in &chain {
+            if let Some(i) = self.sector(*ts)?.chunks(ENTRY_SIZE).position(|e| e[2] == 0) {
+                return Ok((*ts, i * ENTRY_SIZE))
+            }
+        }
+        let last = chain.last().map(|(ts, _)| *ts).unwrap_or(self.first_dir());
+        let ts = self.allocate(Some(last), true)?;
+        self.sector_mut(last)?[0..2].copy_from_slice(&[ts.0, ts.1]);
+        let sector = self.sector_mut(ts)?;
+        sector.fill(0);
+        sector[0..2].copy_from_slice(&[0, 0xff]);
+        Ok((ts, 0))
+    }
+    // Takes a free sector, for a file or the directory, following `last`
+    // at the interleave if it can. Files go on the tracks nearest the
+    // directory first, as the drive does, so seeks stay short.
+    fn allocate(&mut self, last: Option<(u8, u8)>, dir: bool) -> Result<(u8, u8)> {
+        let dir_track = self.dir_track();
+        let tracks: Vec<u8> = match dir {
+            true => vec![dir_track],
+            false => (1..self.geometry.tracks)
+                .flat_map(|d| [dir_track.checked_sub(d), dir_track.checked_add(d)])
+                .flatten()
+                .filter(|t| (1..=self.geometry.tracks).contains(t))
+                .collect(),
+        };
+        let tracks = last.map(|(t, _)| t).filter(|t| tracks.contains(t)).into_iter().chain(tracks);
+        for track in tracks {
+            let n = self.geometry.sectors(track);
+            let first = match last {
+                Some((t, s)) if t == track => (s + self.interleave(dir)) % n,
+                _ => 0,
+            };
+            for s in (0..n).map(|i| (first + i) % n) {
+                if self.is_free(track, s)? {
+                    self.set_free(track, s, false)?;
+                    return Ok((track, s))
+                }
+            }
+        }
+        bail!("The disk is full")
+    }
+    // Position of a track's free count and bitmap in the image
+    fn bam_entry(&self, track: u8) -> Result<(usize, usize)> {
+        let (ts, at) = match (self.geometry.format, track) {
+            (Format::D81, 1..=40) => ((40, 1), 0x10 + 6 * (track as usize - 1)),
+            (Format::D81, _) => ((40, 2), 0x10 + 6 * (track as usize - 41)),
+            (Format::D71, 36..) => {
+                let count = self.geometry.offset(18, 0)? + 0xdd + (track as usize - 36);
+                return Ok((count, self.geometry.offset(53, 0)? + 3 * (track as usize - 36)))
+            },
+            // 40 track D64s keep the extra tracks where SpeedDOS does
+            (_, 36..) => ((18, 0), 0xc0 + 4 * (track as usize - 36)),
+            _ => ((18, 0), 4 * track as usize),
+        };
+        let at = self.geometry.offset(ts.0, ts.1)? + at;
+        Ok((at, at + 1))
+    }
+    fn is_free(&self, track: u8, sector: u8) -> Result<bool> {
+        let (_, bitmap) = self.bam_entry(track)?;
+        Ok(self.data[bitmap + sector as usize / 8] & (1 << (sector % 8)) != 0)
+    }
+    fn set_free(&mut self, track: u8, sector: u8, free: bool) -> Result<()> {
+        let (count, bitmap) = self.bam_entry(track)?;
+        let (byte, bit) = (bitmap + sector as usize / 8, 1 << (sector % 8));
+        if (self.data[byte] & bit != 0) != free {
+            self.data[byte] ^= bit;
+            self.data[count] = if free { self.data[count] + 1 } else { self.data[count] - 1 };
+        }
+        Ok(())
+    }
+}
+
+/// The file type code for a type letter as in `game,p`: p, s, u or l.
+pub fn file_type(letter: &str) -> Result<u8> {
+    match letter.to_ascii_lowercase().as_str() {
+        "s" | "seq" => Ok(1),
+        "p" | "prg" => Ok(2),
+        "u" | "usr" => Ok(3),
+        "l" | "rel" => bail!("REL files can't be added to images"),
+        _ => bail!("Unknown file type {}", letter),
+    }
+}
+
+// A name as stored in the directory, padded to 16 characters
+fn padded_name(name: &[u8]) -> Result<[u8; 16]> {
+    if name.len() > 16 {
+        bail!("{} is longer than the 16 characters a name can have", String::from(PetString::new(&BString::from(name))))
+    }
+    let mut padded = [PAD; 16];
+    padded[..name.len()].copy_from_slice(name);
+    Ok(padded)
+}
+
+fn unpadded(name: &[u8]) -> &[u8] {
+    &name[..name.iter().rposition(|c| *c != PAD).map_or(0, |i| i + 1)]
+}
+
+#[test]
+fn disk_files() {
+    for (format, free) in [(Format::D64, 664), (Format::D71, 1328), (Format::D81, 3160)] {
+        let mut disk = Disk::format(format, "test disk", "ab").unwrap();
+        assert_eq!(disk.blocks_free().unwrap(), free);
+        let (name, id) = disk.label().unwrap();
+        assert_eq!((name.as_slice(), &id[..3]), (&b"TEST DISK"[..], &b"AB\xa0"[..]));
+        let data: Vec<u8> = (0..1000u32).map(|i| i as u8).collect();
+        disk.add(b"GAME", 2, &data).unwrap();
+        disk.add(b"EMPTY", 1, b"").unwrap();
+        assert!(disk.add(b"GAME", 2, b"x").is_err());
+        for i in 0..16 {
+            disk.add(format!("FILE{}", i).as_bytes(), 2, b"hi").unwrap();
+        }
+        let game = disk.find(b"GAME").unwrap().unwrap();
+        assert_eq!((game.blocks, game.type_name()), (4, "PRG"));
+        assert_eq!(disk.read(&game).unwrap(), data);
+        assert_eq!(disk.read(&disk.find(b"EMPTY").unwrap().unwrap()).unwrap(), b"");
+        assert_eq!(disk.entries().unwrap().len(), 18);
+        assert_eq!(disk.blocks_free().unwrap(), free - 21);
+        assert!(disk.listing().unwrap().ends_with(format!("{} BLOCKS FREE.\r", free - 21).as_bytes()));
+        let disk = Disk::open("copy.img", disk.data).unwrap();
+        assert_eq!(disk.read(&disk.find(b"FILE15").unwrap().unwrap()).unwrap(), b"hi");
+    }
+}
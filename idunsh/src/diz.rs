@@ -0,0 +1,121 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+// Copyright (C) 2026 Brian Holdsworth
+
+//! Disk contents as a FILE_ID.DIZ, the description BBSes and file
+//! archives show with a download, for `catalog --diz` and `collection
+//! export`.
+//!
+//! A FILE_ID.DIZ holds at most 10 lines of at most 45 characters. The
+//! disk's name comes first, centered, then a rule, then the files two
+//! to a line, each with its type, and last the blocks free, or how many
+//! files didn't fit. Names keep their PETSCII graphics as the Unicode
+//! characters `petscii` shows them as, or with `ascii` are drawn with
+//! the ASCII characters that look most like them, for boards that only
+//! show ASCII.
+use crate::listing::Listing;
+
+/// The width of a FILE_ID.DIZ
+pub const WIDTH: usize = 45;
+const LINES: usize = 10;
+const COLUMN: usize = 22;
+
+/// A file as the description lists it.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct File {
+    pub name: String,
+    pub ftype: String,
+}
+
+/// The description of a disk named `title` holding `files`, with the
+/// listing's last line, such as its blocks free, if there is one.
+pub fn describe(title: &str, files: &[File], footer: Option<&str>, ascii: bool) -> Vec<String> {
+    let text = |s: &str| match ascii {
+        true => transliterate(s),
+        false => s.to_string(),
+    };
+    let title = fit(&text(title.trim()), WIDTH);
+    let mut lines = vec![format!("{:^width$}", title, width = WIDTH).trim_end().to_string(), "-".repeat(WIDTH)];
+    // The rows left once the header and the last line are in
+    let rows = LINES - lines.len() - 1;
+    let shown = files.len().min(rows * 2);
+    let cell = |f: &File| format!("{:<16} {:<3}", fit(&text(&f.name), 16), fit(&f.ftype, 3));
+    for pair in files[..shown].chunks(2) {
+        let cells: Vec<String> = pair.iter().map(cell).collect();
+        lines.push(format!("{:<COLUMN$}   {}", cells[0], cells.get(1).map_or("", String::as_str)).trim_end().to_string());
+    }
+    let last = match files.len() - shown {
+        0 => footer.map(|f| text(f.trim())).unwrap_or_default(),
+        more => format!("... and {} more file(s)", more),
+    };
+    if !last.is_empty() {
+        lines.push(fit(&last, WIDTH));
+    }
+    lines
+}
+
+/// The description of a disk from its directory listing.
+pub fn of_listing(listing: &Listing, ascii: bool) -> Vec<String> {
+    // The header is the disk's name in quotes, then its id
+    let header = listing.header.as_deref().unwrap_or_default();
+    let title = match header.split('"').collect::<Vec<_>>()[..] {
+        [_, name, id] => format!("{}  {}", name.trim_end(), id.trim()),
+        _ => header.to_string(),
+    };
+    let files: Vec<File> = listing.entries.iter()
+        .map(|e| File { name: e.name.clone(), ftype: e.ftype.clone() })
+        .collect();
+    describe(&title, &files, listing.footer.first().map(String::as_str), ascii)
+}
+
+// `s` cut to `width` characters
+fn fit(s: &str, width: usize) -> String {
+    s.chars().take(width).collect()
+}
+
+/// `s` with the PETSCII graphics in it drawn with ASCII characters.
+pub fn transliterate(s: &str) -> String {
+    s.chars().map(|c| match c {
+        c if c.is_ascii() => c,
+        '\u{a0}' => ' ',
+        '─' | '▔' | '▁' | '▂' | '▃' | '🭶' | '🭷' | '🭸' | '🭹' | '🭺' | '🭻' => '-',
+        '│' | '▏' | '▕' | '▎' | '▍' | '🭰' | '🭱' | '🭲' | '🭳' | '🭴' | '🭵' => '|',
+        '┼' | '├' | '┤' | '┬' | '┴' | '┌' | '┐' | '└' | '┘' | '╭' | '╮' | '╰' | '╯' | '🭼' | '🭽' | '🭾' | '🭿' => '+',
+        '╱' | '◢' | '◤' => '/',
+        '╲' | '◣' | '◥' => '\\',
+        '╳' => 'X',
+        '▒' | '🮌' | '🮏' | '▚' | '🮖' | '🮘' | '🮙' => ':',
+        '●' | '○' => 'o',
+        '♠' | '♥' | '♦' | '♣' => '*',
+        'π' => 'p',
+        '£' => 'L',
+        '↑' => '^',
+        '←' => '<',
+        '✓' => 'v',
+        // The rest are blocks of one size or another
+        _ => '#',
+    }).collect()
+}
+
+#[test]
+fn file_id_diz() {
+    let files: Vec<File> = ["game", "intro", "hi─scores"].iter()
+        .map(|name| File { name: name.to_string(), ftype: "PRG".into() })
+        .collect();
+    let diz = describe("♥ edge ♥", &files, Some("640 blocks free."), false);
+    assert_eq!(diz, [
+        "                  ♥ edge ♥",
+        "---------------------------------------------",
+        "game             PRG     intro            PRG",
+        "hi─scores        PRG",
+        "640 blocks free.",
+    ]);
+    assert_eq!(describe("♥ edge ♥", &files, None, true)[3], "hi-scores        PRG");
+    let many = vec![files[0].clone(); 20];
+    let diz = describe("demos", &many, Some("0 blocks free."), false);
+    assert_eq!((diz.len(), diz[9].as_str()), (LINES, "... and 6 more file(s)"));
+    assert!(diz.iter().all(|l| l.chars().count() <= WIDTH));
+    assert_eq!(transliterate("╭─╮▌x🮖"), "+-+#x:");
+    let listing = Listing::parse("0 \"games           \" 01 2a\n12   \"jumpman\"          prg\n652 blocks free.\n");
+    assert_eq!(of_listing(&listing, false)[0], "                games  01 2a");
+    assert_eq!(of_listing(&listing, false)[2..], ["jumpman          prg", "652 blocks free."]);
+}
@@ -0,0 +1,240 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+// Copyright (C) 2026 Brian Holdsworth
+
+//! Raw DOS commands and the drive's answer on its error channel, and the
+//! mapping between names on a drive and local file names.
+//!
+//! A name on a drive may hold what a local file name can't, like '/' or
+//! control codes, or differ only in case from another. `local_name`
+//! writes such characters as `%` and two hex digits, as it does '%' and
+//! '~' themselves and a leading '.', and `local_names` adds `~2`, `~3`
+//! and so on to names that are the same but for case. `drive_name`
+//! undoes both, and `stored_name` uses it, so a file got and put back
+//! keeps its name.
+use std::collections::HashSet;
+use std::fmt;
+use std::result;
+use failure::Fail;
+
+// Simpler error handling
+type Result<T> = result::Result<T, failure::Error>;
+
+/// Turns a command typed on the command line into the PETSCII bytes
+/// sent to the drive. Letters in either case are sent unshifted, as the
+/// drive expects them. `\xNN` stands for any byte, e.g.
+/// `M-R\x00\x05\x10`, and `\\` for a backslash.
+pub fn unescape(cmd: &str) -> Result<Vec<u8>> {
+    let mut out = vec![];
+    let mut rest = cmd;
+    while let Some(i) = rest.find('\\') {
+        out.extend_from_slice(rest[..i].to_ascii_uppercase().as_bytes());
+        rest = &rest[i + 1..];
+        if let Some(r) = rest.strip_prefix('\\') {
+            out.push(b'\\');
+            rest = r;
+        } else if let Some(byte) = rest.strip_prefix('x').and_then(|r| r.get(..2))
+            .and_then(|h| u8::from_str_radix(h, 16).ok()) {
+            out.push(byte);
+            rest = &rest[3..];
+        } else {
+            bail!("Bad escape in DOS command {:?}; use \\xNN or \\\\", cmd)
+        }
+    }
+    out.extend_from_slice(rest.to_ascii_uppercase().as_bytes());
+    Ok(out)
+}
+
+/// The argument string of the DOS command: the device, then the command
+/// quoted the way `protocol::join_args` quotes.
+pub fn command_line(dev: &str, cmd: &[u8]) -> Vec<u8> {
+    let mut line = dev.as_bytes().to_vec();
+    line.push(b' ');
+    if cmd.is_empty() || cmd.contains(&b' ') || cmd.contains(&b'"') {
+        line.push(b'"');
+        for &b in cmd {
+            line.push(b);
+            if b == b'"' {
+                line.push(b'"');
+            }
+        }
+        line.push(b'"');
+    } else {
+        line.extend_from_slice(cmd);
+    }
+    line
+}
+
+/// The start address of a memory read (`M-R lo hi n`), whose answer is
+/// the memory rather than a status.
+pub fn memory_read(cmd: &[u8]) -> Option<u16> {
+    match cmd {
+        [b'M', b'-', b'R', lo, hi, ..] => Some(u16::from_le_bytes([*lo, *hi])),
+        _ => None,
+    }
+}
+
+/// What a DOS command would destroy, to be asked about before sending
+/// it: `N` formats the disk, and `S` with a wildcard may scratch many
+/// files at once.
+pub fn danger(cmd: &[u8]) -> Option<&'static str> {
+    let name = cmd.iter().position(|&b| b == b':').map_or(&[][..], |i| &cmd[i + 1..]);
+    match cmd.first() {
+        Some(b'N') => Some("Format the disk"),
+        Some(b'S') if cmd.get(1) != Some(&b'-') && name.iter().any(|b| matches!(b, b'*' | b'?')) =>
+            Some("Scratch every matching file"),
+        _ => None,
+    }
+}
+
+/// A status from the error channel, e.g. `62,FILE NOT FOUND,00,00`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct DosStatus {
+    pub code: u8,
+    pub message: String,
+    pub track: u8,
+    pub sector: u8,
+}
+
+impl DosStatus {
+    pub fn parse(text: &str) -> Option<DosStatus> {
+        let mut fields = text.trim_end_matches(['\r', '\n']).split(',');
+        let code = fields.next()?.trim().parse().ok()?;
+        let message = fields.next()?.trim().to_string();
+        let track = fields.next()?.trim().parse().ok()?;
+        let sector = fields.next()?.trim().parse().ok()?;
+        Some(DosStatus { code, message, track, sector })
+    }
+    /// Codes below 20 report success, and 73 is the DOS version shown
+    /// after a reset.
+    pub fn is_error(&self) -> bool {
+        self.code >= 20 && self.code != 73
+    }
+}
+
+impl fmt::Display for DosStatus {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:02} {} (track {}, sector {})", self.code, self.message, self.track, self.sector)
+    }
+}
+
+impl Fail for DosStatus {}
+
+/// The name a local file is stored under on a drive, with the file
+/// type its extension gives, e.g. `game,p` for `games/game.prg`. A
+/// `name` given for it keeps its own type if it has one.
+pub fn stored_name(path: &str, name: &str) -> String {
+    let file = path.rsplit('/').next().unwrap_or(path);
+    let (stem, ext) = file.rsplit_once('.').unwrap_or((file, ""));
+    let stem = drive_name(stem);
+    let ftype = match ext.to_ascii_lowercase().as_str() {
+        "seq" => 's',
+        "usr" => 'u',
+        "rel" => 'l',
+        _ => 'p',
+    };
+    match name {
+        "" => format!("{},{}", stem, ftype),
+        n if n.contains(',') => n.to_string(),
+        n => format!("{},{}", n, ftype),
+    }
+}
+
+/// A name on a drive as a local file name; see above.
+pub fn local_name(name: &str) -> String {
+    let mut local = String::with_capacity(name.len());
+    for (i, c) in name.chars().enumerate() {
+        match c {
+            '/' | '%' | '~' | '\0' => local.push_str(&format!("%{:02X}", c as u32)),
+            '.' if i == 0 => local.push_str("%2E"),
+            c if c.is_control() => local.push_str(&format!("%{:02X}", c as u32)),
+            c => local.push(c),
+        }
+    }
+    local
+}
+
+/// Local file names for the names on a drive, each unique even where
+/// case doesn't count.
+pub fn local_names<S: AsRef<str>>(names: &[S]) -> Vec<String> {
+    let mut seen = HashSet::new();
+    names.iter()
+        .map(|name| {
+            let local = local_name(name.as_ref());
+            let unique = (1..).map(|n| match n {
+                1 => local.clone(),
+                n => format!("{}~{}", local, n),
+            }).find(|l| !seen.contains(&l.to_lowercase())).unwrap_or_default();
+            seen.insert(unique.to_lowercase());
+            unique
+        })
+        .collect()
+}
+
+/// The name on a drive a local file name stands for: `local_name` and
+/// `local_names` undone.
+pub fn drive_name(local: &str) -> String {
+    let local = match local.rsplit_once('~') {
+        Some((name, n)) if !n.is_empty() && n.bytes().all(|b| b.is_ascii_digit()) => name,
+        _ => local,
+    };
+    let mut name = String::with_capacity(local.len());
+    let mut rest = local;
+    while let Some(i) = rest.find('%') {
+        name.push_str(&rest[..i]);
+        match rest.get(i + 1..i + 3).and_then(|h| u8::from_str_radix(h, 16).ok()) {
+            Some(byte) => {
+                name.push(byte as char);
+                rest = &rest[i + 3..];
+            },
+            None => {
+                name.push('%');
+                rest = &rest[i + 1..];
+            },
+        }
+    }
+    name.push_str(rest);
+    name
+}
+
+#[test]
+fn dos_status() {
+    let status = DosStatus::parse("62, file not found,00,00\r").unwrap();
+    assert_eq!((status.code, status.message.as_str()), (62, "file not found"));
+    assert!(status.is_error() && !DosStatus::parse("73,cbm dos v2.6 1541,00,00").unwrap().is_error());
+    assert_eq!(status.to_string(), "62 file not found (track 0, sector 0)");
+    assert_eq!(DosStatus::parse("ready."), None);
+}
+
+#[test]
+fn dos_command() {
+    let cmd = unescape(r"m-r\x00\x05\x10").unwrap();
+    assert_eq!(cmd, b"M-R\x00\x05\x10");
+    assert_eq!(memory_read(&cmd), Some(0x0500));
+    assert!(unescape(r"M-R\x0").is_err());
+    assert_eq!(command_line("c:", b"S0:MY FILE"), b"c: \"S0:MY FILE\"");
+}
+
+#[test]
+fn dangerous_commands() {
+    assert!(danger(b"N0:WORK,01").is_some());
+    assert!(danger(b"NEW:WORK").is_some());
+    assert!(danger(b"S0:*").is_some());
+    assert!(danger(b"SCRATCH:GAME?").is_some());
+    assert_eq!(danger(b"S0:GAME"), None);
+    assert_eq!(danger(b"R0:NEW=OLD*"), None);
+    assert_eq!(danger(b"M-R\x00\x05\x10"), None);
+    assert_eq!(danger(b""), None);
+}
+
+#[test]
+fn stored_names() {
+    assert_eq!(stored_name("games/game.prg", ""), "game,p");
+    assert_eq!(stored_name("notes.SEQ", "my notes"), "my notes,s");
+    assert_eq!(stored_name("notes.seq", "log,u"), "log,u");
+    assert_eq!(stored_name("readme", ""), "readme,p");
+    assert_eq!(stored_name("out/A%2FB~2.prg", ""), "A/B,p");
+    let names = ["a/b", "GAME", "game", ".x", "50%~\u{8d}"];
+    let local = local_names(&names);
+    assert_eq!(local, ["a%2Fb", "GAME", "game~2", "%2Ex", "50%25%7E%8D"]);
+    assert!(local.iter().zip(names).all(|(l, n)| drive_name(l) == n));
+}
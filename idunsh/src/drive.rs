@@ -0,0 +1,111 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+// Copyright (C) 2026 Brian Holdsworth
+
+//! Commands to a drive's DOS: raw commands, the error channel, and
+//! locking files.
+use std::io::stdout;
+use std::result;
+use idun_client::client::{CATALOG_CMD, DOS_CMD};
+use idun_client::dos::{self, DosStatus};
+use idun_client::listing::Listing;
+use idun_client::protocol::{self, RemoteError};
+use idun_client::util::PetString;
+use crate::capture;
+use crate::capture_shell;
+use crate::confirm::confirm;
+use crate::errors::{At, Context};
+use crate::hexdump;
+use crate::labels::Labels;
+use crate::luasend;
+use crate::state;
+
+// Simpler error handling
+type Result<T> = result::Result<T, failure::Error>;
+
+// Sets the locked flag of `file` (e.g. "c:games/tetris"). The DOS L
+// command toggles it, so the catalog is checked first.
+pub fn lock(file: &str, lock: bool) -> Result<()> {
+    let (dev, path) = file.split_once(':')
+        .ok_or_else(|| format_err!("{} doesn't name a device, e.g. c:{}", file, file))?;
+    state::check_writable(&format!("{}:", dev))?;
+    let name = path.rsplit('/').next().unwrap_or(path);
+    let catalog = format!("{}:{}", dev, &path[..path.len() - name.len()]);
+    let listing = Listing::parse(&String::from(capture_shell(CATALOG_CMD, &catalog)?));
+    let entry = listing.entries.iter()
+        .find(|e| e.name == name)
+        .ok_or_else(|| format_err!("{}: file not found", file))?;
+    if entry.locked != lock {
+        let cmd = format!("L0:{}", path).to_ascii_uppercase();
+        let status = dos_status(&format!("{}:", dev), &dos_send(&format!("{}:", dev), cmd.as_bytes())?)?;
+        if status.is_error() {
+            return Err(status.into())
+        }
+    }
+    Ok(())
+}
+
+// Sends a DOS command to the drive `dev` and returns its answer
+pub fn dos_send(dev: &str, cmd: &[u8]) -> Result<Vec<u8>> {
+    let args = protocol::lua_bytes(&dos::command_line(dev, cmd));
+    capture(|id| luasend(format!("sys.shell({}, {}, {})", DOS_CMD, args, id)))
+        .at(Context::Phase("DOS command")).at(Context::Device(dev.to_string()))
+}
+
+// Prints the drive's answer to a DOS command: the memory read by M-R,
+// or the decoded status, which fails the command if it's an error
+pub fn run(dev: &str, cmd: &str, yes: bool) -> Result<()> {
+    let cmd = dos::unescape(cmd)?;
+    if let Some(what) = dos::danger(&cmd) {
+        confirm(&format!("{} in {}?", what, dev), yes)?;
+    }
+    let reply = dos_send(dev, &cmd)?;
+    if let Some(start) = dos::memory_read(&cmd) {
+        hexdump::hexdump(&mut stdout(), start, &reply, 16, false, &Labels::default())?;
+        return Ok(())
+    }
+    let status = dos_status(dev, &reply)?;
+    if status.is_error() {
+        return Err(status.into())
+    }
+    println!("{}", status);
+    Ok(())
+}
+
+// Decodes the answer of `dev` as an error channel status
+pub fn dos_status(dev: &str, reply: &[u8]) -> Result<DosStatus> {
+    let text = String::from(PetString::new(&reply.into()));
+    DosStatus::parse(&text).ok_or_else(|| format_err!("Unexpected answer from {}: {:?}", dev, text))
+}
+
+// Reads the error channel of `dev`, which an empty DOS command does
+pub fn status(dev: &str) -> Result<DosStatus> {
+    dos_status(dev, &dos_send(dev, b"")?)
+}
+
+// Adds the drive's own error to a command the daemon refused, e.g.
+// "74 drive not ready" rather than just "failed"
+pub fn with_status<T>(dev: &str, result: Result<T>) -> Result<T> {
+    let result = result.map_err(|e| match e.downcast::<RemoteError>() {
+        Ok(mut e) => {
+            if let Some(status) = status(dev).ok().filter(|s| s.is_error()) {
+                e.message = match e.message.is_empty() {
+                    true => status.to_string(),
+                    false => format!("{}; drive status {}", e.message, status),
+                };
+            }
+            e.into()
+        },
+        Err(e) => e,
+    });
+    result.at(Context::Device(dev.to_string()))
+}
+
+#[test]
+fn error_channel_replies() {
+    let status = dos_status("8", b"74,DRIVE NOT READY,00,00\r").unwrap();
+    assert_eq!((status.code, status.is_error()), (74, true));
+    // The power-on message is a status, not an error
+    assert!(!dos_status("8", b"73,CBM DOS V2.6 1541,00,00\r").unwrap().is_error());
+    assert_eq!(dos_status("8", b"").unwrap_err().to_string(), "Unexpected answer from 8: \"\"");
+    assert!(dos_status("8", b"00,OK,00\r").is_err());
+}
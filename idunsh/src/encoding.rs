@@ -0,0 +1,272 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+// Copyright (C) 2026 Brian Holdsworth
+
+//! How bytes are carried on the link to the daemon.
+//!
+//! The daemon's Unix socket carries any byte, so calls, answers and
+//! redirected output go over it as they are: `raw`. Links that aren't
+//! 8-bit clean, or that need the end of a message marked, such as a
+//! serial console, carry the same protocol with another `Encoding`:
+//!
+//! * `base64`: RFC 4648 base64 in lines of up to 76 characters, each
+//!   ended by a newline. Whitespace between them is skipped, and every
+//!   piece encoded ends with its own padding.
+//! * `slip`: RFC 1055 framing. Each piece encoded ends with END (0xc0);
+//!   END and ESC (0xdb) inside it are sent as ESC 0xdc and ESC 0xdd.
+//!
+//! Both ends of a link have to use the same encoding. It covers the
+//! whole exchange: the call and its newline, the daemon's answer, and
+//! the data going either way on a redirect socket.
+use std::fmt;
+use std::result;
+use std::sync::Arc;
+
+// Simpler error handling
+type Result<T> = result::Result<T, failure::Error>;
+
+pub trait Encoding: fmt::Debug + Send + Sync {
+    /// The name the encoding is chosen by, e.g. "base64"
+    fn name(&self) -> &'static str;
+    /// One piece of data as it goes on the link.
+    fn encode(&self, data: &[u8]) -> Vec<u8>;
+    /// A decoder for data coming off the link, in pieces of any size.
+    fn decoder(&self) -> Box<dyn Decoder>;
+}
+
+pub trait Decoder: Send {
+    /// The data decoded from `data`, as far as it's whole.
+    fn push(&mut self, data: &[u8]) -> Result<Vec<u8>>;
+    /// The rest once the link has closed; an error if it was cut short.
+    fn finish(&mut self) -> Result<Vec<u8>>;
+}
+
+/// The encodings known, by name.
+pub const NAMES: [&str; 3] = ["raw", "base64", "slip"];
+
+/// The encoding called `name`, one of `NAMES`.
+pub fn by_name(name: &str) -> Result<Arc<dyn Encoding>> {
+    match name {
+        "raw" => Ok(Arc::new(Raw)),
+        "base64" => Ok(Arc::new(Base64)),
+        "slip" => Ok(Arc::new(Slip)),
+        _ => bail!("Unknown encoding {:?}; use one of {}", name, NAMES.join(", ")),
+    }
+}
+
+/// Decodes all of `data` at once.
+pub fn decode(encoding: &dyn Encoding, data: &[u8]) -> Result<Vec<u8>> {
+    let mut decoder = encoding.decoder();
+    let mut decoded = decoder.push(data)?;
+    decoded.extend(decoder.finish()?);
+    Ok(decoded)
+}
+
+/// Bytes as they are.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Raw;
+
+impl Encoding for Raw {
+    fn name(&self) -> &'static str {
+        "raw"
+    }
+    fn encode(&self, data: &[u8]) -> Vec<u8> {
+        data.to_vec()
+    }
+    fn decoder(&self) -> Box<dyn Decoder> {
+        Box::new(Raw)
+    }
+}
+
+impl Decoder for Raw {
+    fn push(&mut self, data: &[u8]) -> Result<Vec<u8>> {
+        Ok(data.to_vec())
+    }
+    fn finish(&mut self) -> Result<Vec<u8>> {
+        Ok(vec![])
+    }
+}
+
+/// Base64 text, for links that only carry printable characters.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Base64;
+
+const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+const LINE: usize = 76;
+
+impl Encoding for Base64 {
+    fn name(&self) -> &'static str {
+        "base64"
+    }
+    fn encode(&self, data: &[u8]) -> Vec<u8> {
+        let mut text = Vec::with_capacity(data.len() / 3 * 4 + data.len() / 57 + 6);
+        for line in data.chunks(LINE / 4 * 3) {
+            for group in line.chunks(3) {
+                let n = group.iter().enumerate().fold(0u32, |n, (i, b)| n | ((*b as u32) << (16 - 8 * i)));
+                for i in 0..4 {
+                    text.push(match i <= group.len() {
+                        true => ALPHABET[((n >> (18 - 6 * i)) & 0x3f) as usize],
+                        false => b'=',
+                    });
+                }
+            }
+            text.push(b'\n');
+        }
+        text
+    }
+    fn decoder(&self) -> Box<dyn Decoder> {
+        Box::new(Base64Decoder::default())
+    }
+}
+
+#[derive(Default)]
+struct Base64Decoder {
+    // The characters of the group read so far, as six bits each
+    group: Vec<u8>,
+    padding: usize,
+}
+
+impl Decoder for Base64Decoder {
+    fn push(&mut self, data: &[u8]) -> Result<Vec<u8>> {
+        let mut decoded = Vec::with_capacity(data.len() / 4 * 3);
+        for &c in data.iter().filter(|c| !c.is_ascii_whitespace()) {
+            match (c, ALPHABET.iter().position(|a| *a == c)) {
+                (b'=', _) if self.group.len() >= 2 => self.padding += 1,
+                (_, Some(bits)) if self.padding == 0 => self.group.push(bits as u8),
+                _ => bail!("Not base64 from the link: {:?}", c as char),
+            }
+            if self.group.len() + self.padding == 4 {
+                let n = self.group.iter().enumerate().fold(0u32, |n, (i, b)| n | ((*b as u32) << (18 - 6 * i)));
+                decoded.extend(n.to_be_bytes()[1..self.group.len()].iter());
+                self.group.clear();
+                self.padding = 0;
+            }
+        }
+        Ok(decoded)
+    }
+    fn finish(&mut self) -> Result<Vec<u8>> {
+        match self.group.len() + self.padding {
+            0 => Ok(vec![]),
+            n => bail!("The link closed {} characters into a base64 group", n),
+        }
+    }
+}
+
+/// SLIP framing, for links that need the end of each piece marked.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Slip;
+
+const END: u8 = 0xc0;
+const ESC: u8 = 0xdb;
+const ESC_END: u8 = 0xdc;
+const ESC_ESC: u8 = 0xdd;
+
+impl Encoding for Slip {
+    fn name(&self) -> &'static str {
+        "slip"
+    }
+    fn encode(&self, data: &[u8]) -> Vec<u8> {
+        let mut framed = Vec::with_capacity(data.len() + 2);
+        for &b in data {
+            match b {
+                END => framed.extend([ESC, ESC_END]),
+                ESC => framed.extend([ESC, ESC_ESC]),
+                b => framed.push(b),
+            }
+        }
+        framed.push(END);
+        framed
+    }
+    fn decoder(&self) -> Box<dyn Decoder> {
+        Box::new(SlipDecoder::default())
+    }
+}
+
+#[derive(Default)]
+struct SlipDecoder {
+    escaped: bool,
+}
+
+impl Decoder for SlipDecoder {
+    fn push(&mut self, data: &[u8]) -> Result<Vec<u8>> {
+        let mut decoded = Vec::with_capacity(data.len());
+        for &b in data {
+            match (self.escaped, b) {
+                (false, END) => (),
+                (false, ESC) => self.escaped = true,
+                (false, b) => decoded.push(b),
+                (true, ESC_END) => decoded.push(END),
+                (true, ESC_ESC) => decoded.push(ESC),
+                (true, b) => bail!("Bad SLIP escape from the link: {:#04x}", b),
+            }
+            if b != ESC {
+                self.escaped = false;
+            }
+        }
+        Ok(decoded)
+    }
+    fn finish(&mut self) -> Result<Vec<u8>> {
+        match self.escaped {
+            true => bail!("The link closed in the middle of a SLIP escape"),
+            false => Ok(vec![]),
+        }
+    }
+}
+
+/// Splits SLIP data into the pieces it frames, as it arrives.
+#[derive(Debug, Default)]
+pub struct SlipFrames {
+    frame: Vec<u8>,
+    escaped: bool,
+}
+
+impl SlipFrames {
+    /// The pieces completed by `data`; empty ones are skipped.
+    pub fn push(&mut self, data: &[u8]) -> Result<Vec<Vec<u8>>> {
+        let mut frames = vec![];
+        for &b in data {
+            match (self.escaped, b) {
+                (false, END) if !self.frame.is_empty() => frames.push(std::mem::take(&mut self.frame)),
+                (false, END) => (),
+                (false, ESC) => self.escaped = true,
+                (false, b) => self.frame.push(b),
+                (true, ESC_END) => self.frame.push(END),
+                (true, ESC_ESC) => self.frame.push(ESC),
+                (true, b) => {
+                    self.frame.clear();
+                    bail!("Bad SLIP escape from the link: {:#04x}", b)
+                },
+            }
+            if b != ESC {
+                self.escaped = false;
+            }
+        }
+        Ok(frames)
+    }
+}
+
+#[test]
+fn encodings() {
+    let data: Vec<u8> = (0..=255).chain([END, ESC, b'\n']).collect();
+    for name in NAMES {
+        let encoding = by_name(name).unwrap();
+        let encoded = encoding.encode(&data);
+        assert_eq!(decode(&*encoding, &encoded).unwrap(), data, "{}", name);
+        // In pieces of any size, and several pieces one after another
+        let mut decoder = encoding.decoder();
+        let mut decoded = vec![];
+        for piece in [encoded.as_slice(), &encoding.encode(b"ab")].concat().chunks(7) {
+            decoded.extend(decoder.push(piece).unwrap());
+        }
+        decoded.extend(decoder.finish().unwrap());
+        assert_eq!(decoded, [data.as_slice(), b"ab"].concat(), "{}", name);
+    }
+    assert_eq!(Base64.encode(b"sys.shell(3)\n"), b"c3lzLnNoZWxsKDMpCg==\n");
+    assert!(Base64.encode(&[0; 100]).split(|b| *b == b'\n').all(|l| l.len() <= LINE));
+    assert!(decode(&Base64, b"c3l").is_err() && decode(&Base64, b"c3*z").is_err());
+    assert_eq!(Slip.encode(&[1, END, ESC]), [1, ESC, ESC_END, ESC, ESC_ESC, END]);
+    assert!(decode(&Slip, &[1, ESC]).is_err() && decode(&Slip, &[ESC, 1]).is_err());
+    let mut frames = SlipFrames::default();
+    assert_eq!(frames.push(&[END, 1, ESC]).unwrap(), Vec::<Vec<u8>>::new());
+    assert_eq!(frames.push(&[ESC_END, END, 2, END]).unwrap(), [vec![1, END], vec![2]]);
+    assert!(by_name("uuencode").is_err());
+}
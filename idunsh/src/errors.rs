@@ -0,0 +1,234 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+// Copyright (C) 2026 Brian Holdsworth
+
+//! Errors as they're shown: what went wrong, where, and what may put it
+//! right.
+//!
+//! On their way up, failures are wrapped in a `Context` naming the
+//! command, drive, file or phase they happened in, with `at`. `using`
+//! notes the backend commands go to. A `Report` gathers these, with a
+//! hint if the failure is a known one, and prints them for people:
+//!
+//! ```text
+//! Error: not found: game
+//!   in: command get, drive c:, file game, phase receiving, backend idun
+//!   hint: check the name with `idunsh dir c:`
+//! ```
+//!
+//! or, with `--errors json`, as one line of JSON on stderr:
+//!
+//! ```text
+//! {"error":"not found: game","context":{"backend":"idun","command":"get",
+//!  "drive":"c:","file":"game","phase":"receiving"},"hint":"check the name
+//!  with `idunsh dir c:`","status":66}
+//! ```
+use std::collections::BTreeMap;
+use std::fmt;
+use std::result;
+use std::sync::Mutex;
+use clap::ValueEnum;
+use failure::{Fail, ResultExt};
+use serde::Serialize;
+use idun_client::dos::DosStatus;
+use idun_client::protocol::{ErrorCode, RemoteError};
+
+// Simpler error handling
+type Result<T> = result::Result<T, failure::Error>;
+
+/// How errors are printed, chosen by `--errors`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, ValueEnum)]
+pub enum ErrorFormat {
+    #[default]
+    Text,
+    Json,
+}
+
+/// Where a failure happened.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Context {
+    Command(String),
+    Device(String),
+    File(String),
+    /// The step under way, e.g. "sending"
+    Phase(&'static str),
+}
+
+impl Context {
+    fn key(&self) -> &'static str {
+        match self {
+            Context::Command(_) => "command",
+            Context::Device(_) => "drive",
+            Context::File(_) => "file",
+            Context::Phase(_) => "phase",
+        }
+    }
+    fn value(&self) -> &str {
+        match self {
+            Context::Command(s) | Context::Device(s) | Context::File(s) => s,
+            Context::Phase(s) => s,
+        }
+    }
+}
+
+impl fmt::Display for Context {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} {}", self.key(), self.value())
+    }
+}
+
+/// Ends idunsh with this status, after any message has been printed.
+#[derive(Debug)]
+pub struct ExitStatus(pub i32);
+
+impl fmt::Display for ExitStatus {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "exit status {}", self.0)
+    }
+}
+
+impl Fail for ExitStatus {}
+
+/// Adds where a failure happened to it.
+pub trait At<T> {
+    fn at(self, context: Context) -> Result<T>;
+}
+
+impl<T> At<T> for Result<T> {
+    fn at(self, context: Context) -> Result<T> {
+        Ok(self.context(context)?)
+    }
+}
+
+// The backend of the command being run
+static BACKEND: Mutex<Option<&'static str>> = Mutex::new(None);
+
+/// Notes the backend commands go to from now on: "idun", "c64u" or
+/// "vice", as `status` names them, or none.
+pub fn using(backend: Option<&'static str>) {
+    if let Ok(mut b) = BACKEND.lock() {
+        *b = backend;
+    }
+}
+
+/// A failure as it's shown.
+#[derive(Debug, Serialize)]
+pub struct Report {
+    pub error: String,
+    pub context: BTreeMap<&'static str, String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub hint: Option<String>,
+    pub status: i32,
+    // Already told, e.g. by a program's own output
+    #[serde(skip)]
+    quiet: bool,
+}
+
+impl Report {
+    pub fn new(e: &failure::Error) -> Report {
+        let mut context = BTreeMap::new();
+        for c in e.iter_chain().filter_map(|f| f.downcast_ref::<failure::Context<Context>>()) {
+            // The innermost, most precise, wins
+            context.insert(c.get_context().key(), c.get_context().value().to_string());
+        }
+        if let Some(backend) = BACKEND.lock().ok().and_then(|b| *b) {
+            context.insert("backend", backend.to_string());
+        }
+        let cause = e.find_root_cause();
+        let (status, quiet) = match find::<RemoteError>(e) {
+            Some(remote) => (remote.code.exit_status(), false),
+            None => match find::<ExitStatus>(e) {
+                Some(exit) => (exit.0, true),
+                None => (1, false),
+            },
+        };
+        let hint = hint(e, &context);
+        Report { error: cause.to_string(), context, hint, status, quiet }
+    }
+    /// Prints the report on stderr, unless it was already told.
+    pub fn print(&self, format: ErrorFormat) {
+        if self.quiet {
+            return
+        }
+        match format {
+            ErrorFormat::Text => eprint!("{}", self),
+            ErrorFormat::Json => eprintln!("{}", serde_json::to_string(self).unwrap_or_default()),
+        }
+    }
+}
+
+impl fmt::Display for Report {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "Error: {}", self.error)?;
+        if !self.context.is_empty() {
+            // In the order things narrow down
+            let order = ["command", "drive", "file", "phase", "backend"];
+            let parts: Vec<String> = order.iter()
+                .filter_map(|k| self.context.get(k).map(|v| format!("{} {}", k, v)))
+                .collect();
+            writeln!(f, "  in: {}", parts.join(", "))?;
+        }
+        if let Some(hint) = &self.hint {
+            writeln!(f, "  hint: {}", hint)?;
+        }
+        Ok(())
+    }
+}
+
+fn find<T: Fail>(e: &failure::Error) -> Option<&T> {
+    e.iter_chain().find_map(|f| f.downcast_ref::<T>())
+}
+
+// What may put a known failure right
+fn hint(e: &failure::Error, context: &BTreeMap<&'static str, String>) -> Option<String> {
+    let dir = match context.get("drive") {
+        Some(dev) => format!("`idunsh dir {}`", dev),
+        None => "`idunsh dir`".to_string(),
+    };
+    if let Some(remote) = find::<RemoteError>(e) {
+        return match remote.code {
+            ErrorCode::NotFound => Some(format!("check the name with {}", dir)),
+            ErrorCode::Busy => Some("a program is running; `idunsh stop` ends it".into()),
+            ErrorCode::Syntax => Some("see `idunsh help` for the command's arguments".into()),
+            ErrorCode::NoMemory => Some("`idunsh reboot` frees the Commodore's memory".into()),
+            _ => None,
+        }
+    }
+    if let Some(status) = find::<DosStatus>(e) {
+        return match status.code {
+            26 => Some("the disk is write protected".into()),
+            62 => Some(format!("check the name with {}", dir)),
+            63 => Some("a file has that name already; scratch it or pick another".into()),
+            72 => Some("the disk is full".into()),
+            74 => Some("is there a disk in the drive?".into()),
+            _ => None,
+        }
+    }
+    let message = e.find_root_cause().to_string();
+    if message.starts_with("Can't reach the daemon") {
+        Some("is idund running? Check `socket` in the config file".into())
+    } else if message.starts_with("Can't listen for output") {
+        Some("set `run_dir` in the config file to a directory idund sees too".into())
+    } else if message.contains(" within ") {
+        Some("allow longer under [timeouts] in the config file".into())
+    } else {
+        None
+    }
+}
+
+#[test]
+fn error_reports() {
+    let e: failure::Error = RemoteError::from_reply(b"\x02game").unwrap().into();
+    let e = Err::<(), _>(e).at(Context::File("game".into())).at(Context::Device("c:".into()))
+        .at(Context::Command("get".into())).unwrap_err();
+    let report = Report::new(&e);
+    assert_eq!((report.error.as_str(), report.status), ("not found: game", 66));
+    assert_eq!(report.context["drive"], "c:");
+    assert_eq!(report.hint.as_deref(), Some("check the name with `idunsh dir c:`"));
+    assert!(report.to_string().contains("  in: command get, drive c:, file game"));
+    let json = serde_json::to_value(&report).unwrap();
+    assert_eq!(json["context"]["file"], "game");
+    let quiet = Report::new(&ExitStatus(74).into());
+    assert_eq!((quiet.status, quiet.quiet, quiet.hint), (74, true, None));
+    let late = Report::new(&format_err!("The daemon didn't answer within 30s"));
+    assert!(late.hint.unwrap().contains("[timeouts]"));
+}
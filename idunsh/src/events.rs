@@ -0,0 +1,284 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+// Copyright (C) 2026 Brian Holdsworth
+
+//! The daemon's event channel.
+//!
+//! idunsh listens on a socket and asks for events with
+//! `sys.events("<socket path>")`. The daemon connects back and writes one
+//! event per line, e.g. `start game.prg` or `exit 0`. `mount d:` or
+//! `assign e:` tells that a drive was given another image or directory,
+//! by whichever side did it. A running program's heartbeats come with
+//! its redirected output instead; see `protocol`.
+//!
+//! Older daemons have no `sys.events()`. For them a channel made with
+//! `polling` asks for the daemon's state instead, no more often than
+//! `MIN_POLL` for all of idunsh, and turns what changed into the same
+//! events: `start` once a program is found running, with no name,
+//! `heartbeat` while it still is, `exit 0` once it isn't (its status
+//! can't be known), and `mount` for a drive that got another target. A
+//! program that starts and ends between two polls is never found
+//! running, so one started by idunsh is marked with `expect_start`.
+use std::collections::VecDeque;
+use std::io::{BufRead, BufReader};
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::Path;
+use std::process;
+use std::result;
+use std::sync::{mpsc, Arc, Mutex};
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::thread;
+use std::time::{Duration, Instant};
+use crate::cleanup::TempPath;
+
+// Simpler error handling
+type Result<T> = result::Result<T, failure::Error>;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Event {
+    /// A program was started on the Commodore
+    Started(String),
+    /// The running program ended, with its exit status
+    Exited(i32),
+    /// The program is still running, as found by polling
+    Heartbeat,
+    /// A drive was mounted or assigned anew
+    Changed(String),
+    /// Anything this version of idunsh doesn't know about
+    Other(String),
+}
+
+impl Event {
+    pub fn parse(line: &str) -> Event {
+        let (kind, arg) = line.split_once(' ').unwrap_or((line, ""));
+        match kind {
+            "start" => Event::Started(arg.to_string()),
+            "mount" | "assign" if !arg.trim().is_empty() =>
+                Event::Changed(arg.split_whitespace().next().unwrap_or_default().to_string()),
+            "exit" => match arg.trim().parse() {
+                Ok(status) => Event::Exited(status),
+                Err(_) => Event::Other(line.to_string()),
+            },
+            _ => Event::Other(line.to_string()),
+        }
+    }
+}
+
+/// The daemon's state, as seen by polling it.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Poll {
+    /// A program is running
+    Busy,
+    /// Nothing is running; the drives and their targets
+    Idle(Vec<(String, String)>),
+}
+
+/// The least time between polls of the daemon.
+pub const MIN_POLL: Duration = Duration::from_secs(1);
+
+/// Waits until the daemon may be polled again, keeping all polls from
+/// this run `MIN_POLL` apart.
+pub fn pace() {
+    static NEXT: Mutex<Option<Instant>> = Mutex::new(None);
+    let at = match NEXT.lock() {
+        Ok(mut next) => {
+            let at = next.map_or(Instant::now(), |n| n.max(Instant::now()));
+            *next = Some(at + MIN_POLL);
+            at
+        },
+        Err(_) => Instant::now() + MIN_POLL,
+    };
+    thread::sleep(at.saturating_duration_since(Instant::now()));
+}
+
+type Poller = Box<dyn FnMut() -> Result<Poll> + Send>;
+
+struct Polling {
+    poll: Poller,
+    running: bool,
+    drives: Option<Vec<(String, String)>>,
+    pending: VecDeque<Event>,
+    start: Option<mpsc::Receiver<()>>,
+}
+
+impl Polling {
+    fn next_event(&mut self) -> Result<Event> {
+        // A program marked as started is running until a poll finds the
+        // daemon idle, even the first
+        if let Some(start) = self.start.take() {
+            if start.recv().is_ok() {
+                self.running = true;
+                self.pending.push_back(Event::Started(String::new()));
+            }
+        }
+        while self.pending.is_empty() {
+            pace();
+            match (self.poll)()? {
+                Poll::Busy if self.running => self.pending.push_back(Event::Heartbeat),
+                Poll::Busy => {
+                    self.running = true;
+                    self.pending.push_back(Event::Started(String::new()));
+                },
+                Poll::Idle(drives) => {
+                    if let Some(old) = &self.drives {
+                        let changed = drives.iter().filter(|d| !old.contains(d)).map(|(dev, _)| dev.clone());
+                        self.pending.extend(changed.map(Event::Changed));
+                    }
+                    self.drives = Some(drives);
+                    if self.running {
+                        self.running = false;
+                        self.pending.push_back(Event::Exited(0));
+                    }
+                },
+            }
+        }
+        Ok(self.pending.pop_front().unwrap())
+    }
+}
+
+enum Source {
+    Daemon {
+        listener: UnixListener,
+        path: TempPath,
+        reader: Option<BufReader<UnixStream>>,
+    },
+    Polling(Polling),
+}
+
+pub struct EventChannel {
+    source: Source,
+}
+
+impl EventChannel {
+    /// Creates the socket the daemon will send events to in `run_dir`.
+    /// Pass `path()` to `sys.events()` to subscribe.
+    pub fn bind(run_dir: &Path) -> Result<EventChannel> {
+        static NEXT: AtomicU32 = AtomicU32::new(0);
+        let path = run_dir.join(format!("{}-{}.events", process::id(), NEXT.fetch_add(1, Ordering::Relaxed)));
+        let listener = UnixListener::bind(&path)?;
+        let source = Source::Daemon { listener, path: TempPath::new(path), reader: None };
+        Ok(EventChannel { source })
+    }
+    /// Makes events from what `poll` gives, for daemons without
+    /// `sys.events()`.
+    pub fn polling<F>(poll: F) -> EventChannel
+    where F: FnMut() -> Result<Poll> + Send + 'static {
+        let polling = Polling { poll: Box::new(poll), running: false, drives: None, pending: VecDeque::new(), start: None };
+        EventChannel { source: Source::Polling(polling) }
+    }
+    /// For a channel made by polling, waits with the polls until a
+    /// program is sent on the returned channel as started, or it's
+    /// dropped. Its exit is then reported even if it ended before the
+    /// first poll. The daemon's own events need no marker.
+    pub fn expect_start(&mut self) -> mpsc::Sender<()> {
+        let (tx, rx) = mpsc::channel();
+        if let Source::Polling(polling) = &mut self.source {
+            polling.start = Some(rx);
+        }
+        tx
+    }
+    /// The socket to pass to `sys.events()`; empty when polling.
+    pub fn path(&self) -> &str {
+        match &self.source {
+            Source::Daemon { path, .. } => path.path().to_str().unwrap_or_default(),
+            Source::Polling(_) => "",
+        }
+    }
+    /// Blocks until the next event arrives.
+    pub fn next_event(&mut self) -> Result<Event> {
+        let (listener, reader) = match &mut self.source {
+            Source::Daemon { listener, reader, .. } => (listener, reader),
+            Source::Polling(polling) => return polling.next_event(),
+        };
+        if reader.is_none() {
+            let (s, _) = listener.accept()?;
+            *reader = Some(BufReader::new(s));
+        }
+        let mut line = String::new();
+        if reader.as_mut().unwrap().read_line(&mut line)? == 0 {
+            bail!("The daemon closed the event channel")
+        }
+        Ok(Event::parse(line.trim_end()))
+    }
+    /// Reads events on a thread of its own. Every event counts as
+    /// `activity`; the exit status arrives on the returned channel.
+    pub fn listen(mut self, activity: Activity) -> mpsc::Receiver<Result<i32>> {
+        let (tx, rx) = mpsc::channel();
+        thread::spawn(move || {
+            let status = loop {
+                match self.next_event() {
+                    Ok(Event::Exited(status)) => break Ok(status),
+                    Ok(_) => activity.touch(),
+                    Err(e) => break Err(e),
+                }
+            };
+            let _ = tx.send(status);
+        });
+        rx
+    }
+}
+
+impl EventChannel {
+    /// Passes every event on to the returned channel, from a thread of
+    /// its own, until the daemon closes the event channel.
+    pub fn forward(mut self) -> mpsc::Receiver<Event> {
+        let (tx, rx) = mpsc::channel();
+        thread::spawn(move || {
+            while let Ok(event) = self.next_event() {
+                if tx.send(event).is_err() {
+                    break;
+                }
+            }
+        });
+        rx
+    }
+}
+
+/// When the remote side was last heard from, shared between threads.
+#[derive(Clone, Debug)]
+pub struct Activity(Arc<Mutex<Instant>>);
+
+impl Activity {
+    pub fn new() -> Activity {
+        Activity(Arc::new(Mutex::new(Instant::now())))
+    }
+    pub fn touch(&self) {
+        if let Ok(mut t) = self.0.lock() {
+            *t = Instant::now();
+        }
+    }
+    /// Time since the remote side was last heard from.
+    pub fn idle(&self) -> Duration {
+        self.0.lock().map(|t| t.elapsed()).unwrap_or_default()
+    }
+}
+
+#[test]
+fn parse_events() {
+    assert_eq!(Event::parse("exit 3"), Event::Exited(3));
+    assert_eq!(Event::parse("start game.prg"), Event::Started(String::from("game.prg")));
+    assert_eq!(Event::parse("exit"), Event::Other(String::from("exit")));
+    assert_eq!(Event::parse("heartbeat"), Event::Other(String::from("heartbeat")));
+    assert_eq!(Event::parse("mount d: /home/idun/work.d64"), Event::Changed(String::from("d:")));
+    assert_eq!(Event::parse("assign"), Event::Other(String::from("assign")));
+    let drives = |target: &str| Poll::Idle(vec![("c:".into(), "/home".into()), ("d:".into(), target.into())]);
+    let mut polls = vec![drives("a.d64"), Poll::Busy, Poll::Busy, drives("b.d64")].into_iter();
+    let mut ch = EventChannel::polling(move || polls.next().ok_or_else(|| format_err!("done")));
+    let events: Vec<Event> = (0..4).map(|_| ch.next_event().unwrap()).collect();
+    assert_eq!(events, [Event::Started(String::new()), Event::Heartbeat, Event::Changed("d:".into()), Event::Exited(0)]);
+    assert!(ch.next_event().is_err());
+}
+
+#[test]
+fn polled_quick_exit() {
+    // The program was over before the first poll
+    let mut polls = vec![Poll::Idle(vec![])].into_iter();
+    let mut ch = EventChannel::polling(move || polls.next().ok_or_else(|| format_err!("done")));
+    ch.expect_start().send(()).unwrap();
+    assert_eq!(ch.next_event().unwrap(), Event::Started(String::new()));
+    assert_eq!(ch.next_event().unwrap(), Event::Exited(0));
+    // A program that didn't start isn't waited for
+    let mut polls = vec![Poll::Idle(vec![])].into_iter();
+    let mut ch = EventChannel::polling(move || polls.next().ok_or_else(|| format_err!("done")));
+    drop(ch.expect_start());
+    assert!(ch.next_event().is_err());
+}
@@ -0,0 +1,272 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+// Copyright (C) 2026 Brian Holdsworth
+
+//! Files into and out of expansion memory, for `idunsh expmem`.
+//!
+//! Both kinds are reached through C64 memory, a chunk at a time, with
+//! the CPU stopped where the backend can stop it.
+//!
+//! An REU (up to 16 MB) copies between its memory and the C64's by DMA.
+//! Its registers at $df02-$df0a take the C64 address, the REU address
+//! (three bytes, the bank last) and the length, low bytes first; writing
+//! $90 to $df01 then copies from the C64 to the REU, $91 the other way.
+//! The chunks go through a buffer in C64 memory, $c000-$cfff unless
+//! another is given, whose contents are put back afterwards.
+//!
+//! A GeoRAM (up to 4 MB) shows one 256 byte page of its memory at
+//! $de00-$deff: the page within a 16 KB block is chosen at $dffe and
+//! the block at $dfff.
+use std::fs;
+use std::result;
+use clap::{Args, Subcommand, ValueEnum};
+use idun_client::util;
+use crate::progress::Progress;
+use crate::target::Memory;
+
+// Simpler error handling
+type Result<T> = result::Result<T, failure::Error>;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum Kind {
+    Reu,
+    Georam,
+}
+
+const REU_COMMAND: u16 = 0xdf01;
+const REU_ADDRESSES: u16 = 0xdf02;
+const REU_STASH: u8 = 0x90;
+const REU_FETCH: u8 = 0x91;
+const REU_CHUNK: usize = 0x1000;
+
+const GEORAM_WINDOW: u16 = 0xde00;
+const GEORAM_PAGE: u16 = 0xdffe;
+const GEORAM_PAGES: u32 = 64;
+const PAGE: usize = 256;
+
+impl Kind {
+    fn size(self) -> u32 {
+        match self {
+            Kind::Reu => 16 << 20,
+            Kind::Georam => 4 << 20,
+        }
+    }
+}
+
+fn check(kind: Kind, offset: u32, len: usize) -> Result<()> {
+    if offset as u64 + len as u64 > kind.size() as u64 {
+        bail!("{} bytes at ${:06x} go past the end of the largest {:?} (${:06x} bytes)",
+            len, offset, kind, kind.size())
+    }
+    Ok(())
+}
+
+// Runs `transfer` with the CPU stopped, if it can be
+fn frozen<T>(memory: &dyn Memory, transfer: impl FnOnce() -> Result<T>) -> Result<T> {
+    let frozen = memory.freeze()?;
+    let result = transfer();
+    if frozen {
+        memory.thaw()?;
+    }
+    result
+}
+
+/// Writes `data` to expansion memory at `offset`.
+pub fn put(memory: &dyn Memory, kind: Kind, offset: u32, data: &[u8], buffer: u16, progress: Progress) -> Result<()> {
+    check(kind, offset, data.len())?;
+    frozen(memory, || match kind {
+        Kind::Reu => reu(memory, offset, data.len(), buffer, progress, |at, chunk| {
+            memory.write(buffer, &data[at..at + chunk])?;
+            Ok(REU_STASH)
+        }, |_, _| Ok(())),
+        Kind::Georam => georam(memory, offset, data.len(), |window, at, n| {
+            memory.write(window, &data[at..at + n])?;
+            progress.report("put", (at + n) as u64, data.len() as u64);
+            Ok(())
+        }),
+    })
+}
+
+/// Reads `len` bytes of expansion memory at `offset`.
+pub fn get(memory: &dyn Memory, kind: Kind, offset: u32, len: usize, buffer: u16, progress: Progress) -> Result<Vec<u8>> {
+    check(kind, offset, len)?;
+    let mut data = Vec::with_capacity(len);
+    frozen(memory, || match kind {
+        Kind::Reu => reu(memory, offset, len, buffer, progress, |_, _| Ok(REU_FETCH), |_, chunk| {
+            data.extend(memory.read(buffer, chunk)?);
+            Ok(())
+        }),
+        Kind::Georam => georam(memory, offset, len, |window, at, n| {
+            data.extend(memory.read(window, n)?);
+            progress.report("get", (at + n) as u64, len as u64);
+            Ok(())
+        }),
+    })?;
+    Ok(data)
+}
+
+// Copies `len` bytes in chunks through the buffer: `before` readies a
+// chunk and gives the command, `after` takes it
+fn reu<B, A>(memory: &dyn Memory, offset: u32, len: usize, buffer: u16, progress: Progress,
+             mut before: B, mut after: A) -> Result<()>
+where B: FnMut(usize, usize) -> Result<u8>, A: FnMut(usize, usize) -> Result<()> {
+    let chunk = REU_CHUNK.min(0x10000 - buffer as usize);
+    let saved = memory.read(buffer, chunk.min(len))?;
+    let copied = (0..len).step_by(chunk).try_for_each(|at| {
+        let n = chunk.min(len - at);
+        let command = before(at, n)?;
+        let reu = (offset + at as u32).to_le_bytes();
+        let n16 = n as u16;
+        let [blo, bhi] = buffer.to_le_bytes();
+        let [nlo, nhi] = n16.to_le_bytes();
+        // Both addresses count up; no interrupts
+        memory.write(REU_ADDRESSES, &[blo, bhi, reu[0], reu[1], reu[2], nlo, nhi, 0, 0])?;
+        memory.write(REU_COMMAND, &[command])?;
+        after(at, n)?;
+        progress.report(if command == REU_STASH { "put" } else { "get" }, (at + n) as u64, len as u64);
+        Ok(())
+    });
+    memory.write(buffer, &saved)?;
+    copied
+}
+
+// Goes through `len` bytes at `offset` a page at a time, showing each
+// page in the window for `each`, given where in the window the part
+// to copy starts, how far into the `len` bytes it is, and its length
+fn georam<F>(memory: &dyn Memory, offset: u32, len: usize, mut each: F) -> Result<()>
+where F: FnMut(u16, usize, usize) -> Result<()> {
+    let mut at = 0;
+    while at < len {
+        let address = offset + at as u32;
+        let (page, within) = (address / PAGE as u32, address as usize % PAGE);
+        memory.write(GEORAM_PAGE, &[(page % GEORAM_PAGES) as u8, (page / GEORAM_PAGES) as u8])?;
+        let n = (PAGE - within).min(len - at);
+        each(GEORAM_WINDOW + within as u16, at, n)?;
+        at += n;
+    }
+    Ok(())
+}
+
+// A C64 with an REU and a GeoRAM, moving their memory the way they do
+#[cfg(test)]
+struct Expanded {
+    ram: std::sync::Mutex<Vec<u8>>,
+    reu: std::sync::Mutex<Vec<u8>>,
+    georam: std::sync::Mutex<Vec<u8>>,
+}
+
+#[cfg(test)]
+impl crate::target::Target for Expanded {
+    fn name(&self) -> &str {
+        "test"
+    }
+    fn capabilities(&self) -> Result<crate::target::Capabilities> {
+        Ok(Default::default())
+    }
+}
+
+#[cfg(test)]
+impl Expanded {
+    // Where `addr` in the window is in the GeoRAM, if it's in the window
+    fn in_window(&self, addr: u16) -> Option<usize> {
+        let ram = self.ram.lock().unwrap();
+        (GEORAM_WINDOW..GEORAM_WINDOW + PAGE as u16).contains(&addr)
+            .then(|| (ram[0xdfff] as usize * 64 + ram[0xdffe] as usize) * PAGE + (addr - GEORAM_WINDOW) as usize)
+    }
+}
+
+#[cfg(test)]
+impl Memory for Expanded {
+    fn read(&self, addr: u16, len: usize) -> Result<Vec<u8>> {
+        if let Some(at) = self.in_window(addr) {
+            return Ok(self.georam.lock().unwrap()[at..at + len].to_vec())
+        }
+        Ok(self.ram.lock().unwrap()[addr as usize..addr as usize + len].to_vec())
+    }
+    fn write(&self, addr: u16, data: &[u8]) -> Result<()> {
+        if let Some(at) = self.in_window(addr) {
+            self.georam.lock().unwrap()[at..at + data.len()].copy_from_slice(data);
+            return Ok(())
+        }
+        let mut ram = self.ram.lock().unwrap();
+        ram[addr as usize..addr as usize + data.len()].copy_from_slice(data);
+        if addr == REU_COMMAND {
+            let r = ram[0xdf02..0xdf09].to_vec();
+            let c64 = u16::from_le_bytes([r[0], r[1]]) as usize;
+            let reu = u32::from_le_bytes([r[2], r[3], r[4], 0]) as usize;
+            let len = u16::from_le_bytes([r[5], r[6]]) as usize;
+            let mut mem = self.reu.lock().unwrap();
+            match data[0] {
+                REU_STASH => mem[reu..reu + len].copy_from_slice(&ram[c64..c64 + len]),
+                _ => ram[c64..c64 + len].copy_from_slice(&mem[reu..reu + len]),
+            }
+        }
+        Ok(())
+    }
+    fn call(&self, _: u16) -> Result<()> {
+        Ok(())
+    }
+}
+
+#[derive(Subcommand)]
+pub enum ExpmemCommands {
+    /// Write a file into expansion memory
+    Put {
+        file: String,
+        #[command(flatten)]
+        at: Expansion,
+    },
+    /// Read expansion memory into a file
+    Get {
+        file: String,
+        #[arg(long, default_value="65536", value_parser=util::parse_number)]
+        /// Number of bytes to read
+        len: u32,
+        #[command(flatten)]
+        at: Expansion,
+    },
+}
+
+#[derive(Args)]
+pub struct Expansion {
+    #[arg(long, default_value="0", value_parser=util::parse_number)]
+    /// Where in expansion memory, in bytes ($ or 0x for hex)
+    offset: u32,
+    #[arg(long = "type", value_enum, default_value_t=Kind::Reu, value_name="kind")]
+    /// The kind of expansion
+    kind: Kind,
+    #[arg(long, default_value="c000", value_parser=util::parse_addr, value_name="addr")]
+    /// C64 memory REU transfers go through, 4K of it; put back afterwards
+    buffer: u16,
+}
+
+// Copies a file into expansion memory, or out of it
+pub fn run(memory: &dyn Memory, cmd: ExpmemCommands, progress: Progress) -> Result<()> {
+    match cmd {
+        ExpmemCommands::Put { file, at } => {
+            let data = fs::read(&file).map_err(|e| format_err!("{}: {}", file, e))?;
+            put(memory, at.kind, at.offset, &data, at.buffer, progress)
+        },
+        ExpmemCommands::Get { file, len, at } => {
+            let data = get(memory, at.kind, at.offset, len as usize, at.buffer, progress)?;
+            Ok(fs::write(&file, data).map_err(|e| format_err!("{}: {}", file, e))?)
+        },
+    }
+}
+
+#[test]
+fn expansion_memory() {
+    use std::sync::Mutex;
+    let c64 = Expanded { ram: Mutex::new(vec![0xaa; 0x10000]), reu: Mutex::new(vec![0; 0x20000]), georam: Mutex::new(vec![0; 0x20000]) };
+    let data: Vec<u8> = (0..0x2345).map(|i| (i * 7) as u8).collect();
+    for kind in [Kind::Reu, Kind::Georam] {
+        put(&c64, kind, 0x10010, &data, 0xc000, Progress::default()).unwrap();
+        assert_eq!(get(&c64, kind, 0x10010, data.len(), 0xc000, Progress::default()).unwrap(), data);
+    }
+    assert_eq!(c64.reu.lock().unwrap()[0x10010..0x10010 + data.len()], data);
+    // Block 4, page 0 of the GeoRAM holds $10000 on
+    assert_eq!(c64.georam.lock().unwrap()[0x10010..0x10020], data[..16]);
+    assert_eq!(c64.georam.lock().unwrap()[0x10000..0x10010], [0; 16]);
+    // The buffer is left as it was
+    assert!(c64.ram.lock().unwrap()[0xc000..0xd000].iter().all(|b| *b == 0xaa));
+    assert!(put(&c64, Kind::Georam, (4 << 20) - 1, &[1, 2], 0xc000, Progress::default()).is_err());
+}
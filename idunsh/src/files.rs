@@ -0,0 +1,256 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+// Copyright (C) 2026 Brian Holdsworth
+
+//! Files to and from the drives, for `put`, `get`, `edit` and `info`.
+//!
+//! Puts are journaled (see `transfers`), so one cut short is put right by
+//! the next run.
+use std::env;
+use std::fs;
+use std::io::{Write, stdout};
+use std::path::Path;
+use std::process;
+use std::result;
+use std::sync::Arc;
+use tokio::io::AsyncWriteExt;
+use idun_client::cleanup;
+use idun_client::client::{FILE_GET_CMD, FILE_PUT_CMD, accept_redirect, within};
+use idun_client::dos;
+use idun_client::petscii::{self, Charset, Controls};
+use idun_client::protocol;
+use idun_client::runtime;
+use idun_client::util::PetString;
+use crate::capture;
+use crate::catalogs;
+use crate::drive;
+use crate::errors::{At, Context};
+use crate::formats::FileInfo;
+use crate::idun;
+use crate::is_device_path;
+use crate::luasend;
+use crate::profile::{Drive, Profile, Settings};
+use crate::progress::Progress;
+use crate::saves;
+use crate::transfers::{self, Transfer};
+
+// Simpler error handling
+type Result<T> = result::Result<T, failure::Error>;
+
+// Splits `dev:name` into the device, e.g. "c:", and the name
+pub fn split_device(path: &str) -> Result<(String, &str)> {
+    let (dev, name) = path.split_once(':')
+        .ok_or_else(|| format_err!("{} doesn't name a device, e.g. c:{}", path, path))?;
+    Ok((format!("{}:", dev), name))
+}
+
+// Copies a local file to a drive. The daemon connects for the file's
+// contents, which are sent unchanged, and stores them under the PETSCII
+// name.
+pub fn put(file: &str, dest: &str, settings: Settings, progress: Progress) -> Result<()> {
+    let data = fs::read(file).map_err(|e| format_err!("{}: {}", file, e))?;
+    let (dev, name) = split_device(dest)?;
+    store(&dev, &dos::stored_name(file, name), data, settings, progress)
+        .map_err(|e| format_err!("Failed sending {}: {}", file, e))
+}
+
+// Writes `data` to the file `name` on the drive `dev`
+pub fn store(dev: &str, name: &str, data: Vec<u8>, settings: Settings, progress: Progress) -> Result<()> {
+    let transfer = Arc::new(Transfer::begin(dev, name, &data)?);
+    let sent = send_file(dev, name, data, settings, progress, transfer.clone())
+        .at(Context::Phase("sending")).at(Context::File(name.to_string()));
+    match sent {
+        Ok(()) => transfer.finish(),
+        // What the drive took of the file goes, unless it can't be reached;
+        // then the next run puts it right
+        Err(e) if transfer.sent() > 0 => {
+            if scratch(dev, name).is_ok() {
+                let _ = transfer.finish();
+            }
+            Err(e)
+        },
+        Err(e) => {
+            let _ = transfer.finish();
+            Err(e)
+        },
+    }
+}
+
+fn send_file(dev: &str, name: &str, data: Vec<u8>, settings: Settings, progress: Progress,
+             transfer: Arc<Transfer>) -> Result<()> {
+    let mut args = format!("{} ", settings.switches()).into_bytes();
+    args.extend(dos::command_line(dev, PetString::from(name).as_slice()));
+    let (resport, respath, id) = idun().response_listener()?;
+    let encoding = idun().encoding();
+    let writer = runtime::spawn(within(idun().timeouts().transfer, "The file didn't all go", async move {
+        let mut s = accept_redirect(resport).await?;
+        let total = data.len() as u64;
+        let mut sent = 0;
+        for chunk in data.chunks(transfers::CHUNK) {
+            s.write_all(&encoding.encode(chunk)).await?;
+            transfer.chunk_sent()?;
+            sent += chunk.len() as u64;
+            progress.report("put", sent, total);
+        }
+        s.shutdown().await?;
+        Ok(())
+    }));
+    let message = format!("sys.shell({}, {}, {})", FILE_PUT_CMD, protocol::lua_bytes(&args), id);
+    if let Err(e) = drive::with_status(dev, luasend(message)) {
+        writer.abort();
+        return Err(e)
+    }
+    runtime::join(writer)?;
+    drop(respath);
+    Ok(())
+}
+
+// Removes a file from a drive; `name` may end in its type, e.g. ",p"
+pub fn scratch(dev: &str, name: &str) -> Result<()> {
+    let name = name.rsplit_once(',').map_or(name, |(name, _)| name);
+    let status = drive::dos_status(dev, &drive::dos_send(dev, format!("S0:{}", name).to_ascii_uppercase().as_bytes())?)?;
+    if status.is_error() {
+        return Err(status.into())
+    }
+    Ok(())
+}
+
+// Edits the SEQ file `src` as text in $VISUAL or $EDITOR, then replaces
+// the file on the drive if it was changed, and reads it back to check
+pub fn edit(src: &str, charset: Charset, settings: Settings, progress: Progress) -> Result<()> {
+    let (dev, name) = split_device(src)?;
+    let data = fetch(src, settings)?;
+    let base = name.rsplit('/').next().unwrap_or(name);
+    let temp = cleanup::TempPath::new(env::temp_dir().join(format!("idunsh-{}-{}.txt", process::id(), base.replace(',', "."))));
+    let text = petscii::decode(&data, charset, Controls::Names);
+    fs::write(temp.path(), &text)?;
+    let editor = env::var("VISUAL").or_else(|_| env::var("EDITOR")).unwrap_or_else(|_| "vi".to_string());
+    let status = process::Command::new("sh")
+        .arg("-c").arg(format!("{} \"$1\"", editor)).arg("sh").arg(temp.path())
+        .status()?;
+    if !status.success() {
+        bail!("{} failed, {} is left unchanged", editor, src)
+    }
+    let mut edited = fs::read_to_string(temp.path())?;
+    // Editors end the last line, which the file may not have done
+    if data.last() != Some(&0x0d) && edited.ends_with('\n') {
+        edited.pop();
+    }
+    if edited == text {
+        eprintln!("{} is unchanged", src);
+        return Ok(())
+    }
+    let pet = petscii::encode(&edited, charset, true)?;
+    let stored = match name.contains(',') {
+        true => name.to_string(),
+        false => format!("{},s", name),
+    };
+    let scratch = format!("S0:{}", name.split(',').next().unwrap_or(name)).to_ascii_uppercase();
+    let status = drive::dos_status(&dev, &drive::dos_send(&dev, scratch.as_bytes())?)?;
+    if status.is_error() {
+        return Err(status.into())
+    }
+    let keep = || {
+        let kept = env::temp_dir().join(format!("idunsh-{}.txt", base.replace(',', ".")));
+        fs::copy(temp.path(), &kept).map(|_| kept.display().to_string()).unwrap_or_default()
+    };
+    if let Err(e) = store(&dev, &stored, pet.clone(), settings, progress) {
+        bail!("Writing {} failed, the edited text is in {}: {}", src, keep(), e)
+    }
+    if fetch(src, settings)? != pet {
+        bail!("{} reads back differently, the edited text is in {}", src, keep())
+    }
+    Ok(())
+}
+
+// Copies a file from a drive to a local file, byte for byte
+pub fn get(src: &str, file: Option<String>, settings: Settings) -> Result<()> {
+    let data = fetch(src, settings)?;
+    let file = file.unwrap_or_else(|| {
+        let name = src.rsplit(['/', ':']).next().unwrap_or(src);
+        dos::local_name(name.split(',').next().unwrap_or(name))
+    });
+    if file == "-" {
+        stdout().write_all(&data)?;
+        return Ok(stdout().flush()?)
+    }
+    fs::write(&file, data).map_err(|e| format_err!("{}: {}", file, e))?;
+    Ok(())
+}
+
+// The contents of the file `dev:name` on a drive
+pub fn fetch(src: &str, settings: Settings) -> Result<Vec<u8>> {
+    let (dev, name) = split_device(src)?;
+    if name.is_empty() {
+        bail!("No file named in {}, e.g. {}game", src, src)
+    }
+    let mut args = format!("{} ", settings.switches()).into_bytes();
+    args.extend(dos::command_line(&dev, PetString::from(name).as_slice()));
+    capture(|id| drive::with_status(&dev,
+        luasend(format!("sys.shell({}, {}, {})", FILE_GET_CMD, protocol::lua_bytes(&args), id))))
+        .at(Context::Phase("receiving")).at(Context::File(name.to_string()))
+}
+
+// The transfer settings for the drive `dev`, whose model is read from
+// its ROM so that it isn't reset
+pub fn transfer_settings(dev: &str, profile: Profile) -> Result<Settings> {
+    let drive = Drive::identify(|addr| {
+        let [lo, hi] = addr.to_le_bytes();
+        let reply = drive::dos_send(dev, &[b'M', b'-', b'R', lo, hi, 1])?;
+        reply.first().copied().ok_or_else(|| format_err!("{} sent nothing for M-R ${:04X}", dev, addr))
+    })?;
+    Ok(Settings::new(profile, drive))
+}
+
+// Copies the files in `dir` matching `pattern` into the local directory `to`
+pub fn get_matching(dir: &str, pattern: &str, to: Option<String>, settings: Settings) -> Result<()> {
+    let to = to.unwrap_or_else(|| String::from("."));
+    if !Path::new(&to).is_dir() {
+        bail!("{} isn't a directory, which it must be for several files", to)
+    }
+    let names: Vec<String> = catalogs::cached_names(dir)?.into_iter()
+        .filter(|n| !n.ends_with('/') && saves::matches(pattern, n))
+        .collect();
+    if names.is_empty() {
+        bail!("No files in {} match {}", dir, pattern)
+    }
+    // Names only the case tells apart stay apart here too
+    for (name, local) in names.iter().zip(dos::local_names(&names)) {
+        let file = Path::new(&to).join(local).to_string_lossy().into_owned();
+        get(&format!("{}{}", dir, name), Some(file), settings)?;
+    }
+    Ok(())
+}
+
+pub fn info(file: &str, profile: Profile) -> Result<()> {
+    let data = match is_device_path(file) {
+        true => fetch(file, transfer_settings(&split_device(file)?.0, profile)?)?,
+        false => fs::read(file).map_err(|e| format_err!("{}: {}", file, e))?,
+    };
+    let info = FileInfo::identify(file, &data);
+
+    println!("file:   {}", file);
+    println!("type:   {}", info.format);
+    println!("size:   {} bytes ({} blocks)", info.size, info.blocks());
+    if let Some(title) = &info.title {
+        println!("title:  {}", title);
+    }
+    if let Some(addr) = info.load_addr {
+        println!("load:   ${:04x}", addr);
+    }
+    match info.basic_line {
+        Some((line, Some(sys))) => println!("basic:  line {}, SYS {}", line, sys),
+        Some((line, None)) => println!("basic:  line {}", line),
+        None => (),
+    }
+    println!("crc32:  {:08x}", info.crc32);
+    Ok(())
+}
+
+#[test]
+fn device_paths() {
+    assert_eq!(split_device("c:game.prg").unwrap(), ("c:".to_string(), "game.prg"));
+    assert_eq!(split_device("c:").unwrap(), ("c:".to_string(), ""));
+    // Only the first colon ends the device
+    assert_eq!(split_device("8:save:1").unwrap(), ("8:".to_string(), "save:1"));
+    assert_eq!(split_device("game.prg").unwrap_err().to_string(), "game.prg doesn't name a device, e.g. c:game.prg");
+}
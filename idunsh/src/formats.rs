@@ -0,0 +1,179 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+// Copyright (C) 2026 Brian Holdsworth
+use std::fmt;
+use crate::util::{self, PetString};
+use bstr::BString;
+
+/// Commodore file and image formats that idunsh knows about
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Format {
+    Prg,
+    Seq,
+    Crt,
+    Sid,
+    Mod,
+    Tap,
+    T64,
+    D64,
+    D71,
+    D81,
+    G64,
+    Unknown,
+}
+
+impl fmt::Display for Format {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            Format::Prg => "PRG",
+            Format::Seq => "SEQ",
+            Format::Crt => "CRT",
+            Format::Sid => "SID",
+            Format::Mod => "MOD",
+            Format::Tap => "TAP",
+            Format::T64 => "T64",
+            Format::D64 => "D64",
+            Format::D71 => "D71",
+            Format::D81 => "D81",
+            Format::G64 => "G64",
+            Format::Unknown => "unknown",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+/// What can be told about a file from its contents
+#[derive(Debug)]
+pub struct FileInfo {
+    pub format: Format,
+    pub size: usize,
+    /// Where a PRG (or SID tune) loads
+    pub load_addr: Option<u16>,
+    /// First line number of a BASIC program, and the SYS target when
+    /// that line is just a machine code starter
+    pub basic_line: Option<(u16, Option<u16>)>,
+    /// Disk name, cartridge name, or SID title
+    pub title: Option<String>,
+    pub crc32: u32,
+}
+
+impl FileInfo {
+    /// Identifies a file by its signature or size, falling back on the
+    /// file name extension for formats without one (PRG, SEQ).
+    pub fn identify(name: &str, data: &[u8]) -> FileInfo {
+        let ext = name.rsplit_once('.')
+            .map(|(_, e)| e.to_lowercase())
+            .unwrap_or_default();
+        let format = Self::detect(&ext, data);
+        let mut info = FileInfo {
+            format,
+            size: data.len(),
+            load_addr: None,
+            basic_line: None,
+            title: None,
+            crc32: util::crc32(data),
+        };
+        match format {
+            Format::Prg if data.len() >= 2 => {
+                let addr = u16::from_le_bytes([data[0], data[1]]);
+                info.load_addr = Some(addr);
+                info.basic_line = basic_line(addr, &data[2..]);
+            },
+            Format::Sid if data.len() >= 0x76 => {
+                let addr = u16::from_be_bytes([data[8], data[9]]);
+                let offset = u16::from_be_bytes([data[6], data[7]]) as usize;
+                info.load_addr = if addr == 0 && data.len() > offset + 1 {
+                    Some(u16::from_le_bytes([data[offset], data[offset + 1]]))
+                } else {
+                    Some(addr)
+                };
+                info.title = text(&data[0x16..0x36]);
+            },
+            Format::Crt if data.len() >= 0x40 => info.title = text(&data[0x20..0x40]),
+            Format::D64 | Format::D71 => info.title = pet_text(&data[0x16590..0x165a0]),
+            Format::D81 => info.title = pet_text(&data[0x61804..0x61814]),
+            _ => (),
+        }
+        info
+    }
+    fn detect(ext: &str, data: &[u8]) -> Format {
+        const D64_SIZES: [usize; 4] = [174848, 175531, 196608, 197376];
+        const D71_SIZES: [usize; 2] = [349696, 351062];
+        const D81_SIZES: [usize; 2] = [819200, 822400];
+        if data.starts_with(b"C64 CARTRIDGE") {
+            Format::Crt
+        } else if data.starts_with(b"PSID") || data.starts_with(b"RSID") {
+            Format::Sid
+        } else if data.starts_with(b"C64-TAPE-RAW") {
+            Format::Tap
+        } else if data.starts_with(b"C64 tape image") || data.starts_with(b"C64S tape") {
+            Format::T64
+        } else if data.starts_with(b"GCR-1541") {
+            Format::G64
+        } else if D64_SIZES.contains(&data.len()) {
+            Format::D64
+        } else if D71_SIZES.contains(&data.len()) {
+            Format::D71
+        } else if D81_SIZES.contains(&data.len()) {
+            Format::D81
+        } else if data.len() > 1084 && [&b"M.K."[..], b"M!K!", b"FLT4", b"4CHN", b"6CHN", b"8CHN"]
+                .contains(&&data[1080..1084]) {
+            Format::Mod
+        } else {
+            match ext {
+                "prg" | "" if data.len() > 2 => Format::Prg,
+                "seq" => Format::Seq,
+                _ => Format::Unknown,
+            }
+        }
+    }
+    /// Size in 254 byte blocks, as a directory listing shows it
+    pub fn blocks(&self) -> usize {
+        self.size.div_ceil(254)
+    }
+}
+
+// First line of a BASIC program at one of the usual start addresses
+fn basic_line(addr: u16, body: &[u8]) -> Option<(u16, Option<u16>)> {
+    const SYS: u8 = 0x9e;
+    if ![0x0801, 0x1001, 0x1201, 0x1c01, 0x4001].contains(&addr) || body.len() < 5 {
+        return None;
+    }
+    let line = u16::from_le_bytes([body[2], body[3]]);
+    let stmt = &body[4..];
+    let sys = if stmt.first() == Some(&SYS) {
+        let digits: String = stmt[1..].iter()
+            .skip_while(|c| **c == b' ' || **c == b'(')
+            .take_while(|c| c.is_ascii_digit())
+            .map(|c| *c as char)
+            .collect();
+        digits.parse().ok()
+    } else {
+        None
+    };
+    Some((line, sys))
+}
+
+// NUL padded ASCII text from a header field
+fn text(field: &[u8]) -> Option<String> {
+    let end = field.iter().position(|c| *c == 0).unwrap_or(field.len());
+    let s = String::from_utf8_lossy(&field[..end]).trim().to_string();
+    (!s.is_empty()).then_some(s)
+}
+
+// Shifted-space ($A0) padded PETSCII text from a disk header
+fn pet_text(field: &[u8]) -> Option<String> {
+    let end = field.iter().position(|c| *c == 0xa0).unwrap_or(field.len());
+    let s = String::from(PetString::new(&BString::from(&field[..end])));
+    (!s.is_empty()).then_some(s)
+}
+
+#[test]
+fn identify_prg() {
+    // 10 SYS2061
+    let prg = b"\x01\x08\x0b\x08\x0a\x00\x9e2061\x00\x00\x00";
+    let info = FileInfo::identify("game.prg", prg);
+    assert_eq!(info.format, Format::Prg);
+    assert_eq!(info.load_addr, Some(0x0801));
+    assert_eq!(info.basic_line, Some((10, Some(2061))));
+    assert_eq!(info.blocks(), 1);
+}
@@ -0,0 +1,185 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+// Copyright (C) 2026 Brian Holdsworth
+
+//! Frames of the Ultimate's video stream, kept as PNG files and
+//! compared with them for `idunsh test`.
+//!
+//! Each UDP packet of the video stream has a 12 byte header: sequence
+//! number, frame number and line number, two bytes each, with bit 15 of
+//! the line number set in the last packet of a frame; then the pixels
+//! per line in two bytes, the lines per packet and bits per pixel in one
+//! byte each, and two bytes of encoding, 0 for none. The lines follow,
+//! two pixels a byte, the left one in the low nibble, each pixel a VIC
+//! color. A frame is only kept when every line of it arrived.
+use std::fs::File;
+use std::io::{BufReader, BufWriter, Write};
+use std::net::UdpSocket;
+use std::result;
+use std::time::{Duration, Instant};
+
+// Simpler error handling
+type Result<T> = result::Result<T, failure::Error>;
+
+/// The port the Ultimate sends its video stream to by default
+pub const VIDEO_PORT: u16 = 11000;
+
+const HEADER: usize = 12;
+const LAST_LINE: u16 = 0x8000;
+
+/// The VIC colors as RGB, after Pepto's measurements of a PAL machine
+const PALETTE: [[u8; 3]; 16] = [
+    [0x00, 0x00, 0x00], [0xff, 0xff, 0xff], [0x68, 0x37, 0x2b], [0x70, 0xa4, 0xb2],
+    [0x6f, 0x3d, 0x86], [0x58, 0x8d, 0x43], [0x35, 0x28, 0x79], [0xb8, 0xc7, 0x6f],
+    [0x6f, 0x4f, 0x25], [0x43, 0x39, 0x00], [0x9a, 0x67, 0x59], [0x44, 0x44, 0x44],
+    [0x6c, 0x6c, 0x6c], [0x9a, 0xd2, 0x84], [0x6c, 0x5e, 0xb5], [0x95, 0x95, 0x95],
+];
+
+/// An image as RGB, three bytes a pixel.
+#[derive(Debug, PartialEq, Eq)]
+pub struct Image {
+    pub width: usize,
+    pub height: usize,
+    pub rgb: Vec<u8>,
+}
+
+/// Puts the packets of the video stream together into frames.
+#[derive(Default)]
+pub struct Assembler {
+    frame: Option<u16>,
+    width: usize,
+    // The colors of each line that arrived, by line number
+    lines: Vec<Option<Vec<u8>>>,
+}
+
+impl Assembler {
+    /// Takes a packet, giving the frame it completes, if any.
+    pub fn add(&mut self, packet: &[u8]) -> Result<Option<Image>> {
+        if packet.len() < HEADER {
+            bail!("A video packet was too short")
+        }
+        let word = |i: usize| u16::from_le_bytes([packet[i], packet[i + 1]]);
+        let (frame, line) = (word(2), word(4));
+        let (width, count, bits) = (word(6) as usize, packet[8] as usize, packet[9]);
+        if bits != 4 || word(10) != 0 {
+            bail!("The video stream is encoded in a way idunsh doesn't know")
+        }
+        if self.frame != Some(frame) || self.width != width {
+            // A frame joined partway is incomplete from the start
+            let joined = line & !LAST_LINE != 0;
+            *self = Assembler { frame: Some(frame), width, lines: vec![] };
+            if joined {
+                self.lines.push(None);
+            }
+        }
+        let first = (line & !LAST_LINE) as usize;
+        for (i, pixels) in packet[HEADER..].chunks(width / 2).take(count).enumerate() {
+            if self.lines.len() <= first + i {
+                self.lines.resize(first + i + 1, None);
+            }
+            let colors = pixels.iter().flat_map(|b| [b & 0xf, b >> 4]).collect();
+            self.lines[first + i] = Some(colors);
+        }
+        if line & LAST_LINE == 0 {
+            return Ok(None)
+        }
+        let lines = std::mem::take(&mut self.lines);
+        self.frame = None;
+        let Some(lines) = lines.into_iter().collect::<Option<Vec<Vec<u8>>>>() else { return Ok(None) };
+        let rgb = lines.iter().flatten().flat_map(|c| PALETTE[*c as usize]).collect();
+        Ok(Some(Image { width, height: lines.len(), rgb }))
+    }
+}
+
+impl Image {
+    /// The next whole frame sent to `socket`.
+    pub fn receive(socket: &UdpSocket, timeout: Duration) -> Result<Image> {
+        let start = Instant::now();
+        let mut assembler = Assembler::default();
+        let mut buf = [0u8; 2048];
+        loop {
+            let left = timeout.checked_sub(start.elapsed())
+                .filter(|left| !left.is_zero())
+                .ok_or_else(|| format_err!("No whole video frame arrived in {}s", timeout.as_secs()))?;
+            socket.set_read_timeout(Some(left))?;
+            let n = match socket.recv(&mut buf) {
+                Ok(n) => n,
+                Err(e) if e.kind() == std::io::ErrorKind::WouldBlock || e.kind() == std::io::ErrorKind::TimedOut => continue,
+                Err(e) => return Err(e.into()),
+            };
+            if let Some(image) = assembler.add(&buf[..n])? {
+                return Ok(image)
+            }
+        }
+    }
+    pub fn load_png(path: &str) -> Result<Image> {
+        let file = File::open(path).map_err(|e| format_err!("{}: {}", path, e))?;
+        let mut decoder = png::Decoder::new(BufReader::new(file));
+        decoder.set_transformations(png::Transformations::normalize_to_color8());
+        let mut reader = decoder.read_info().map_err(|e| format_err!("{}: {}", path, e))?;
+        let mut buf = vec![0; reader.output_buffer_size()];
+        let info = reader.next_frame(&mut buf).map_err(|e| format_err!("{}: {}", path, e))?;
+        let bytes = &buf[..info.buffer_size()];
+        let rgb = match info.color_type {
+            png::ColorType::Rgb => bytes.to_vec(),
+            png::ColorType::Rgba => bytes.chunks(4).flat_map(|p| [p[0], p[1], p[2]]).collect(),
+            png::ColorType::Grayscale => bytes.iter().flat_map(|g| [*g; 3]).collect(),
+            png::ColorType::GrayscaleAlpha => bytes.chunks(2).flat_map(|p| [p[0]; 3]).collect(),
+            png::ColorType::Indexed => bail!("{}: the palette of the image couldn't be read", path),
+        };
+        Ok(Image { width: info.width as usize, height: info.height as usize, rgb })
+    }
+    pub fn save_png(&self, path: &str) -> Result<()> {
+        let file = File::create(path).map_err(|e| format_err!("{}: {}", path, e))?;
+        self.write_png(BufWriter::new(file)).map_err(|e| format_err!("{}: {}", path, e))
+    }
+    /// The image as a PNG file would hold it.
+    pub fn png(&self) -> Result<Vec<u8>> {
+        let mut data = vec![];
+        self.write_png(&mut data)?;
+        Ok(data)
+    }
+    fn write_png(&self, w: impl Write) -> result::Result<(), png::EncodingError> {
+        let mut encoder = png::Encoder::new(w, self.width as u32, self.height as u32);
+        encoder.set_color(png::ColorType::Rgb);
+        encoder.set_depth(png::BitDepth::Eight);
+        encoder.write_header()?.write_image_data(&self.rgb)
+    }
+    /// The percentage of pixels that differ from `other`, which must be
+    /// the same size.
+    pub fn differing(&self, other: &Image) -> Result<f64> {
+        if (self.width, self.height) != (other.width, other.height) {
+            bail!("the frame is {}x{}, the image {}x{}", self.width, self.height, other.width, other.height)
+        }
+        let pixels = self.rgb.chunks(3).zip(other.rgb.chunks(3));
+        let differing = pixels.filter(|(a, b)| a != b).count();
+        Ok(100.0 * differing as f64 / (self.width * self.height).max(1) as f64)
+    }
+}
+
+#[cfg(test)]
+fn packet(frame: u16, line: u16, width: u16, colors: &[u8]) -> Vec<u8> {
+    let mut p = vec![0, 0];
+    for word in [frame, line, width] {
+        p.extend_from_slice(&word.to_le_bytes());
+    }
+    p.extend_from_slice(&[(colors.len() / width as usize) as u8, 4, 0, 0]);
+    p.extend(colors.chunks(2).map(|c| c[0] | c[1] << 4));
+    p
+}
+
+#[test]
+fn video_frames() {
+    let mut assembler = Assembler::default();
+    // The end of a frame joined partway is dropped
+    assert_eq!(assembler.add(&packet(1, 2 | LAST_LINE, 4, &[1; 8])).unwrap(), None);
+    assert_eq!(assembler.add(&packet(2, 0, 4, &[0, 1, 2, 3, 1, 1, 1, 1])).unwrap(), None);
+    let image = assembler.add(&packet(2, 2 | LAST_LINE, 4, &[0; 8])).unwrap().unwrap();
+    assert_eq!((image.width, image.height), (4, 4));
+    assert_eq!(image.rgb[..9], [0, 0, 0, 0xff, 0xff, 0xff, 0x68, 0x37, 0x2b]);
+    let mut other = Image { width: 4, height: 4, rgb: image.rgb.clone() };
+    assert_eq!(image.differing(&other).unwrap(), 0.0);
+    other.rgb[0] = 1;
+    assert_eq!(image.differing(&other).unwrap(), 6.25);
+    assert!(image.differing(&Image { width: 2, height: 2, rgb: vec![0; 12] }).is_err());
+    assert!(assembler.add(&[0; 4]).is_err());
+}
@@ -0,0 +1,115 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+// Copyright (C) 2026 Brian Holdsworth
+
+//! Just enough FTP to put a file on the Ultimate, whose FTP server takes
+//! anonymous logins, for uploads its web service has no endpoint for.
+//!
+//! Replies are a three digit code, then a space, or a dash on each line
+//! of a reply that goes on, until a line with the code and a space. The
+//! file itself goes over a second connection, to the address and port
+//! the server gives in its answer to PASV: `227 Entering Passive Mode
+//! (h1,h2,h3,h4,p1,p2)`, the port being p1 * 256 + p2.
+use std::io::{BufRead, BufReader, Write};
+use std::net::{Ipv4Addr, SocketAddrV4, TcpStream};
+use std::result;
+use std::time::Duration;
+
+// Simpler error handling
+type Result<T> = result::Result<T, failure::Error>;
+
+const TIMEOUT: Duration = Duration::from_secs(30);
+
+pub struct Ftp {
+    control: BufReader<TcpStream>,
+}
+
+impl Ftp {
+    /// Logs in anonymously to the FTP server on `host`.
+    pub fn connect(host: &str) -> Result<Ftp> {
+        let stream = TcpStream::connect((host, 21)).map_err(|e| format_err!("FTP to {}: {}", host, e))?;
+        stream.set_read_timeout(Some(TIMEOUT))?;
+        let mut ftp = Ftp { control: BufReader::new(stream) };
+        ftp.expect(&[220])?;
+        if ftp.command("USER anonymous", &[230, 331])? == 331 {
+            ftp.command("PASS idunsh@", &[230])?;
+        }
+        ftp.command("TYPE I", &[200])?;
+        Ok(ftp)
+    }
+    /// Stores `data` as the file at `path`.
+    pub fn put(&mut self, path: &str, data: &[u8]) -> Result<()> {
+        self.send("PASV")?;
+        let (code, text) = reply(&mut self.control)?;
+        if code != 227 {
+            bail!("The FTP server refused passive mode: {} {}", code, text)
+        }
+        let addr = passive_addr(&text).ok_or_else(|| format_err!("The FTP server gave no address: {}", text))?;
+        let mut conn = TcpStream::connect(addr)?;
+        self.command(&format!("STOR {}", path), &[125, 150])?;
+        conn.write_all(data)?;
+        drop(conn);
+        self.expect(&[226, 250])?;
+        Ok(())
+    }
+    fn send(&mut self, line: &str) -> Result<()> {
+        let stream = self.control.get_mut();
+        stream.write_all(line.as_bytes())?;
+        Ok(stream.write_all(b"\r\n")?)
+    }
+    // Sends a command, giving the code of its reply if it's one of `ok`
+    fn command(&mut self, line: &str, ok: &[u16]) -> Result<u16> {
+        self.send(line)?;
+        self.expect(ok).map_err(|e| format_err!("{}: {}", line.split(' ').next().unwrap_or(line), e))
+    }
+    fn expect(&mut self, ok: &[u16]) -> Result<u16> {
+        match reply(&mut self.control)? {
+            (code, _) if ok.contains(&code) => Ok(code),
+            (code, text) => bail!("the FTP server answered {} {}", code, text),
+        }
+    }
+}
+
+// The code and text of the next reply
+fn reply<R: BufRead>(r: &mut R) -> Result<(u16, String)> {
+    let mut text = String::new();
+    loop {
+        let mut line = String::new();
+        if r.read_line(&mut line)? == 0 {
+            bail!("The FTP server closed the connection")
+        }
+        let line = line.trim_end();
+        let code = line.get(..3).and_then(|c| c.parse::<u16>().ok());
+        let last = line.as_bytes().get(3) != Some(&b'-');
+        match code {
+            Some(code) if last => {
+                text.push_str(line.get(4..).unwrap_or_default());
+                return Ok((code, text))
+            },
+            _ => {
+                text.push_str(line.get(4..).unwrap_or(line));
+                text.push(' ');
+            },
+        }
+    }
+}
+
+// The address in a reply to PASV
+fn passive_addr(text: &str) -> Option<SocketAddrV4> {
+    let inside = text.split_once('(')?.1.split_once(')')?.0;
+    let n: Vec<u8> = inside.split(',').map(|n| n.trim().parse().ok()).collect::<Option<_>>()?;
+    match n.as_slice() {
+        [a, b, c, d, p1, p2] => Some(SocketAddrV4::new(Ipv4Addr::new(*a, *b, *c, *d), *p1 as u16 * 256 + *p2 as u16)),
+        _ => None,
+    }
+}
+
+#[test]
+fn ftp_replies() {
+    let mut r = std::io::Cursor::new("220-Welcome\r\n220 Ultimate FTP\r\n227 Entering Passive Mode (192,168,1,64,4,1)\r\n");
+    assert_eq!(reply(&mut r).unwrap(), (220, String::from("Welcome Ultimate FTP")));
+    let (code, text) = reply(&mut r).unwrap();
+    assert_eq!(code, 227);
+    assert_eq!(passive_addr(&text), Some(SocketAddrV4::new(Ipv4Addr::new(192, 168, 1, 64), 1025)));
+    assert!(reply(&mut r).is_err());
+    assert_eq!(passive_addr("(1,2,3)"), None);
+}
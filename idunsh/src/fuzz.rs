@@ -0,0 +1,174 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+// Copyright (C) 2026 Brian Holdsworth
+
+//! Random input for `idunsh fuzz`, and the signs that it broke the
+//! program under test.
+//!
+//! Input is drawn from the keys a player reaches for: letters, digits,
+//! space and punctuation, the cursor keys, RETURN, DEL and the function
+//! keys. It is kept as text in the form `idunsh keys` reads, e.g.
+//! `a{f1}{return}`, so a sequence that broke something can be typed
+//! again. The same seed gives the same sequence.
+//!
+//! A canary is a byte that must keep its value, such as the first byte
+//! of the program's main loop; a crash that overwrites memory or drops
+//! back to BASIC is likely to change it.
+use std::fs;
+use std::io::Write;
+use std::result;
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
+use idun_client::petscii::{self, Charset, Layout};
+use idun_client::util;
+use crate::Syscommands;
+use crate::c64ultimate::C64Ultimate;
+use crate::errors::ExitStatus;
+use crate::saves;
+use crate::smoke;
+use crate::target::{self, Memory, Target};
+
+// Simpler error handling
+type Result<T> = result::Result<T, failure::Error>;
+
+const KEYS: &[&str] = &[
+    "{return}", "{up}", "{down}", "{left}", "{right}", "{delete}", "{home}",
+    "{f1}", "{f2}", "{f3}", "{f4}", "{f5}", "{f6}", "{f7}", "{f8}",
+];
+const CHARACTERS: &str = "abcdefghijklmnopqrstuvwxyz0123456789 ,.:;+-*/=@";
+
+/// A xorshift generator; random enough to fuzz with, and repeatable.
+pub struct Rng {
+    seed: u64,
+    state: u64,
+}
+
+impl Rng {
+    /// A generator started from `seed`, or from the clock if there's none.
+    pub fn new(seed: Option<u64>) -> Rng {
+        let seed = seed.unwrap_or_else(|| {
+            SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_nanos() as u64).unwrap_or(1)
+        });
+        Rng { seed, state: seed.max(1) }
+    }
+    /// The seed to give for the same sequence again.
+    pub fn seed(&self) -> u64 {
+        self.seed
+    }
+    fn next(&mut self) -> u64 {
+        self.state ^= self.state << 13;
+        self.state ^= self.state >> 7;
+        self.state ^= self.state << 17;
+        self.state
+    }
+    /// `n` random keys as text, e.g. "q{f1}7".
+    pub fn keys(&mut self, n: usize) -> String {
+        let mut text = String::new();
+        for _ in 0..n {
+            let i = (self.next() % (KEYS.len() + CHARACTERS.len()) as u64) as usize;
+            match KEYS.get(i) {
+                Some(key) => text.push_str(key),
+                None => text.push(CHARACTERS.as_bytes()[i - KEYS.len()] as char),
+            }
+        }
+        text
+    }
+    /// A number from 1 to `max`.
+    pub fn count(&mut self, max: usize) -> usize {
+        1 + (self.next() % max as u64) as usize
+    }
+}
+
+/// A byte of memory that must keep its value.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Canary {
+    pub addr: u16,
+    pub value: u8,
+}
+
+impl Canary {
+    /// Reads a canary given as addr=value in hex, e.g. 0810=a9.
+    pub fn parse(s: &str) -> result::Result<Canary, String> {
+        let (addr, value) = s.split_once('=').ok_or_else(|| format!("'{}' isn't addr=value, e.g. 0810=a9", s))?;
+        let value = value.strip_prefix('$').unwrap_or(value);
+        Ok(Canary {
+            addr: util::parse_addr(addr)?,
+            value: u8::from_str_radix(value, 16).map_err(|_| format!("invalid byte '{}'", value))?,
+        })
+    }
+    /// What's wrong, if the byte at the canary's address is `found`.
+    pub fn check(&self, found: u8) -> Option<String> {
+        (found != self.value).then(|| format!("${:04x} changed from ${:02x} to ${:02x}", self.addr, self.value, found))
+    }
+}
+
+// Types random keys until the program shows a sign of a crash or time
+// is up, then gives the keys that did it
+pub fn run(c64u: &C64Ultimate, cmd: Syscommands, charset: Charset, layout: Layout) -> Result<()> {
+    let Syscommands::Fuzz { duration, canary: canaries, screen, seed, log, .. } = cmd else { return Ok(()) };
+    if !c64u.capabilities()?.memory_access {
+        return Err(target::unsupported(c64u, "Memory access"))
+    }
+    let patterns = match screen.is_empty() {
+        true => vec![String::from("ready."), String::from("?*error")],
+        false => screen,
+    };
+    let shown = || -> Result<Vec<&String>> {
+        let lines = smoke::screen_text(&c64u.read(0x0400, smoke::COLUMNS * smoke::ROWS)?);
+        Ok(patterns.iter().filter(|p| lines.iter().any(|l| saves::matches(&format!("*{}*", p), l))).collect())
+    };
+    // What's on the screen before is no sign of a crash
+    let before = shown()?;
+    for c in &canaries {
+        if let Some(problem) = c.check(c64u.read(c.addr, 1)?[0]) {
+            bail!("The canary doesn't hold before fuzzing: {}", problem)
+        }
+    }
+    let mut log = log.map(|file| fs::File::create(&file).map_err(|e| format_err!("{}: {}", file, e))).transpose()?;
+    let mut rng = Rng::new(seed);
+    eprintln!("Fuzzing with seed {} for {}s, Ctrl-C to stop", rng.seed(), duration.as_secs());
+    let (mut typed, mut count) = (String::new(), 0);
+    let started = Instant::now();
+    while started.elapsed() < duration {
+        let n = rng.count(10);
+        let keys = rng.keys(n);
+        typed.push_str(&keys);
+        count += n;
+        if let Some(file) = &mut log {
+            file.write_all(keys.as_bytes())?;
+        }
+        let problem = match c64u.type_text(&petscii::keys(&keys, charset, layout)?) {
+            Err(e) => Some(e.to_string()),
+            Ok(()) => {
+                let mut problems: Vec<String> = canaries.iter()
+                    .map(|c| Ok(c.check(c64u.read(c.addr, 1)?[0])))
+                    .filter_map(Result::transpose)
+                    .collect::<Result<_>>()?;
+                problems.extend(shown()?.into_iter().filter(|p| !before.contains(p)).map(|p| format!("{:?} on the screen", p)));
+                (!problems.is_empty()).then(|| problems.join(", "))
+            },
+        };
+        if let Some(problem) = problem {
+            println!("Crashed after {} keys: {}", count, problem);
+            println!("Seed {}; the keys were:", rng.seed());
+            println!("{}", typed);
+            return Err(ExitStatus(1).into())
+        }
+    }
+    eprintln!("No crash after {} keys", count);
+    Ok(())
+}
+
+#[test]
+fn fuzz_input() {
+    let (mut a, mut b) = (Rng::new(Some(42)), Rng::new(Some(42)));
+    let keys = a.keys(50);
+    assert_eq!(keys, b.keys(50));
+    use idun_client::petscii::{self, Charset, Layout};
+    let all = KEYS.concat() + CHARACTERS;
+    assert!(petscii::keys(&all, Charset::Upper, Layout::Us).is_ok());
+    assert!((0..100).map(|_| a.count(10)).all(|n| (1..=10).contains(&n)));
+    let canary = Canary::parse("0810=$a9").unwrap();
+    assert_eq!(canary, Canary { addr: 0x0810, value: 0xa9 });
+    assert_eq!(canary.check(0xa9), None);
+    assert_eq!(canary.check(0).unwrap(), "$0810 changed from $a9 to $00");
+    assert!(Canary::parse("0810").is_err() && Canary::parse("0810=100").is_err());
+}
@@ -0,0 +1,51 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+// Copyright (C) 2026 Brian Holdsworth
+use std::io::{self, Write};
+use crate::labels::Labels;
+use crate::util;
+
+/// Writes memory as a canonical hexdump: address, hex bytes in groups of
+/// eight, the bytes as text, then the names of any labels on that row.
+/// Bytes are read as PETSCII, or as screen codes for screen memory.
+pub fn hexdump<W: Write>(out: &mut W, start: u16, data: &[u8], width: usize,
+                         screen_codes: bool, labels: &Labels) -> io::Result<()> {
+    let width = width.max(1);
+    for (row, chunk) in data.chunks(width).enumerate() {
+        let addr = start.wrapping_add((row * width) as u16);
+        let mut line = format!("{:04x} ", addr);
+        for i in 0..width {
+            if i % 8 == 0 {
+                line.push(' ');
+            }
+            match chunk.get(i) {
+                Some(b) => line.push_str(&format!("{:02x} ", b)),
+                None => line.push_str("   "),
+            }
+        }
+        line.push('|');
+        for b in chunk {
+            let p = if screen_codes { util::screen_to_pet(*b) } else { *b };
+            let a = util::pet_to_ascii(&[p])[0];
+            line.push(if a.is_ascii_graphic() || a == b' ' { a as char } else { '.' });
+        }
+        line.push('|');
+        let names: Vec<&str> = (0..chunk.len())
+            .flat_map(|i| labels.at(addr.wrapping_add(i as u16)))
+            .map(|s| s.as_str())
+            .collect();
+        if !names.is_empty() {
+            line.push_str("  ");
+            line.push_str(&names.join(" "));
+        }
+        writeln!(out, "{}", line)?;
+    }
+    Ok(())
+}
+
+#[test]
+fn hexdump_rows() {
+    let labels = Labels::parse("al C:c002 .msg\n");
+    let mut out = vec![];
+    hexdump(&mut out, 0xc000, b"\x00\x01\xc8\x49", 4, false, &labels).unwrap();
+    assert_eq!(String::from_utf8(out).unwrap(), "c000  00 01 c8 49 |..Hi|  msg\n");
+}
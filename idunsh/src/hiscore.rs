@@ -0,0 +1,159 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+// Copyright (C) 2026 Brian Holdsworth
+
+//! Game profiles naming where a game keeps its scores, for `idunsh
+//! hiscore`. A profile is a small TOML file, kept in
+//! `~/.config/idunsh/hiscore/<game>.toml` or given by path:
+//!
+//! ```toml
+//! game = "Boulder Dash"
+//!
+//! [[score]]
+//! name = "score"
+//! addr = "$03f3"
+//! len = 3
+//! encoding = "bcd"
+//!
+//! [[score]]
+//! name = "initials"
+//! addr = "$0450"
+//! len = 3
+//! encoding = "screen"
+//! ```
+//!
+//! `encoding` is one of `bcd` (two digits a byte, the highest first),
+//! `bcd-le` (the lowest first), `binary` (little-endian), `binary-be`,
+//! `screen` (screen codes, as in screen memory) or `petscii`. The first
+//! four give numbers, multiplied by `multiply` for games that show a
+//! fixed 0 after the score; the others give text, in capitals.
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::result;
+use serde::{de, Deserialize, Deserializer};
+use serde_json::{json, Value};
+use idun_client::util;
+use crate::target::Memory;
+
+// Simpler error handling
+type Result<T> = result::Result<T, failure::Error>;
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct GameProfile {
+    pub game: String,
+    #[serde(rename = "score")]
+    pub scores: Vec<Score>,
+}
+
+/// One value kept in memory, such as a score or a player's initials.
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Score {
+    pub name: String,
+    #[serde(deserialize_with = "addr")]
+    pub addr: u16,
+    pub len: usize,
+    pub encoding: Encoding,
+    #[serde(default = "default_multiply")]
+    pub multiply: u64,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Encoding {
+    Bcd,
+    BcdLe,
+    Binary,
+    BinaryBe,
+    Screen,
+    Petscii,
+}
+
+fn addr<'de, D: Deserializer<'de>>(d: D) -> result::Result<u16, D::Error> {
+    util::parse_addr(&String::deserialize(d)?).map_err(de::Error::custom)
+}
+fn default_multiply() -> u64 {
+    1
+}
+
+impl GameProfile {
+    /// Reads the profile at `game` if there's such a file, or else the
+    /// one of that name in the profile directory.
+    pub fn load(game: &str) -> Result<GameProfile> {
+        let path = match Path::new(game).is_file() {
+            true => PathBuf::from(game),
+            false => dirs::config_dir()
+                .ok_or_else(|| format_err!("No config directory"))?
+                .join("idunsh")
+                .join("hiscore")
+                .join(format!("{}.toml", game)),
+        };
+        let text = fs::read_to_string(&path).map_err(|e| format_err!("{}: {}", path.display(), e))?;
+        Self::parse(&text).map_err(|e| format_err!("{}: {}", path.display(), e))
+    }
+    fn parse(text: &str) -> Result<GameProfile> {
+        let profile: GameProfile = toml::from_str(text)?;
+        if let Some(s) = profile.scores.iter().find(|s| s.len == 0 || s.addr as usize + s.len > 0x10000) {
+            bail!("{} doesn't fit in memory", s.name)
+        }
+        Ok(profile)
+    }
+    /// Reads each score from memory, giving a JSON object such as
+    /// `{"game": "Boulder Dash", "scores": {"score": 1250}}`.
+    pub fn read(&self, memory: &dyn Memory) -> Result<Value> {
+        let mut scores = serde_json::Map::new();
+        for s in &self.scores {
+            let value = s.decode(&memory.read(s.addr, s.len)?)?;
+            scores.insert(s.name.clone(), value);
+        }
+        Ok(json!({ "game": self.game, "scores": scores }))
+    }
+}
+
+impl Score {
+    /// The value of the bytes read from memory.
+    pub fn decode(&self, bytes: &[u8]) -> Result<Value> {
+        let number = |digits: &mut dyn Iterator<Item=&u8>, base: u64| -> Result<Value> {
+            let mut n: u64 = 0;
+            for b in digits {
+                let digit = match base {
+                    100 if b >> 4 > 9 || b & 0xf > 9 => bail!("{} holds ${:02x}, which isn't BCD", self.name, b),
+                    100 => (b >> 4) as u64 * 10 + (b & 0xf) as u64,
+                    _ => *b as u64,
+                };
+                n = n.checked_mul(base).and_then(|n| n.checked_add(digit))
+                    .ok_or_else(|| format_err!("{} is too large", self.name))?;
+            }
+            Ok(json!(n.saturating_mul(self.multiply)))
+        };
+        match self.encoding {
+            Encoding::Bcd => number(&mut bytes.iter(), 100),
+            Encoding::BcdLe => number(&mut bytes.iter().rev(), 100),
+            Encoding::Binary => number(&mut bytes.iter().rev(), 256),
+            Encoding::BinaryBe => number(&mut bytes.iter(), 256),
+            Encoding::Screen | Encoding::Petscii => {
+                let pet: Vec<u8> = match self.encoding {
+                    Encoding::Screen => bytes.iter().map(|b| util::screen_to_pet(*b)).collect(),
+                    _ => bytes.to_vec(),
+                };
+                let text = String::from_utf8_lossy(&util::pet_to_ascii(&pet)).to_ascii_uppercase();
+                Ok(json!(text.trim()))
+            },
+        }
+    }
+}
+
+#[test]
+fn game_profiles() {
+    let text = "game = \"Test\"\n[[score]]\nname = \"score\"\naddr = \"$03f3\"\nlen = 3\nencoding = \"bcd\"\nmultiply = 10\n";
+    let profile = GameProfile::parse(text).unwrap();
+    let score = &profile.scores[0];
+    assert_eq!(score.addr, 0x03f3);
+    assert_eq!(score.decode(&[0x01, 0x23, 0x45]).unwrap(), json!(123450));
+    assert!(score.decode(&[0x0a, 0, 0]).is_err());
+    let score = Score { name: "hi".into(), addr: 0, len: 2, encoding: Encoding::Binary, multiply: 1 };
+    assert_eq!(score.decode(&[0x34, 0x12]).unwrap(), json!(0x1234));
+    let score = Score { encoding: Encoding::Screen, ..score };
+    assert_eq!(score.decode(&[0x02, 0x04, 0x20]).unwrap(), json!("BD"));
+    assert!(GameProfile::parse("game = \"Test\"\n[[score]]\nname = \"x\"\naddr = \"ffff\"\nlen = 2\nencoding = \"bcd\"\n").is_err());
+}
@@ -0,0 +1,94 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+// Copyright (C) 2026 Brian Holdsworth
+
+//! Local shell commands run around idunsh's own, from `[hooks]` in the
+//! config file.
+//!
+//! ```toml
+//! [hooks]
+//! pre_mount = "obs-cli scene switch Loading"
+//! post_load = "notify-send \"$IDUNSH_FILE loaded\""
+//! on_error = "logger -t idunsh \"$IDUNSH_COMMAND: $IDUNSH_ERROR\""
+//! ```
+//!
+//! `pre_mount` runs before an image is mounted, and the mount is given
+//! up if it fails. `post_load` runs once a program has been loaded, by
+//! `load` or `run`, and `on_error` once any command has failed; these
+//! failing is only reported. Each runs with `sh -c` and is told what
+//! happened in its environment:
+//!
+//! ```text
+//! IDUNSH_HOOK     the hook's name, e.g. pre_mount
+//! IDUNSH_COMMAND  the sub-command, e.g. mount
+//! IDUNSH_DEV      the drive, when there is one
+//! IDUNSH_FILE     the image or program, when there is one
+//! IDUNSH_ERROR    the error, for on_error
+//! IDUNSH_STATUS   the exit status idunsh gives, for on_error
+//! ```
+use std::process::Command;
+use std::result;
+use crate::config::Hooks;
+
+// Simpler error handling
+type Result<T> = result::Result<T, failure::Error>;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Hook {
+    PreMount,
+    PostLoad,
+    OnError,
+}
+
+impl Hook {
+    pub fn name(self) -> &'static str {
+        match self {
+            Hook::PreMount => "pre_mount",
+            Hook::PostLoad => "post_load",
+            Hook::OnError => "on_error",
+        }
+    }
+    fn command(self, hooks: &Hooks) -> Option<&str> {
+        match self {
+            Hook::PreMount => hooks.pre_mount.as_deref(),
+            Hook::PostLoad => hooks.post_load.as_deref(),
+            Hook::OnError => hooks.on_error.as_deref(),
+        }
+    }
+}
+
+/// Runs `hook` if one is set, for the sub-command `command`, with each
+/// of `context` as `IDUNSH_` and its name in the environment; an error
+/// if it doesn't succeed.
+pub fn run(hooks: &Hooks, hook: Hook, command: &str, context: &[(&str, &str)]) -> Result<()> {
+    let Some(line) = hook.command(hooks) else { return Ok(()) };
+    let status = Command::new("sh").arg("-c").arg(line)
+        .env("IDUNSH_HOOK", hook.name())
+        .env("IDUNSH_COMMAND", command)
+        .envs(context.iter().map(|(name, value)| (format!("IDUNSH_{}", name), value)))
+        .status()
+        .map_err(|e| format_err!("The {} hook couldn't be run: {}", hook.name(), e))?;
+    match status.code() {
+        Some(0) => Ok(()),
+        Some(code) => bail!("The {} hook failed with exit status {}", hook.name(), code),
+        None => bail!("The {} hook was killed", hook.name()),
+    }
+}
+
+/// Runs `hook` after the fact, where its failing is only reported.
+pub fn after(hooks: &Hooks, hook: Hook, command: &str, context: &[(&str, &str)]) {
+    if let Err(e) = run(hooks, hook, command, context) {
+        eprintln!("{}", e);
+    }
+}
+
+#[test]
+fn run_hooks() {
+    let hooks: Hooks = toml::from_str("pre_mount = 'test \"$IDUNSH_HOOK $IDUNSH_COMMAND $IDUNSH_DEV\" = \"pre_mount mount a:\"'\n\
+                                       on_error = 'exit 3'\n").unwrap();
+    run(&hooks, Hook::PreMount, "mount", &[("DEV", "a:"), ("FILE", "game.d64")]).unwrap();
+    assert!(run(&hooks, Hook::PreMount, "mount", &[("DEV", "b:")]).is_err());
+    let failed = run(&hooks, Hook::OnError, "load", &[]).unwrap_err();
+    assert_eq!(failed.to_string(), "The on_error hook failed with exit status 3");
+    // Hooks not set do nothing
+    run(&hooks, Hook::PostLoad, "load", &[]).unwrap();
+}
@@ -0,0 +1,476 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+// Copyright (C) 2026 Brian Holdsworth
+
+//! Track and sector layout of D64, D71 and D81 disk images, and the
+//! `image` commands that work on them.
+//!
+//! A copy of an image is brought up to date by comparing the CRC-32 of
+//! each of its sectors with the other's. DOS never moves a sector, so
+//! there are no shifted blocks for a rolling hash to find; a block that
+//! differs is one that was written.
+use std::env;
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::process;
+use std::result;
+use clap::Subcommand;
+use tokio::io::AsyncReadExt;
+use idun_client::cleanup;
+use idun_client::client::{IMAGE_BURN_CMD, IMAGE_HASH_CMD, IMAGE_RIP_CMD, accept_redirect};
+use idun_client::dos;
+use idun_client::listing::Listing;
+use idun_client::protocol;
+use idun_client::runtime;
+use idun_client::util::{self, PetString};
+use crate::catalogs;
+use crate::confirm::confirm;
+use crate::dir::ListFormat;
+use crate::disk::{self, Disk};
+use crate::drive;
+use crate::files;
+use crate::formats::{self, FileInfo, Format};
+use crate::idun;
+use crate::profile::{Profile, Settings};
+use crate::progress::Progress;
+use crate::sector;
+use crate::shell;
+use crate::theme::Theme;
+
+// Simpler error handling
+type Result<T> = result::Result<T, failure::Error>;
+
+pub const SECTOR_SIZE: usize = 256;
+
+/// The tracks of a disk image format, numbered from 1.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Geometry {
+    pub format: Format,
+    pub tracks: u8,
+}
+
+impl Geometry {
+    /// The layout of an image of `size` bytes, which tells 35 and 40
+    /// track D64s apart.
+    pub fn of(format: Format, size: usize) -> Option<Geometry> {
+        let tracks = match format {
+            Format::D64 if size >= 196608 => 40,
+            Format::D64 => 35,
+            Format::D71 => 70,
+            Format::D81 => 80,
+            _ => return None,
+        };
+        Some(Geometry { format, tracks })
+    }
+    pub fn sectors(&self, track: u8) -> u8 {
+        match self.format {
+            Format::D81 => 40,
+            // The second side of a D71 repeats the zones of the first
+            Format::D71 if track > 35 => sectors_1541(track - 35),
+            _ => sectors_1541(track),
+        }
+    }
+    pub fn total_sectors(&self) -> usize {
+        (1..=self.tracks).map(|t| self.sectors(t) as usize).sum()
+    }
+    /// Every sector of the disk in image order, as (track, sector)
+    pub fn sector_list(&self) -> impl Iterator<Item = (u8, u8)> + '_ {
+        (1..=self.tracks).flat_map(move |t| (0..self.sectors(t)).map(move |s| (t, s)))
+    }
+    /// Position of a sector in the image, counting sectors from the start
+    pub fn index(&self, track: u8, sector: u8) -> Result<usize> {
+        if track == 0 || track > self.tracks {
+            bail!("Track {} is outside the {} tracks of a {}", track, self.tracks, self.format)
+        }
+        if sector >= self.sectors(track) {
+            bail!("Track {} has sectors 0 to {}", track, self.sectors(track) - 1)
+        }
+        Ok((1..track).map(|t| self.sectors(t) as usize).sum::<usize>() + sector as usize)
+    }
+    pub fn offset(&self, track: u8, sector: u8) -> Result<usize> {
+        Ok(self.index(track, sector)? * SECTOR_SIZE)
+    }
+}
+
+/// Builds a D64 from its sectors and the DOS status of reading each.
+/// The error map is only appended if a sector failed, as most tools
+/// expect plain images otherwise.
+pub fn d64_with_errors(sectors: Vec<u8>, codes: &[u8]) -> Vec<u8> {
+    let mut image = sectors;
+    if codes.iter().any(|c| *c != 0) {
+        image.extend(codes.iter().map(|c| error_map_code(*c)));
+    }
+    image
+}
+
+/// The CRC-32 of each sector of an image, in image order. Sectors a
+/// short image lacks count as empty.
+pub fn sector_crcs(geometry: Geometry, data: &[u8]) -> Vec<u32> {
+    let empty = util::crc32(&[0; SECTOR_SIZE]);
+    (0..geometry.total_sectors())
+        .map(|i| data.get(i * SECTOR_SIZE..(i + 1) * SECTOR_SIZE).map_or(empty, util::crc32))
+        .collect()
+}
+
+/// The indexes of the sectors whose CRCs differ.
+pub fn changed_sectors(ours: &[u32], theirs: &[u32]) -> Vec<usize> {
+    (0..ours.len().max(theirs.len())).filter(|i| ours.get(*i) != theirs.get(*i)).collect()
+}
+
+// The error map stores DOS errors 20 to 29 as 2 to 11, 74 as 15 and
+// success as 1
+fn error_map_code(dos: u8) -> u8 {
+    match dos {
+        0 => 1,
+        20..=29 => dos - 18,
+        74 => 15,
+        _ => 0,
+    }
+}
+
+// Sectors per track on a 1541, whose outer tracks hold more
+fn sectors_1541(track: u8) -> u8 {
+    match track {
+        1..=17 => 21,
+        18..=24 => 19,
+        25..=30 => 18,
+        _ => 17,
+    }
+}
+
+#[derive(Subcommand)]
+pub enum ImageCommands {
+    /// Read every sector of a disk into a D64, with an error map if any failed
+    Rip {
+        /// A drive on the cartridge's IEC bus, e.g. 8:
+        dev: String,
+        file: String,
+        #[arg(long, default_value_t=35, value_parser=clap::value_parser!(u8).range(35..=40))]
+        /// Number of tracks to read
+        tracks: u8,
+    },
+    /// Write a D64 to a real disk, then read it back to check every sector
+    Burn {
+        file: String,
+        /// A drive on the cartridge's IEC bus, e.g. 8:
+        dev: String,
+        #[arg(long)]
+        /// Don't read the disk back after writing
+        no_verify: bool,
+    },
+    /// Bring a local copy of a disk up to date, reading only the sectors
+    /// that differ, e.g. image pull d: work.d81
+    Pull {
+        dev: String,
+        file: String,
+    },
+    /// Write only the sectors of an image that differ from the disk in a
+    /// drive, e.g. image push work.d81 d:
+    Push {
+        file: String,
+        dev: String,
+    },
+    /// Make an empty D64, D71 or D81, e.g. image create blank.d64 --label mydisk
+    Create {
+        file: String,
+        #[arg(long, default_value="")]
+        /// Disk name
+        label: String,
+        #[arg(long, default_value="00")]
+        /// Disk id, two characters
+        id: String,
+    },
+    /// Show the directory of a D64, D71 or D81
+    List {
+        file: String,
+        #[arg(long)]
+        /// Print the files as CSV: name, type, blocks, locked, splat, dir
+        csv: bool,
+    },
+    /// Copy a file out of a disk image, e.g. image extract work.d64 game
+    Extract {
+        image: String,
+        /// The file's name on the disk
+        name: String,
+        /// Where to write it, by default the file's name on the disk
+        file: Option<String>,
+    },
+    /// Copy a file into a disk image, e.g. image add work.d64 game.prg
+    Add {
+        image: String,
+        file: String,
+        /// Name to store it under, with ,p ,s or ,u for its type if the extension doesn't give it
+        name: Option<String>,
+    },
+}
+
+impl ImageCommands {
+    /// Whether this works on a disk in a drive, rather than on image files
+    /// alone.
+    pub fn needs_drive(&self) -> bool {
+        matches!(self, ImageCommands::Rip { .. } | ImageCommands::Burn { .. } |
+                       ImageCommands::Pull { .. } | ImageCommands::Push { .. })
+    }
+}
+
+// The `image` commands; those that need a drive reach it through the
+// cartridge
+pub fn run(cmd: &ImageCommands, format: Option<ListFormat>, theme: &Theme, profile: Profile, progress: Progress,
+        yes: bool) -> Result<()> {
+    let open = |image: &str| Disk::open(image, fs::read(image).map_err(|e| format_err!("{}: {}", image, e))?);
+    match cmd {
+        ImageCommands::Create { file, label, id } => {
+            let disk = Disk::format(named_format(file)?, label, id)?;
+            if Path::new(file).exists() {
+                confirm(&format!("Replace {} with an empty disk?", file), yes)?;
+            }
+            fs::write(file, disk.data).map_err(|e| format_err!("{}: {}", file, e))?;
+        },
+        ImageCommands::List { file, .. } => {
+            let listing = Listing::parse(&String::from(PetString::new(&open(file)?.listing()?)));
+            match format {
+                Some(format) => format.print_listing(&listing)?,
+                None => for line in theme.listing(&listing) {
+                    println!("{}", line);
+                },
+            }
+        },
+        ImageCommands::Extract { image, name, file } => {
+            let disk = open(image)?;
+            let name = name.split(',').next().unwrap_or(name);
+            let entry = disk.find(PetString::from(name).as_slice())?
+                .ok_or_else(|| format_err!("There is no file named {} in {}", name, image))?;
+            let file = file.clone().unwrap_or_else(|| dos::local_name(name));
+            fs::write(&file, disk.read(&entry)?).map_err(|e| format_err!("{}: {}", file, e))?;
+        },
+        ImageCommands::Add { image, file, name } => {
+            let mut disk = open(image)?;
+            let data = fs::read(file).map_err(|e| format_err!("{}: {}", file, e))?;
+            let stored = dos::stored_name(file, name.as_deref().unwrap_or_default());
+            let (name, ftype) = stored.rsplit_once(',').unwrap_or((&stored, "p"));
+            disk.add(PetString::from(name).as_slice(), disk::file_type(ftype)?, &data)?;
+            fs::write(image, disk.data).map_err(|e| format_err!("{}: {}", image, e))?;
+        },
+        ImageCommands::Rip { dev, file, tracks } =>
+            rip_cmd(dev, file, *tracks, files::transfer_settings(dev, profile)?, progress)?,
+        ImageCommands::Burn { file, dev, no_verify } => {
+            confirm(&format!("Overwrite the disk in {} with {}?", dev, file), yes)?;
+            burn(file, dev, !no_verify, files::transfer_settings(dev, profile)?, progress)?
+        },
+        ImageCommands::Pull { dev, file } => pull(dev, file, progress)?,
+        ImageCommands::Push { file, dev } => {
+            catalogs::forget(dev);
+            push(file, dev, yes, progress)?
+        },
+    }
+    Ok(())
+}
+
+// Runs a whole-disk command, which the daemon answers with one record
+// of `len` bytes per sector, and collects the records
+fn sector_records(dev: &str, cmd: u8, args: &str, total: usize, len: usize,
+                  phase: &'static str, progress: Progress) -> Result<Vec<Vec<u8>>> {
+    let (resport, respath, id) = idun().response_listener()?;
+    let mut decoder = idun().encoding().decoder();
+    let reader = runtime::spawn(async move {
+        let mut s = accept_redirect(resport).await?;
+        let mut records = Vec::with_capacity(total);
+        let mut pending = vec![];
+        let mut buf = [0u8; 4096];
+        while records.len() < total {
+            let n = s.read(&mut buf).await?;
+            match n {
+                0 => pending.extend(decoder.finish()?),
+                n => pending.extend(decoder.push(&buf[..n])?),
+            }
+            while records.len() < total && pending.len() >= len {
+                records.push(pending.drain(..len).collect());
+                progress.report(phase, (records.len() * SECTOR_SIZE) as u64, (total * SECTOR_SIZE) as u64);
+            }
+            if n == 0 && records.len() < total {
+                bail!("The drive stopped after {} of {} sectors", records.len(), total)
+            }
+        }
+        Ok(records)
+    });
+    if let Err(e) = drive::with_status(dev, shell(cmd, args, id)) {
+        reader.abort();
+        return Err(e)
+    }
+    let records = runtime::join(reader);
+    drop(respath);
+    records
+}
+
+// Reads a whole disk as its sectors and the DOS status of reading each.
+// Each record is the status byte, then the 256 bytes of the sector.
+fn rip(dev: &str, geometry: Geometry, settings: Settings, progress: Progress) -> Result<(Vec<u8>, Vec<u8>)> {
+    let args = format!("{} {}", settings.switches(), protocol::join_args(&[dev, &geometry.tracks.to_string()]));
+    let records = sector_records(dev, IMAGE_RIP_CMD, &args, geometry.total_sectors(),
+                                 1 + SECTOR_SIZE, "rip", progress)?;
+    let codes = records.iter().map(|r| r[0]).collect();
+    Ok((records.iter().flat_map(|r| r[1..].to_vec()).collect(), codes))
+}
+
+fn rip_cmd(dev: &str, file: &str, tracks: u8, settings: Settings, progress: Progress) -> Result<()> {
+    let geometry = Geometry { format: formats::Format::D64, tracks };
+    let (sectors, codes) = rip(dev, geometry, settings, progress)?;
+    fs::write(file, d64_with_errors(sectors, &codes)).map_err(|e| format_err!("{}: {}", file, e))?;
+    let failed = codes.iter().filter(|c| **c != 0).count();
+    if failed > 0 {
+        eprintln!("{} of {} sectors could not be read; see the error map in {}", failed, codes.len(), file);
+    }
+    Ok(())
+}
+
+// The CRC-32 of each sector of the disk in `dev`, summed up by the daemon
+// so the sectors themselves needn't be sent
+fn disk_crcs(dev: &str, geometry: Geometry, progress: Progress) -> Result<Vec<u32>> {
+    let format = geometry.format.to_string().to_ascii_lowercase();
+    let args = protocol::join_args(&[dev, &format, &geometry.tracks.to_string()]);
+    let records = sector_records(dev, IMAGE_HASH_CMD, &args, geometry.total_sectors(), 5, "hash", progress)?;
+    geometry.sector_list().zip(records)
+        .map(|((track, sector), r)| match r[0] {
+            0 => Ok(u32::from_le_bytes([r[1], r[2], r[3], r[4]])),
+            code => bail!("Reading track {} sector {} of {} failed with DOS error {}", track, sector, dev, code),
+        })
+        .collect()
+}
+
+// Reads the sectors of the disk in `dev` that differ from the image in
+// `file`, which is made if there's none yet
+fn pull(dev: &str, file: &str, progress: Progress) -> Result<()> {
+    let mut data = match fs::read(file) {
+        Ok(data) => data,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => vec![],
+        Err(e) => bail!("{}: {}", file, e),
+    };
+    let geometry = match data.is_empty() {
+        true => Geometry::of(named_format(file)?, 0).ok_or_else(|| format_err!("{} isn't a disk image", file))?,
+        false => geometry(file, &data)?,
+    };
+    let size = geometry.total_sectors() * SECTOR_SIZE;
+    if data.len() < size {
+        data.resize(size, 0);
+    }
+    let changed = changed_sectors(&sector_crcs(geometry, &data), &disk_crcs(dev, geometry, progress)?);
+    let sectors: Vec<(u8, u8)> = geometry.sector_list().collect();
+    for (n, i) in changed.iter().enumerate() {
+        let (track, sector) = sectors[*i];
+        data[i * SECTOR_SIZE..(i + 1) * SECTOR_SIZE].copy_from_slice(&sector::read_block(dev, track, sector)?);
+        progress.report("pull", ((n + 1) * SECTOR_SIZE) as u64, (changed.len() * SECTOR_SIZE) as u64);
+    }
+    if !changed.is_empty() || !Path::new(file).exists() {
+        let tmp = cleanup::TempPath::new(format!("{}.tmp-{}", file, process::id()));
+        fs::write(tmp.path(), &data).map_err(|e| format_err!("{}: {}", file, e))?;
+        fs::rename(tmp.path(), file)?;
+    }
+    eprintln!("{} of {} sectors differed", changed.len(), sectors.len());
+    Ok(())
+}
+
+// Writes the sectors of the image in `file` that differ from the disk in
+// `dev`, then checks the disk matches
+fn push(file: &str, dev: &str, yes: bool, progress: Progress) -> Result<()> {
+    let data = fs::read(file).map_err(|e| format_err!("{}: {}", file, e))?;
+    let geometry = geometry(file, &data)?;
+    let ours = sector_crcs(geometry, &data);
+    let changed = changed_sectors(&ours, &disk_crcs(dev, geometry, progress)?);
+    if changed.is_empty() {
+        eprintln!("{} already matches {}", dev, file);
+        return Ok(())
+    }
+    confirm(&format!("Write {} changed sector(s) of {} to {}?", changed.len(), file, dev), yes)?;
+    let sectors: Vec<(u8, u8)> = geometry.sector_list().collect();
+    for (n, i) in changed.iter().enumerate() {
+        let (track, sector) = sectors[*i];
+        sector::write_block(dev, track, sector, &data[i * SECTOR_SIZE..(i + 1) * SECTOR_SIZE])?;
+        progress.report("push", ((n + 1) * SECTOR_SIZE) as u64, (changed.len() * SECTOR_SIZE) as u64);
+    }
+    let left = changed_sectors(&ours, &disk_crcs(dev, geometry, progress)?);
+    if let Some(i) = left.first() {
+        let (track, sector) = sectors[*i];
+        bail!("{} sector(s) of {} still differ from {}, the first track {} sector {}", left.len(), dev, file, track, sector)
+    }
+    eprintln!("Wrote {} of {} sectors", changed.len(), sectors.len());
+    Ok(())
+}
+
+// Writes a D64 to a real disk, sector by sector, then reads the disk back
+// to check it. The daemon reads the image itself and answers with the
+// DOS status of writing each sector.
+fn burn(file: &str, dev: &str, verify: bool, settings: Settings, progress: Progress) -> Result<()> {
+    let data = fs::read(file).map_err(|e| format_err!("{}: {}", file, e))?;
+    let geometry = geometry(file, &data)?;
+    if geometry.format != formats::Format::D64 {
+        bail!("Only D64 images can be written to a disk, but {} is a {}", file, geometry.format)
+    }
+    let path = env::current_dir()?.join(file);
+    let args = format!("{} {}", settings.switches(), protocol::join_args(&[dev, &path.to_string_lossy()]));
+    let total = geometry.total_sectors();
+    let codes = sector_records(dev, IMAGE_BURN_CMD, &args, total, 1, "burn", progress)?;
+
+    let mut bad = vec![];
+    for ((track, sector), code) in geometry.sector_list().zip(&codes) {
+        if code[0] != 0 {
+            bad.push(format!("track {} sector {}: write error {}", track, sector, code[0]));
+        }
+    }
+    if verify {
+        let (sectors, codes) = rip(dev, geometry, settings, progress)?;
+        for (i, (track, sector)) in geometry.sector_list().enumerate() {
+            let range = i * SECTOR_SIZE..(i + 1) * SECTOR_SIZE;
+            if codes[i] != 0 {
+                bad.push(format!("track {} sector {}: read error {}", track, sector, codes[i]));
+            } else if sectors[range.clone()] != data[range] {
+                bad.push(format!("track {} sector {}: differs from the image", track, sector));
+            }
+        }
+    }
+    for b in &bad {
+        eprintln!("{}", b);
+    }
+    if !bad.is_empty() {
+        bail!("{} of {} sectors failed to write to {}", bad.len(), total, dev)
+    }
+    Ok(())
+}
+
+// The format of a disk image to be made, as its extension tells
+fn named_format(file: &str) -> Result<formats::Format> {
+    let ext = Path::new(file).extension().map(|e| e.to_string_lossy().to_lowercase());
+    match ext.as_deref() {
+        Some("d64") => Ok(formats::Format::D64),
+        Some("d71") => Ok(formats::Format::D71),
+        Some("d81") => Ok(formats::Format::D81),
+        _ => bail!("{} should end in .d64, .d71 or .d81 to tell its format", file),
+    }
+}
+
+pub fn geometry(name: &str, image: &[u8]) -> Result<Geometry> {
+    Geometry::of(FileInfo::identify(name, image).format, image.len())
+        .ok_or_else(|| format_err!("{} is not a D64, D71 or D81 disk image", name))
+}
+
+#[test]
+fn d64_layout() {
+    let g = Geometry::of(Format::D64, 174848).unwrap();
+    assert_eq!(g.offset(35, 16).unwrap(), 174848 - 256);
+    assert_eq!(g.offset(18, 0).unwrap(), 0x16500);
+    assert!(g.offset(18, 19).is_err() && g.offset(36, 0).is_err());
+    assert_eq!(Geometry::of(Format::D64, 197376).unwrap().offset(40, 16).unwrap(), 196608 - 256);
+    assert_eq!(Geometry::of(Format::D71, 349696).unwrap().offset(70, 16).unwrap(), 349696 - 256);
+    assert_eq!(g.total_sectors(), 683);
+    let image = d64_with_errors(vec![0; 683 * SECTOR_SIZE], &[0; 683]);
+    assert_eq!(image.len(), 174848);
+    let mut codes = [0; 683];
+    codes[1] = 23;
+    let image = d64_with_errors(vec![0; 683 * SECTOR_SIZE], &codes);
+    assert_eq!((image.len(), image[174848], image[174849]), (175531, 1, 5));
+    assert_eq!(Geometry::of(Format::D81, 819200).unwrap().offset(40, 0).unwrap(), 0x61800);
+    let mut data = vec![0; 683 * SECTOR_SIZE];
+    let before = sector_crcs(g, &data);
+    data[g.offset(18, 1).unwrap()] = 1;
+    assert_eq!(changed_sectors(&before, &sector_crcs(g, &data)), [g.index(18, 1).unwrap()]);
+    assert_eq!(sector_crcs(g, &[]), before);
+}
@@ -0,0 +1,205 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+// Copyright (C) 2026 Brian Holdsworth
+
+//! A first config file, from `idunsh init`.
+//!
+//! `init` looks for the daemon's socket and for a C64U on the LAN, and
+//! proposes what it found as the settings; each can be changed before
+//! the config file is written. It then offers to assign the drives
+//! idunrc.toml gives the idun-base directories, `e:` the apps and `g:`
+//! the games, where those directories are there, and last checks that
+//! the daemon and the C64U answer with the settings written. `--yes`
+//! takes every proposal without asking.
+use std::env;
+use std::fs;
+use std::io::{self, IsTerminal};
+use std::path::Path;
+use std::result;
+use clap::ValueEnum;
+use idun_client::client::{IdunClient, LUAPORT, Timeouts};
+use idun_client::petscii::{Charset, Layout};
+use crate::c64ultimate::C64Ultimate;
+use crate::config::{self, Config};
+use crate::confirm::confirm;
+use crate::connect;
+use crate::daemon_reachable;
+use crate::idun;
+use crate::state::{self, State};
+use crate::store;
+
+// Simpler error handling
+type Result<T> = result::Result<T, failure::Error>;
+
+/// The drives idunrc.toml assigns, with their directories under the
+/// home directory
+pub const ASSIGNS: [(&str, &str); 2] = [("e:", "idun-base/apps"), ("g:", "idun-base/C64-PD-Games")];
+
+/// The settings chosen.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Setup {
+    pub socket: String,
+    pub device: String,
+    pub c64u_ip: Option<String>,
+    pub charset: Charset,
+    pub keyboard: Layout,
+}
+
+// The name `value` is given as on the command line and in the config
+fn name<T: ValueEnum>(value: &T) -> String {
+    value.to_possible_value().map(|v| v.get_name().to_string()).unwrap_or_default()
+}
+
+impl Setup {
+    /// The config file holding these settings.
+    pub fn render(&self) -> String {
+        let quote = |s: &str| toml::Value::String(s.to_string()).to_string();
+        let c64u_ip = match &self.c64u_ip {
+            Some(ip) => format!("c64u_ip = {}\n", quote(ip)),
+            None => "# No c64u_ip: the C64U is looked for on the LAN when needed\n".to_string(),
+        };
+        format!("# Written by idunsh init\nsocket = {}\ndevice = {}\n{}charset = {}\nkeyboard = {}\n",
+            quote(&self.socket), quote(&self.device), c64u_ip,
+            quote(&name(&self.charset)), quote(&name(&self.keyboard)))
+    }
+}
+
+/// The standard assigns whose directories are under `home`.
+pub fn standard_assigns(home: &Path) -> Vec<(String, String)> {
+    ASSIGNS.iter()
+        .map(|(dev, dir)| (dev.to_string(), home.join(dir)))
+        .filter(|(_, dir)| dir.is_dir())
+        .map(|(dev, dir)| (dev, dir.to_string_lossy().into_owned()))
+        .collect()
+}
+
+/// Asks for `what`, proposing `proposed`; the proposal as is with `yes`.
+pub fn ask(what: &str, proposed: &str, yes: bool) -> Result<String> {
+    if yes {
+        return Ok(proposed.to_string())
+    }
+    let answer: String = dialoguer::Input::new()
+        .with_prompt(what)
+        .with_initial_text(proposed)
+        .allow_empty(true)
+        .interact_text()?;
+    Ok(answer.trim().to_string())
+}
+
+/// Asks which of the values of `T` to take, proposing `proposed`.
+pub fn choose<T: ValueEnum + Copy>(what: &str, proposed: T, yes: bool) -> Result<T> {
+    if yes {
+        return Ok(proposed)
+    }
+    let values = T::value_variants();
+    let names: Vec<String> = values.iter().map(name).collect();
+    let default = values.iter().position(|v| name(v) == name(&proposed)).unwrap_or_default();
+    let choice = dialoguer::Select::new()
+        .with_prompt(what)
+        .items(&names)
+        .default(default)
+        .interact_opt()?
+        .ok_or_else(|| format_err!("Nothing chosen for {}", what.to_lowercase()))?;
+    Ok(values[choice])
+}
+
+/// Asks `question`, yes unless answered no; yes with `yes`.
+pub fn agree(question: &str, yes: bool) -> Result<bool> {
+    match yes {
+        true => Ok(true),
+        false => Ok(dialoguer::Confirm::new().with_prompt(question).default(true).interact()?),
+    }
+}
+
+// Writes a first config file from what is found and chosen, assigns the
+// standard drives and checks that the daemon and the C64U answer
+pub fn run(socket: Option<&str>, yes: bool, timeouts: Timeouts) -> Result<()> {
+    let path = Config::path().ok_or_else(|| format_err!("No config directory"))?;
+    if !yes && (!io::stdin().is_terminal() || !io::stderr().is_terminal()) {
+        bail!("init asks its questions on a terminal; add --yes to take what it finds")
+    }
+    // The C64U is looked for while the daemon is
+    let discovery = C64Ultimate::discover(env::var("C64_ULTIMATE_IP").ok(), timeouts);
+    let socket = socket.map(String::from).or_else(|| env::var("IDUNSH_SOCKET").ok())
+        .unwrap_or_else(|| LUAPORT.to_string());
+    match IdunClient::with_socket(&socket).reachable() {
+        true => eprintln!("The daemon answers at {}", socket),
+        false => eprintln!("The daemon doesn't answer at {}", socket),
+    }
+    let found = discovery.connect().ip().clone();
+    match &found {
+        Some(ip) => eprintln!("Found a C64U at {}", ip),
+        None => eprintln!("No C64U found on the LAN"),
+    }
+    let setup = Setup {
+        socket: ask("Daemon socket", &socket, yes)?,
+        device: ask("Drive for commands given none", "c:", yes)?,
+        c64u_ip: Some(ask("C64U address, empty to look on the LAN each time", found.as_deref().unwrap_or_default(), yes)?)
+            .filter(|ip| !ip.is_empty()),
+        charset: choose("Character set", Charset::default(), yes)?,
+        keyboard: choose("Keyboard", Layout::default(), yes)?,
+    };
+    if path.exists() {
+        confirm(&format!("Replace {}?", path.display()), yes)?;
+    }
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    store::write(&path, setup.render().as_bytes())?;
+    eprintln!("Wrote {}", path.display());
+
+    connect(Some(&setup.socket), &config::Connection::default(), timeouts)?;
+    let daemon = daemon_reachable();
+    let assigns = dirs::home_dir().map(|home| standard_assigns(&home)).unwrap_or_default();
+    if daemon && !assigns.is_empty() {
+        let listed: Vec<String> = assigns.iter().map(|(dev, dir)| format!("{} to {}", dev, dir)).collect();
+        if agree(&format!("Assign {}?", listed.join(", ")), yes)? {
+            state::apply(&State { assigns: assigns.into_iter().collect(), ..State::default() })?;
+        }
+    }
+
+    // Nothing answering at all is a failure; either one missing may not be
+    let drives = match daemon {
+        true => Some(idun().drives()),
+        false => None,
+    };
+    match &drives {
+        Some(Ok(mounts)) => eprintln!("ok       daemon, {} drive(s) active", mounts.len()),
+        Some(Err(e)) => eprintln!("FAILED   daemon: {}", e),
+        None => eprintln!("skipped  daemon, not answering at {}", setup.socket),
+    }
+    let version = match &setup.c64u_ip {
+        Some(_) => Some(C64Ultimate::discover(setup.c64u_ip.clone(), timeouts).connect().version()),
+        None => None,
+    };
+    match &version {
+        Some(Ok(v)) => eprintln!("ok       C64U, API version {}", v),
+        Some(Err(e)) => eprintln!("FAILED   C64U: {}", e),
+        None => eprintln!("skipped  C64U, none found"),
+    }
+    if !matches!(drives, Some(Ok(_))) && !matches!(version, Some(Ok(_))) {
+        bail!("Neither the daemon nor a C64U answered; correct {} or run init again", path.display())
+    }
+    Ok(())
+}
+
+#[test]
+fn first_config() {
+    let mut setup = Setup {
+        socket: "/tmp/idunmm-lua".into(),
+        device: "c:".into(),
+        c64u_ip: Some("192.168.1.64".into()),
+        charset: Charset::Upper,
+        keyboard: Layout::Se,
+    };
+    let config: crate::config::Config = toml::from_str(&setup.render()).unwrap();
+    let connection = config.connection;
+    assert_eq!((connection.socket.as_deref(), connection.device.as_deref()), (Some("/tmp/idunmm-lua"), Some("c:")));
+    assert_eq!(connection.c64u_ip.as_deref(), Some("192.168.1.64"));
+    assert_eq!((config.charset, config.keyboard), (Some(Charset::Upper), Layout::Se));
+    setup.c64u_ip = None;
+    setup.socket = "serial:///dev/ttyUSB0?baud=115200".into();
+    let config: crate::config::Config = toml::from_str(&setup.render()).unwrap();
+    assert_eq!(config.connection.c64u_ip, None);
+    assert_eq!(config.connection.socket.as_deref(), Some("serial:///dev/ttyUSB0?baud=115200"));
+    assert!(standard_assigns(Path::new("/nonexistent")).is_empty());
+}
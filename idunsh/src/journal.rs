@@ -0,0 +1,226 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+// Copyright (C) 2026 Brian Holdsworth
+
+//! A record of what the Commodore writes to an assigned directory.
+//!
+//! `assign --journal` keeps watching the directory after assigning it.
+//! Every file written, deleted or renamed there is logged with the time,
+//! its size and CRC-32, and the program that was running, to
+//! `~/.local/share/idunsh/journal/<drive>.log`, one tab-separated record
+//! per line.
+use std::collections::HashMap;
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::result;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{SystemTime, UNIX_EPOCH};
+use nix::sys::inotify::{AddWatchFlags, InitFlags, Inotify, WatchDescriptor};
+use crate::events::Event;
+use crate::store;
+use crate::subscribe_events;
+use crate::util;
+
+// Simpler error handling
+type Result<T> = result::Result<T, failure::Error>;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Record {
+    /// Seconds since the Unix epoch
+    pub time: u64,
+    /// "write", "delete", "rename-from" or "rename-to"
+    pub action: String,
+    /// Path within the assigned directory
+    pub file: String,
+    pub size: u64,
+    pub crc32: u32,
+    /// The program running on the Commodore, if known
+    pub program: Option<String>,
+}
+
+impl Record {
+    fn to_line(&self) -> String {
+        format!("{}\t{}\t{}\t{}\t{:08x}\t{}", self.time, self.action, self.file,
+            self.size, self.crc32, self.program.as_deref().unwrap_or("-"))
+    }
+    fn from_line(line: &str) -> Option<Record> {
+        let mut f = line.split('\t');
+        Some(Record {
+            time: f.next()?.parse().ok()?,
+            action: f.next()?.to_string(),
+            file: f.next()?.to_string(),
+            size: f.next()?.parse().ok()?,
+            crc32: u32::from_str_radix(f.next()?, 16).ok()?,
+            program: f.next().filter(|p| *p != "-").map(String::from),
+        })
+    }
+    /// The record as shown by `journal show`.
+    pub fn display(&self) -> String {
+        let changed = match self.action.as_str() {
+            "write" => format!("{:>8} bytes  crc {:08x}", self.size, self.crc32),
+            _ => String::new(),
+        };
+        let line = format!("{}  {:<11} {:<24} {:<30} {}", format_time(self.time), self.action,
+            self.file, changed, self.program.as_deref().unwrap_or(""));
+        line.trim_end().to_string()
+    }
+}
+
+pub struct Journal {
+    path: PathBuf,
+}
+
+impl Journal {
+    /// The journal for a drive such as "e:".
+    pub fn open(dev: &str) -> Result<Journal> {
+        let dir = store::subdir("journal")?;
+        let name: String = dev.trim_end_matches(':').chars()
+            .map(|c| if c.is_ascii_alphanumeric() { c.to_ascii_lowercase() } else { '_' })
+            .collect();
+        Ok(Journal { path: dir.join(format!("{}.log", name)) })
+    }
+    pub fn append(&self, record: &Record) -> Result<()> {
+        let mut f = fs::File::options().create(true).append(true).open(&self.path)?;
+        writeln!(f, "{}", record.to_line())?;
+        Ok(())
+    }
+    pub fn records(&self) -> Result<Vec<Record>> {
+        let text = fs::read_to_string(&self.path)
+            .map_err(|e| format_err!("{}: {}", self.path.display(), e))?;
+        Ok(text.lines().filter_map(Record::from_line).collect())
+    }
+    /// Watches `dir` and its subdirectories, journaling every change until
+    /// idunsh is stopped. `program` names what is running at the time.
+    pub fn watch<F>(&self, dir: &Path, program: F) -> Result<()>
+    where F: Fn() -> Option<String> {
+        const MASK: AddWatchFlags = AddWatchFlags::IN_CLOSE_WRITE
+            .union(AddWatchFlags::IN_DELETE)
+            .union(AddWatchFlags::IN_MOVED_FROM)
+            .union(AddWatchFlags::IN_MOVED_TO)
+            .union(AddWatchFlags::IN_CREATE);
+        let inotify = Inotify::init(InitFlags::empty())?;
+        let mut dirs: HashMap<WatchDescriptor, PathBuf> = HashMap::new();
+        let mut pending = vec![dir.to_path_buf()];
+        loop {
+            // Watch directories as they are found, including new ones
+            while let Some(d) = pending.pop() {
+                dirs.insert(inotify.add_watch(&d, MASK)?, d.clone());
+                for entry in fs::read_dir(&d)? {
+                    let entry = entry?;
+                    if entry.file_type()?.is_dir() {
+                        pending.push(entry.path());
+                    }
+                }
+            }
+            for ev in inotify.read_events()? {
+                let (Some(parent), Some(name)) = (dirs.get(&ev.wd), ev.name) else {
+                    continue;
+                };
+                let path = parent.join(name);
+                let is_dir = ev.mask.contains(AddWatchFlags::IN_ISDIR);
+                let action = if ev.mask.contains(AddWatchFlags::IN_CLOSE_WRITE) {
+                    "write"
+                } else if ev.mask.contains(AddWatchFlags::IN_DELETE) {
+                    "delete"
+                } else if ev.mask.contains(AddWatchFlags::IN_MOVED_FROM) {
+                    "rename-from"
+                } else if ev.mask.contains(AddWatchFlags::IN_MOVED_TO) {
+                    "rename-to"
+                } else {
+                    // Created: only new directories need anything done
+                    if is_dir {
+                        pending.push(path);
+                    }
+                    continue;
+                };
+                if is_dir && action == "rename-to" {
+                    pending.push(path.clone());
+                }
+                let data = if action == "write" { fs::read(&path).unwrap_or_default() } else { vec![] };
+                self.append(&Record {
+                    time: SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0),
+                    action: action.to_string(),
+                    file: path.strip_prefix(dir).unwrap_or(&path).to_string_lossy().into_owned(),
+                    size: data.len() as u64,
+                    crc32: util::crc32(&data),
+                    program: program(),
+                })?;
+            }
+        }
+    }
+}
+
+// UTC date and time, e.g. "2026-10-16 09:30:00"
+pub fn format_time(secs: u64) -> String {
+    // Days to civil date, after Howard Hinnant's days_from_civil inverse
+    let days = (secs / 86400) as i64 + 719468;
+    let era = days.div_euclid(146097);
+    let doe = days - era * 146097;
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+    let t = secs % 86400;
+    format!("{:04}-{:02}-{:02} {:02}:{:02}:{:02}", year, month, day, t / 3600, t / 60 % 60, t % 60)
+}
+
+// Prints the journal of a drive, or only its last `tail` records
+pub fn run(dev: &str, tail: Option<usize>) -> Result<()> {
+    let records = Journal::open(dev)?.records()?;
+    let skip = records.len().saturating_sub(tail.unwrap_or(usize::MAX));
+    for r in &records[skip..] {
+        println!("{}", r.display());
+    }
+    Ok(())
+}
+
+// Journals changes to an assigned directory until stopped, crediting
+// them to the program the daemon last reported starting
+pub fn follow(dev: &str, path: &str) -> Result<()> {
+    let running = Arc::new(Mutex::new(None));
+    if let Ok(ch) = subscribe_events() {
+        let events = ch.forward();
+        let running = running.clone();
+        thread::spawn(move || {
+            for ev in events {
+                if let Ok(mut r) = running.lock() {
+                    match ev {
+                        Event::Started(name) => *r = Some(name),
+                        Event::Exited(_) => *r = None,
+                        _ => (),
+                    }
+                }
+            }
+        });
+    }
+    eprintln!("Journaling changes to {} (Ctrl-C to stop)", path);
+    Journal::open(dev)?.watch(Path::new(path), || running.lock().ok().and_then(|r| r.clone()))
+}
+
+#[test]
+fn journal_records() {
+    let r = Record {
+        time: 1792143000,
+        action: String::from("write"),
+        file: String::from("save/hiscore.seq"),
+        size: 254,
+        crc32: 0xcbf43926,
+        program: Some(String::from("game.prg")),
+    };
+    assert_eq!(Record::from_line(&r.to_line()), Some(r));
+    assert_eq!(format_time(1792143000), "2026-10-16 09:30:00");
+    assert_eq!(format_time(951782400), "2000-02-29 00:00:00");
+}
+
+#[test]
+fn journal_damaged_lines() {
+    let r = Record::from_line("1792143000\tdelete\told.seq\t0\t0\t-").unwrap();
+    assert_eq!((r.program.as_deref(), r.display().ends_with("old.seq")), (None, true));
+    // A line cut short by a crash, or with a bad checksum, is skipped
+    assert_eq!(Record::from_line("1792143000\twrite\tsave.seq\t254"), None);
+    assert_eq!(Record::from_line("1792143000\twrite\tsave.seq\t254\txyz"), None);
+    assert_eq!(Record::from_line(""), None);
+}
@@ -0,0 +1,211 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+// Copyright (C) 2026 Brian Holdsworth
+
+//! Playlists for kiosk mode, which shows one item after another.
+//!
+//! ```toml
+//! duration = "3m"        # default time per item
+//!
+//! [[item]]
+//! file = "demos/edge.d64"
+//! duration = "5m"
+//!
+//! [[item]]
+//! file = "music/commando.sid"
+//! fade = "5s"
+//!
+//! [[item]]
+//! mount = "games/jumpman.d64"    # mounted on drive d: (idun only)
+//! file = "d:jumpman"
+//! ```
+use std::fs;
+use std::path::Path;
+use std::result;
+use std::sync::mpsc::RecvTimeoutError;
+use std::thread;
+use std::time::{Duration, Instant};
+use serde::{de, Deserialize, Deserializer};
+use idun_client::client::Batch;
+use crate::batch;
+use crate::c64ultimate::C64Ultimate;
+use crate::events::Event;
+use crate::reboot_cmd;
+use crate::subscribe_events;
+use crate::util;
+
+// Simpler error handling
+type Result<T> = result::Result<T, failure::Error>;
+
+#[derive(Debug, Deserialize)]
+pub struct Playlist {
+    /// Time per item, unless the item says otherwise
+    #[serde(default = "default_duration", deserialize_with = "duration")]
+    pub duration: Duration,
+    /// Start over after the last item
+    #[serde(default = "default_repeat")]
+    pub repeat: bool,
+    /// Time for the idun cartridge to reboot between items
+    #[serde(default = "default_settle", deserialize_with = "duration")]
+    pub settle: Duration,
+    #[serde(default, rename = "item")]
+    pub items: Vec<Item>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct Item {
+    pub file: String,
+    /// Disk image to mount first
+    pub mount: Option<String>,
+    /// Drive for `mount`
+    #[serde(default = "default_drive")]
+    pub drive: String,
+    #[serde(default, deserialize_with = "opt_duration")]
+    pub duration: Option<Duration>,
+    /// Fade-out at the end of SID tunes (C64 Ultimate)
+    #[serde(default, deserialize_with = "opt_duration")]
+    pub fade: Option<Duration>,
+}
+
+fn default_duration() -> Duration {
+    Duration::from_secs(180)
+}
+fn default_repeat() -> bool {
+    true
+}
+fn default_settle() -> Duration {
+    Duration::from_secs(10)
+}
+fn default_drive() -> String {
+    String::from("d:")
+}
+
+fn duration<'de, D: Deserializer<'de>>(d: D) -> result::Result<Duration, D::Error> {
+    util::parse_duration(&String::deserialize(d)?).map_err(de::Error::custom)
+}
+fn opt_duration<'de, D: Deserializer<'de>>(d: D) -> result::Result<Option<Duration>, D::Error> {
+    duration(d).map(Some)
+}
+
+impl Playlist {
+    /// Reads a playlist. Files named relative to the playlist are found
+    /// next to it; anything else, such as `d:jumpman`, is left alone.
+    pub fn load(file: &str) -> Result<Playlist> {
+        let text = fs::read_to_string(file)?;
+        let mut playlist = Self::parse(&text).map_err(|e| format_err!("{}: {}", file, e))?;
+        let dir = Path::new(file).parent().unwrap_or(Path::new(""));
+        for item in &mut playlist.items {
+            for f in std::iter::once(&mut item.file).chain(item.mount.as_mut()) {
+                let local = dir.join(&*f);
+                if Path::new(f).is_relative() && local.exists() {
+                    *f = local.to_string_lossy().into_owned();
+                }
+            }
+        }
+        Ok(playlist)
+    }
+    fn parse(text: &str) -> Result<Playlist> {
+        let playlist: Playlist = toml::from_str(text)?;
+        if playlist.items.is_empty() {
+            bail!("The playlist has no items")
+        }
+        Ok(playlist)
+    }
+}
+
+impl Item {
+    /// How long to show this item for.
+    pub fn duration(&self, playlist: &Playlist) -> Duration {
+        self.duration.unwrap_or(playlist.duration)
+    }
+}
+
+/// Runs the playlist in `file` until stopped, on the C64U if given.
+pub fn run(c64u: Option<&C64Ultimate>, file: &str) -> Result<()> {
+    let playlist = Playlist::load(file)?;
+    match c64u {
+        Some(c64u) => on_ult(c64u, &playlist),
+        None => on_idun(&playlist),
+    }
+}
+
+// Kiosk mode on the idun cartridge. An item ends when its program exits
+// or its time is up; the cartridge is then rebooted for the next one. An
+// item that fails to start is reported and skipped, so the show goes on.
+fn on_idun(playlist: &Playlist) -> Result<()> {
+    let events = subscribe_events()?.forward();
+    loop {
+        for item in &playlist.items {
+            while events.try_recv().is_ok() {}
+            let started = batch(item.mount.iter()
+                .fold(Batch::new(), |b, image| b.mount(&item.drive, image))
+                .load(&item.file));
+            if let Err(e) = started {
+                eprintln!("Skipping {}: {}", item.file, e);
+                thread::sleep(playlist.settle);
+                continue;
+            }
+            let deadline = Instant::now() + item.duration(playlist);
+            let exited = loop {
+                match events.recv_timeout(deadline.saturating_duration_since(Instant::now())) {
+                    Ok(Event::Exited(_)) => break true,
+                    Ok(_) => (),
+                    Err(RecvTimeoutError::Timeout) => break false,
+                    // Without events, every item gets its full time
+                    Err(RecvTimeoutError::Disconnected) => {
+                        thread::sleep(deadline.saturating_duration_since(Instant::now()));
+                        break false
+                    },
+                }
+            };
+            if !exited {
+                reboot_cmd(0)?;
+                thread::sleep(playlist.settle);
+            }
+        }
+        if !playlist.repeat {
+            return Ok(())
+        }
+    }
+}
+
+// Kiosk mode on the C64 Ultimate, which has no exit events, so every item
+// runs for its full time and ends with a reset.
+fn on_ult(c64u: &C64Ultimate, playlist: &Playlist) -> Result<()> {
+    loop {
+        for item in &playlist.items {
+            let started = item.mount.iter()
+                .try_for_each(|image| c64u.mount("a:", image))
+                .and_then(|_| c64u.load(&item.file, &[]));
+            if let Err(e) = started {
+                eprintln!("Skipping {}: {}", item.file, e);
+                thread::sleep(playlist.settle);
+                continue;
+            }
+            c64u.stop_after(item.duration(playlist), item.fade)?;
+        }
+        if !playlist.repeat {
+            return Ok(())
+        }
+    }
+}
+
+#[test]
+fn parse_playlist() {
+    let p = Playlist::parse("duration = \"2m\"\n[[item]]\nfile = \"a.prg\"\n[[item]]\nfile = \"b.sid\"\nduration = \"90\"\nfade = \"5s\"\n").unwrap();
+    assert!(p.repeat);
+    assert_eq!(p.items[0].duration(&p), Duration::from_secs(120));
+    assert_eq!(p.items[1].duration(&p), Duration::from_secs(90));
+    assert_eq!(p.items[1].fade, Some(Duration::from_secs(5)));
+    assert!(Playlist::parse("item = []").is_err());
+}
+
+#[test]
+fn playlist_mistakes() {
+    let err = Playlist::parse("duration = \"2m\"\n").unwrap_err();
+    assert_eq!(err.to_string(), "The playlist has no items");
+    // A bad duration names the field instead of falling back to the default
+    assert!(Playlist::parse("[[item]]\nfile = \"a.prg\"\nduration = \"soon\"\n").is_err());
+    assert!(Playlist::parse("[[item]]\nduration = \"1m\"\n").is_err());
+    let p = Playlist::parse("repeat = false\n[[item]]\nfile = \"a.prg\"\n").unwrap();
+    assert_eq!((p.repeat, p.items[0].drive.as_str(), p.items[0].fade), (false, default_drive().as_str(), None));
+}
@@ -0,0 +1,93 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+// Copyright (C) 2026 Brian Holdsworth
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use std::result;
+
+// Simpler error handling
+type Result<T> = result::Result<T, failure::Error>;
+
+/// Symbol table read from assembler label files. Understands the VICE
+/// format written by cc65 (`-Ln`) and others (`al C:0810 .start`), and
+/// the `name = $0810` assignments written by KickAss, ACME and 64tass.
+#[derive(Default)]
+pub struct Labels {
+    by_addr: BTreeMap<u16, Vec<String>>,
+    by_name: HashMap<String, u16>,
+}
+
+impl Labels {
+    /// Reads a label file, failing if nothing in it looks like a label.
+    pub fn load(path: &str) -> Result<Labels> {
+        let text = fs::read_to_string(path)
+            .map_err(|e| format_err!("{}: {}", path, e))?;
+        let labels = Self::parse(&text);
+        if labels.by_name.is_empty() {
+            bail!("{}: no labels found", path)
+        }
+        Ok(labels)
+    }
+    /// The labels in `path`, or none without one.
+    pub fn open(path: Option<&str>) -> Result<Labels> {
+        path.map_or_else(|| Ok(Labels::default()), Self::load)
+    }
+    pub fn parse(text: &str) -> Labels {
+        let mut labels = Labels::default();
+        for line in text.lines() {
+            let line = line.split(';').next().unwrap_or("").trim();
+            let mut words = line.split_whitespace();
+            let entry = match (words.next(), words.next(), words.next()) {
+                // al C:0810 .start
+                (Some("al"), Some(addr), Some(name)) => {
+                    let addr = addr.trim_start_matches("C:");
+                    u16::from_str_radix(addr, 16).ok().map(|a| (name.trim_start_matches('.'), a))
+                },
+                // start = $0810
+                (Some(name), Some("="), Some(value)) => {
+                    let value = value.trim_start_matches('$');
+                    u16::from_str_radix(value, 16).ok().map(|a| (name.trim_start_matches('.'), a))
+                },
+                _ => None,
+            };
+            if let Some((name, addr)) = entry {
+                labels.by_addr.entry(addr).or_default().push(name.to_string());
+                labels.by_name.insert(name.to_string(), addr);
+            }
+        }
+        labels
+    }
+    /// Names given to `addr`, if any.
+    pub fn at(&self, addr: u16) -> &[String] {
+        self.by_addr.get(&addr).map(|v| v.as_slice()).unwrap_or(&[])
+    }
+    /// Address of a named label.
+    pub fn lookup(&self, name: &str) -> Option<u16> {
+        self.by_name.get(name).copied()
+    }
+    /// Every labelled address in order, with its first name.
+    pub fn addresses(&self) -> impl Iterator<Item = (u16, &str)> {
+        self.by_addr.iter().filter_map(|(a, names)| names.first().map(|n| (*a, n.as_str())))
+    }
+}
+
+#[test]
+fn label_formats() {
+    let labels = Labels::parse("al C:0810 .start\nloop = $0820 ; main loop\n");
+    assert_eq!(labels.lookup("start"), Some(0x0810));
+    assert_eq!(labels.at(0x0820), ["loop"]);
+}
+
+#[test]
+fn label_clashes() {
+    // Two names for one address keep both, in file order; junk lines are skipped
+    let labels = Labels::parse("irq = $ea31
+al C:ea31 .kernal_irq
+bad = $10000
+al C:zz .x
+; irq = $0314
+");
+    assert_eq!(labels.at(0xea31), ["irq", "kernal_irq"]);
+    assert_eq!(labels.addresses().collect::<Vec<_>>(), [(0xea31, "irq")]);
+    assert_eq!((labels.lookup("bad"), labels.lookup("x")), (None, None));
+    assert!(labels.at(0x0314).is_empty());
+}
@@ -0,0 +1,18 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+// Copyright (C) 2026 Brian Holdsworth
+
+//! Control of the idun cartridge through its daemon: the protocol client
+//! behind `idunsh`, for embedding in other tools. Start with
+//! `client::IdunClient`.
+#[macro_use] extern crate failure;
+
+pub mod util;
+pub mod cleanup;
+pub mod protocol;
+pub mod encoding;
+pub mod serial;
+pub mod runtime;
+pub mod client;
+pub mod dos;
+pub mod listing;
+pub mod petscii;
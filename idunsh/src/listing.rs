@@ -0,0 +1,231 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+// Copyright (C) 2026 Brian Holdsworth
+use serde_json::{json, Value};
+use crate::util;
+
+/// One file in a Commodore directory listing, e.g.
+/// `12   "GAME"             PRG<`
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Entry {
+    pub blocks: u32,
+    pub name: String,
+    pub ftype: String,
+    /// `*` before the type: the file was never closed properly
+    pub splat: bool,
+    /// `<` after the type: the file is write protected
+    pub locked: bool,
+    /// The line as it was received
+    pub line: String,
+}
+
+/// A directory listing as printed by the remote `catalog` command: the
+/// disk header, the file entries, then whatever follows them (normally
+/// the BLOCKS FREE line).
+#[derive(Clone, Debug, Default)]
+pub struct Listing {
+    pub header: Option<String>,
+    pub entries: Vec<Entry>,
+    pub footer: Vec<String>,
+}
+
+impl Listing {
+    /// Parses listing text that has already been converted from PETSCII.
+    pub fn parse(text: &str) -> Listing {
+        let mut listing = Listing::default();
+        for line in text.split(['\r', '\n']).filter(|l| !l.trim().is_empty()) {
+            if listing.header.is_none() && listing.entries.is_empty() {
+                listing.header = Some(line.to_string());
+            } else if let Some(entry) = Entry::parse(line).filter(|_| listing.footer.is_empty()) {
+                listing.entries.push(entry);
+            } else {
+                listing.footer.push(line.to_string());
+            }
+        }
+        listing
+    }
+    /// Keeps only the entries in a window of the listing: skip `offset`
+    /// entries, then keep at most `limit`. With `tail`, the window is
+    /// counted from the end instead.
+    pub fn paginate(&mut self, offset: usize, limit: Option<usize>, tail: bool) {
+        if tail {
+            self.entries.reverse();
+        }
+        self.entries = self.entries.drain(..)
+            .skip(offset)
+            .take(limit.unwrap_or(usize::MAX))
+            .collect();
+        if tail {
+            self.entries.reverse();
+        }
+    }
+    /// The entries as CSV with a header row, one file per record.
+    pub fn csv(&self) -> String {
+        let mut out = String::from("name,type,blocks,locked,splat,dir\n");
+        for e in &self.entries {
+            out.push_str(&format!("{},{},{},{},{},{}\n",
+                csv_field(&e.name), csv_field(&e.ftype), e.blocks, e.locked, e.splat, e.is_dir()));
+        }
+        out
+    }
+    /// The entries as tab-separated values with a header row, in the
+    /// same columns as `csv`.
+    pub fn tsv(&self) -> String {
+        let mut out = String::from("name\ttype\tblocks\tlocked\tsplat\tdir\n");
+        for e in &self.entries {
+            out.push_str(&format!("{}\t{}\t{}\t{}\t{}\t{}\n",
+                e.name.replace('\t', " "), e.ftype, e.blocks, e.locked, e.splat, e.is_dir()));
+        }
+        out
+    }
+    /// The listing as a JSON object: the disk name and id from the
+    /// header, the files, and the blocks free from the footer.
+    pub fn json(&self) -> Value {
+        let header = self.header.as_deref().unwrap_or_default();
+        let (name, id) = match header.split_once('"').and_then(|(_, r)| r.split_once('"')) {
+            Some((name, id)) => (Some(name.trim_end()), Some(id.trim())),
+            None => (None, None),
+        };
+        let free = self.footer.iter()
+            .find(|f| f.to_ascii_lowercase().contains("blocks free"))
+            .and_then(|f| f.split_whitespace().next()?.parse::<u32>().ok());
+        json!({
+            "name": name,
+            "id": id,
+            "files": Entry::json_array(&self.entries),
+            "blocks_free": free,
+        })
+    }
+}
+
+// Quotes a CSV field if it holds a separator, quote or line break
+fn csv_field(s: &str) -> String {
+    if s.contains([',', '"', '\r', '\n']) {
+        format!("\"{}\"", s.replace('"', "\"\""))
+    } else {
+        s.to_string()
+    }
+}
+
+impl Entry {
+    /// True for entries that can be listed in turn: subdirectories,
+    /// which list with type DIR, and 1581 partitions, type CBM
+    pub fn is_dir(&self) -> bool {
+        self.ftype.eq_ignore_ascii_case("dir") || self.ftype.eq_ignore_ascii_case("cbm")
+    }
+    /// The flags as the listing shows them: `*` for splat, `<` for
+    /// locked, or nothing
+    pub fn attrs(&self) -> &'static str {
+        match (self.splat, self.locked) {
+            (true, true) => "*<",
+            (true, false) => "*",
+            (false, true) => "<",
+            (false, false) => "",
+        }
+    }
+    /// The entry laid out in the columns of a drive's listing, whatever
+    /// spacing it arrived with: blocks, quoted name, then type and flags
+    pub fn row(&self) -> String {
+        format!("{} {}{}{}{}",
+            util::right_aligned(self.blocks, 4),
+            util::padded(&format!("\"{}\"", self.name), 18),
+            if self.splat { '*' } else { ' ' },
+            util::padded(&self.ftype, 3),
+            if self.locked { "<" } else { "" })
+    }
+    /// The entry as a JSON object with the fields of `Listing::csv`
+    pub fn json(&self) -> Value {
+        json!({
+            "name": self.name,
+            "type": self.ftype,
+            "blocks": self.blocks,
+            "locked": self.locked,
+            "splat": self.splat,
+            "dir": self.is_dir(),
+        })
+    }
+    pub fn json_array(entries: &[Entry]) -> Value {
+        Value::Array(entries.iter().map(Entry::json).collect())
+    }
+    fn parse(line: &str) -> Option<Entry> {
+        let (blocks, rest) = line.trim_start().split_once(' ')?;
+        let blocks = blocks.parse().ok()?;
+        let rest = rest.trim_start().strip_prefix('"')?;
+        let (name, attrs) = rest.split_once('"')?;
+        let attrs = attrs.trim();
+        let splat = attrs.starts_with('*');
+        let locked = attrs.ends_with('<');
+        let ftype = attrs.trim_start_matches('*').trim_end_matches('<').trim();
+        if ftype.is_empty() {
+            return None;
+        }
+        Some(Entry {
+            blocks,
+            name: name.to_string(),
+            ftype: ftype.to_string(),
+            splat,
+            locked,
+            line: line.to_string(),
+        })
+    }
+}
+
+/// One line of the remote `drives` listing: a drive and the directory
+/// or disk image it is assigned to, e.g. `C:=/home/idun`
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Mount {
+    pub device: String,
+    pub target: String,
+}
+
+impl Mount {
+    /// The drives in listing text; lines that don't name one are left out.
+    pub fn parse_all(text: &str) -> Vec<Mount> {
+        text.split(['\r', '\n'])
+            .filter_map(|line| {
+                let (device, target) = line.split_once('=')?;
+                let device = device.trim();
+                if !device.ends_with(':') || device.contains(' ') {
+                    return None
+                }
+                Some(Mount { device: device.to_ascii_lowercase(), target: target.trim().to_string() })
+            })
+            .collect()
+    }
+    pub fn json(&self) -> Value {
+        json!({ "device": self.device, "target": self.target })
+    }
+}
+
+#[test]
+fn parse_drives() {
+    let mounts = Mount::parse_all("C:=/home/idun\rD:=game.d64\rREADY.\r");
+    assert_eq!(mounts, [
+        Mount { device: "c:".into(), target: "/home/idun".into() },
+        Mount { device: "d:".into(), target: "game.d64".into() },
+    ]);
+    assert_eq!(mounts[1].json()["target"], "game.d64");
+}
+
+#[test]
+fn parse_listing() {
+    let text = "0 \"work disk\" 2a\r12   \"game\"   prg<\r3    \"notes\"  *seq\r0    \"src\"    dir\r649 blocks free.\r";
+    let mut listing = Listing::parse(text);
+    assert_eq!(listing.header.as_deref(), Some("0 \"work disk\" 2a"));
+    assert_eq!(listing.entries.len(), 3);
+    assert!(listing.entries[0].locked && listing.entries[1].splat);
+    assert_eq!((listing.entries[0].attrs(), listing.entries[1].attrs()), ("<", "*"));
+    assert_eq!(listing.entries[1].ftype, "seq");
+    assert_eq!(listing.footer, ["649 blocks free."]);
+    assert!(listing.entries[2].is_dir() && !listing.entries[0].is_dir());
+    assert_eq!(listing.csv(), "name,type,blocks,locked,splat,dir\ngame,prg,12,true,false,false\n\
+        notes,seq,3,false,true,false\nsrc,dir,0,false,false,true\n");
+    assert!(listing.tsv().starts_with("name\ttype\tblocks\tlocked\tsplat\tdir\ngame\tprg\t12\ttrue"));
+    let json = listing.json();
+    assert_eq!((json["name"].as_str(), json["id"].as_str()), (Some("work disk"), Some("2a")));
+    assert_eq!(json["blocks_free"], 649);
+    assert_eq!(json["files"][1]["type"], "seq");
+    assert_eq!(listing.entries[0].row(), r#"  12 "game"             prg<"#);
+    assert_eq!(listing.entries[1].row(), r#"   3 "notes"           *seq"#);
+    listing.paginate(0, Some(1), true);
+    assert_eq!(listing.entries[0].name, "src");
+}
@@ -0,0 +1,52 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+// Copyright (C) 2026 Brian Holdsworth
+
+//! Advisory file locks, for files shared between idunsh runs.
+use std::fs::File;
+use std::os::unix::io::AsRawFd;
+use std::path::Path;
+use std::result;
+use nix::fcntl::{flock, FlockArg};
+
+// Simpler error handling
+type Result<T> = result::Result<T, failure::Error>;
+
+/// An exclusive lock on a file, held until dropped.
+pub struct FileLock(File);
+
+impl FileLock {
+    /// Waits for the lock, creating the lock file if needed.
+    pub fn exclusive(path: &Path) -> Result<FileLock> {
+        let f = File::options().create(true).append(true).open(path)?;
+        flock(f.as_raw_fd(), FlockArg::LockExclusive)?;
+        Ok(FileLock(f))
+    }
+}
+
+impl Drop for FileLock {
+    fn drop(&mut self) {
+        let _ = flock(self.0.as_raw_fd(), FlockArg::Unlock);
+    }
+}
+
+#[test]
+fn lock_waits() {
+    use std::sync::mpsc;
+    use std::time::Duration;
+    let path = std::env::temp_dir().join(format!("idunsh-lock-{}", std::process::id()));
+    let first = FileLock::exclusive(&path).unwrap();
+    // Even in the same process, a second lock waits for the first
+    let (tx, rx) = mpsc::channel();
+    let second = {
+        let path = path.clone();
+        std::thread::spawn(move || {
+            let _lock = FileLock::exclusive(&path).unwrap();
+            tx.send(()).unwrap();
+        })
+    };
+    assert!(rx.recv_timeout(Duration::from_millis(200)).is_err());
+    drop(first);
+    rx.recv_timeout(Duration::from_secs(5)).unwrap();
+    second.join().unwrap();
+    std::fs::remove_file(path).unwrap();
+}
@@ -8,35 +8,116 @@ use std::process;
 use std::fs;
 use std::str;
 use std::thread;
-use std::time::Duration;
-use bstr::BString;
-use nix::unistd;
+use std::time::{Duration, Instant};
+use std::sync::{Arc, RwLock};
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::path::Path;
-use std::io::{Read, Write, stdout};
-use std::os::unix::net::{UnixListener, UnixStream};
-use clap::{Parser,Subcommand,ArgGroup};
+use std::io::{self, IsTerminal};
+use clap::{Parser,Subcommand,Args,ArgGroup,ValueEnum};
+use clap::builder::BoolishValueParser;
 use shell_words::split;
-mod util;
-use util::PetString;
+use idun_client::{util, runtime, cleanup, protocol, listing, petscii, encoding, serial};
+use idun_client::client::{IdunClient, Batch, Timeouts, LUAPORT, streams_call, crc_call};
+use idun_client::client::{EXEC_CMD, GO_CMD, LOAD_CMD, DIR_CMD, CATALOG_CMD, DRIVES_CMD, MOUNT_CMD, ASSIGN_CMD,
+    MKDIR_CMD, RMDIR_CMD};
+use protocol::RemoteError;
+mod parsers;
+mod confirm;
+use confirm::confirm;
+mod repl;
+use repl::Repl;
+mod config;
+use config::Config;
+use hooks::Hook;
+mod events;
+use events::{Activity, EventChannel, Poll};
+use util::{PetString, Newline};
+mod labels;
+use labels::Labels;
+mod hexdump;
+mod disasm;
+mod memory;
+mod mon;
+mod profiler;
+mod progress;
+use progress::{Progress, ProgressFormat, Summary};
+mod profile;
+use profile::Profile;
+mod tape;
+use tape::TapeCommands;
+mod basic;
+use basic::{BasicCommands, ConvertTo};
+use petscii::{Charset, Layout};
+mod watch;
+mod reload;
+mod theme;
+use theme::Theme;
+mod formats;
+mod image;
+use image::ImageCommands;
+mod disk;
+mod journal;
+mod kiosk;
+mod lock;
+mod queue;
+use queue::QueueCommands;
+mod cache;
+use cache::{Cache, CacheCommands};
+mod catalogs;
+mod transfers;
+mod errors;
+mod store;
+use errors::{At, Context, ErrorFormat, ExitStatus, Report};
+mod state;
+use state::StateCommands;
+mod target;
+use target::{Idun, Target};
 mod c64ultimate;
 use c64ultimate::C64Ultimate;
-
-const LUAPORT: &str          = "/tmp/idunmm-lua";
-
-// Supported shell command constants
-const EXEC_CMD: u8      = 0;
-const GO_CMD: u8        = 1;
-const LOAD_CMD: u8      = 2;
-const DIR_CMD: u8       = 3;
-const CATALOG_CMD: u8   = 4;
-const DRIVES_CMD: u8    = 5;
-const MOUNT_CMD: u8     = 6;
-const ASSIGN_CMD: u8    = 7;
+mod vice;
+use vice::Vice;
+mod status;
+mod saves;
+mod pokes;
+use pokes::CheatCommands;
+mod hiscore;
+mod smoke;
+mod frame;
+mod fuzz;
+mod clock;
+use clock::ClockCommands;
+mod ftp;
+mod pkg;
+mod pack;
+mod wic64;
+mod term;
+mod expmem;
+use expmem::ExpmemCommands;
+mod hooks;
+mod obs;
+mod collection;
+use collection::CollectionCommands;
+mod diz;
+mod init;
+mod ult;
+use ult::UltCommands;
+mod sector;
+use sector::SectorCommands;
+mod files;
+mod drive;
+mod dir;
+use dir::{ListFormat, PageOpts};
+mod output;
+use pkg::PkgCommands;
+use saves::SavesCommands;
 
 #[derive(Parser)]
 #[command(version, about, long_about=None, arg_required_else_help=true,
     group(
-        ArgGroup::new("command").required(true).args(&["cmd", "rest"])
+        ArgGroup::new("command").args(&["cmd", "rest"])
+    ),
+    group(
+        ArgGroup::new("redirect").args(&["output", "output_file", "raw"]).multiple(true)
     )
 )]
 struct Cli {
@@ -46,91 +127,691 @@ struct Cli {
     #[arg(short)]
     /// Redirect program output to terminal
     output: bool,
+    #[arg(short='O', long, value_name="file", conflicts_with="raw")]
+    /// Redirect program output to a file, byte for byte
+    output_file: Option<String>,
+    #[arg(long)]
+    /// Redirect program output to stdout byte for byte, without PETSCII translation
+    raw: bool,
+    #[arg(long, value_parser=util::parse_duration, value_name="time", requires="redirect")]
+    /// Give up on redirected output after this long without output or
+    /// heartbeat, also waiting for the remote side to connect
+    output_timeout: Option<Duration>,
+    #[arg(long, value_name="fd", requires="redirect")]
+    /// Write status messages of a program run with -o to this file descriptor
+    status_fd: Option<i32>,
+    #[arg(long, value_name="file", requires="redirect")]
+    /// Write error messages of a program run with -o to this file
+    stderr_file: Option<String>,
+    #[arg(long, requires="redirect")]
+    /// Check redirected output against a CRC the daemon sends at its end
+    crc: bool,
+    #[arg(short, long)]
+    /// Write redirected output as raw bytes, even if not valid UTF-8
+    bytes: bool,
+    #[arg(long, value_enum, default_value_t=Newline::Lf, value_name="style")]
+    /// Line ending used for redirected output
+    newline: Newline,
+    #[arg(long, value_enum, value_name="set")]
+    /// Character set for redirected output and convert, by default lower
+    charset: Option<Charset>,
+    #[arg(short, long)]
+    /// Don't ask before destructive commands
+    yes: bool,
+    #[arg(short, long)]
+    /// Don't print the summary after a program run with --wait or -o
+    quiet: bool,
+    #[arg(long, value_enum, value_name="format")]
+    /// Report transfer and mount progress on stderr
+    progress: Option<ProgressFormat>,
+    #[arg(long, value_enum, default_value_t=Profile::Safe, value_name="profile")]
+    /// Transfer settings for disk images, files and saves
+    profile: Profile,
+    #[arg(long)]
+    /// Keep assign, mount and put for later if the daemon isn't running;
+    /// they're sent once it's reached again
+    queue: bool,
     #[arg(short)]
     /// Use the C64 Ultimate runner to load content
     ultimate: bool,
+    #[arg(long, value_name="host:port", num_args=0..=1, default_missing_value="localhost:6502")]
+    /// Use the VICE emulator's binary monitor, for mon
+    vice: Option<String>,
     #[arg(short, long, value_name="flags")]
-    /// Add flag arguments to the command
-    xarg: Option<String>,
+    /// Add flag arguments to the command: letters, key=value or /name
+    xarg: Vec<String>,
+    #[arg(long, value_enum, default_value_t=ErrorFormat::Text, value_name="format")]
+    /// Print errors as text, or as JSON for scripts
+    errors: ErrorFormat,
+    #[arg(long, value_name="path")]
+    /// The daemon's Lua socket, or its UART as serial:///dev/ttyUSB0?baud=115200,
+    /// instead of the configured one
+    socket: Option<String>,
+    #[arg(long, value_name="name")]
+    /// Use a profile from the config file, e.g. for another cartridge
+    config_profile: Option<String>,
+    #[arg(long, conflicts_with="tsv")]
+    /// Print dir, catalog, drives and image list listings as JSON
+    json: bool,
+    #[arg(long)]
+    /// Print dir, catalog, drives and image list listings as tab-separated values
+    tsv: bool,
     #[arg(short, long, value_name="cmdline")]
     /// Pass sub-command as a single argument (for shell wrappers)
     cmd: Option<String>,
     #[arg(trailing_var_arg=true, value_name="COMMAND", help="Subcommand with arguments")]
     /// Pass sub-command as additional args (for normal CLI usage)
     rest: Vec<String>,
-    // TODO: Run idunsh in interactive mode
-    // #[arg(short)]
-    // interactive: bool,
+    #[arg(short, conflicts_with="command")]
+    /// Run commands typed at a prompt, with history and Tab completion
+    interactive: bool,
 }
 
 #[derive(Parser)]
 struct Syscommand {
     #[command(subcommand)]
     cmd: Syscommands,
+    /// The sub-command's name, for its settings in the config file
+    #[arg(skip)]
+    name: String,
 }
 
 #[derive(Subcommand)]
 enum Syscommands {
     /// Launch an application on the Commodore
-    Go { app:String},
+    Go {
+        app:String,
+        #[arg(long)]
+        /// Wait until the application exits
+        wait: bool,
+    },
     /// Launch a native program on the Commodore
-    Load { prg:String },
+    Load {
+        prg:String,
+        #[arg(long)]
+        /// Wait until the program exits
+        wait: bool,
+        #[command(flatten)]
+        player: PlayerOpts,
+    },
     /// Launch content on the C64 Ultimate
-    Run { prg:String },
+    Run { prg:String, #[command(flatten)] player: PlayerOpts },
+    /// Load a program again every time it changes, e.g. after each build
+    Watch {
+        prg:String,
+        #[arg(long)]
+        /// Stop the running program (reset the C64 Ultimate) before each load
+        reset_before: bool,
+        #[arg(long, default_value="300ms", value_parser=util::parse_duration, value_name="time")]
+        /// How long the file has to stay unchanged before it is loaded
+        settle: Duration,
+    },
     /// Execute remote idun command/program with arguments
-    Exec { cmd:String, args: Vec<String> },
+    Exec {
+        #[arg(long)]
+        /// Wait until the program exits
+        wait: bool,
+        cmd:String,
+        args: Vec<String>,
+    },
+    /// Execute a remote program with an argument template from the config,
+    /// e.g. x xlink game.prg
+    X {
+        /// The template's name, under [exec] in the config file
+        name:String,
+        /// Values for the names in braces in the template, in turn
+        values: Vec<String>,
+    },
     /// Get file list from Idun device using short format
-    Dir { dev:String },
+    Dir {
+        #[arg(value_name="DEV")]
+        /// One or more devices, fetched concurrently with -o; the configured
+        /// device, or chosen from a list on a terminal, if left out
+        devs: Vec<String>,
+        #[arg(short='R', long)]
+        /// List subdirectories too, each file with its size in blocks and its path
+        recursive: bool,
+        #[arg(long)]
+        /// Print the files as CSV: name, type, blocks, locked, splat, dir
+        csv: bool,
+    },
     /// Get file list from Idun device using long format
-    Catalog { dev:String },
+    Catalog {
+        dev:String,
+        #[arg(long)]
+        /// Print the files as CSV: name, type, blocks, locked, splat, dir
+        csv: bool,
+        #[arg(long, conflicts_with="csv")]
+        /// Print the disk's name and files as a FILE_ID.DIZ
+        diz: bool,
+        #[arg(long, requires="diz")]
+        /// Draw PETSCII graphics in the FILE_ID.DIZ with ASCII characters
+        ascii: bool,
+        #[command(flatten)]
+        page: PageOpts,
+    },
+    /// Find files by name on drives, in subdirectories too, e.g.
+    /// find "*.prg" c: d:
+    Find {
+        pattern: String,
+        /// The drives to look on, by default the configured device
+        devs: Vec<String>,
+    },
     /// Show list of the active virtual drives and mounts
-    Drives { dev:Option<String> },
+    Drives {
+        dev:Option<String>,
+        #[arg(long)]
+        /// Keep refreshing the listing in place, highlighting changes
+        watch: bool,
+        #[arg(long, default_value="2s", value_parser=util::parse_duration, value_name="time")]
+        /// Time between refreshes with --watch, at least 1s
+        interval: Duration,
+    },
     /// Mount a virtual floppy image
-    Mount { dev:String, dimage:String },
+    #[command(allow_missing_positional=true)]
+    Mount {
+        #[arg(default_value="", hide_default_value=true)]
+        /// Drive to mount on; the configured device, or chosen from a list on a terminal, if left out
+        dev:String,
+        dimage:String,
+    },
     /// Assign local path to a virtual drive
-    Assign { dev:String, path:String },
+    Assign {
+        dev:String,
+        path:String,
+        #[arg(long)]
+        /// Have idunsh refuse to change anything on the drive (put, edit,
+        /// mkdir, ...). Only idunsh keeps to it: the daemon isn't told, and
+        /// the Commodore itself still can
+        protect: bool,
+        #[arg(long)]
+        /// Keep running, journaling every file the Commodore changes in path
+        journal: bool,
+    },
+    /// Show the changes journaled by assign --journal
+    Journal {
+        dev:String,
+        #[arg(long, value_name="n")]
+        /// Show only the last n changes
+        tail: Option<usize>,
+    },
+    /// Create a subdirectory or partition, e.g. c:games
+    Mkdir { path:String },
+    /// Remove an empty subdirectory or partition
+    Rmdir { path:String },
+    /// Send a DOS command to a drive and show its answer, e.g. dos c: "s0:old"
+    Dos {
+        dev:String,
+        /// The command; \xNN stands for any byte, e.g. "m-r\x00\x05\x10"
+        cmd:String,
+    },
+    /// Show the status on a drive's error channel
+    Err { dev:String },
+    /// Read or write single sectors of a drive or disk image
+    Sector {
+        #[command(subcommand)]
+        cmd: SectorCommands,
+    },
+    /// Convert between PETSCII files and UTF-8 text, e.g. convert game.prg game.bas
+    Convert {
+        input: String,
+        output: String,
+        #[arg(long, value_enum, value_name="kind")]
+        /// What to write; by default prg for a .prg output, seq for .seq, text otherwise
+        to: Option<ConvertTo>,
+        #[arg(long, default_value="0801", value_parser=util::parse_addr, value_name="addr")]
+        /// Load address of a BASIC program written
+        load: u16,
+    },
+    /// Convert datasette images between TAP and WAV
+    Tape {
+        #[command(subcommand)]
+        cmd: TapeCommands,
+    },
+    /// Index a collection of images and programs and search it; `@` and
+    /// a title then names one to mount, load or run
+    Collection {
+        #[command(subcommand)]
+        cmd: CollectionCommands,
+    },
+    /// Work with disk images locally, or copy whole disks from and to real drives
+    Image {
+        #[command(subcommand)]
+        cmd: ImageCommands,
+    },
+    /// Copy a file to a drive, e.g. put game.prg c: or put notes.seq c:log
+    Put {
+        file:String,
+        /// The drive, with the name to store the file under if it differs
+        dest:String,
+    },
+    /// Copy a file from a drive, e.g. get c:game game.prg, or get c:game - > game.prg;
+    /// with wildcards, every file that matches into a directory, e.g. get "c:*.seq" logs
+    Get {
+        src:String,
+        /// Where to write it, by default the file's name on the drive; - for stdout
+        file:Option<String>,
+    },
+    /// Edit a SEQ file on a drive as text in $VISUAL or $EDITOR, e.g. edit c:notes
+    Edit { file:String },
+    /// Write protect a file, e.g. c:game
+    Lock { file:String },
+    /// Remove the write protection from a file
+    Unlock { file:String },
     /// Fully reboot the idun cartridge and Commodore
     Reboot,
-    /// Stop a running program (sends "STOP" key)
+    /// Stop a running program (sends "STOP" key, or resets the C64U to BASIC)
     Stop,
+    /// Identify a content file, local or on a drive (e.g. c:game): type, size, load address and checksum
+    Info { file:String },
+    /// Set up idunsh: find the daemon and the C64U, write the config file,
+    /// assign the standard drives and check that everything answers
+    Init,
+    /// Show C64 memory as a hexdump (C64 Ultimate)
+    Peek {
+        /// Start address in hex, or a label name
+        addr: String,
+        #[arg(default_value="256", value_parser=util::parse_number)]
+        /// Number of bytes to show
+        len: u32,
+        #[arg(long, default_value_t=16)]
+        /// Bytes per row
+        width: usize,
+        #[arg(long)]
+        /// Show the text column as screen codes rather than PETSCII
+        screen_codes: bool,
+        #[arg(long, value_name="file")]
+        /// Label file used to name addresses (VICE or name=$addr format)
+        labels: Option<String>,
+    },
+    /// Show the C64's text screen here and type on it, until Ctrl-]
+    Term {
+        #[arg(long, default_value="0400", value_parser=util::parse_addr, value_name="addr")]
+        /// Where screen memory is, in hex
+        screen: u16,
+        #[arg(long, default_value="250ms", value_parser=util::parse_duration, value_name="time")]
+        /// How often to read the screen
+        interval: Duration,
+    },
+    /// Copy files into and out of REU or GeoRAM memory, e.g. expmem put
+    /// data.bin --offset 0x10000
+    Expmem {
+        #[command(subcommand)]
+        cmd: ExpmemCommands,
+    },
+    /// Examine and change C64U memory with a machine code monitor
+    Mon {
+        #[arg(long, value_name="file")]
+        /// Label file used to name addresses (VICE or name=$addr format)
+        labels: Option<String>,
+    },
+    /// Compare memory with a file, listing the bytes that differ, e.g.
+    /// memcmp c000 code.bin
+    Memcmp {
+        #[arg(value_parser=util::parse_addr)]
+        /// Where the file's bytes belong, in hex
+        addr: u16,
+        file: String,
+    },
+    /// Report changes to memory as they happen, e.g. memwatch 0400 28
+    Memwatch {
+        #[arg(value_parser=util::parse_addr)]
+        /// Start address in hex
+        addr: u16,
+        #[arg(value_parser=util::parse_number)]
+        /// Number of bytes to watch
+        len: u32,
+        #[arg(long, default_value="100ms", value_parser=util::parse_duration, value_name="time")]
+        /// How often to read the memory
+        interval: Duration,
+        #[arg(long)]
+        /// Show which pages change, as a map redrawn in place
+        heatmap: bool,
+    },
+    /// Count where the C64U's CPU spends its cycles, from the debug stream,
+    /// e.g. profile --seconds 10 out.json
+    Profile {
+        #[arg(long, default_value_t=5, value_name="n")]
+        /// How long to capture
+        seconds: u64,
+        /// Where to write the JSON report; - for stdout
+        out: String,
+        #[arg(long, value_name="file")]
+        /// Label file whose labels start the ranges counted (VICE or name=$addr format)
+        labels: Option<String>,
+        #[arg(long, default_value_t=profiler::DEBUG_PORT)]
+        /// UDP port to receive the debug stream on
+        port: u16,
+    },
+    /// Inspect the BASIC program in the C64U's memory
+    Basic {
+        #[command(subcommand)]
+        cmd: BasicCommands,
+    },
+    /// Type on the Commodore, e.g. keys 'load"*",8{return}'; names in
+    /// braces stand for keys such as {return}, {runstop}, {f1} or {up}
+    Keys { #[command(flatten)] text: TypedText },
+    /// Print a one-line summary for status bars, e.g.
+    /// status --format '{backend} {running|idle} {drive8}'
+    Status {
+        #[arg(long, default_value="{backend} {running}", value_name="template")]
+        /// Fields: backend, running, and drive followed by a device, e.g.
+        /// drivec, or a bus id on the C64U, e.g. drive8
+        format: String,
+        #[arg(long, value_parser=util::parse_duration, value_name="time")]
+        /// Keep printing a new line this often
+        interval: Option<Duration>,
+    },
+    /// Show the items of a playlist one after another, resetting between them
+    Kiosk { playlist: String },
+    /// Answer programs written for the WiC64 from this machine, until
+    /// interrupted
+    Wic64Bridge,
+    /// Tell OBS browser sources what runs and show the screen (C64U),
+    /// over HTTP until interrupted
+    ObsBridge {
+        #[arg(long, default_value=obs::LISTEN, value_name="addr:port")]
+        /// Where to answer
+        listen: String,
+        #[arg(long, default_value_t=frame::VIDEO_PORT)]
+        /// UDP port to receive the video stream on
+        port: u16,
+    },
+    /// Apply cheats from a poke file to the game in memory
+    Cheat {
+        #[command(subcommand)]
+        cmd: CheatCommands,
+    },
+    /// Read a game's scores from memory, as named by a game profile, e.g.
+    /// hiscore boulderdash --webhook https://example.org/scores
+    Hiscore {
+        /// Profile file, or the name of one in ~/.config/idunsh/hiscore
+        game: String,
+        #[arg(long, value_name="url")]
+        /// Also post the scores as JSON to this URL
+        webhook: Option<String>,
+        #[arg(long)]
+        /// Print the scores as JSON
+        json: bool,
+    },
+    /// Run a program on the C64U with the keys and screens of a test plan,
+    /// e.g. test plan.toml
+    Test {
+        plan: String,
+        #[arg(long)]
+        /// Save the video frames as the golden images rather than compare them
+        update: bool,
+        #[arg(long, default_value_t=frame::VIDEO_PORT)]
+        /// UDP port to receive the video stream on
+        port: u16,
+    },
+    /// Type random keys into the program running on the C64U until it
+    /// breaks or time is up, e.g. fuzz --keys --duration 60s --canary 0810=a9
+    Fuzz {
+        #[arg(long)]
+        /// Fuzz with keys; the default, as the C64U takes no other input
+        keys: bool,
+        #[arg(long, default_value="60s", value_parser=util::parse_duration, value_name="time")]
+        /// How long to go on
+        duration: Duration,
+        #[arg(long, value_parser=fuzz::Canary::parse, value_name="addr=value")]
+        /// A byte that must keep its value, in hex; may be repeated
+        canary: Vec<fuzz::Canary>,
+        #[arg(long, value_name="pattern")]
+        /// Screen text that shows a crash, by default "ready." and "?*error";
+        /// may be repeated
+        screen: Vec<String>,
+        #[arg(long, value_name="n")]
+        /// Start the random keys from this seed, to type them again
+        seed: Option<u64>,
+        #[arg(long, value_name="file")]
+        /// Also write the keys to this file as they are typed
+        log: Option<String>,
+    },
+    /// Set the Commodore's clocks from this machine's, or show them
+    Clock {
+        #[command(subcommand)]
+        cmd: ClockCommands,
+    },
+    /// Switch the C64 on through a smart plug, or shut the C64U down, e.g.
+    /// power on
+    Power {
+        #[arg(value_enum)]
+        state: PowerState,
+        #[arg(long, default_value="60s", value_parser=util::parse_duration, value_name="time")]
+        /// How long to wait for the C64U to answer after power on; 0 not to
+        boot: Duration,
+    },
+    /// Install idun applications from the package index in the config
+    /// file, e.g. pkg install ftp
+    Pkg {
+        #[command(subcommand)]
+        cmd: PkgCommands,
+    },
+    /// Bundle the drives' images and directories, and a config fragment,
+    /// into one .idunpack file, e.g. pack demo-night.idunpack
+    Pack {
+        file: String,
+        #[arg(long)]
+        /// The name to unpack it as; the file's by default
+        name: Option<String>,
+        #[arg(long)]
+        description: Option<String>,
+        #[arg(long="drive", value_name="dev")]
+        /// A drive to include, as many as needed; all assigned and mounted ones by default
+        drives: Vec<String>,
+        #[arg(long, value_name="file")]
+        /// Aliases, keys, exec templates and xargs to add to the config
+        config: Option<String>,
+    },
+    /// Unpack an .idunpack file and set its drives up as it says
+    Unpack {
+        file: String,
+        #[arg(long, value_name="path")]
+        /// Where to unpack it; under ~/.local/share/idunsh/packs by default
+        dir: Option<String>,
+    },
+    /// Keep snapshots of game saves and bring them back
+    Saves {
+        #[command(subcommand)]
+        cmd: SavesCommands,
+    },
+    /// Send or inspect the commands kept with --queue
+    Queue {
+        #[command(subcommand)]
+        cmd: QueueCommands,
+    },
+    /// List or empty the cache of downloaded files
+    Cache {
+        #[command(subcommand)]
+        cmd: CacheCommands,
+    },
+    /// Save or restore the assigns and mounts of the virtual drives
+    State {
+        #[command(subcommand)]
+        cmd: StateCommands,
+    },
+    /// C64 Ultimate specific commands
+    #[command(visible_alias="ultimate")]
+    Ult {
+        #[command(subcommand)]
+        cmd: UltCommands,
+    },
 }
-fn parse_sys_command(cli: &Cli) -> Syscommand {
-    let mut argv = vec!["idunsh".to_string()];
-
-    if let Some(cmdline) = &cli.cmd {
-        argv.extend(
-            split(&cmdline).unwrap_or_else(|e| {
-                eprintln!("Invalid --cmd syntax: {e}");
-                std::process::exit(2);
-            }),
-        );
-    } else {
-        argv.extend(cli.rest.clone());
+
+#[derive(Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum PowerState {
+    On,
+    Off,
+}
+
+/// Text to type on the Commodore
+#[derive(Args)]
+struct TypedText {
+    #[arg(required_unless_present="stdin")]
+    text: Option<String>,
+    #[arg(long, conflicts_with="text")]
+    /// Type the text read from stdin, e.g. to paste a BASIC program
+    stdin: bool,
+}
+
+impl TypedText {
+    /// The keys that type the text
+    fn keys(self, charset: Charset, layout: Layout) -> Result<Vec<u8>> {
+        let text = match self.text {
+            Some(text) => text,
+            None => io::read_to_string(io::stdin())?,
+        };
+        petscii::keys(&text, charset, layout)
     }
+}
+
+/// Options for music played on the C64 Ultimate
+#[derive(Args)]
+struct PlayerOpts {
+    #[arg(long, value_parser=util::parse_duration, value_name="time")]
+    /// Stop SID playback after this long (e.g. 90s, 3m)
+    duration: Option<Duration>,
+    #[arg(long, value_parser=util::parse_duration, value_name="time", requires="duration")]
+    /// Fade the volume out over the end of the duration
+    fade: Option<Duration>,
+    #[arg(long="loop", value_name="on|off", value_parser=BoolishValueParser::new(), hide_possible_values=true)]
+    /// Repeat MOD playback when the end of the song is reached
+    looping: Option<bool>,
+    #[arg(long, value_parser=clap::value_parser!(u32).range(8000..=48000), value_name="hz")]
+    /// Sample rate for MOD playback
+    rate: Option<u32>,
+}
+
+impl PlayerOpts {
+    /// Runner query parameters for the MOD player options
+    fn mod_options(&self, prg: &str) -> Result<Vec<(&'static str, String)>> {
+        let mut opts = vec![];
+        if let Some(looping) = self.looping {
+            opts.push(("loop", String::from(if looping { "1" } else { "0" })));
+        }
+        if let Some(rate) = self.rate {
+            opts.push(("rate", rate.to_string()));
+        }
+        if !opts.is_empty() && !prg.to_lowercase().ends_with(".mod") {
+            bail!("--loop and --rate only apply to MOD playback")
+        }
+        Ok(opts)
+    }
+}
+
+fn parse_sys_command(cli: &Cli, config: &Config) -> result::Result<Syscommand, clap::Error> {
+    let words = match &cli.cmd {
+        Some(cmdline) => split(cmdline).map_err(|e| {
+            clap::Error::raw(clap::error::ErrorKind::ValueValidation, format!("Invalid --cmd syntax: {e}\n"))
+        })?,
+        None => cli.rest.clone(),
+    };
+    let words = config.expand_alias(words)
+        .map_err(|e| clap::Error::raw(clap::error::ErrorKind::ValueValidation, format!("{e}\n")))?;
 
-    Syscommand::parse_from(argv)
+    use clap::{CommandFactory, FromArgMatches};
+    let matches = Syscommand::command().try_get_matches_from(["idunsh".to_string()].into_iter().chain(words))?;
+    let mut syscmd = Syscommand::from_arg_matches(&matches)?;
+    syscmd.name = matches.subcommand_name().unwrap_or_default().to_string();
+    Ok(syscmd)
 }
 
 // Simpler error handling
 type Result<T> = result::Result<T, failure::Error>;
 
+// The daemon connection of the command being run, from the config file
+static CLIENT: RwLock<Option<IdunClient>> = RwLock::new(None);
+
+fn idun() -> IdunClient {
+    CLIENT.read().ok().and_then(|c| c.clone()).unwrap_or_default()
+}
+
 fn luasend(message: String) -> Result<()> {
-    let mut s = UnixStream::connect(LUAPORT)?;
-    let mut r: Vec<u8> = Vec::new();
+    idun().send(message)
+}
 
-    s.write_all(message.as_bytes())?;
-    s.write(&['\n' as u8])?;
-    s.read_to_end(&mut r)?;
-    if r.len()>0 && r[0]>0 {
-        let emsg = str::from_utf8(&r[1..])?;
-        eprintln!("Remote sys.shell() fail: {}", emsg);
+fn shell(cmd: u8, args: &str, proc: u32) -> Result<()> {
+    idun().shell(cmd, args, proc)
+}
+
+fn batch(batch: Batch) -> Result<()> {
+    idun().batch(&batch, 0)
+}
+
+fn daemon_reachable() -> bool {
+    idun().reachable()
+}
+
+fn capture_shell(cmd: u8, args: &str) -> Result<PetString> {
+    idun().capture_shell(cmd, args)
+}
+
+fn capture(send: impl FnOnce(u32) -> Result<()>) -> Result<Vec<u8>> {
+    idun().capture(send)
+}
+
+// Opens the event channel and asks the daemon to start sending events
+fn subscribe_events() -> Result<EventChannel> {
+    if !Idun.capabilities()?.events {
+        return Err(target::unsupported(&Idun, "Waiting for exit (--wait)"))
+    }
+    let ev = EventChannel::bind(idun().run_dir())?;
+    match luasend(format!("sys.events({})", protocol::lua_string(ev.path()))) {
+        Ok(()) => Ok(ev),
+        // Older daemons have no event channel; their state is polled
+        Err(e) if e.iter_chain().any(|f| f.downcast_ref::<RemoteError>().is_some()) =>
+            Ok(EventChannel::polling(poll_daemon)),
+        Err(e) => Err(e),
     }
-    Ok(())
 }
 
-fn shell(cmd: u8, args: &String, proc: u32) -> Result<()> {
-    let cmd = format!("sys.shell({}, \"{}\", {})", cmd, args, proc);
-    luasend(cmd)
+// The daemon's state, for event channels made by polling. The drives
+// can't be listed while a program runs; the daemons without events
+// refuse with a status of their own rather than busy, so any refusal
+// counts.
+fn poll_daemon() -> Result<Poll> {
+    match idun().drives() {
+        Ok(mounts) => Ok(Poll::Idle(mounts.into_iter().map(|m| (m.device, m.target)).collect())),
+        Err(e) if e.iter_chain().any(|f| f.downcast_ref::<RemoteError>().is_some()) => Ok(Poll::Busy),
+        Err(e) => Err(e),
+    }
+}
+
+fn check_subdirectories() -> Result<()> {
+    match Idun.capabilities()?.subdirectories {
+        true => Ok(()),
+        false => Err(target::unsupported(&Idun, "Subdirectories")),
+    }
+}
+
+// The configured device, or one picked from the active drives
+fn default_device(connection: &config::Connection) -> Result<String> {
+    match &connection.device {
+        Some(dev) => Ok(dev.clone()),
+        None => pick_device(),
+    }
+}
+
+// Lets the user choose one of the active drives, when there's a user to ask
+fn pick_device() -> Result<String> {
+    if !io::stdin().is_terminal() || !io::stderr().is_terminal() {
+        bail!("No device given, e.g. c:")
+    }
+    let listing = String::from(capture_shell(DRIVES_CMD, "")?);
+    let drives: Vec<&str> = listing.split(['\r', '\n']).filter(|l| l.contains(':')).collect();
+    if drives.is_empty() {
+        bail!("No device given, and there are no active drives to choose from")
+    }
+    let choice = dialoguer::Select::new()
+        .with_prompt("Drive")
+        .items(&drives)
+        .default(0)
+        .interact_opt()?
+        .ok_or_else(|| format_err!("No drive chosen"))?;
+    // Listing lines start with the device, e.g. "c:=/home/idun"
+    let line = drives[choice];
+    Ok(line[..=line.find(':').unwrap_or_default()].trim().to_string())
 }
 
 fn stop_cmd() -> Result<()> {
@@ -138,158 +819,582 @@ fn stop_cmd() -> Result<()> {
     luasend(cmd)
 }
 
+// The program running on the Commodore, if the daemon is busy with
+// one; named after what was loaded last, as the daemon doesn't tell
+fn running_program() -> Option<String> {
+    match poll_daemon() {
+        Ok(Poll::Busy) => Some(obs::Playing::last().map(|p| p.name).filter(|n| !n.is_empty())
+            .unwrap_or_else(|| String::from("A program"))),
+        _ => None,
+    }
+}
+
 fn reboot_cmd(mode: u8) -> Result<()> {
     let cmd = format!("sys.reboot({})", mode);
     luasend(cmd)
 }
 
-fn main() -> Result<()> {
+// True for idun style device paths such as "c:" or "d:game.prg"
+fn is_device_path(path: &str) -> bool {
+    let b = path.as_bytes();
+    b.len() >= 2 && b[1] == b':' && !Path::new(path).exists()
+        && (b[0].is_ascii_alphabetic() || b"@[\\]^_".contains(&b[0]))
+}
+
+// How long the exit of a program run with -o is waited for once its
+// output has ended
+const EXIT_GRACE: Duration = Duration::from_secs(2);
+
+// Remote failures exit with a status telling what went wrong; see
+// `errors`
+fn main() {
+    cleanup::install();
     let cli = Cli::parse();
+    let format = cli.errors;
+    let result = Config::load().and_then(|config| match cli.interactive {
+        true => interactive(&config),
+        false => {
+            let syscmd = parse_sys_command(&cli, &config).unwrap_or_else(|e| e.exit());
+            let name = syscmd.name.clone();
+            let result = run(cli, syscmd, &config).at(Context::Command(name.clone()));
+            failed(&config, &name, &result);
+            result
+        },
+    });
+    cleanup::remove_all();
+    if let Err(e) = result {
+        let report = Report::new(&e);
+        report.print(format);
+        process::exit(report.status);
+    }
+}
+
+// Runs command lines typed at a prompt until the input ends or `exit`.
+// A failing command is reported and the session goes on.
+fn interactive(config: &Config) -> Result<()> {
+    use clap::CommandFactory;
+    let commands = Syscommand::command().get_subcommands()
+        .map(|c| c.get_name().to_string())
+        .chain(["exit".to_string()])
+        .collect();
+    let mut repl = Repl::new(commands, &config.keys, |dir| catalogs::cached_names(dir).unwrap_or_default())?;
+    // Listings are refreshed only between commands
+    let waiting = Arc::new(AtomicBool::new(false));
+    connect(None, &config.connection(None)?, config.timeouts("")?)?;
+    catalogs::refresh(waiting.clone());
+    loop {
+        waiting.store(true, Ordering::Relaxed);
+        let read = repl.read("idunsh> ");
+        waiting.store(false, Ordering::Relaxed);
+        let Some(line) = read? else { break };
+        let words = match split(&line) {
+            Ok(words) => words,
+            Err(e) => {
+                eprintln!("{}", e);
+                continue
+            },
+        };
+        if matches!(words.first().map(String::as_str), Some("exit" | "quit")) {
+            break
+        }
+        let parsed = Cli::try_parse_from(["idunsh".to_string()].into_iter().chain(words))
+            .and_then(|cli| Ok((parse_sys_command(&cli, config)?, cli)));
+        let (result, format) = match parsed {
+            Ok((_, cli)) if cli.interactive => (Err(format_err!("Already in interactive mode")), cli.errors),
+            Ok((syscmd, cli)) => {
+                let (name, format) = (syscmd.name.clone(), cli.errors);
+                let result = run(cli, syscmd, config).at(Context::Command(name.clone()));
+                failed(config, &name, &result);
+                (result, format)
+            },
+            Err(e) => {
+                let _ = e.print();
+                continue
+            },
+        };
+        cleanup::remove_all();
+        if let Err(e) = result {
+            Report::new(&e).print(format);
+        }
+    }
+    Ok(())
+}
+
+// Runs the on_error hook if the command `name` failed
+fn failed(config: &Config, name: &str, result: &Result<()>) {
+    if let Err(e) = result {
+        let report = Report::new(e);
+        hooks::after(&config.hooks, Hook::OnError, name, &[
+            ("ERROR", &report.error), ("STATUS", &report.status.to_string()),
+        ]);
+    }
+}
+
+// Talks to the daemon at `socket`, or as configured, from now on
+fn connect(socket: Option<&str>, connection: &config::Connection, timeouts: Timeouts) -> Result<()> {
+    let socket = socket.or(connection.socket.as_deref()).unwrap_or(LUAPORT);
+    let mut client = IdunClient::with_socket(socket).with_timeouts(timeouts);
+    if let Some(dir) = &connection.run_dir {
+        client = client.with_run_dir(dir);
+    }
+    if let Some(name) = &connection.encoding {
+        let encoding = encoding::by_name(name)?;
+        if serial::Port::parse(socket)?.is_some() && encoding.name() != "raw" {
+            bail!("A serial line carries SLIP frames already; leave out `encoding = {:?}`", name)
+        }
+        client = client.with_encoding(encoding);
+    }
+    if let Ok(mut c) = CLIENT.write() {
+        *c = Some(client);
+    }
+    Ok(())
+}
+
+fn run(mut cli: Cli, mut syscmd: Syscommand, config: &Config) -> Result<()> {
+    errors::using(None);
+    // -O and --raw redirect output the way -o does
+    cli.output |= cli.output_file.is_some() || cli.raw;
     let mut xargs = String::new();
+    let progress = Progress::new(cli.progress);
+    let yes = cli.yes || config.yes;
+    let connection = config.connection(cli.config_profile.as_deref())?;
+    let timeouts = config.timeouts(&syscmd.name)?;
+    connect(cli.socket.as_deref(), &connection, timeouts)?;
+    let charset = cli.charset.or(config.charset).unwrap_or_default();
+    // Keys are typed for the set the Commodore starts in, unless told
+    let typing = cli.charset.unwrap_or(Charset::Upper);
 
-    // Extract the sub-command
-    let syscmd = parse_sys_command(&cli);
+    // Local commands need neither the cartridge nor the C64U
+    if let Syscommands::Init = syscmd.cmd {
+        return init::run(cli.socket.as_deref(), yes, timeouts);
+    }
+    if let Syscommands::Info { file } = &syscmd.cmd {
+        return files::info(file, cli.profile);
+    }
+    if let Syscommands::Cache { cmd } = syscmd.cmd {
+        return cache::run(cmd, yes);
+    }
+    if let Syscommands::Tape { cmd } = syscmd.cmd {
+        return tape::run(cmd);
+    }
+    if let Syscommands::Convert { input, output, to, load } = &syscmd.cmd {
+        return basic::convert(input, output, *to, *load, charset);
+    }
+    if let Syscommands::Image { cmd } = &syscmd.cmd {
+        if !cmd.needs_drive() {
+            let format = ListFormat::of(&cli, matches!(cmd, ImageCommands::List { csv: true, .. }));
+            return image::run(cmd, format, &Theme::load(&config.theme)?, cli.profile, progress, yes);
+        }
+    }
+    if let Syscommands::Collection { cmd } = syscmd.cmd {
+        return collection::run(cmd, cli.json);
+    }
+    if let Syscommands::Journal { dev, tail } = &syscmd.cmd {
+        return journal::run(dev, *tail);
+    }
+    if let Syscommands::Pack { file, name, description, drives, config } = &syscmd.cmd {
+        return pack::pack_cmd(file, name.as_deref(), description.as_deref(), drives, config.as_deref());
+    }
+    if let Syscommands::Power { state: PowerState::On, boot } = syscmd.cmd {
+        return ult::power_on(config, connection.c64u_ip.clone(), boot);
+    }
+    // A template from the config becomes the exec it stands for
+    if let Syscommands::X { name, values } = &syscmd.cmd {
+        let template = config.exec.get(name)
+            .ok_or_else(|| format_err!("There is no [exec.{}] template in the config file", name))?;
+        let args = template.expand(values, || default_device(&connection))?;
+        let cmd = template.program.clone().unwrap_or_else(|| name.clone());
+        syscmd.cmd = Syscommands::Exec { wait: false, cmd, args };
+    }
+    // C64U commands that are general ones run with -u
+    let ult = matches!(syscmd.cmd, Syscommands::Ult { .. });
+    if let Syscommands::Ult { cmd } = &mut syscmd.cmd {
+        let general = match std::mem::replace(cmd, UltCommands::Swap) {
+            UltCommands::Load { prg, player } => Syscommands::Run { prg, player },
+            UltCommands::Mount { dev, dimage } => Syscommands::Mount { dev, dimage },
+            UltCommands::Drives { dev } => Syscommands::Drives { dev, watch: false, interval: Duration::from_secs(2) },
+            other => Syscommands::Ult { cmd: other },
+        };
+        syscmd.cmd = general;
+    }
+    if let Syscommands::Cheat { cmd: CheatCommands::Ls { file } } = &syscmd.cmd {
+        return pokes::list(file);
+    }
+    // The emulator stands in for the C64 where memory is all that's needed
+    if let Some(address) = &cli.vice {
+        errors::using(Some("vice"));
+        let vice = Vice::connect(address)?;
+        return match syscmd.cmd {
+            cmd @ (Syscommands::Mon { .. } | Syscommands::Memcmp { .. } | Syscommands::Memwatch { .. } |
+                   Syscommands::Cheat { .. } | Syscommands::Hiscore { .. } | Syscommands::Clock { .. } |
+                   Syscommands::Term { .. } | Syscommands::Expmem { .. }) =>
+                memory::run(&vice, cmd, charset, progress),
+            _ => Err(target::unsupported(&vice, "This command")),
+        }
+    }
+    // Files given by URL are downloaded to the cache and used from there
+    // The C64U is looked for while any URL is downloaded
+    let ultimate = cli.ultimate || ult || matches!(syscmd.cmd, Syscommands::Run{..} | Syscommands::Peek{..} |
+        Syscommands::Basic{..} | Syscommands::Mon{..} | Syscommands::Profile{..} |
+        Syscommands::Memcmp{..} | Syscommands::Memwatch{..} | Syscommands::Cheat{..} | Syscommands::Hiscore{..} |
+        Syscommands::Test{..} | Syscommands::Fuzz{..} | Syscommands::Power{..} | Syscommands::Term{..} |
+        Syscommands::Expmem{..});
+    // Detection looks on the LAN even with an address configured
+    let detect = matches!(syscmd.cmd, Syscommands::Ult { cmd: UltCommands::Detect });
+    let discovery = ultimate.then(|| C64Ultimate::discover(connection.c64u_ip.clone().filter(|_| !detect), timeouts));
+    if let Syscommands::Mount { dimage: file, .. } | Syscommands::Load { prg: file, .. } |
+           Syscommands::Run { prg: file, .. } = &mut syscmd.cmd {
+        if cache::is_url(file) {
+            *file = Cache::open()?.fetch(file)?.to_string_lossy().into_owned();
+        }
+        if let Some(query) = file.strip_prefix('@') {
+            *file = collection::Collection::load()?.find(query)?.path.clone();
+        }
+    }
 
     // Check for C64-Ultimate commands first, since they circumvent chrir and redirect processing
-    if cli.ultimate || matches!(syscmd.cmd, Syscommands::Run{..}) {
+    if let Some(discovery) = discovery {
         // Check that we have access to the C64 Ultimate web service
-        let c64u = C64Ultimate::new();
+        errors::using(Some("c64u"));
+        let c64u = discovery.connect().with_progress(progress);
         if c64u.ip().is_none() {
-            bail!("C64 Ultimate loads require $C64_ULTIMATE_IP set!")
+            if detect {
+                bail!("No C64 Ultimate answered on the LAN; is its Ident Service enabled?")
+            }
+            bail!("C64 Ultimate loads require $C64_ULTIMATE_IP or c64u_ip in the config file!")
         }
 
         match syscmd.cmd {
-            Syscommands::Load { prg } |
-            Syscommands::Run  { prg } =>
-                return c64u.load(&prg),
-            Syscommands::Mount { dev, dimage } =>
-                return c64u.mount(&dev, &dimage),
-            Syscommands::Drives { dev } => {
-                match c64u.getdrv(&dev) {
-                    Ok(ultid) => {
-                        for entry in ultid.drives {
-                            let (drive, settings) = entry.devices.into_iter().next().unwrap();
-                            if drive.len()==1 {     // Just listing a:, b:
-                                if settings.enabled {
-                                    println!("{}", format!("{}:={}", drive, settings.image_file.unwrap()));
-                                } else {
-                                    println!("{}", format!("{}:={}", drive, "<Disabled>"));
-                                }
-                            }
-                        }
-                        return Ok(())
-                    }
-                    Err(e) => bail!("C64 Ultimate drive settings Error: {}", e)
+            Syscommands::Go { wait: true, .. } | Syscommands::Load { wait: true, .. } |
+            Syscommands::Exec { wait: true, .. } if !c64u.capabilities()?.events =>
+                return Err(target::unsupported(&c64u, "Waiting for exit (--wait)")),
+            Syscommands::Mkdir { .. } | Syscommands::Rmdir { .. } if !c64u.capabilities()?.subdirectories =>
+                return Err(target::unsupported(&c64u, "Subdirectories")),
+            Syscommands::Load { prg, wait: false, player } |
+            Syscommands::Run  { prg, player } => return ult::load(&c64u, &prg, &player, config, &syscmd.name),
+            Syscommands::Mount { dev, dimage } => return ult::mount(&c64u, &dev, &dimage, config, &syscmd.name),
+            Syscommands::Drives { dev, watch, interval } =>
+                return dir::ult_drives(&c64u, &dev, watch.then_some(interval), ListFormat::of(&cli, false), &Theme::load(&config.theme)?),
+            // Stops the runner's player with a C64 reset; mounts and the
+            // Ultimate itself are left alone.
+            Syscommands::Stop => return c64u.stop(),
+            Syscommands::Watch { prg, reset_before, settle } => return reload::run(Some(&c64u), &prg, reset_before, settle),
+            Syscommands::Status { format, interval } => return status::run(&format, interval, Some(&c64u)),
+            Syscommands::ObsBridge { listen, port } => return obs::run(&listen, Some(&c64u), port),
+            Syscommands::Ult { cmd } => return ult::run(&c64u, cmd, typing, config.keyboard, yes),
+            Syscommands::Keys { text } => return c64u.type_text(&text.keys(typing, config.keyboard)?),
+            Syscommands::Kiosk { playlist } => return kiosk::run(Some(&c64u), &playlist),
+            Syscommands::Power { .. } => return ult::power_off(&c64u, config),
+            cmd @ Syscommands::Fuzz { .. } => return fuzz::run(&c64u, cmd, typing, config.keyboard),
+            Syscommands::Test { plan, update, port } =>
+                return smoke::run(&c64u, &smoke::Plan::load(&plan)?, update, port, typing, config.keyboard),
+            Syscommands::Profile { seconds, out, labels, port } => {
+                return profiler::run(&c64u, Duration::from_secs(seconds), &out, &Labels::open(labels.as_deref())?, port)
+            },
+            cmd @ (Syscommands::Peek { .. } | Syscommands::Basic { .. } | Syscommands::Mon { .. } |
+                   Syscommands::Memcmp { .. } | Syscommands::Memwatch { .. } | Syscommands::Cheat { .. } |
+                   Syscommands::Hiscore { .. } | Syscommands::Clock { .. } | Syscommands::Term { .. } |
+                   Syscommands::Expmem { .. }) => {
+                if !c64u.capabilities()?.memory_access {
+                    return Err(target::unsupported(&c64u, "Memory access"))
                 }
-                // Idun virtual drives handled below...
+                return memory::run(&c64u, cmd, charset, progress)
             },
-            _ => bail!("Command not supported for the C64 Ultimate")
+            _ => return Err(target::unsupported(&c64u, "This command"))
         }
     }
 
+    errors::using(Some("idun"));
+    if let Syscommands::Status { format, interval } = &syscmd.cmd {
+        return status::run(format, *interval, None)
+    }
+    // A device left out is the configured one, or picked from the drives listing
+    match &mut syscmd.cmd {
+        Syscommands::Mount { dev, .. } if dev.is_empty() => *dev = default_device(&connection)?,
+        Syscommands::Dir { devs, .. } | Syscommands::Find { devs, .. } if devs.is_empty() =>
+            devs.push(default_device(&connection)?),
+        _ => (),
+    }
+    // With --queue, assigns, mounts and puts are kept for later if the daemon is down
+    if cli.queue && queue::defer(&syscmd.cmd)? {
+        return Ok(())
+    }
+    if let Syscommands::Queue { cmd } = syscmd.cmd {
+        return queue::run(cmd, cli.profile, progress, yes);
+    }
+    // Puts cut short by an earlier run are put right, and what was queued
+    // while the daemon was down is sent, before anything else
+    if daemon_reachable() {
+        transfers::repair(cli.profile, progress);
+        if let Err(e) = queue::replay(cli.profile, progress) {
+            eprintln!("Queue not sent: {}", e);
+        }
+    }
+    if let Syscommands::Kiosk { playlist } = &syscmd.cmd {
+        return kiosk::run(None, playlist);
+    }
+    if let Syscommands::Wic64Bridge = syscmd.cmd {
+        return wic64::run();
+    }
+    if let Syscommands::ObsBridge { listen, .. } = &syscmd.cmd {
+        return obs::run(listen, None, 0);
+    }
+    if let Syscommands::Pkg { cmd } = syscmd.cmd {
+        return pkg::run(cmd, config);
+    }
+    if let Syscommands::Unpack { file, dir } = syscmd.cmd {
+        return pack::unpack_cmd(&file, dir, yes);
+    }
+    if let Syscommands::Saves { cmd } = syscmd.cmd {
+        return saves::run(cmd, cli.profile, progress, yes);
+    }
+    if let Syscommands::Watch { prg, reset_before, settle } = &syscmd.cmd {
+        return reload::run(None, prg, *reset_before, *settle);
+    }
     // 'cd' commands as needed
     if cli.syncdir {
         let path = env::current_dir().unwrap();
-        let cmd = format!("sys.chdir(\"{}\")", path.to_string_lossy());
+        let cmd = format!("sys.chdir({})", protocol::lua_string(&path.to_string_lossy()));
 
         luasend(cmd)?;
         // TESTING - pause here to allow first NMI to complete
         thread::sleep(Duration::from_millis(500));
     }
-    if let Some(flags)=cli.xarg {
-        // Create a switch style flag for each of the characters in xarg.
-        for c in flags.chars() {
-            xargs.push('/');
-            xargs.push(c);
-            xargs.push(' ');
-        }
+    // Create switch style flags from the configured defaults and -x
+    let defaults = match &syscmd.cmd {
+        Syscommands::Dir { .. } => config.xargs("dir"),
+        Syscommands::Catalog { .. } => config.xargs("catalog"),
+        Syscommands::Exec { cmd, .. } => config.xargs(cmd),
+        _ => &[],
+    };
+    let specs: Vec<&String> = defaults.iter().chain(&cli.xarg).collect();
+    let switches = protocol::xarg_switches(&specs);
+    if !switches.is_empty() {
+        xargs = protocol::join_args(&switches);
+        xargs.push(' ');
+    }
+    // Paged, structured, colored and recursive listings are parsed, so the
+    // whole listing is collected first
+    let listing = matches!(syscmd.cmd,
+        Syscommands::Dir { .. } | Syscommands::Catalog { .. } | Syscommands::Drives { .. } | Syscommands::Find { .. });
+    if listing && dir::run(&cli, &syscmd.cmd, &xargs, charset, &Theme::load(&config.theme)?)? {
+        return Ok(())
+    }
+    if let Syscommands::State { cmd } = syscmd.cmd {
+        return state::run(cmd);
     }
+    // Subscribe to events before starting anything we have to wait for
+    let wait = matches!(syscmd.cmd,
+        Syscommands::Go { wait: true, .. } |
+        Syscommands::Load { wait: true, .. } |
+        Syscommands::Exec { wait: true, .. });
+    let program = matches!(syscmd.cmd, Syscommands::Go { .. } | Syscommands::Load { .. } | Syscommands::Exec { .. });
+    // Go and load leave a program's output on the Commodore, so the
+    // daemon never connects to a redirect socket for them
+    let output = cli.output && !matches!(syscmd.cmd, Syscommands::Go { .. } | Syscommands::Load { .. });
+    let activity = Activity::new();
+    let output_timeout = cli.output_timeout.or(config.output_timeout()?).filter(|_| output);
+    // Without --wait, events are only used where the daemon has them
+    let mut events = if wait {
+        Some(subscribe_events()?)
+    } else if program && output {
+        subscribe_events().ok()
+    } else {
+        None
+    };
+    let start = events.as_mut().map(EventChannel::expect_start);
+    let exit_status = events.map(|ev| ev.listen(activity.clone()));
     // If output is redirected, create a thread to handle this...
-    let ojoin = match cli.output {
+    // `proc` names the redirect socket, or is 0 to leave output on the Commodore
+    let (proc, ojoin) = match output {
         true => {
-            // Create listening socket for response
-            let respath = format!("/run/user/{}/{}", unistd::getuid(), process::id());
-            let resport = UnixListener::bind(Path::new(&respath))?;
-            Some(thread::spawn(move || -> Result<()> {
-                // Wait on response
-                match resport.accept()? {
-                    (mut s, _) => {
-                        let mut buf = [0u8; 4096];
-                        loop {
-                            match s.read(&mut buf)? {
-                                0 => break,
-                                n => {
-                                    let pet = PetString::new(&BString::new(buf[..n].to_vec()));
-                                    let pets = String::from(pet).replace('\r', "\n");
-                                    print!("{}", pets);
-                                },
-                            }
-                        }
-                    }
-                }
-                // Cleanup
-                println!();
-                stdout().flush()?;
-                fs::remove_file(&respath)?;
-                Ok(())
-            }))
+            // Output of a tool with a configured parser is collected and printed as JSON
+            let parser = match &syscmd.cmd {
+                Syscommands::Exec { cmd, .. } => config.parsers.get(cmd).copied(),
+                _ => None,
+            };
+            let (proc, reader) = output::read(&cli, parser, charset, output_timeout, &activity)?;
+            (proc, Some(reader))
         },
-        false => None
+        false => (0, None),
     };
 
-    // Assign `proc` variable if output needs to be redirected to this process.
-    let proc = if ojoin.is_some() {process::id()} else {0};
+    // Redirected output comes in frames where asked for: for every
+    // command with --crc, else for programs, whose errors and status are
+    // told apart
+    let redirect = |cmd: u8, args: &str| match proc {
+        0 => shell(cmd, args, 0),
+        _ if cli.crc => luasend(crc_call(cmd, args, proc)),
+        _ if cmd == EXEC_CMD => luasend(streams_call(cmd, args, proc)),
+        _ => shell(cmd, args, proc),
+    };
 
     // Handle commands
+    let started = Instant::now();
     match syscmd.cmd {
-        Syscommands::Go { app } => return shell(GO_CMD, &app, 0),
-        Syscommands::Load { prg } => return shell(LOAD_CMD, &prg, 0),
-        Syscommands::Reboot => return reboot_cmd(0),
+        // Only --wait has anything to stay for once the program is started
+        Syscommands::Go { app, wait } => {
+            shell(GO_CMD, &app, 0)?;
+            if !wait {
+                return Ok(())
+            }
+        },
+        Syscommands::Load { prg, player, wait } => {
+            if player.duration.is_some() || !player.mod_options(&prg)?.is_empty() {
+                bail!("Player options require the C64 Ultimate (-u)")
+            }
+            shell(LOAD_CMD, &prg, 0)?;
+            let _ = obs::Playing::record(&prg);
+            hooks::after(&config.hooks, Hook::PostLoad, &syscmd.name, &[("FILE", &prg)]);
+            if !wait {
+                return Ok(())
+            }
+        },
+        Syscommands::Reboot => {
+            if let Some(name) = running_program() {
+                confirm(&format!("{} is still running. Reboot anyway?", name), yes)?;
+            }
+            return reboot_cmd(0)
+        },
         Syscommands::Stop   => return stop_cmd(),
-        Syscommands::Dir { dev } => shell(DIR_CMD, &dev, proc)?,
-        Syscommands::Catalog { dev } => {
+        Syscommands::Keys { text } => {
+            let keys = text.keys(typing, config.keyboard)?;
+            return luasend(format!("sys.keys({})", protocol::lua_bytes(&keys)))
+        },
+        Syscommands::Clock { cmd: ClockCommands::Sync { utc } } => return luasend(format!("sys.clock({})", clock::now(utc)?)),
+        Syscommands::Clock { cmd: ClockCommands::Show } => return Err(target::unsupported(&Idun, "Reading the clock")),
+        Syscommands::Dir { devs, .. } => {
+            for dev in devs {
+                let argstr = format!("{}{}", xargs, dev);
+                redirect(DIR_CMD, &argstr)?
+            }
+        },
+        Syscommands::Catalog { dev, .. } => {
             let argstr = format!("{}{}", xargs, dev);
-            shell(CATALOG_CMD, &argstr, proc)?
+            redirect(CATALOG_CMD, &argstr)?
         },
-        Syscommands::Drives { dev} => {
+        Syscommands::Drives { dev, .. } => {
             let argstr = dev.clone().unwrap_or_default();
-            shell(DRIVES_CMD, &argstr, proc)?
+            redirect(DRIVES_CMD, &argstr)?
         },
         Syscommands::Mount { dev, dimage } => {
-            let mut argstr = String::from(dev);
-            argstr.push(' ');
-            argstr.push_str(&dimage);
-            shell(MOUNT_CMD, &argstr, proc)?
+            hooks::run(&config.hooks, Hook::PreMount, &syscmd.name, &[("DEV", &dev), ("FILE", &dimage)])?;
+            catalogs::forget(&dev);
+            let argstr = protocol::join_args(&[&dev, &dimage]);
+            drive::with_status(&dev, redirect(MOUNT_CMD, &argstr)).at(Context::File(dimage.clone()))?;
+            state::set_protected(&dev, false)?;
+            // The image is read by the daemon on this machine, not sent
+            let size = fs::metadata(&dimage).map(|m| m.len()).unwrap_or(0);
+            progress.report("mount", size, size)
         }
-        Syscommands::Assign { dev, path } => {
-            let mut argstr = String::from(dev);
-            argstr.push(' ');
-            argstr.push_str(&path);
-            shell(ASSIGN_CMD, &argstr, proc)?
+        Syscommands::Assign { dev, path, protect, journal } => {
+            catalogs::forget(&dev);
+            let argstr = protocol::join_args(&[&dev, &path]);
+            redirect(ASSIGN_CMD, &argstr)?;
+            state::set_protected(&dev, protect)?;
+            if journal {
+                return journal::follow(&dev, &path)
+            }
         }
-        Syscommands::Exec { cmd, args} =>
+        Syscommands::Mkdir { path } => {
+            check_subdirectories()?;
+            state::check_writable(&files::split_device(&path)?.0)?;
+            catalogs::forget(&path);
+            redirect(MKDIR_CMD, &protocol::join_args(&[path]))?
+        },
+        Syscommands::Rmdir { path } => {
+            check_subdirectories()?;
+            state::check_writable(&files::split_device(&path)?.0)?;
+            confirm(&format!("Remove {}?", path), yes)?;
+            catalogs::forget(&path);
+            redirect(RMDIR_CMD, &protocol::join_args(&[path]))?
+        },
+        Syscommands::Dos { dev, cmd } => {
+            // Only reading the error channel leaves the drive as it is
+            if !cmd.is_empty() {
+                state::check_writable(&dev)?;
+            }
+            catalogs::forget(&dev);
+            return drive::run(&dev, &cmd, yes)
+        },
+        Syscommands::Sector { cmd } => return sector::run(cmd, yes),
+        Syscommands::Image { cmd } => return image::run(&cmd, None, &Theme::load(&config.theme)?, cli.profile, progress, yes),
+        Syscommands::Put { file, dest } => {
+            state::check_writable(&files::split_device(&dest)?.0)?;
+            let settings = files::transfer_settings(&files::split_device(&dest)?.0, cli.profile)?;
+            catalogs::forget(&dest);
+            return files::put(&file, &dest, settings, progress)
+        },
+        Syscommands::Get { src, file } => {
+            let settings = files::transfer_settings(&files::split_device(&src)?.0, cli.profile)?;
+            let (dir, name) = catalogs::split_dir(&src);
+            if catalogs::has_wildcards(name) {
+                return files::get_matching(dir, name, file, settings)
+            }
+            return files::get(&src, file, settings)
+        },
+        Syscommands::Edit { file } => {
+            state::check_writable(&files::split_device(&file)?.0)?;
+            let settings = files::transfer_settings(&files::split_device(&file)?.0, cli.profile)?;
+            return files::edit(&file, charset, settings, progress)
+        },
+        Syscommands::Err { dev } => {
+            let status = drive::status(&dev)?;
+            println!("{}", status);
+            if status.is_error() {
+                return Err(ExitStatus(1).into())
+            }
+            return Ok(())
+        },
+        Syscommands::Lock { file } => return drive::lock(&file, true),
+        Syscommands::Unlock { file } => return drive::lock(&file, false),
+        Syscommands::Exec { cmd, args, .. } =>
         {
-            let argstr = args.join(" ");
-            let mut exe = cmd.to_owned();
-
-            exe.push(' ');
-            exe.push_str(&xargs);
-            exe.push_str(&argstr);
-            shell(EXEC_CMD, &exe, proc)?
+            // flashcrt rewrites the cartridge's flash memory
+            if cmd == "flashcrt" {
+                confirm(&format!("Write {} to the cartridge's flash?", args.first().map_or("it", |a| a.as_str())), yes)?;
+            }
+            // The program, its switches and its arguments, each quoted as need be
+            let line = protocol::join_args(&[vec![cmd], switches, args].concat());
+            redirect(EXEC_CMD, &line)?
         },
-        Syscommands::Run { .. } => return Ok(()),   //not used, handled above
+        Syscommands::Run { .. } | Syscommands::Ult { .. } | Syscommands::State { .. } |
+        Syscommands::Cache { .. } | Syscommands::Queue { .. } | Syscommands::Kiosk { .. } |
+        Syscommands::Journal { .. } | Syscommands::Collection { .. } | Syscommands::Tape { .. } | Syscommands::Convert { .. } |
+        Syscommands::Watch { .. } | Syscommands::X { .. } | Syscommands::Find { .. } |
+        Syscommands::Peek { .. } | Syscommands::Basic { .. } | Syscommands::Mon { .. } | Syscommands::Profile { .. } |
+        Syscommands::Memcmp { .. } | Syscommands::Memwatch { .. } | Syscommands::Status { .. } | Syscommands::Saves { .. } |
+        Syscommands::Pkg { .. } | Syscommands::Pack { .. } | Syscommands::Unpack { .. } |
+        Syscommands::Cheat { .. } | Syscommands::Hiscore { .. } | Syscommands::Test { .. } |
+        Syscommands::Fuzz { .. } | Syscommands::Power { .. } | Syscommands::Wic64Bridge | Syscommands::Term { .. } |
+        Syscommands::Expmem { .. } | Syscommands::ObsBridge { .. } |
+        Syscommands::Init | Syscommands::Info { .. } => return Ok(()),   //not used, handled above
+    }
+    // Polling for the program's exit starts once it's sent
+    if let Some(start) = start {
+        let _ = start.send(());
     }
     
-    // Rejoin thread
-    match ojoin {
-        Some(oj) => {
-            match oj.join() {
-                Ok(_) => Ok(()),
-                Err(e) => bail!("Failed receiving redirected output E:{:?}", e)
-            }
-        },
-        None => Ok(())
+    // Rejoin the output reader
+    let received = match ojoin.map(runtime::join) {
+        Some(r) => r?,
+        None => 0,
+    };
+    // Wait for the program to finish, passing on its exit status
+    let status = match exit_status {
+        Some(status) if wait => Some(status.recv().map_err(|_| format_err!("The daemon closed the event channel"))??),
+        // A program whose output has ended has exited, or is about to
+        Some(status) if program && output => status.recv_timeout(EXIT_GRACE).ok().and_then(|s| s.ok()),
+        _ => None,
+    };
+    if program && (wait || output) {
+        let summary = Summary { runtime: started.elapsed().as_secs_f64(), bytes: received, status };
+        progress.summary(&summary, cli.quiet);
     }
+    if let Some(status) = status.filter(|s| *s != 0) {
+        return Err(ExitStatus(status).into())
+    }
+    Ok(())
 }
 
 #[test]
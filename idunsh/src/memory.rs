@@ -0,0 +1,266 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+// Copyright (C) 2026 Brian Holdsworth
+
+//! Comparing C64 memory with a file, and watching it change, for
+//! `idunsh memcmp` and `idunsh memwatch`.
+//!
+//! The heatmap shows each 256 byte page as a cell, sixteen to a row,
+//! glowing with the bytes changed in it lately; the glow fades by half
+//! about every second reading, so the pages a program works in stand out
+//! from those it touched once.
+use std::env;
+use std::fs;
+use std::io::{IsTerminal, Write, stdout};
+use std::result;
+use std::thread;
+use std::time::{Duration, Instant};
+use idun_client::petscii::Charset;
+use idun_client::util;
+use crate::Syscommands;
+use crate::basic;
+use crate::clock::{self, ClockCommands};
+use crate::errors::ExitStatus;
+use crate::expmem;
+use crate::hexdump;
+use crate::hiscore;
+use crate::labels::Labels;
+use crate::mon;
+use crate::pokes::{self, CheatCommands};
+use crate::progress::Progress;
+use crate::target::Memory;
+use crate::term;
+use crate::watch;
+
+// Simpler error handling
+type Result<T> = result::Result<T, failure::Error>;
+
+/// A run of bytes that differ: where it starts, and the bytes on each
+/// side.
+#[derive(Debug, PartialEq, Eq)]
+pub struct Difference {
+    pub addr: u16,
+    pub expected: Vec<u8>,
+    pub found: Vec<u8>,
+}
+
+impl Difference {
+    pub fn line(&self) -> String {
+        let hex = |bytes: &[u8]| bytes.iter().map(|b| format!("{:02x}", b)).collect::<Vec<_>>().join(" ");
+        match self.expected.len() {
+            1 => format!("{:04x}: {} -> {}", self.addr, hex(&self.expected), hex(&self.found)),
+            n => format!("{:04x}-{:04x}: {} -> {}", self.addr, self.addr.wrapping_add(n as u16 - 1),
+                hex(&self.expected), hex(&self.found)),
+        }
+    }
+}
+
+/// The runs of bytes in `found` that differ from `expected`, both
+/// starting at `addr`.
+pub fn differences(addr: u16, expected: &[u8], found: &[u8]) -> Vec<Difference> {
+    let mut diffs: Vec<Difference> = Vec::new();
+    let mut last = None;
+    for (i, (e, f)) in expected.iter().zip(found).enumerate() {
+        if e == f {
+            continue
+        }
+        match diffs.last_mut() {
+            Some(d) if last == Some(i - 1) => {
+                d.expected.push(*e);
+                d.found.push(*f);
+            },
+            _ => diffs.push(Difference { addr: addr.wrapping_add(i as u16), expected: vec![*e], found: vec![*f] }),
+        }
+        last = Some(i);
+    }
+    diffs
+}
+
+/// Reads `len` bytes at `addr` every `interval` and calls `changed` with
+/// the time since watching began and what changed, if anything, until
+/// it fails.
+pub fn watch<F>(memory: &dyn Memory, addr: u16, len: usize, interval: Duration, mut changed: F) -> Result<()>
+where F: FnMut(Duration, &[Difference]) -> Result<()> {
+    let start = Instant::now();
+    let mut previous = memory.read(addr, len)?;
+    loop {
+        thread::sleep(interval);
+        let current = memory.read(addr, len)?;
+        let diffs = differences(addr, &previous[..len.min(previous.len())], &current);
+        changed(start.elapsed(), &diffs)?;
+        previous = current;
+    }
+}
+
+/// How much each page has changed lately.
+pub struct Heatmap {
+    first: u16,
+    heat: Vec<f64>,
+}
+
+impl Heatmap {
+    /// A heatmap of the pages holding `len` bytes from `addr`.
+    pub fn new(addr: u16, len: usize) -> Heatmap {
+        let first = addr >> 8;
+        let last = (addr as usize + len.max(1) - 1).min(0xffff) >> 8;
+        Heatmap { first, heat: vec![0.0; last - first as usize + 1] }
+    }
+    /// Fades what was seen before and adds `diffs`.
+    pub fn update(&mut self, diffs: &[Difference]) {
+        const FADE: f64 = 0.7;
+        for h in &mut self.heat {
+            *h *= FADE;
+        }
+        for d in diffs {
+            for (i, (e, f)) in d.expected.iter().zip(&d.found).enumerate() {
+                if e != f {
+                    let page = (d.addr.wrapping_add(i as u16) >> 8) as usize;
+                    if let Some(h) = page.checked_sub(self.first as usize).and_then(|p| self.heat.get_mut(p)) {
+                        *h += 1.0;
+                    }
+                }
+            }
+        }
+    }
+    /// Rows of sixteen pages, led by the address of the first, in colors
+    /// from dark blue to white, or as characters without color.
+    pub fn render(&self, color: bool) -> String {
+        const SHADES: &[u8] = b" .:-=+*#%@";
+        const COLORS: [u8; 10] = [236, 17, 19, 21, 27, 39, 220, 208, 196, 231];
+        let first = self.first as usize;
+        let mut text = String::new();
+        for row in (first & !0x0f..first + self.heat.len()).step_by(16) {
+            text.push_str(&format!("{:04x} ", row << 8));
+            for page in row..row + 16 {
+                let heat = match page.checked_sub(first).and_then(|p| self.heat.get(p)) {
+                    Some(h) => *h,
+                    None => {
+                        text.push_str("  ");
+                        continue
+                    },
+                };
+                // Each level takes about twice the changes of the one below
+                let level = match heat < 0.5 {
+                    true => 0,
+                    false => ((heat.log2() + 2.0) as usize).clamp(1, SHADES.len() - 1),
+                };
+                match color {
+                    true => text.push_str(&format!("\x1b[48;5;{}m  ", COLORS[level])),
+                    false => text.push_str(&format!("{} ", SHADES[level] as char)),
+                }
+            }
+            if color {
+                text.push_str("\x1b[0m");
+            }
+            text.push('\n');
+        }
+        text
+    }
+}
+
+// The commands that need only C64 memory, on the C64U or VICE
+pub fn run(memory: &dyn Memory, cmd: Syscommands, charset: Charset, progress: Progress) -> Result<()> {
+    match cmd {
+        Syscommands::Mon { labels } => mon::run(memory, Labels::open(labels.as_deref())?),
+        Syscommands::Peek { addr, len, width, screen_codes, labels } => {
+            let labels = Labels::open(labels.as_deref())?;
+            let start = match labels.lookup(&addr) {
+                Some(a) => a,
+                None => util::parse_addr(&addr).map_err(failure::err_msg)?,
+            };
+            let len = len.min(0x10000 - start as u32) as usize;
+            Ok(hexdump::hexdump(&mut stdout(), start, &memory.read(start, len)?, width, screen_codes, &labels)?)
+        },
+        Syscommands::Basic { cmd } => basic::run(memory, cmd, charset),
+        Syscommands::Term { screen, interval } => term::run(memory, screen, interval, charset),
+        Syscommands::Expmem { cmd } => expmem::run(memory, cmd, progress),
+        Syscommands::Memcmp { addr, file } => {
+            let expected = fs::read(&file).map_err(|e| format_err!("{}: {}", file, e))?;
+            if addr as usize + expected.len() > 0x10000 {
+                bail!("{} doesn't fit in memory at ${:04x}", file, addr)
+            }
+            let diffs = differences(addr, &expected, &memory.read(addr, expected.len())?);
+            for d in &diffs {
+                println!("{}", d.line());
+            }
+            // Like cmp, differences are a failure
+            match diffs.is_empty() {
+                true => Ok(()),
+                false => Err(ExitStatus(1).into()),
+            }
+        },
+        Syscommands::Memwatch { addr, len, interval, heatmap: true } => {
+            let len = len.min(0x10000 - addr as u32) as usize;
+            let mut heatmap = Heatmap::new(addr, len);
+            let color = stdout().is_terminal() && env::var_os("NO_COLOR").is_none();
+            watch(memory, addr, len, interval, |_, diffs| {
+                heatmap.update(diffs);
+                print!("{}{}", watch::CLEAR, heatmap.render(color));
+                Ok(stdout().flush()?)
+            })
+        },
+        Syscommands::Memwatch { addr, len, interval, .. } => {
+            let len = len.min(0x10000 - addr as u32) as usize;
+            watch(memory, addr, len, interval, |elapsed, diffs| {
+                for d in diffs {
+                    println!("{:>9.3}s {}", elapsed.as_secs_f64(), d.line());
+                }
+                Ok(stdout().flush()?)
+            })
+        },
+        Syscommands::Cheat { cmd: CheatCommands::Apply { file, trainer, freeze, force, wait } } =>
+            pokes::apply(memory, &file, &trainer, freeze, force, wait),
+        Syscommands::Clock { cmd: ClockCommands::Sync { utc } } => clock::set(memory, clock::now(utc)?),
+        Syscommands::Clock { cmd: ClockCommands::Show } => {
+            let (ti, tod) = clock::read(memory)?;
+            println!("TI$  {}", clock::format(ti));
+            println!("TOD  {}", clock::format(tod));
+            println!("here {}", clock::format(clock::now(false)?));
+            Ok(())
+        },
+        Syscommands::Hiscore { game, webhook, json } => {
+            let scores = hiscore::GameProfile::load(&game)?.read(memory)?;
+            if json {
+                println!("{}", serde_json::to_string_pretty(&scores)?);
+            } else if let Some(fields) = scores["scores"].as_object() {
+                for (name, value) in fields {
+                    match value {
+                        serde_json::Value::String(text) => println!("{}: {}", name, text),
+                        value => println!("{}: {}", name, value),
+                    }
+                }
+            }
+            if let Some(url) = webhook {
+                ureq::post(&url).send_json(&scores).map_err(|e| format_err!("{}: {}", url, e))?;
+            }
+            Ok(())
+        },
+        _ => Ok(()),
+    }
+}
+
+#[test]
+fn memory_differences() {
+    let diffs = differences(0xc000, &[1, 2, 3, 4, 5], &[1, 9, 9, 4, 0]);
+    assert_eq!(diffs.iter().map(Difference::line).collect::<Vec<_>>(), ["c001-c002: 02 03 -> 09 09", "c004: 05 -> 00"]);
+    assert!(differences(0, &[1, 2], &[1, 2]).is_empty());
+    let mut heatmap = Heatmap::new(0x0400, 0x400);
+    heatmap.update(&differences(0x0400, &[0; 300], &[1; 300]));
+    assert_eq!(heatmap.render(false), format!("0000 {:8}@ # {:20}\n", "", ""));
+}
+
+#[test]
+fn memory_at_the_top() {
+    let ram = crate::target::Ram::new();
+    let run = |cmd| run(&ram, cmd, Charset::Upper, Progress::default());
+    // Peeking past the end of memory shows what there is
+    run(Syscommands::Peek { addr: "fff8".into(), len: 256, width: 8, screen_codes: false, labels: None }).unwrap();
+    let file = env::temp_dir().join(format!("idunsh-memcmp-{}", std::process::id()));
+    fs::write(&file, [0; 9]).unwrap();
+    let memcmp = |addr| Syscommands::Memcmp { addr, file: file.to_string_lossy().into_owned() };
+    run(memcmp(0xfff7)).unwrap();
+    let failed = run(memcmp(0xfff8)).unwrap_err();
+    assert_eq!(failed.to_string(), format!("{} doesn't fit in memory at $fff8", file.display()));
+    ram.write(0xffff, &[1]).unwrap();
+    assert_eq!(run(memcmp(0xfff7)).unwrap_err().downcast::<ExitStatus>().unwrap().0, 1);
+    fs::remove_file(file).unwrap();
+}
@@ -0,0 +1,242 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+// Copyright (C) 2026 Brian Holdsworth
+
+//! A machine code monitor for `idunsh mon`, on a backend with memory
+//! access.
+//!
+//! ```text
+//! m [start [end]]      show memory
+//! d [start [end]]      disassemble
+//! a start instruction  assemble, then go on at the next address
+//! > start bytes...     write bytes
+//! g start              start the code there with SYS
+//! r                    show the registers SYS loads and saves
+//! x                    leave
+//! ```
+//!
+//! On a backend with a debugger, such as VICE, `r` shows the CPU's own
+//! registers, `g` jumps without SYS, and there are breakpoints:
+//!
+//! ```text
+//! break start          stop before the code at start runs
+//! continue             run until a breakpoint is hit
+//! ```
+//!
+//! Numbers are hex and addresses can be label names. Without a start,
+//! `m` and `d` go on where the last one stopped.
+use std::io::stdout;
+use std::result;
+use rustyline::error::ReadlineError;
+use rustyline::DefaultEditor;
+use crate::disasm;
+use crate::hexdump;
+use crate::labels::Labels;
+use crate::target::{Debugger, Memory, Registers};
+
+// Simpler error handling
+type Result<T> = result::Result<T, failure::Error>;
+
+// Where SYS keeps A, X, Y and the status register
+const SYS_REGISTERS: u16 = 0x030c;
+const HELP: &str = "m [start [end]], d [start [end]], a start instruction, > start bytes, g start, r, x";
+const DEBUGGER_HELP: &str = "break start, continue";
+
+struct Monitor<'a> {
+    memory: &'a dyn Memory,
+    labels: Labels,
+    // Where the next m or d without a start begins
+    next: u16,
+}
+
+/// Reads monitor commands until `x` or the end of input. A failing
+/// command is reported and the monitor goes on.
+pub fn run(memory: &dyn Memory, labels: Labels) -> Result<()> {
+    let mut editor = DefaultEditor::new()?;
+    let mut monitor = Monitor { memory, labels, next: 0 };
+    let mut initial = String::new();
+    loop {
+        let line = match editor.readline_with_initial(". ", (&initial, "")) {
+            Ok(line) => line,
+            Err(ReadlineError::Interrupted) => {
+                initial.clear();
+                continue
+            },
+            Err(ReadlineError::Eof) => return Ok(()),
+            Err(e) => return Err(e.into()),
+        };
+        initial.clear();
+        let line = line.trim();
+        if line.is_empty() {
+            continue
+        }
+        let _ = editor.add_history_entry(line);
+        if line == "x" {
+            return Ok(())
+        }
+        match monitor.command(line) {
+            // Assembling goes on at the next address
+            Ok(Some(next)) => initial = format!("a {:04x} ", next),
+            Ok(None) => (),
+            Err(e) => eprintln!("? {}", e),
+        }
+    }
+}
+
+impl Monitor<'_> {
+    // Runs one command, giving the address after an assembled instruction
+    fn command(&mut self, line: &str) -> Result<Option<u16>> {
+        let (cmd, rest) = match line.strip_prefix('>') {
+            Some(rest) => (">", rest),
+            None => line.split_at(line.find(char::is_whitespace).unwrap_or(line.len())),
+        };
+        let args: Vec<&str> = rest.split_whitespace().collect();
+        match cmd {
+            "m" => self.memory_dump(&args)?,
+            "d" => self.disassembly(&args)?,
+            "a" => {
+                let (start, text) = rest.trim().split_once(char::is_whitespace)
+                    .ok_or_else(|| format_err!("a needs an address and an instruction"))?;
+                let start = self.address(start)?;
+                let code = disasm::assemble(start, text, &self.labels)?;
+                self.memory.write(start, &code)?;
+                let (len, text) = disasm::disassemble(start, &code, &self.labels);
+                println!("{}", text);
+                return Ok(Some(start.wrapping_add(len)))
+            },
+            ">" => {
+                let (start, bytes) = args.split_first().ok_or_else(|| format_err!("> needs an address"))?;
+                let bytes = bytes.iter()
+                    .map(|b| u8::from_str_radix(b.trim_start_matches('$'), 16)
+                        .map_err(|_| format_err!("{:?} is not a hex byte", b)))
+                    .collect::<Result<Vec<u8>>>()?;
+                self.memory.write(self.address(start)?, &bytes)?;
+            },
+            "g" => {
+                let start = args.first().ok_or_else(|| format_err!("g needs an address"))?;
+                self.memory.call(self.address(start)?)?;
+            },
+            "r" => match self.memory.debugger() {
+                Some(debugger) => self.show_registers(&debugger.registers()?),
+                None => {
+                    let r = self.memory.read(SYS_REGISTERS, 4)?;
+                    println!(" ac xr yr sr nv-bdizc");
+                    println!(" {:02x} {:02x} {:02x} {:02x} {:08b}", r[0], r[1], r[2], r[3], r[3]);
+                },
+            },
+            "break" | "b" => {
+                let start = args.first().ok_or_else(|| format_err!("break needs an address"))?;
+                let start = self.address(start)?;
+                let number = self.debugger()?.set_breakpoint(start)?;
+                println!("Breakpoint {} at ${:04x}", number, start);
+            },
+            "continue" | "c" => {
+                let regs = self.debugger()?.resume()?;
+                self.show_registers(&regs);
+                let code = self.memory.read(regs.pc, 3)?;
+                println!("{}", disasm::disassemble(regs.pc, &code, &self.labels).1);
+                self.next = regs.pc;
+            },
+            "?" | "h" if self.memory.debugger().is_some() => println!("{}, {}", HELP, DEBUGGER_HELP),
+            "?" | "h" => println!("{}", HELP),
+            _ => bail!("Unknown command {}, try ?", cmd),
+        }
+        Ok(None)
+    }
+
+    fn debugger(&self) -> Result<&dyn Debugger> {
+        self.memory.debugger().ok_or_else(|| format_err!("This backend has no breakpoints"))
+    }
+
+    fn show_registers(&self, r: &Registers) {
+        println!("   pc ac xr yr sp nv-bdizc");
+        println!(" {:04x} {:02x} {:02x} {:02x} {:02x} {:08b}", r.pc, r.a, r.x, r.y, r.sp, r.flags);
+    }
+
+    fn address(&self, text: &str) -> Result<u16> {
+        disasm::parse_value(text, &self.labels)
+    }
+
+    // The start, and the end if given, of m and d
+    fn range(&self, args: &[&str]) -> Result<(u16, Option<u16>)> {
+        let start = match args.first() {
+            Some(a) => self.address(a)?,
+            None => self.next,
+        };
+        let end = args.get(1).map(|a| self.address(a)).transpose()?;
+        match end {
+            Some(end) if end < start => bail!("The end ${:04x} is before the start ${:04x}", end, start),
+            _ => Ok((start, end)),
+        }
+    }
+
+    fn memory_dump(&mut self, args: &[&str]) -> Result<()> {
+        let (start, end) = self.range(args)?;
+        let len = match end {
+            Some(end) => end as usize - start as usize + 1,
+            None => 0x80.min(0x10000 - start as usize),
+        };
+        let data = self.memory.read(start, len)?;
+        hexdump::hexdump(&mut stdout(), start, &data, 8, false, &self.labels)?;
+        self.next = start.wrapping_add(len as u16);
+        Ok(())
+    }
+
+    fn disassembly(&mut self, args: &[&str]) -> Result<()> {
+        const LINES: usize = 20;
+        let (start, end) = self.range(args)?;
+        // Three bytes an instruction at most, and the last may run over
+        let len = match end {
+            Some(end) => end as usize - start as usize + 3,
+            None => LINES * 3,
+        }.min(0x10000 - start as usize);
+        let data = self.memory.read(start, len)?;
+        let mut offset = 0;
+        for _ in 0..end.map_or(LINES, |_| usize::MAX) {
+            let addr = start.wrapping_add(offset as u16);
+            if offset >= data.len() || end.is_some_and(|end| addr > end || addr < start) {
+                break
+            }
+            for label in self.labels.at(addr) {
+                println!("{}:", label);
+            }
+            let (len, text) = disasm::disassemble(addr, &data[offset..], &self.labels);
+            println!("{}", text);
+            offset += len as usize;
+        }
+        self.next = start.wrapping_add(offset as u16);
+        Ok(())
+    }
+}
+
+#[test]
+fn monitor_writes() {
+    let ram = crate::target::Ram::new();
+    let mut monitor = Monitor { memory: &ram, labels: Labels::parse("border = $d020\n"), next: 0 };
+    assert_eq!(monitor.command("a c000 lda #$01").unwrap(), Some(0xc002));
+    assert_eq!(monitor.command("a c002 sta border").unwrap(), Some(0xc005));
+    monitor.command(">c005 60 $ea").unwrap();
+    assert_eq!(ram.read(0xc000, 7).unwrap(), [0xa9, 0x01, 0x8d, 0x20, 0xd0, 0x60, 0xea]);
+    // Nothing is written unless every byte is good
+    assert!(monitor.command(">c000 a9 1ff").is_err());
+    assert_eq!(ram.read(0xc000, 1).unwrap(), [0xa9]);
+    assert!(monitor.command(">").is_err() && monitor.command("a c000").is_err());
+    assert!(monitor.command("a zzzz nop").is_err());
+}
+
+#[test]
+fn monitor_ranges() {
+    let ram = crate::target::Ram::new();
+    ram.write(0xffff, &[0xa9]).unwrap();
+    let mut monitor = Monitor { memory: &ram, labels: Labels::default(), next: 0 };
+    assert_eq!(monitor.command("m c010 c000").unwrap_err().to_string(), "The end $c000 is before the start $c010");
+    // Dumps and disassemblies stop at the top of memory, and the next
+    // one goes on from the bottom
+    monitor.command("m fff0").unwrap();
+    assert_eq!(monitor.next, 0);
+    monitor.command("d ffff").unwrap();
+    assert_eq!(monitor.next, 0);
+    monitor.command("m").unwrap();
+    assert_eq!(monitor.next, 0x80);
+    assert_eq!(monitor.command("break c000").unwrap_err().to_string(), "This backend has no breakpoints");
+    assert!(monitor.command("g c000").is_err() && monitor.command("q").is_err());
+}
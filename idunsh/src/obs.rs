@@ -0,0 +1,280 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+// Copyright (C) 2026 Brian Holdsworth
+
+//! What the C64 is showing, for OBS browser sources, from `idunsh
+//! obs-bridge`.
+//!
+//! The bridge answers HTTP on a local port, 127.0.0.1:8064 unless
+//! another is given:
+//!
+//! ```text
+//! /            an overlay page to use as a browser source: the name of
+//!              what runs, a SID tune's details, and the C64U's screen
+//! /now.json    {"name": "Commando", "file": "music/commando.sid",
+//!               "format": "SID", "sid": {"title": "Commando", ...}}
+//! /screen.png  a frame of the C64U's video stream
+//! ```
+//!
+//! What runs is what idunsh last loaded or ran, which `load` and `run`
+//! keep in `playing.json` in the state directory, or on idun the
+//! program the daemon last said was started, if that's another. The
+//! screen is only there with the C64U (`-u`), whose video stream is
+//! started for each frame asked for.
+use std::io::{BufRead, BufReader, Write};
+use std::net::{TcpListener, TcpStream};
+use std::path::Path;
+use std::result;
+use std::time::Duration;
+use serde::{Deserialize, Serialize};
+use crate::c64ultimate::{C64Ultimate, Stream};
+use crate::events::{Event, EventChannel};
+use crate::formats::{Format, FileInfo};
+use crate::frame;
+use crate::store;
+use crate::subscribe_events;
+
+// Simpler error handling
+type Result<T> = result::Result<T, failure::Error>;
+
+/// Where the bridge answers unless told otherwise
+pub const LISTEN: &str = "127.0.0.1:8064";
+const PLAYING: &str = "playing.json";
+
+/// The overlay, asking for what runs and the screen every two seconds
+pub const OVERLAY: &str = r#"<!DOCTYPE html>
+<html><head><meta charset="utf-8"><style>
+body { margin: 0; background: transparent; color: #fff; font: 32px sans-serif; text-shadow: 2px 2px 4px #000; }
+#sid { font-size: 24px; }
+#screen { display: block; max-width: 100%; image-rendering: pixelated; }
+</style></head><body>
+<div id="name"></div><div id="sid"></div><img id="screen" alt="">
+<script>
+const screen = document.getElementById('screen');
+screen.onerror = () => screen.style.display = 'none';
+screen.onload = () => screen.style.display = 'block';
+async function update() {
+  try {
+    const now = await (await fetch('/now.json')).json();
+    const sid = now.sid;
+    document.getElementById('name').textContent = now.name;
+    document.getElementById('sid').textContent = sid
+      ? [sid.author, sid.released, `song ${sid.start_song} of ${sid.songs}`].filter(s => s).join(' · ') : '';
+  } catch (e) {}
+  screen.src = '/screen.png?' + Date.now();
+}
+update();
+setInterval(update, 2000);
+</script></body></html>
+"#;
+
+/// The details in a PSID or RSID header.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SidInfo {
+    pub title: String,
+    pub author: String,
+    pub released: String,
+    pub songs: u16,
+    pub start_song: u16,
+}
+
+impl SidInfo {
+    pub fn parse(data: &[u8]) -> Option<SidInfo> {
+        if data.len() < 0x76 || !(data.starts_with(b"PSID") || data.starts_with(b"RSID")) {
+            return None
+        }
+        // Latin-1, padded with NULs
+        let text = |field: &[u8]| field.iter().take_while(|b| **b != 0).map(|b| *b as char).collect::<String>();
+        Some(SidInfo {
+            title: text(&data[0x16..0x36]),
+            author: text(&data[0x36..0x56]),
+            released: text(&data[0x56..0x76]),
+            songs: u16::from_be_bytes([data[0x0e], data[0x0f]]),
+            start_song: u16::from_be_bytes([data[0x10], data[0x11]]),
+        })
+    }
+}
+
+/// What runs, as far as it's known.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Playing {
+    pub name: String,
+    pub file: Option<String>,
+    pub format: Option<String>,
+    pub sid: Option<SidInfo>,
+}
+
+impl Playing {
+    /// What the file at `path` is, told from its contents if it's here,
+    /// or by its name if it's only on a drive.
+    pub fn of_file(path: &str) -> Playing {
+        let base = Path::new(path).file_stem().map(|s| s.to_string_lossy().into_owned()).unwrap_or_default();
+        let Ok(data) = std::fs::read(path) else {
+            return Playing { name: base, file: Some(path.to_string()), ..Playing::default() }
+        };
+        let info = FileInfo::identify(path, &data);
+        let sid = SidInfo::parse(&data);
+        Playing {
+            name: info.title.filter(|t| !t.is_empty()).unwrap_or(base),
+            file: Some(path.to_string()),
+            format: (info.format != Format::Unknown).then(|| info.format.to_string()),
+            sid,
+        }
+    }
+    /// Keeps `path` as what was loaded last, for the bridge.
+    pub fn record(path: &str) -> Result<()> {
+        let json = serde_json::to_vec_pretty(&Playing::of_file(path))?;
+        store::write(&store::dir()?.join(PLAYING), &json)
+    }
+    /// What was loaded last, if anything was.
+    pub fn last() -> Option<Playing> {
+        let text = std::fs::read(store::dir().ok()?.join(PLAYING)).ok()?;
+        serde_json::from_slice(&text).ok()
+    }
+}
+
+/// What runs, from what was loaded last and the program the daemon
+/// last said was started; the one loaded if they're the same.
+pub fn now(last: Option<Playing>, started: Option<&str>) -> Playing {
+    let same = |p: &Playing, name: &str| {
+        let base = |f: &str| Path::new(f).file_name().map(|n| n.to_string_lossy().to_lowercase());
+        p.file.as_deref().is_some_and(|f| base(f) == base(name))
+    };
+    match (last, started.filter(|s| !s.is_empty())) {
+        (Some(p), Some(name)) if same(&p, name) => p,
+        (_, Some(name)) => Playing { name: name.to_string(), ..Playing::default() },
+        (last, None) => last.unwrap_or_default(),
+    }
+}
+
+/// An answer to a request.
+pub struct Reply {
+    status: &'static str,
+    content_type: &'static str,
+    body: Vec<u8>,
+}
+
+impl Reply {
+    pub fn ok(content_type: &'static str, body: impl Into<Vec<u8>>) -> Reply {
+        Reply { status: "200 OK", content_type, body: body.into() }
+    }
+    pub fn json<T: Serialize>(value: &T) -> Reply {
+        Reply::ok("application/json", serde_json::to_vec(value).unwrap_or_default())
+    }
+    pub fn unavailable(why: impl ToString) -> Reply {
+        Reply { status: "503 Service Unavailable", content_type: "text/plain", body: why.to_string().into_bytes() }
+    }
+    pub fn not_found() -> Reply {
+        Reply { status: "404 Not Found", content_type: "text/plain", body: b"Not found".to_vec() }
+    }
+    fn write(&self, w: &mut impl Write) -> std::io::Result<()> {
+        write!(w, "HTTP/1.1 {}\r\nContent-Type: {}\r\nContent-Length: {}\r\n\
+                   Access-Control-Allow-Origin: *\r\nCache-Control: no-store\r\nConnection: close\r\n\r\n",
+               self.status, self.content_type, self.body.len())?;
+        w.write_all(&self.body)?;
+        w.flush()
+    }
+}
+
+// The path asked for in a request line, without its query
+fn path(request_line: &str) -> Option<&str> {
+    match request_line.split_whitespace().collect::<Vec<_>>()[..] {
+        ["GET", target, _] => Some(target.split('?').next().unwrap_or(target)),
+        _ => None,
+    }
+}
+
+/// Answers requests one after another with `route`, given the path
+/// asked for, until the listener fails.
+pub fn serve(listener: TcpListener, mut route: impl FnMut(&str) -> Reply) -> Result<()> {
+    for conn in listener.incoming() {
+        // A client that went away is no reason to stop
+        let _ = answer(conn?, &mut route);
+    }
+    Ok(())
+}
+
+fn answer(mut conn: TcpStream, route: &mut impl FnMut(&str) -> Reply) -> Result<()> {
+    conn.set_read_timeout(Some(Duration::from_secs(5)))?;
+    let mut reader = BufReader::new(conn.try_clone()?);
+    let mut request_line = String::new();
+    reader.read_line(&mut request_line)?;
+    // The headers tell nothing needed
+    let mut header = String::new();
+    while reader.read_line(&mut header)? > 0 && header.trim_end() != "" {
+        header.clear();
+    }
+    let reply = match path(&request_line) {
+        Some(path) => route(path),
+        None => Reply { status: "405 Method Not Allowed", content_type: "text/plain", body: b"Only GET".to_vec() },
+    };
+    Ok(reply.write(&mut conn)?)
+}
+
+// Answers OBS browser sources with what runs, as the daemon tells it
+// without a C64U, and with its screen on one
+pub fn run(listen: &str, c64u: Option<&C64Ultimate>, port: u16) -> Result<()> {
+    let listener = std::net::TcpListener::bind(listen).map_err(|e| format_err!("Can't listen at {}: {}", listen, e))?;
+    let video = match c64u {
+        Some(_) => Some(std::net::UdpSocket::bind(("0.0.0.0", port)).map_err(|e| format_err!("UDP port {}: {}", port, e))?),
+        None => None,
+    };
+    // The C64U doesn't tell what it runs
+    let events = match c64u {
+        Some(_) => None,
+        None => subscribe_events().ok().map(EventChannel::forward),
+    };
+    let mut started: Option<String> = None;
+    eprintln!("OBS bridge at http://{}/ (Ctrl-C to stop)", listen);
+    serve(listener, |path| match path {
+        "/" => Reply::ok("text/html; charset=utf-8", OVERLAY),
+        "/now.json" => {
+            for event in events.iter().flat_map(|e| e.try_iter()) {
+                match event {
+                    Event::Started(name) => started = Some(name),
+                    Event::Exited(_) => started = None,
+                    _ => (),
+                }
+            }
+            Reply::json(&now(Playing::last(), started.as_deref()))
+        },
+        "/screen.png" => match (c64u, &video) {
+            (Some(c64u), Some(socket)) => match screenshot(c64u, socket, port) {
+                Ok(png) => Reply::ok("image/png", png),
+                Err(e) => Reply::unavailable(e),
+            },
+            _ => Reply::unavailable("The screen is only shown from a C64U (-u)"),
+        },
+        _ => Reply::not_found(),
+    })
+}
+
+// A frame of the C64U's video stream as PNG
+fn screenshot(c64u: &C64Ultimate, socket: &std::net::UdpSocket, port: u16) -> Result<Vec<u8>> {
+    c64u.stream_start(Stream::Video, &c64u.stream_dest(port)?)?;
+    let frame = frame::Image::receive(socket, Duration::from_secs(2));
+    c64u.stream_stop(Stream::Video)?;
+    frame?.png()
+}
+
+#[test]
+fn obs_overlay() {
+    let mut sid = vec![0u8; 0x7c];
+    sid[..4].copy_from_slice(b"PSID");
+    sid[0x0f] = 3;
+    sid[0x11] = 1;
+    sid[0x16..0x1e].copy_from_slice(b"Commando");
+    sid[0x36..0x42].copy_from_slice(b"Rob Hubbard\xe9");
+    let info = SidInfo::parse(&sid).unwrap();
+    assert_eq!((info.title.as_str(), info.author.as_str(), info.songs, info.start_song), ("Commando", "Rob Hubbardé", 3, 1));
+    assert_eq!(SidInfo::parse(b"PSID"), None);
+    let commando = Playing { name: "Commando".into(), file: Some("music/Commando.sid".into()), ..Playing::default() };
+    assert_eq!(now(Some(commando.clone()), Some("commando.sid")), commando);
+    assert_eq!(now(Some(commando.clone()), Some("")), commando);
+    assert_eq!(now(Some(commando), Some("edge.prg")).name, "edge.prg");
+    assert_eq!(now(None, None), Playing::default());
+    assert_eq!(path("GET /now.json?t=1 HTTP/1.1\r\n"), Some("/now.json"));
+    assert_eq!(path("POST / HTTP/1.1\r\n"), None);
+    let mut out = vec![];
+    Reply::ok("text/plain", "hi").write(&mut out).unwrap();
+    assert!(out.starts_with(b"HTTP/1.1 200 OK\r\n") && out.ends_with(b"\r\n\r\nhi"));
+}
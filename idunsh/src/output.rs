@@ -0,0 +1,293 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+// Copyright (C) 2026 Brian Holdsworth
+
+//! A program's redirected output, read while it runs.
+//!
+//! Output comes in frames where asked for (see `protocol::Frames`):
+//! stdout, stderr and status each go their own way, and a CRC of the lot
+//! closes it with `--crc`.
+use std::env;
+use std::fs;
+use std::io::{self, IsTerminal, Read, Write, stdout};
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::result;
+use std::thread;
+use std::time::{Duration, Instant};
+use tokio::task::JoinHandle;
+use idun_client::petscii::{Charset, Controls, Decoder};
+use idun_client::protocol;
+use idun_client::runtime;
+use idun_client::util::{self, Newline};
+use crate::Cli;
+use crate::errors::ExitStatus;
+use crate::events::Activity;
+use crate::idun;
+use crate::parsers::Parser;
+
+// Simpler error handling
+type Result<T> = result::Result<T, failure::Error>;
+
+// How long the remote shell has to connect for redirected output, unless
+// a command timeout is configured
+const ACCEPT_TIMEOUT: Duration = Duration::from_secs(30);
+
+// Waits for the remote shell to connect for redirected output, for no
+// longer than `connect`. With a timeout, gives up sooner once nothing has
+// been heard from the remote side for that long.
+fn accept_within(listener: &UnixListener, connect: Duration, timeout: Option<Duration>, activity: &Activity) -> Result<UnixStream> {
+    const POLL: Duration = Duration::from_millis(100);
+    let started = Instant::now();
+    listener.set_nonblocking(true)?;
+    loop {
+        match listener.accept() {
+            Ok((s, _)) => {
+                s.set_nonblocking(false)?;
+                s.set_read_timeout(Some(POLL))?;
+                return Ok(s);
+            },
+            Err(e) if e.kind() == io::ErrorKind::WouldBlock => {
+                check_idle(timeout, activity)?;
+                if started.elapsed() > connect {
+                    bail!("The remote program didn't connect for its output within {:?}", connect)
+                }
+                thread::sleep(POLL);
+            },
+            Err(e) => return Err(e.into()),
+        }
+    }
+}
+
+fn check_idle(timeout: Option<Duration>, activity: &Activity) -> Result<()> {
+    match timeout {
+        Some(t) if activity.idle() > t =>
+            bail!("No output or heartbeat from the remote program for {:?}; it may have crashed", t),
+        _ => Ok(()),
+    }
+}
+
+// Writes all of `data` to a file descriptor inherited from the caller
+fn write_fd(fd: i32, data: &[u8]) -> Result<()> {
+    let mut rest = data;
+    while !rest.is_empty() {
+        let n = nix::unistd::write(fd, rest).map_err(|e| format_err!("Can't write status to fd {}: {}", fd, e))?;
+        rest = &rest[n..];
+    }
+    Ok(())
+}
+
+/// Colors and reverse video are shown on a terminal
+pub fn controls() -> Controls {
+    match stdout().is_terminal() && env::var_os("NO_COLOR").is_none() {
+        true => Controls::Ansi,
+        false => Controls::Drop,
+    }
+}
+
+/// Where output from the Commodore goes, as `cli` asks: as it comes to
+/// the -O file or, with --raw, to stdout; else printed as ASCII bytes
+/// with --bytes, or decoded in the character set.
+pub struct Sink {
+    raw: Option<Box<dyn Write + Send>>,
+    bytes: bool,
+    newline: Newline,
+    decoder: Decoder,
+}
+
+impl Sink {
+    pub fn new(cli: &Cli, charset: Charset, controls: Controls) -> Result<Sink> {
+        let raw: Option<Box<dyn Write + Send>> = match (&cli.output_file, cli.raw) {
+            (Some(f), _) => Some(Box::new(fs::File::create(f).map_err(|e| format_err!("{}: {}", f, e))?)),
+            (None, true) => Some(Box::new(stdout())),
+            (None, false) => None,
+        };
+        Ok(Sink { raw, bytes: cli.bytes, newline: cli.newline, decoder: Decoder::new(charset, controls) })
+    }
+    /// Whether the output is taken as it comes, without translation
+    pub fn is_raw(&self) -> bool {
+        self.raw.is_some()
+    }
+    /// Output decoded in the character set, e.g. to be parsed
+    pub fn decode(&mut self, data: &[u8]) -> String {
+        self.decoder.decode(data)
+    }
+    /// Passes on output in PETSCII
+    pub fn write(&mut self, data: &[u8]) -> Result<()> {
+        match self.raw.as_mut() {
+            Some(out) => out.write_all(data)?,
+            None if self.bytes => stdout().write_all(&self.newline.translate(&util::pet_to_ascii(data)))?,
+            None => print!("{}", self.newline.translate(self.decoder.decode(data).as_bytes())),
+        }
+        Ok(())
+    }
+    /// Passes on text of idunsh's own, e.g. a heading or JSON, as it is
+    pub fn text(&mut self, text: &str) -> Result<()> {
+        match self.raw.as_mut() {
+            Some(out) => out.write_all(text.as_bytes())?,
+            None => print!("{}", text),
+        }
+        Ok(())
+    }
+    /// Ends the output with what the decoder still holds, and a last
+    /// newline unless the bytes are left as they are
+    pub fn finish(&mut self) -> Result<()> {
+        print!("{}", self.decoder.finish());
+        match self.raw.as_mut() {
+            Some(out) => out.flush()?,
+            None if !self.bytes => println!(),
+            None => (),
+        }
+        stdout().flush()?;
+        Ok(())
+    }
+}
+
+// Redirected output ended before the remote program did (EX_IOERR)
+const EXIT_TRUNCATED: i32 = 74;
+
+// Redirected output doesn't match its CRC (EX_DATAERR)
+const EXIT_CORRUPT: i32 = 65;
+
+/// Listens for a command's redirected output and reads it on a thread of
+/// its own, as `cli` asks: printed, written to a file, or parsed into JSON
+/// by `parser`. Gives the id that names the redirect socket, and the
+/// reader, which ends with the number of bytes received.
+pub fn read(cli: &Cli, parser: Option<Parser>, charset: Charset, timeout: Option<Duration>, activity: &Activity)
+        -> Result<(u32, JoinHandle<Result<u64>>)> {
+    // Create listening socket for response
+    let (resport, respath, id) = idun().response_listener()?;
+    let newline = cli.newline;
+    let mut parsed = String::new();
+    let controls = match parser {
+        Some(_) => Controls::Drop,
+        None => controls(),
+    };
+    // -O and --raw take the output as it comes, without translation
+    let mut out = Sink::new(cli, charset, controls)?;
+    // A program's errors and status come in streams of their own
+    let mut frames = protocol::Frames::default();
+    let mut link_decoder = idun().encoding().decoder();
+    let mut errors = Decoder::new(charset, Controls::Drop);
+    let status_fd = cli.status_fd;
+    let mut stderr_file = cli.stderr_file.as_ref()
+        .map(|f| fs::File::create(f).map_err(|e| format_err!("{}: {}", f, e)))
+        .transpose()?;
+    let connect = idun().timeouts().command.unwrap_or(ACCEPT_TIMEOUT);
+    let check_crc = cli.crc;
+    let activity = activity.clone();
+    let reader = runtime::get().spawn_blocking(move || -> Result<u64> {
+        // Wait on response
+        let mut s = accept_within(&resport, connect, timeout, &activity)?;
+        let mut buf = [0u8; 4096];
+        let mut received = 0;
+        // Why the output ended early, if the remote side went away
+        let mut cut = None;
+        // The CRC of the output so far, and the one the daemon sent
+        let mut crc = 0;
+        let mut sent_crc = None;
+        loop {
+            let n = match s.read(&mut buf) {
+                Err(e) if matches!(e.kind(), io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut) => {
+                    check_idle(timeout, &activity)?;
+                    continue
+                },
+                Err(e) if matches!(e.kind(), io::ErrorKind::ConnectionReset | io::ErrorKind::ConnectionAborted |
+                                   io::ErrorKind::BrokenPipe | io::ErrorKind::UnexpectedEof) => {
+                    cut = Some(e.to_string());
+                    0
+                },
+                r => r?,
+            };
+            let decoded = match n {
+                0 => link_decoder.finish()?,
+                n => link_decoder.push(&buf[..n])?,
+            };
+            let mut received_frames = frames.push(&decoded);
+            if n == 0 && cut.is_none() && frames.pending() > 0 {
+                cut = Some(format!("the last {} bytes are part of an unfinished frame", frames.pending()));
+            }
+            activity.touch();
+            received += decoded.len() as u64;
+            if n == 0 {
+                received_frames.extend(frames.finish());
+            }
+            for (stream, data) in received_frames {
+                if stream != protocol::Stream::Crc {
+                    crc = util::crc32_update(crc, &data);
+                }
+                match stream {
+                    protocol::Stream::Stdout => match parser {
+                        Some(_) if !out.is_raw() => parsed.push_str(&out.decode(&data)),
+                        _ => out.write(&data)?,
+                    },
+                    protocol::Stream::Stderr => match stderr_file.as_mut() {
+                        Some(f) => f.write_all(&newline.translate(errors.decode(&data).as_bytes()))?,
+                        None => eprint!("{}", newline.translate(errors.decode(&data).as_bytes())),
+                    },
+                    protocol::Stream::Status => if let Some(fd) = status_fd {
+                        write_fd(fd, &newline.translate(errors.decode(&data).as_bytes()))?;
+                    },
+                    protocol::Stream::Crc => sent_crc = <[u8; 4]>::try_from(data.as_slice()).ok().map(u32::from_le_bytes),
+                    protocol::Stream::Heartbeat | protocol::Stream::Other(_) => (),
+                }
+            }
+            if n == 0 {
+                break
+            }
+        }
+        // Cleanup
+        match parser {
+            _ if out.is_raw() => (),
+            // What came of cut short output may not parse, so it's kept as it is
+            Some(_) if cut.is_some() => print!("{}", parsed),
+            Some(parser) => print!("{}", serde_json::to_string_pretty(&parser.parse(&parsed)?)?),
+            None => (),
+        }
+        // Raw bytes are left exactly as the program wrote them
+        out.finish()?;
+        drop(respath);
+        if let Some(reason) = cut {
+            eprintln!("[output truncated after {} bytes: {}]", received, reason);
+            return Err(ExitStatus(EXIT_TRUNCATED).into())
+        }
+        match sent_crc {
+            None if check_crc => bail!("The daemon sent no CRC of the output; it may be too old for --crc"),
+            Some(sent) if check_crc && sent != crc => {
+                eprintln!("[output corrupted: its CRC is {:08x}, the daemon sent {:08x}]", crc, sent);
+                return Err(ExitStatus(EXIT_CORRUPT).into())
+            },
+            _ => (),
+        }
+        Ok(received)
+    });
+    Ok((id, reader))
+}
+
+#[test]
+fn remote_side_missing() {
+    let path = env::temp_dir().join(format!("idunsh-output-{}", std::process::id()));
+    let _ = fs::remove_file(&path);
+    let listener = UnixListener::bind(&path).unwrap();
+    let activity = Activity::new();
+    let failed = accept_within(&listener, Duration::from_millis(200), None, &activity).unwrap_err();
+    assert_eq!(failed.to_string(), "The remote program didn't connect for its output within 200ms");
+    // Silence gives up sooner than the wait to connect
+    let started = Instant::now();
+    let failed = accept_within(&listener, Duration::from_secs(30), Some(Duration::from_millis(100)), &activity).unwrap_err();
+    assert!(failed.to_string().starts_with("No output or heartbeat") && started.elapsed() < Duration::from_secs(5));
+    activity.touch();
+    assert!(check_idle(Some(Duration::from_secs(30)), &activity).is_ok() && check_idle(None, &activity).is_ok());
+    fs::remove_file(path).unwrap();
+}
+
+#[test]
+fn status_to_fd() {
+    use std::os::unix::io::AsRawFd;
+    let (ours, mut theirs) = UnixStream::pair().unwrap();
+    write_fd(ours.as_raw_fd(), b"loading\n").unwrap();
+    drop(ours);
+    let mut status = String::new();
+    theirs.read_to_string(&mut status).unwrap();
+    assert_eq!(status, "loading\n");
+    assert!(write_fd(-1, b"x").unwrap_err().to_string().starts_with("Can't write status to fd -1"));
+    write_fd(-1, b"").unwrap();
+}
@@ -0,0 +1,252 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+// Copyright (C) 2026 Brian Holdsworth
+
+//! `.idunpack` files, a whole drive setup in one file for `pack` and
+//! `unpack`.
+//!
+//! A pack is a gzipped tar archive. `manifest.toml` names it and says
+//! where its drives go, in the form of a state file:
+//!
+//! ```toml
+//! name = "demo-night"
+//! description = "Party demos on d: and the tools on e:"
+//!
+//! [assigns]
+//! "e:" = "drives/e"
+//!
+//! [mounts]
+//! "d:" = "drives/d/demos.d81"
+//!
+//! protected = ["e:"]
+//! ```
+//!
+//! Paths are within the archive: the image mounted on a drive is kept
+//! as `drives/<drive>/<image>`, and the files of an assigned directory
+//! under `drives/<drive>`. `config.toml`, if the pack has one, is a
+//! config file fragment adding aliases, keys, exec templates and xargs.
+use std::fs::{self, File};
+use std::io::Read;
+use std::path::{Component, Path, PathBuf};
+use std::process;
+use std::result;
+use flate2::Compression;
+use flate2::read::GzDecoder;
+use flate2::write::GzEncoder;
+use serde::{Deserialize, Serialize};
+use crate::cleanup::TempPath;
+use crate::config::{Config, Fragment};
+use crate::confirm::confirm;
+use crate::idun;
+use crate::state::{self, State};
+use crate::store;
+
+// Simpler error handling
+type Result<T> = result::Result<T, failure::Error>;
+
+const MANIFEST: &str = "manifest.toml";
+const CONFIG: &str = "config.toml";
+
+#[derive(Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct Manifest {
+    pub name: String,
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub description: String,
+    #[serde(flatten)]
+    pub state: State,
+}
+
+/// A pack unpacked, with the paths of its drives made whole.
+pub struct Unpacked {
+    pub manifest: Manifest,
+    /// The config fragment, if there is one
+    pub config: Option<PathBuf>,
+}
+
+/// A name that can be a file name: letters, digits, '-', '_' and '.'.
+pub fn check_name(name: &str) -> Result<()> {
+    let ok = |c: char| c.is_ascii_alphanumeric() || "-_.".contains(c);
+    if name.is_empty() || name.starts_with('.') || !name.chars().all(ok) {
+        bail!("{:?} can't name a pack; use letters, digits, '-', '_' and '.'", name)
+    }
+    Ok(())
+}
+
+// The directory within the pack for a drive such as "e:"
+fn drive_dir(dev: &str) -> String {
+    let name: String = dev.trim_end_matches(':').chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c.to_ascii_lowercase() } else { '_' })
+        .collect();
+    format!("drives/{}", name)
+}
+
+impl Manifest {
+    fn parse(text: &str) -> Result<Manifest> {
+        let manifest: Manifest = toml::from_str(text)?;
+        check_name(&manifest.name)?;
+        let paths = manifest.state.assigns.values().chain(manifest.state.mounts.values());
+        if let Some(p) = paths.into_iter().find(|p| !Path::new(p).components().all(|c| matches!(c, Component::Normal(_)))) {
+            bail!("{} is outside the pack", p)
+        }
+        Ok(manifest)
+    }
+}
+
+/// Writes a pack of the drives of `state`, with the images they mount
+/// and the files of the directories they're assigned to, and a config
+/// fragment.
+pub fn pack(file: &str, name: &str, description: &str, state: &State, config: Option<&str>) -> Result<()> {
+    check_name(name)?;
+    if let Some(config) = config {
+        // Only what a fragment may hold is let in
+        Fragment::load(Path::new(config))?;
+    }
+    let tmp = TempPath::new(PathBuf::from(format!("{}.tmp-{}", file, process::id())));
+    let out = File::create(tmp.path()).map_err(|e| format_err!("{}: {}", file, e))?;
+    let mut tar = tar::Builder::new(GzEncoder::new(out, Compression::default()));
+    let mut manifest = Manifest { name: name.into(), description: description.into(), ..Default::default() };
+    let mut images = vec![];
+    for (dev, image) in &state.mounts {
+        let image_name = Path::new(image).file_name()
+            .ok_or_else(|| format_err!("{} on {} isn't a file", image, dev))?;
+        let path = format!("{}/{}", drive_dir(dev), image_name.to_string_lossy());
+        images.push((image, path.clone()));
+        manifest.state.mounts.insert(dev.clone(), path);
+    }
+    for dev in state.assigns.keys() {
+        manifest.state.assigns.insert(dev.clone(), drive_dir(dev));
+    }
+    manifest.state.protected = state.protected.iter().filter(|d| state.assigns.contains_key(*d)).cloned().collect();
+    // The manifest goes first, so it's found without reading the rest
+    append(&mut tar, MANIFEST, toml::to_string(&manifest)?.as_bytes())?;
+    for (image, path) in images {
+        tar.append_path_with_name(image, &path).map_err(|e| format_err!("{}: {}", image, e))?;
+    }
+    for (dev, dir) in &state.assigns {
+        tar.append_dir_all(drive_dir(dev), dir).map_err(|e| format_err!("{}: {}", dir, e))?;
+    }
+    if let Some(config) = config {
+        tar.append_path_with_name(config, CONFIG).map_err(|e| format_err!("{}: {}", config, e))?;
+    }
+    tar.into_inner()?.finish()?.sync_all()?;
+    fs::rename(tmp.path(), file)?;
+    Ok(())
+}
+
+fn append<W: std::io::Write>(tar: &mut tar::Builder<W>, path: &str, data: &[u8]) -> Result<()> {
+    let mut header = tar::Header::new_gnu();
+    header.set_size(data.len() as u64);
+    header.set_mode(0o644);
+    header.set_cksum();
+    tar.append_data(&mut header, path, data)?;
+    Ok(())
+}
+
+/// The manifest of a pack, without unpacking it.
+pub fn manifest(file: &str) -> Result<Manifest> {
+    let mut archive = open(file)?;
+    for entry in archive.entries()? {
+        let mut entry = entry?;
+        if entry.path()?.as_os_str() == MANIFEST {
+            let mut text = String::new();
+            entry.read_to_string(&mut text)?;
+            return Manifest::parse(&text).map_err(|e| format_err!("{}: {}", file, e))
+        }
+    }
+    bail!("{} has no {}; is it an .idunpack file?", file, MANIFEST)
+}
+
+fn open(file: &str) -> Result<tar::Archive<GzDecoder<File>>> {
+    let f = File::open(file).map_err(|e| format_err!("{}: {}", file, e))?;
+    Ok(tar::Archive::new(GzDecoder::new(f)))
+}
+
+/// Unpacks a pack into `dir`, which mustn't exist yet.
+pub fn unpack(file: &str, dir: &Path) -> Result<Unpacked> {
+    let mut manifest = manifest(file)?;
+    if dir.exists() {
+        bail!("{} is already there", dir.display())
+    }
+    let tmp = dir.with_extension(format!("tmp-{}", process::id()));
+    // Entries that would land outside the directory are left out
+    if let Err(e) = open(file)?.unpack(&tmp).and_then(|_| fs::rename(&tmp, dir)) {
+        let _ = fs::remove_dir_all(&tmp);
+        bail!("{}: {}", file, e)
+    }
+    for path in manifest.state.assigns.values_mut().chain(manifest.state.mounts.values_mut()) {
+        let whole = dir.join(&*path);
+        if !whole.exists() {
+            bail!("{} has no {}", file, path)
+        }
+        *path = whole.to_string_lossy().into_owned();
+    }
+    let config = Some(dir.join(CONFIG)).filter(|p| p.exists());
+    Ok(Unpacked { manifest, config })
+}
+
+// Packs the live drive setup, or that of `drives` only, into `file`. The
+// pack is named after the file unless given a name.
+pub fn pack_cmd(file: &str, name: Option<&str>, description: Option<&str>, drives: &[String],
+        config: Option<&str>) -> Result<()> {
+    let mut state = State::live(&idun().drives()?)?;
+    if !drives.is_empty() {
+        let drives: Vec<String> = drives.iter().map(|d| d.to_ascii_lowercase()).collect();
+        state.assigns.retain(|dev, _| drives.contains(dev));
+        state.mounts.retain(|dev, _| drives.contains(dev));
+    }
+    let name = match name {
+        Some(name) => name.to_string(),
+        None => Path::new(file).file_stem().unwrap_or_default().to_string_lossy().into_owned(),
+    };
+    pack(file, &name, description.unwrap_or_default(), &state, config)
+}
+
+// Unpacks `file` into `dir`, or the packs directory, and sets up its drives
+pub fn unpack_cmd(file: &str, dir: Option<String>, yes: bool) -> Result<()> {
+    let name = manifest(file)?.name;
+    let dir = match dir {
+        Some(dir) => std::path::PathBuf::from(dir),
+        None => store::subdir("packs")?.join(&name),
+    };
+    if dir.exists() {
+        confirm(&format!("Replace {} with {}?", dir.display(), file), yes)?;
+        fs::remove_dir_all(&dir)?;
+    }
+    if let Some(parent) = dir.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    let unpacked = unpack(file, &dir)?;
+    if let Some(config) = unpacked.config {
+        let fragments = Config::fragment_dir().ok_or_else(|| format_err!("No config directory"))?;
+        fs::create_dir_all(&fragments)?;
+        fs::copy(config, fragments.join(format!("{}.toml", name)))?;
+    }
+    state::apply(&unpacked.manifest.state)?;
+    eprintln!("Unpacked {} to {}", name, dir.display());
+    Ok(())
+}
+
+#[test]
+fn pack_and_unpack() {
+    let dir = std::env::temp_dir().join(format!("idunsh-pack-{}", process::id()));
+    fs::create_dir_all(dir.join("apps/sub")).unwrap();
+    fs::write(dir.join("apps/sub/a.app"), b"app").unwrap();
+    fs::write(dir.join("demos.d81"), b"image").unwrap();
+    fs::write(dir.join("extra.toml"), "[aliases]\nd = \"dir d:\"\n").unwrap();
+    let mut state = State::default();
+    state.assigns.insert("e:".into(), dir.join("apps").to_string_lossy().into());
+    state.mounts.insert("d:".into(), dir.join("demos.d81").to_string_lossy().into());
+    state.protected.push("e:".into());
+    let file = dir.join("demo.idunpack").to_string_lossy().into_owned();
+    assert!(pack(&file, "../x", "", &state, None).is_err());
+    pack(&file, "demo", "Demo night", &state, Some(&dir.join("extra.toml").to_string_lossy())).unwrap();
+    let m = manifest(&file).unwrap();
+    assert_eq!((m.name.as_str(), m.state.mounts["d:"].as_str(), m.state.assigns["e:"].as_str()), ("demo", "drives/d/demos.d81", "drives/e"));
+    let unpacked = unpack(&file, &dir.join("out")).unwrap();
+    assert_eq!(fs::read(&unpacked.manifest.state.mounts["d:"]).unwrap(), b"image");
+    assert_eq!(fs::read(Path::new(&unpacked.manifest.state.assigns["e:"]).join("sub/a.app")).unwrap(), b"app");
+    assert_eq!(unpacked.manifest.state.protected, ["e:"]);
+    assert!(unpacked.config.is_some());
+    assert!(unpack(&file, &dir.join("out")).is_err());
+    assert!(Manifest::parse("name = \"x\"\n[mounts]\n\"d:\" = \"../etc/passwd\"\n").is_err());
+    fs::remove_dir_all(&dir).unwrap();
+}
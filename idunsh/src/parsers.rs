@@ -0,0 +1,81 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+// Copyright (C) 2026 Brian Holdsworth
+
+//! Turns the screen output of remote tools into JSON.
+//!
+//! Which parser a tool's output goes through is set per remote program
+//! in the config file:
+//!
+//! ```toml
+//! [parsers]
+//! diskinfo = "key-value"
+//! ls = "table"
+//! ```
+use std::result;
+use serde::Deserialize;
+use serde_json::{Map, Value};
+
+// Simpler error handling
+type Result<T> = result::Result<T, failure::Error>;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Parser {
+    /// A header line naming the columns, then one row per line. Each
+    /// column starts below its name, as in screen-formatted tables.
+    Table,
+    /// `key: value` or `key=value` lines, giving one object
+    KeyValue,
+    /// Output that is JSON already, checked and passed on
+    Json,
+}
+
+impl Parser {
+    pub fn parse(&self, text: &str) -> Result<Value> {
+        let mut lines = text.split(['\r', '\n']).map(str::trim_end).filter(|l| !l.trim().is_empty());
+        match self {
+            Parser::Table => {
+                let header = lines.next().unwrap_or_default();
+                // Each column starts where its name starts
+                let columns: Vec<(usize, &str)> = header.char_indices()
+                    .filter(|&(i, c)| c != ' ' && (i == 0 || header[..i].ends_with(' ')))
+                    .map(|(i, _)| (i, header[i..].split(' ').next().unwrap_or_default()))
+                    .collect();
+                let rows = lines
+                    .map(|line| {
+                        let mut row = Map::new();
+                        for (n, &(start, name)) in columns.iter().enumerate() {
+                            let end = columns.get(n + 1).map_or(line.len(), |c| c.0).min(line.len());
+                            let field = line.get(start.min(end)..end).unwrap_or_default().trim();
+                            row.insert(name.to_string(), Value::from(field));
+                        }
+                        Value::Object(row)
+                    })
+                    .collect();
+                Ok(Value::Array(rows))
+            },
+            Parser::KeyValue => {
+                let mut object = Map::new();
+                for line in lines {
+                    let (key, value) = line.split_once(':').or_else(|| line.split_once('='))
+                        .ok_or_else(|| format_err!("Expected key: value, not {:?}", line))?;
+                    object.insert(key.trim().to_string(), Value::from(value.trim()));
+                }
+                Ok(Value::Object(object))
+            },
+            Parser::Json => serde_json::from_str(text.trim())
+                .map_err(|e| format_err!("The output is not JSON: {}", e)),
+        }
+    }
+}
+
+#[test]
+fn parse_output() {
+    let table = Parser::Table.parse("name      blocks type\rgame      12     prg\rmy notes  3      seq file\r").unwrap();
+    assert_eq!(table[1]["name"], "my notes");
+    assert_eq!(table[1]["type"], "seq file");
+    assert_eq!(table[0]["blocks"], "12");
+    let info = Parser::KeyValue.parse("name: work disk\nid=2a\n").unwrap();
+    assert_eq!((info["name"].as_str(), info["id"].as_str()), (Some("work disk"), Some("2a")));
+    assert!(Parser::Json.parse("[1, 2]").is_ok() && Parser::Json.parse("ready.").is_err());
+}
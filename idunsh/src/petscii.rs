@@ -0,0 +1,312 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+// Copyright (C) 2026 Brian Holdsworth
+
+//! PETSCII, the Commodore's character set, to and from Unicode.
+//!
+//! A PETSCII byte shows one of two glyphs, depending on the character
+//! set the Commodore is switched to: the unshifted set, with upper case
+//! letters and graphics, or the shifted set, with lower and upper case
+//! letters and fewer graphics. Graphics map to box drawing, block and
+//! Symbols for Legacy Computing characters, so a font with those shows
+//! them best.
+//!
+//! Control codes, e.g. for colors, reverse video and the cursor, have no
+//! glyph. They are dropped, turned into terminal escape sequences, or
+//! written as names in braces the way petcat writes them, e.g. `{clr}`,
+//! `{rvon}` or `{$07}`. Names read back to the same codes.
+//!
+//! Machines sold in Germany, Sweden and Denmark show their own letters
+//! in place of a few symbols, the way their national variants of ASCII
+//! do. Text typed on them is encoded with their `Layout`.
+use std::result;
+use clap::ValueEnum;
+use serde::Deserialize;
+
+// Simpler error handling
+type Result<T> = result::Result<T, failure::Error>;
+
+/// Which glyphs PETSCII codes show.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize, ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum Charset {
+    /// Upper case letters and graphics, the set the Commodore starts in
+    Upper,
+    /// Lower and upper case letters
+    #[default]
+    Lower,
+}
+
+/// The keyboard and character ROM of a localized Commodore.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize, ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum Layout {
+    /// The US and UK machines
+    #[default]
+    Us,
+    /// Ä, Ö and Ü in place of [, £ and ], § in place of @
+    De,
+    /// Ä, Ö and Å in place of [, £ and ]
+    Se,
+    /// Æ, Ø and Å in place of [, £ and ]
+    Dk,
+}
+
+impl Layout {
+    // Letters and the codes they take; lower case ones take the shifted
+    // codes of the same keys
+    fn letters(self) -> &'static [(char, u8)] {
+        match self {
+            Layout::Us => &[],
+            Layout::De => &[('Ä', 0x5b), ('Ö', 0x5c), ('Ü', 0x5d), ('ä', 0xdb), ('ö', 0xdc), ('ü', 0xdd), ('§', 0x40)],
+            Layout::Se => &[('Ä', 0x5b), ('Ö', 0x5c), ('Å', 0x5d), ('ä', 0xdb), ('ö', 0xdc), ('å', 0xdd)],
+            Layout::Dk => &[('Æ', 0x5b), ('Ø', 0x5c), ('Å', 0x5d), ('æ', 0xdb), ('ø', 0xdc), ('å', 0xdd)],
+        }
+    }
+    /// The PETSCII code that shows `c` on a machine with this layout.
+    /// Characters whose codes show a letter of the layout instead have
+    /// none.
+    pub fn encode_char(self, charset: Charset, c: char) -> Option<u8> {
+        let letters = self.letters();
+        if let Some((_, code)) = letters.iter().find(|(l, _)| *l == c) {
+            return Some(*code)
+        }
+        encode_char(charset, c).filter(|code| !letters.iter().any(|(_, taken)| taken == code))
+    }
+}
+
+/// What becomes of control codes when decoding.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Controls {
+    /// Leave them out; CR and LF are kept as they are
+    Drop,
+    /// Colors, reverse video, cursor moves and clear screen as terminal
+    /// escape sequences; CR and LF are kept as they are
+    Ansi,
+    /// Names in braces; CR starts a new line
+    Names,
+}
+
+// Glyphs of codes $60-$7F in the unshifted set; $C0-$DF are the same
+const GRAPHICS: [char; 32] = [
+    '─', '♠', '🭲', '🭸', '🭷', '🭶', '🭺', '🭱', '🭴', '╮', '╰', '╯', '🭼', '╲', '╱', '🭽',
+    '🭾', '●', '🭻', '♥', '🭰', '╭', '╳', '○', '♣', '🭵', '♦', '┼', '🮌', '│', 'π', '◥',
+];
+// Glyphs of codes $A0-$BF in the unshifted set; $E0-$FE are the same
+const BLOCKS: [char; 32] = [
+    '\u{a0}', '▌', '▄', '▔', '▁', '▏', '▒', '▕', '🮏', '◤', '🮇', '├', '▗', '└', '┐', '▂',
+    '┌', '┴', '┬', '┤', '▎', '▍', '🮈', '🮂', '🮃', '▃', '🭿', '▖', '▝', '┘', '▘', '▚',
+];
+
+// Control codes by the names petcat gives them
+const CONTROLS: [(u8, &str); 43] = [
+    (0x05, "wht"), (0x07, "bell"), (0x08, "dish"), (0x09, "ensh"), (0x0e, "swlc"),
+    (0x11, "down"), (0x12, "rvon"), (0x13, "home"), (0x14, "del"), (0x1c, "red"),
+    (0x1d, "rght"), (0x1e, "grn"), (0x1f, "blu"), (0x81, "orng"), (0x85, "f1"),
+    (0x86, "f3"), (0x87, "f5"), (0x88, "f7"), (0x89, "f2"), (0x8a, "f4"),
+    (0x8b, "f6"), (0x8c, "f8"), (0x8d, "sret"), (0x8e, "swuc"), (0x90, "blk"),
+    (0x91, "up"), (0x92, "rvof"), (0x93, "clr"), (0x94, "inst"), (0x95, "brn"),
+    (0x96, "lred"), (0x97, "gry1"), (0x98, "gry2"), (0x99, "lgrn"), (0x9a, "lblu"),
+    (0x9b, "gry3"), (0x9c, "pur"), (0x9d, "left"), (0x9e, "yel"), (0x9f, "cyn"),
+    (0x0d, "cr"), (0x0a, "lf"), (0x03, "stop"),
+];
+// Other names read for keys, as they are labelled on the keyboard
+const KEY_NAMES: [(u8, &str); 4] = [(0x0d, "return"), (0x03, "runstop"), (0x1d, "right"), (0x14, "delete")];
+
+/// The glyph PETSCII code `b` shows in `charset`, or None for control
+/// codes.
+pub fn glyph(charset: Charset, b: u8) -> Option<char> {
+    let lower = charset == Charset::Lower;
+    Some(match b {
+        0x20..=0x40 | 0x5b | 0x5d => b as char,
+        0x41..=0x5a if lower => (b + 0x20) as char,
+        0x41..=0x5a => b as char,
+        0x5c => '£',
+        0x5e => '↑',
+        0x5f => '←',
+        0x61..=0x7a if lower => (b - 0x20) as char,
+        0x7e if lower => '🮖',
+        0x7f if lower => '🮘',
+        0x60..=0x7f => GRAPHICS[b as usize - 0x60],
+        0xa9 if lower => '🮙',
+        0xba if lower => '✓',
+        0xa0..=0xbf => BLOCKS[b as usize - 0xa0],
+        0xc0..=0xdf => return glyph(charset, b - 0x60),
+        0xe0..=0xfe => return glyph(charset, b - 0x40),
+        0xff => return glyph(charset, 0x7e),
+        _ => return None,
+    })
+}
+
+/// The PETSCII code that shows `c` in `charset`. ASCII characters without
+/// a glyph of their own, such as `_` or `{`, get the codes `ascii_to_pet`
+/// gives them; letters of the wrong case in the unshifted set are taken
+/// as upper case.
+pub fn encode_char(charset: Charset, c: char) -> Option<u8> {
+    match c {
+        'a'..='z' if charset == Charset::Upper => Some(c.to_ascii_uppercase() as u8),
+        'A'..='Z' if charset == Charset::Upper => Some(c as u8),
+        '\n' => Some(0x0d),
+        c if c.is_ascii() => Some(crate::util::ascii_to_pet(&[c as u8])[0]),
+        c => (0x20..=0xbf).find(|b| glyph(charset, *b) == Some(c)),
+    }
+}
+
+// SGR parameters or other escape sequence for a control code
+fn ansi(b: u8) -> Option<&'static str> {
+    Some(match b {
+        0x05 => "\x1b[97m",
+        0x1c => "\x1b[31m",
+        0x1e => "\x1b[32m",
+        0x1f => "\x1b[34m",
+        0x81 => "\x1b[38;5;208m",
+        0x90 => "\x1b[30m",
+        0x95 => "\x1b[38;5;94m",
+        0x96 => "\x1b[91m",
+        0x97 => "\x1b[90m",
+        0x98 => "\x1b[38;5;244m",
+        0x99 => "\x1b[92m",
+        0x9a => "\x1b[94m",
+        0x9b => "\x1b[37m",
+        0x9c => "\x1b[35m",
+        0x9e => "\x1b[93m",
+        0x9f => "\x1b[36m",
+        0x12 => "\x1b[7m",
+        0x92 => "\x1b[27m",
+        0x93 => "\x1b[2J\x1b[H",
+        0x13 => "\x1b[H",
+        0x11 => "\x1b[B",
+        0x91 => "\x1b[A",
+        0x1d => "\x1b[C",
+        0x9d => "\x1b[D",
+        _ => return None,
+    })
+}
+
+/// Turns PETSCII into text, following the switches between character
+/// sets that come with it. Text can be decoded in pieces as it arrives.
+#[derive(Clone, Debug)]
+pub struct Decoder {
+    charset: Charset,
+    controls: Controls,
+    // An escape sequence is in effect and has to be reset at the end
+    styled: bool,
+}
+
+impl Decoder {
+    pub fn new(charset: Charset, controls: Controls) -> Decoder {
+        Decoder { charset, controls, styled: false }
+    }
+    pub fn decode(&mut self, pet: &[u8]) -> String {
+        let mut text = String::with_capacity(pet.len());
+        for b in pet {
+            if let Some(c) = glyph(self.charset, *b) {
+                text.push(c);
+                continue
+            }
+            match *b {
+                0x0e => self.charset = Charset::Lower,
+                0x8e => self.charset = Charset::Upper,
+                _ => (),
+            }
+            match (self.controls, *b) {
+                (Controls::Names, 0x0d) => text.push('\n'),
+                (Controls::Names, b) => match CONTROLS.iter().find(|(code, _)| *code == b) {
+                    Some((_, name)) => text.push_str(&format!("{{{}}}", name)),
+                    None => text.push_str(&format!("{{${:02x}}}", b)),
+                },
+                (_, 0x0d) => text.push('\r'),
+                (_, 0x0a) => text.push('\n'),
+                (Controls::Ansi, b) => if let Some(seq) = ansi(b) {
+                    self.styled = true;
+                    text.push_str(seq);
+                },
+                (Controls::Drop, _) => (),
+            }
+        }
+        text
+    }
+    /// What has to follow the last piece: resets any color or reverse
+    /// video left on.
+    pub fn finish(&mut self) -> &'static str {
+        match std::mem::take(&mut self.styled) {
+            true => "\x1b[0m",
+            false => "",
+        }
+    }
+}
+
+/// All of `pet` as text.
+pub fn decode(pet: &[u8], charset: Charset, controls: Controls) -> String {
+    let mut decoder = Decoder::new(charset, controls);
+    let mut text = decoder.decode(pet);
+    text.push_str(decoder.finish());
+    text
+}
+
+/// Turns text into PETSCII, reading control codes written as names in
+/// braces when `names` is set. Line ends become CR.
+pub fn encode(text: &str, charset: Charset, names: bool) -> Result<Vec<u8>> {
+    encode_for(text, charset, names, Layout::Us)
+}
+
+/// Turns text into the keys typing it on a machine with `layout`, with
+/// control codes written as names in braces, e.g. `{f1}`, `{return}` or
+/// `{runstop}`.
+pub fn keys(text: &str, charset: Charset, layout: Layout) -> Result<Vec<u8>> {
+    encode_for(text, charset, true, layout)
+}
+
+fn encode_for(text: &str, charset: Charset, names: bool, layout: Layout) -> Result<Vec<u8>> {
+    let mut charset = charset;
+    let mut pet = Vec::with_capacity(text.len());
+    for (n, line) in text.split_inclusive('\n').enumerate() {
+        let mut rest = line.strip_suffix('\n').map(|l| l.strip_suffix('\r').unwrap_or(l)).unwrap_or(line);
+        while let Some(c) = rest.chars().next() {
+            if let Some(name) = rest.strip_prefix('{').and_then(|r| r.split_once('}')).map(|(name, _)| name).filter(|_| names) {
+                let code = match name.strip_prefix('$') {
+                    Some(hex) => u8::from_str_radix(hex, 16).ok(),
+                    None => CONTROLS.iter().chain(&KEY_NAMES).find(|(_, n)| n.eq_ignore_ascii_case(name)).map(|(code, _)| *code),
+                }.ok_or_else(|| format_err!("Unknown control code {{{}}} on line {}", name, n + 1))?;
+                match code {
+                    0x0e => charset = Charset::Lower,
+                    0x8e => charset = Charset::Upper,
+                    _ => (),
+                }
+                pet.push(code);
+                rest = &rest[name.len() + 2..];
+                continue
+            }
+            pet.push(layout.encode_char(charset, c)
+                .ok_or_else(|| format_err!("{:?} on line {} has no PETSCII code", c, n + 1))?);
+            rest = &rest[c.len_utf8()..];
+        }
+        if line.ends_with('\n') {
+            pet.push(0x0d);
+        }
+    }
+    Ok(pet)
+}
+
+#[test]
+fn petscii_unicode() {
+    assert_eq!(decode(b"\xc8ELLO \x5c\x5e\x61\xff", Charset::Lower, Controls::Drop), "Hello £↑A🮖");
+    assert_eq!(decode(b"HELLO \x61\xb0\xff", Charset::Upper, Controls::Drop), "HELLO ♠┌π");
+    assert_eq!(decode(b"\x93\x12HI\x92\x0eHI\r", Charset::Upper, Controls::Names), "{clr}{rvon}HI{rvof}{swlc}hi\n");
+    assert_eq!(decode(b"\x1cRED\x07", Charset::Upper, Controls::Ansi), "\x1b[31mRED\x1b[0m");
+    assert_eq!(encode("{clr}{RVON}♠┌π\n", Charset::Upper, true).unwrap(), b"\x93\x12\x61\xb0\x7e\x0d");
+    assert_eq!(encode("Hi {$07}\r\n", Charset::Lower, true).unwrap(), b"\xc8I \x07\x0d");
+    assert!(encode("{nope}", Charset::Lower, true).is_err());
+    assert!(encode("€", Charset::Lower, false).is_err());
+    assert_eq!(keys("Åsa {f1}\n", Charset::Lower, Layout::Se).unwrap(), b"\x5dSA \x85\x0d");
+    assert!(keys("Grüße", Charset::Lower, Layout::De).is_err());
+    assert_eq!(keys("Rüde §1", Charset::Lower, Layout::De).unwrap(), b"\xd2\xdd\x44\x45 \x401");
+    assert!(keys("[x]", Charset::Lower, Layout::Dk).is_err());
+    assert_eq!(keys("run{Return}{runstop}{right}", Charset::Upper, Layout::Us).unwrap(), b"RUN\x0d\x03\x1d");
+    for b in (0x20..=0x7f).chain(0xa0..=0xbf) {
+        for charset in [Charset::Upper, Charset::Lower] {
+            let c = glyph(charset, b).unwrap();
+            let code = encode_char(charset, c).unwrap();
+            assert_eq!(glyph(charset, code), Some(c), "{:?} {:02x} {:?}", charset, b, c);
+        }
+    }
+}
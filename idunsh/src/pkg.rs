@@ -0,0 +1,293 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+// Copyright (C) 2026 Brian Holdsworth
+
+//! Idun applications for `idunsh pkg`, installed from a package index.
+//!
+//! The index is a TOML file, found at the URL or path set as `index`
+//! under `[pkg]` in the config file:
+//!
+//! ```toml
+//! [[package]]
+//! name = "ftp"
+//! version = "1.2.0"
+//! description = "FTP client"
+//! files = [
+//!     { url = "ftp/ftp.app", sha256 = "9f86d081884c7d65..." },
+//!     { url = "ftp/ftp.hlp", sha256 = "60303ae22b998861..." },
+//! ]
+//! ```
+//!
+//! A file URL that isn't a whole one is relative to the index. Each file
+//! is downloaded through the cache, must have its SHA-256 sum, and is
+//! copied under its own name to the directory the apps drive, `e:` by
+//! default, is assigned to. What was installed is recorded in
+//! `~/.local/share/idunsh/pkg.toml`, so an update can remove the files a
+//! new version no longer has.
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::result;
+use clap::Subcommand;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use idun_client::client::ASSIGN_CMD;
+use idun_client::protocol;
+use crate::cache::{self, Cache};
+use crate::config::Config;
+use crate::daemon_reachable;
+use crate::saves;
+use crate::shell;
+use crate::state::State;
+use crate::store;
+
+// Simpler error handling
+type Result<T> = result::Result<T, failure::Error>;
+
+#[derive(Debug, Deserialize)]
+pub struct Index {
+    #[serde(default, rename = "package")]
+    pub packages: Vec<Package>,
+    // Where the index was read from, for the relative file URLs
+    #[serde(skip)]
+    location: String,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Package {
+    pub name: String,
+    pub version: String,
+    #[serde(default)]
+    pub description: String,
+    pub files: Vec<PackageFile>,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct PackageFile {
+    pub url: String,
+    pub sha256: String,
+}
+
+/// An installed package.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Record {
+    pub version: String,
+    /// The paths of the files copied
+    pub files: Vec<String>,
+}
+
+/// The packages installed, by name.
+pub struct Installed {
+    path: PathBuf,
+    pub packages: BTreeMap<String, Record>,
+}
+
+impl Index {
+    /// Reads the index at a URL or path.
+    pub fn load(location: &str) -> Result<Index> {
+        let text = if cache::is_url(location) {
+            ureq::get(location).call()
+                .and_then(|resp| resp.into_body().read_to_string())
+                .map_err(|e| format_err!("{}: {}", location, e))?
+        } else {
+            fs::read_to_string(location).map_err(|e| format_err!("{}: {}", location, e))?
+        };
+        let mut index = Self::parse(&text).map_err(|e| format_err!("{}: {}", location, e))?;
+        index.location = location.to_string();
+        Ok(index)
+    }
+    fn parse(text: &str) -> Result<Index> {
+        let index: Index = toml::from_str(text)?;
+        for p in &index.packages {
+            if let Some(f) = p.files.iter().find(|f| file_name(&f.url).is_none()) {
+                bail!("{} has a file without a name: {}", p.name, f.url)
+            }
+        }
+        Ok(index)
+    }
+    pub fn find(&self, name: &str) -> Result<&Package> {
+        self.packages.iter()
+            .find(|p| p.name.eq_ignore_ascii_case(name))
+            .ok_or_else(|| format_err!("There is no package {} in {}", name, self.location))
+    }
+    // Where a file of a package is fetched from
+    fn resolve(&self, url: &str) -> String {
+        if cache::is_url(url) || Path::new(url).is_absolute() {
+            return url.to_string()
+        }
+        match self.location.rsplit_once('/') {
+            Some((dir, _)) => format!("{}/{}", dir, url),
+            None => url.to_string(),
+        }
+    }
+    /// Fetches the files of `package` and copies them to `dir`, giving
+    /// the record of them. Nothing is copied unless every file arrived
+    /// with the right sum.
+    pub fn install(&self, package: &Package, dir: &Path) -> Result<Record> {
+        let mut fetched = vec![];
+        for f in &package.files {
+            let url = self.resolve(&f.url);
+            let local = if cache::is_url(&url) { Cache::open()?.fetch(&url)? } else { PathBuf::from(&url) };
+            let data = fs::read(&local).map_err(|e| format_err!("{}: {}", url, e))?;
+            let digest: String = Sha256::digest(&data).iter().map(|b| format!("{:02x}", b)).collect();
+            if !digest.eq_ignore_ascii_case(f.sha256.trim()) {
+                bail!("{} doesn't have the SHA-256 sum the index gives; not installing {}", url, package.name)
+            }
+            fetched.push((dir.join(file_name(&f.url).unwrap_or_default()), data));
+        }
+        let mut files = vec![];
+        for (path, data) in fetched {
+            fs::write(&path, data).map_err(|e| format_err!("{}: {}", path.display(), e))?;
+            files.push(path.to_string_lossy().into_owned());
+        }
+        Ok(Record { version: package.version.clone(), files })
+    }
+}
+
+// The last part of a URL or path, without any query
+fn file_name(url: &str) -> Option<&str> {
+    let path = url.split(['?', '#']).next().unwrap_or(url);
+    path.rsplit('/').next().filter(|name| !name.is_empty() && *name != "..")
+}
+
+/// True if version `a` is later than `b`, comparing the numbers between
+/// the dots, e.g. 1.10 after 1.9.
+pub fn newer(a: &str, b: &str) -> bool {
+    let parts = |v: &str| -> Vec<(u64, String)> {
+        v.trim_start_matches('v').split(['.', '-'])
+            .map(|p| {
+                let digits: String = p.chars().take_while(char::is_ascii_digit).collect();
+                (digits.parse().unwrap_or(0), p[digits.len()..].to_string())
+            })
+            .collect()
+    };
+    parts(a) > parts(b)
+}
+
+impl Installed {
+    pub fn open() -> Result<Installed> {
+        let path = store::dir()?.join("pkg.toml");
+        let packages = match fs::read_to_string(&path) {
+            Ok(text) => toml::from_str(&text).map_err(|e| format_err!("{}: {}", path.display(), e))?,
+            Err(_) => BTreeMap::new(),
+        };
+        Ok(Installed { path, packages })
+    }
+    /// Records package `name` as installed, removing the files of the version
+    /// before that the new one doesn't have.
+    pub fn add(&mut self, name: &str, record: Record) -> Result<()> {
+        if let Some(old) = self.packages.insert(name.to_string(), record.clone()) {
+            for f in old.files.iter().filter(|f| !record.files.contains(f)) {
+                if let Err(e) = fs::remove_file(f) {
+                    eprintln!("Can't remove {}: {}", f, e);
+                }
+            }
+        }
+        store::write(&self.path, toml::to_string(&self.packages)?.as_bytes())
+    }
+}
+
+#[derive(Subcommand)]
+pub enum PkgCommands {
+    /// Install a package, or install it again
+    Install { name: String },
+    /// Update a package, or else every installed one with a newer version
+    Update { name: Option<String> },
+    /// List the packages in the index matching a pattern, or all of them
+    List { pattern: Option<String> },
+}
+
+pub fn run(cmd: PkgCommands, config: &Config) -> Result<()> {
+    let location = config.pkg.index.as_deref()
+        .ok_or_else(|| format_err!("Packages need an index, index under [pkg] in the config file"))?;
+    let index = Index::load(location)?;
+    let mut installed = Installed::open()?;
+    let update = matches!(cmd, PkgCommands::Update { .. });
+    let packages = match cmd {
+        PkgCommands::List { pattern } => {
+            for p in index.packages.iter().filter(|p| pattern.as_ref().is_none_or(|pat| saves::matches(pat, &p.name))) {
+                let version = match installed.packages.get(&p.name) {
+                    Some(r) if r.version == p.version => format!("{} (installed)", p.version),
+                    Some(r) => format!("{} ({} installed)", p.version, r.version),
+                    None => p.version.clone(),
+                };
+                println!("{:<16} {:<24} {}", p.name, version, p.description);
+            }
+            return Ok(())
+        },
+        PkgCommands::Install { name } => vec![index.find(&name)?],
+        PkgCommands::Update { name: Some(name) } => {
+            let package = index.find(&name)?;
+            if !installed.packages.contains_key(&package.name) {
+                bail!("{} isn't installed; pkg install {} installs it", package.name, package.name)
+            }
+            vec![package]
+        },
+        PkgCommands::Update { name: None } => {
+            index.packages.iter().filter(|p| installed.packages.contains_key(&p.name)).collect()
+        },
+    };
+    let mut updates = vec![];
+    for package in packages {
+        match installed.packages.get(&package.name) {
+            Some(r) if update && !newer(&package.version, &r.version) =>
+                eprintln!("{} {} is up to date", package.name, r.version),
+            _ => updates.push(package),
+        }
+    }
+    if updates.is_empty() {
+        return Ok(())
+    }
+    // The apps go wherever the apps drive is assigned
+    let dev = config.pkg.drive.as_deref().unwrap_or("e:").to_ascii_lowercase();
+    let state = State::current()?;
+    let dir = state.assigns.get(&dev)
+        .ok_or_else(|| format_err!("{} isn't assigned to a directory; assign it, or set drive under [pkg]", dev))?;
+    for package in updates {
+        let record = index.install(package, Path::new(dir))?;
+        eprintln!("Installed {} {} to {}", package.name, package.version, dir);
+        installed.add(&package.name, record)?;
+    }
+    // Assigning the drive again has the daemon find the new files
+    if daemon_reachable() {
+        shell(ASSIGN_CMD, &protocol::join_args(&[&dev, dir]), 0)?;
+    }
+    Ok(())
+}
+
+#[test]
+fn package_index() {
+    let text = "[[package]]\nname = \"ftp\"\nversion = \"1.2.0\"\nfiles = [{ url = \"ftp/ftp.app?dl=1\", sha256 = \"ab\" }]\n";
+    let mut index = Index::parse(text).unwrap();
+    index.location = "https://example.com/idun/index.toml".into();
+    let ftp = index.find("FTP").unwrap();
+    assert_eq!((ftp.version.as_str(), ftp.description.as_str()), ("1.2.0", ""));
+    assert_eq!(index.resolve(&ftp.files[0].url), "https://example.com/idun/ftp/ftp.app?dl=1");
+    assert_eq!(index.resolve("/srv/a.app"), "/srv/a.app");
+    assert_eq!(file_name(&ftp.files[0].url), Some("ftp.app"));
+    assert!(index.find("irc").is_err());
+    assert!(Index::parse("[[package]]\nname = \"x\"\nversion = \"1\"\nfiles = [{ url = \"x/\", sha256 = \"\" }]\n").is_err());
+    assert!(newer("1.10", "1.9") && newer("2.0", "1.9.9") && newer("1.2.1", "1.2"));
+    assert!(!newer("1.2", "1.2") && !newer("v1.2", "1.3"));
+}
+
+#[test]
+fn package_install() {
+    let dir = std::env::temp_dir().join(format!("idunsh-pkg-{}", std::process::id()));
+    fs::create_dir_all(dir.join("repo")).unwrap();
+    fs::write(dir.join("repo/a.app"), b"abc").unwrap();
+    let sum = "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad";
+    let text = format!("[[package]]\nname = \"a\"\nversion = \"1\"\nfiles = [{{ url = \"a.app\", sha256 = \"{}\" }}]\n", sum);
+    let index_path = dir.join("repo/index.toml");
+    fs::write(&index_path, text.replace(sum, "00")).unwrap();
+    let index = Index::load(&index_path.to_string_lossy()).unwrap();
+    assert!(index.install(&index.packages[0], &dir).is_err());
+    assert!(!dir.join("a.app").exists());
+    fs::write(&index_path, text).unwrap();
+    let index = Index::load(&index_path.to_string_lossy()).unwrap();
+    let record = index.install(&index.packages[0], &dir).unwrap();
+    assert_eq!(fs::read(dir.join("a.app")).unwrap(), b"abc");
+    assert_eq!(record.files, [dir.join("a.app").to_string_lossy()]);
+    fs::remove_dir_all(&dir).unwrap();
+}
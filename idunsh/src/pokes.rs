@@ -0,0 +1,237 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+// Copyright (C) 2026 Brian Holdsworth
+
+//! Cheat and trainer poke files, for `idunsh cheat`.
+//!
+//! Three kinds of line are understood, and may be mixed:
+//!
+//! ```text
+//! NInfinite lives          .pok style: a trainer's name,
+//! Z 8 2345 173 206         then bank, address, value and original value
+//! Y                        the end of the file
+//!
+//! [No collisions]          a trainer's name in brackets
+//! POKE 4711,234:POKE 4712,234
+//! $1268 $ea $20            address, value and original value
+//! ```
+//!
+//! Numbers are decimal, or hex after `$` or `0x`. The bank of a `.pok`
+//! line is ignored; the C64 has no paging the file could name. Lines
+//! starting with `;`, `#` or `//` are comments. Pokes before any name
+//! belong to a trainer without one.
+use std::fs;
+use std::result;
+use std::thread;
+use std::time::{Duration, Instant};
+use clap::Subcommand;
+use idun_client::util;
+use crate::target::Memory;
+
+// Simpler error handling
+type Result<T> = result::Result<T, failure::Error>;
+
+/// One byte to write, and what should be there before, if known.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Poke {
+    pub addr: u16,
+    pub value: u8,
+    pub original: Option<u8>,
+}
+
+/// A named set of pokes, such as infinite lives.
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct Trainer {
+    pub name: String,
+    pub pokes: Vec<Poke>,
+}
+
+/// The trainers in a poke file, in file order.
+pub fn parse(text: &str) -> Result<Vec<Trainer>> {
+    let mut trainers: Vec<Trainer> = vec![];
+    for (n, line) in text.lines().enumerate() {
+        let line = line.trim();
+        let context = |e: failure::Error| format_err!("line {}: {}", n + 1, e);
+        if line.is_empty() || line.starts_with([';', '#']) || line.starts_with("//") {
+            continue
+        }
+        if line == "Y" {
+            break
+        }
+        let name = line.strip_prefix('[').and_then(|l| l.strip_suffix(']'))
+            .or_else(|| line.strip_prefix('N'));
+        if let Some(name) = name {
+            trainers.push(Trainer { name: name.trim().to_string(), pokes: vec![] });
+            continue
+        }
+        let pokes = match line.split_once(char::is_whitespace) {
+            Some(("M" | "Z", fields)) => vec![pok_line(fields).map_err(context)?],
+            _ if line.to_ascii_uppercase().contains("POKE") => basic_line(line).map_err(context)?,
+            _ => vec![poke(&fields(line)).map_err(context)?],
+        };
+        match trainers.last_mut() {
+            Some(t) => t.pokes.extend(pokes),
+            None => trainers.push(Trainer { name: String::new(), pokes }),
+        }
+    }
+    trainers.retain(|t| !t.pokes.is_empty());
+    Ok(trainers)
+}
+
+/// The pokes whose original value isn't in memory, with what is there
+/// instead; a sign the game isn't loaded yet, or is another version.
+pub fn unexpected(memory: &dyn Memory, pokes: &[Poke]) -> Result<Vec<(Poke, u8)>> {
+    let mut found = vec![];
+    for p in pokes {
+        if let Some(original) = p.original {
+            let byte = memory.read(p.addr, 1)?.first().copied().unwrap_or_default();
+            if byte != original {
+                found.push((*p, byte));
+            }
+        }
+    }
+    Ok(found)
+}
+
+// The fields of a line, split at commas or white space
+fn fields(line: &str) -> Vec<&str> {
+    line.split(|c: char| c == ',' || c.is_whitespace()).filter(|f| !f.is_empty()).collect()
+}
+
+fn number(field: &str, max: u32) -> Result<u32> {
+    match util::parse_number(field).map_err(failure::err_msg)? {
+        n if n > max => bail!("{} is more than {}", field, max),
+        n => Ok(n),
+    }
+}
+
+// Address, value and maybe the original value
+fn poke(fields: &[&str]) -> Result<Poke> {
+    let original = match fields {
+        [_, _] => None,
+        [_, _, original] => Some(number(original, 255)? as u8),
+        _ => bail!("expected an address and a value, e.g. $1268 $ea"),
+    };
+    Ok(Poke { addr: number(fields[0], 0xffff)? as u16, value: number(fields[1], 255)? as u8, original })
+}
+
+// The fields of an M or Z line: bank, address, value and original
+fn pok_line(line: &str) -> Result<Poke> {
+    match fields(line).as_slice() {
+        [_, _, "256", _] => bail!("the trainer asks for a value, which idunsh can't do; edit the file to give one"),
+        [_, addr, value, original] => poke(&[addr, value, original]),
+        _ => bail!("expected a bank, address, value and original value"),
+    }
+}
+
+// Each POKE statement of a BASIC line, e.g. 10 POKE 4711,234:POKE 4712,234
+fn basic_line(line: &str) -> Result<Vec<Poke>> {
+    let mut pokes = vec![];
+    for statement in line.split(':') {
+        let upper = statement.to_ascii_uppercase();
+        if let Some(i) = upper.find("POKE") {
+            pokes.push(poke(&fields(&statement[i + 4..]))?);
+        }
+    }
+    Ok(pokes)
+}
+
+#[derive(Subcommand)]
+pub enum CheatCommands {
+    /// Write the pokes of a file's trainers into memory, e.g. cheat apply
+    /// game.pok --trainer 'Infinite lives'
+    Apply {
+        file: String,
+        #[arg(long, value_name="name")]
+        /// Apply only this trainer, by name or number; may be repeated
+        trainer: Vec<String>,
+        #[arg(long)]
+        /// Stop the CPU while poking, where the backend can
+        freeze: bool,
+        #[arg(long)]
+        /// Poke even where memory doesn't hold the original values
+        force: bool,
+        #[arg(long, value_parser=util::parse_duration, value_name="time")]
+        /// Wait this long for the game to load, i.e. for the original values
+        wait: Option<Duration>,
+    },
+    /// List the trainers in a poke file
+    Ls { file: String },
+}
+
+// The trainers of a poke file, or those of them named or numbered in
+// `chosen`
+pub fn trainers(file: &str, chosen: &[String]) -> Result<Vec<Trainer>> {
+    let text = fs::read_to_string(file).map_err(|e| format_err!("{}: {}", file, e))?;
+    let mut trainers = parse(&text).map_err(|e| format_err!("{}: {}", file, e))?;
+    if trainers.is_empty() {
+        bail!("{} has no pokes", file)
+    }
+    let named = |i: usize, t: &Trainer, name: &str| t.name.eq_ignore_ascii_case(name) || (i + 1).to_string() == name;
+    if let Some(name) = chosen.iter().find(|name| !trainers.iter().enumerate().any(|(i, t)| named(i, t, name))) {
+        bail!("{} has no trainer {:?}; see cheat ls {}", file, name, file)
+    }
+    if !chosen.is_empty() {
+        trainers = trainers.into_iter().enumerate()
+            .filter(|(i, t)| chosen.iter().any(|name| named(*i, t, name)))
+            .map(|(_, t)| t)
+            .collect();
+    }
+    Ok(trainers)
+}
+
+// Lists the trainers of a poke file, numbered for `cheat apply`
+pub fn list(file: &str) -> Result<()> {
+    for (i, t) in trainers(file, &[])?.iter().enumerate() {
+        let name = if t.name.is_empty() { "(no name)" } else { &t.name };
+        println!("{:>3}. {} ({} poke(s))", i + 1, name, t.pokes.len());
+    }
+    Ok(())
+}
+
+// Pokes the trainers of a file into memory, once the game is there
+pub fn apply(memory: &dyn Memory, file: &str, chosen: &[String], freeze: bool, force: bool,
+        wait: Option<Duration>) -> Result<()> {
+    const POLL: Duration = Duration::from_millis(500);
+    let trainers = trainers(file, chosen)?;
+    let all: Vec<Poke> = trainers.iter().flat_map(|t| t.pokes.iter().copied()).collect();
+    let started = Instant::now();
+    // The original values show the game is in memory
+    while let Some((p, found)) = unexpected(memory, &all)?.first().filter(|_| !force) {
+        if started.elapsed() >= wait.unwrap_or_default() {
+            bail!("${:04x} holds ${:02x}, not ${:02x}: the game isn't loaded, or is another version; \
+                --force pokes anyway", p.addr, found, p.original.unwrap_or_default())
+        }
+        thread::sleep(POLL);
+    }
+    let frozen = freeze && memory.freeze()?;
+    if freeze && !frozen {
+        eprintln!("The {} backend can't stop the CPU; poking while it runs", memory.name());
+    }
+    let poked = all.iter().try_for_each(|p| memory.write(p.addr, &[p.value]));
+    if frozen {
+        memory.thaw()?;
+    }
+    poked?;
+    for t in &trainers {
+        eprintln!("Applied {} ({} poke(s))", if t.name.is_empty() { file } else { &t.name }, t.pokes.len());
+    }
+    Ok(())
+}
+
+#[test]
+fn poke_files() {
+    let text = "; Example\nNInfinite lives\nZ 8 2345 173 206\nM 8 $0930 0 1\nNNo timer\nZ 8 4000 234 0\nY\nNIgnored\nZ 8 1 1 1\n";
+    let trainers = parse(text).unwrap();
+    assert_eq!(trainers.len(), 2);
+    assert_eq!(trainers[0].name, "Infinite lives");
+    assert_eq!(trainers[0].pokes, [
+        Poke { addr: 2345, value: 173, original: Some(206) },
+        Poke { addr: 0x930, value: 0, original: Some(1) },
+    ]);
+    let trainers = parse("10 poke 4711,234: POKE 4712, 234\n[Lives]\n$1268 $ea $20\n").unwrap();
+    assert_eq!((trainers[0].name.as_str(), trainers[0].pokes.len()), ("", 2));
+    assert_eq!(trainers[1].pokes, [Poke { addr: 0x1268, value: 0xea, original: Some(0x20) }]);
+    assert!(parse("Z 8 2345 256 0\n").is_err());
+    assert!(parse("POKE 70000,1\n").is_err());
+    assert!(parse("1234\n").is_err());
+}
@@ -0,0 +1,107 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+// Copyright (C) 2026 Brian Holdsworth
+
+//! Transfer settings for whole-disk and file transfers over IEC.
+//!
+//! The daemon moves sectors with the settings given as switches, e.g.
+//! `/interleave=6 /blocks=4 /retries=2 /fast 8: 35`, a proposed part of
+//! the protocol (see `protocol`). What's fastest depends on the drive: a
+//! 1541 needs a wide interleave to keep up, a 1581 can send a whole track
+//! at once.
+use clap::ValueEnum;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, ValueEnum)]
+pub enum Profile {
+    /// Standard KERNAL transfers, one sector at a time, many retries
+    #[default]
+    Safe,
+    /// The drive's fastloader or burst mode with larger batches
+    Fast,
+}
+
+/// Drive models, as told apart by their ROMs.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Drive {
+    D1541,
+    D1571,
+    D1581,
+}
+
+/// Where a drive's ROM holds a digit of its model number, and the digit
+/// each model has there. The 1541 and 1571 share an address; the 1581's
+/// ROM is laid out differently.
+pub const SIGNATURES: [(u16, u8, Drive); 3] = [
+    (0xe5c6, b'4', Drive::D1541),
+    (0xe5c6, b'7', Drive::D1571),
+    (0xa6e9, b'8', Drive::D1581),
+];
+
+impl Drive {
+    /// Tells the drive from its ROM, reading bytes of it with `peek`;
+    /// asking it with `UI` would reset it. Unknown drives are taken as
+    /// 1541s, which every compatible drive can act as.
+    pub fn identify<E>(mut peek: impl FnMut(u16) -> Result<u8, E>) -> Result<Drive, E> {
+        let mut last = None;
+        for (addr, digit, drive) in SIGNATURES {
+            let byte = match last {
+                Some((a, b)) if a == addr => b,
+                _ => peek(addr)?,
+            };
+            last = Some((addr, byte));
+            if byte == digit {
+                return Ok(drive)
+            }
+        }
+        Ok(Drive::D1541)
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Settings {
+    /// Sectors skipped between two that are transferred in a row
+    pub interleave: u8,
+    /// Sectors sent in one batch
+    pub blocks: u8,
+    /// Reads or writes of a failing sector before giving up on it
+    pub retries: u8,
+    /// Use the fastloader or burst mode rather than KERNAL transfers
+    pub fast: bool,
+}
+
+impl Settings {
+    pub fn new(profile: Profile, drive: Drive) -> Settings {
+        let (interleave, blocks, retries, fast) = match (profile, drive) {
+            (Profile::Safe, Drive::D1541) => (10, 1, 5, false),
+            (Profile::Safe, Drive::D1571) => (6, 1, 5, false),
+            (Profile::Safe, Drive::D1581) => (1, 1, 5, false),
+            (Profile::Fast, Drive::D1541) => (6, 4, 2, true),
+            (Profile::Fast, Drive::D1571) => (4, 8, 2, true),
+            (Profile::Fast, Drive::D1581) => (1, 40, 2, true),
+        };
+        Settings { interleave, blocks, retries, fast }
+    }
+    /// The settings as switches to put before the arguments
+    pub fn switches(&self) -> String {
+        let mut s = format!("/interleave={} /blocks={} /retries={}", self.interleave, self.blocks, self.retries);
+        if self.fast {
+            s.push_str(" /fast");
+        }
+        s
+    }
+}
+
+#[test]
+fn drive_settings() {
+    let rom = |model: &'static [u8; 2]| move |addr| Ok::<_, ()>(match addr {
+        0xe5c6 => model[0],
+        _ => model[1],
+    });
+    let drive = Drive::identify(rom(b"x8")).unwrap();
+    assert_eq!(drive, Drive::D1581);
+    assert_eq!(Drive::identify(rom(b"7x")), Ok(Drive::D1571));
+    assert_eq!(Drive::identify(rom(b"4x")), Ok(Drive::D1541));
+    assert_eq!(Settings::new(Profile::Fast, drive).switches(), "/interleave=1 /blocks=40 /retries=2 /fast");
+    assert_eq!(Settings::new(Profile::Safe, Drive::identify(rom(b"??")).unwrap()).switches(), "/interleave=10 /blocks=1 /retries=5");
+    // A drive that can't be read isn't guessed at
+    assert_eq!(Drive::identify(|_| Err("no drive")), Err("no drive"));
+}
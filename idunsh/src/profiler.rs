@@ -0,0 +1,164 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+// Copyright (C) 2026 Brian Holdsworth
+
+//! Where the CPU spends its cycles, from the Ultimate's debug stream,
+//! for `idunsh profile`.
+//!
+//! The debug stream is a trace of the C64's bus sent over UDP. Each
+//! packet has a two byte sequence number and two reserved bytes, then
+//! 360 words of four bytes: the address in bits 0-15, the data in bits
+//! 16-23, read/write and other bus lines above that, and in bit 31
+//! whether the 6510 (1) or the VIC (0) had the bus. Every 6510 cycle is
+//! counted at the address it accessed, and the counts are added up per
+//! label, each label's range ending at the next one, or per page without
+//! labels. Operand and data accesses count where they land, so tables
+//! and variables show up as ranges of their own.
+use std::fs;
+use std::net::UdpSocket;
+use std::result;
+use std::time::{Duration, Instant};
+use serde::Serialize;
+use crate::c64ultimate::{C64Ultimate, Stream};
+use crate::labels::Labels;
+
+// Simpler error handling
+type Result<T> = result::Result<T, failure::Error>;
+
+/// The port the Ultimate sends its debug stream to by default
+pub const DEBUG_PORT: u16 = 11002;
+
+const HEADER: usize = 4;
+const CPU_CYCLE: u32 = 1 << 31;
+
+/// Cycles counted at every address.
+pub struct Capture {
+    cycles: Vec<u64>,
+    packets: u64,
+    lost: u64,
+    last_sequence: Option<u16>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Range {
+    pub name: String,
+    pub start: String,
+    pub end: String,
+    pub cycles: u64,
+    pub percent: f64,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Report {
+    pub seconds: f64,
+    pub cycles: u64,
+    pub packets: u64,
+    pub lost_packets: u64,
+    pub ranges: Vec<Range>,
+}
+
+impl Default for Capture {
+    fn default() -> Capture {
+        Capture { cycles: vec![0; 0x10000], packets: 0, lost: 0, last_sequence: None }
+    }
+}
+
+impl Capture {
+    /// Receives the stream on `socket` for `duration`.
+    pub fn receive(socket: &UdpSocket, duration: Duration) -> Result<Capture> {
+        let mut capture = Capture::default();
+        let end = Instant::now() + duration;
+        let mut buf = [0u8; 2048];
+        socket.set_read_timeout(Some(Duration::from_millis(100)))?;
+        while Instant::now() < end {
+            match socket.recv(&mut buf) {
+                Ok(n) => capture.add(&buf[..n]),
+                Err(e) if matches!(e.kind(), std::io::ErrorKind::WouldBlock | std::io::ErrorKind::TimedOut) => (),
+                Err(e) => return Err(e.into()),
+            }
+        }
+        if capture.packets == 0 {
+            bail!("Nothing arrived from the debug stream in {:?}", duration)
+        }
+        Ok(capture)
+    }
+    /// Counts the 6510 cycles in one packet.
+    pub fn add(&mut self, packet: &[u8]) {
+        if packet.len() < HEADER {
+            return
+        }
+        let sequence = u16::from_le_bytes([packet[0], packet[1]]);
+        if let Some(last) = self.last_sequence {
+            self.lost += sequence.wrapping_sub(last).wrapping_sub(1) as u64;
+        }
+        self.last_sequence = Some(sequence);
+        self.packets += 1;
+        for word in packet[HEADER..].chunks_exact(4) {
+            let word = u32::from_le_bytes([word[0], word[1], word[2], word[3]]);
+            if word & CPU_CYCLE != 0 {
+                self.cycles[(word & 0xffff) as usize] += 1;
+            }
+        }
+    }
+    /// The cycles per label range, or per page without labels, busiest
+    /// first, leaving out ranges with none.
+    pub fn report(&self, labels: &Labels, seconds: f64) -> Report {
+        let mut starts: Vec<(u16, String)> = labels.addresses().map(|(a, n)| (a, n.to_string())).collect();
+        if starts.is_empty() {
+            starts = (0..=0xffu16).map(|p| (p << 8, format!("page ${:02x}", p))).collect();
+        } else if starts[0].0 > 0 {
+            starts.insert(0, (0, String::from("(before labels)")));
+        }
+        let total: u64 = self.cycles.iter().sum();
+        let mut ranges: Vec<Range> = starts.iter().enumerate().map(|(i, (start, name))| {
+            let end = starts.get(i + 1).map_or(0xffff, |(next, _)| next - 1);
+            let cycles = self.cycles[*start as usize..=end as usize].iter().sum();
+            Range {
+                name: name.clone(),
+                start: format!("${:04x}", start),
+                end: format!("${:04x}", end),
+                cycles,
+                percent: if total == 0 { 0.0 } else { (cycles as f64 * 10000.0 / total as f64).round() / 100.0 },
+            }
+        }).filter(|r| r.cycles > 0).collect();
+        ranges.sort_by_key(|r| std::cmp::Reverse(r.cycles));
+        Report { seconds, cycles: total, packets: self.packets, lost_packets: self.lost, ranges }
+    }
+}
+
+// Captures the debug stream for `duration` and writes where the cycles
+// went to `out`
+pub fn run(c64u: &C64Ultimate, duration: Duration, out: &str, labels: &Labels, port: u16) -> Result<()> {
+    let dest = c64u.stream_dest(port)?;
+    let socket = std::net::UdpSocket::bind(("0.0.0.0", port)).map_err(|e| format_err!("UDP port {}: {}", port, e))?;
+    c64u.stream_start(Stream::Debug, &dest)?;
+    let started = Instant::now();
+    let capture = Capture::receive(&socket, duration);
+    c64u.stream_stop(Stream::Debug)?;
+    let report = capture?.report(labels, started.elapsed().as_secs_f64());
+    let json = serde_json::to_string_pretty(&report)?;
+    if out == "-" {
+        println!("{}", json);
+    } else {
+        fs::write(out, json + "\n").map_err(|e| format_err!("{}: {}", out, e))?;
+    }
+    eprintln!("{} cycles in {:.1}s, {} packets lost", report.cycles, report.seconds, report.lost_packets);
+    Ok(())
+}
+
+#[test]
+fn debug_stream_profile() {
+    let mut capture = Capture::default();
+    let word = |addr: u32, cpu: bool| (addr | 0xea << 16 | if cpu { CPU_CYCLE } else { 0 }).to_le_bytes();
+    let mut packet = vec![1, 0, 0, 0];
+    for w in [word(0xc000, true), word(0xc001, true), word(0xc100, true), word(0x0400, false)] {
+        packet.extend_from_slice(&w);
+    }
+    capture.add(&packet);
+    packet[0] = 3;
+    capture.add(&packet);
+    let report = capture.report(&Labels::parse("irq = $c000\nmusic = $c100\n"), 1.0);
+    assert_eq!((report.cycles, report.packets, report.lost_packets), (6, 2, 1));
+    assert_eq!(report.ranges.iter().map(|r| (r.name.as_str(), r.cycles)).collect::<Vec<_>>(), [("irq", 4), ("music", 2)]);
+    assert_eq!(report.ranges[1].end, "$ffff");
+    assert_eq!(capture.report(&Labels::default(), 1.0).ranges[0].name, "page $c0");
+}
@@ -0,0 +1,94 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+// Copyright (C) 2026 Brian Holdsworth
+
+//! Progress reports for wrappers that draw their own progress bars.
+//!
+//! With `--progress json`, each step of a transfer or mount is written to
+//! stderr as one JSON object per line:
+//!
+//! ```text
+//! {"phase":"upload","bytes":0,"total":174848}
+//! {"phase":"upload","bytes":174848,"total":174848}
+//! {"phase":"mount","bytes":174848,"total":174848}
+//! ```
+//!
+//! A program run with `--wait` or `-o` ends with a summary, written the
+//! same way:
+//!
+//! ```text
+//! {"runtime":12.5,"bytes":4096,"status":0}
+//! ```
+use std::fmt;
+use clap::ValueEnum;
+use serde::Serialize;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum ProgressFormat {
+    /// Line-delimited JSON on stderr
+    Json,
+}
+
+#[derive(Serialize)]
+struct Report<'a> {
+    phase: &'a str,
+    bytes: u64,
+    total: u64,
+}
+
+/// How a remote program went. `status` is only known with `--wait`.
+#[derive(Debug, Serialize)]
+pub struct Summary {
+    /// Seconds from starting the program until it finished
+    pub runtime: f64,
+    /// Bytes of redirected output received
+    pub bytes: u64,
+    pub status: Option<i32>,
+}
+
+impl fmt::Display for Summary {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "runtime {:.1}s, {} bytes of output", self.runtime, self.bytes)?;
+        match self.status {
+            Some(status) => write!(f, ", exit status {}", status),
+            None => Ok(()),
+        }
+    }
+}
+
+/// Where progress goes; reports nothing unless a format was chosen.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Progress {
+    format: Option<ProgressFormat>,
+}
+
+impl Progress {
+    pub fn new(format: Option<ProgressFormat>) -> Self {
+        Progress { format }
+    }
+    /// Reports that `phase` has got through `bytes` of `total`.
+    pub fn report(&self, phase: &str, bytes: u64, total: u64) {
+        if let Some(ProgressFormat::Json) = self.format {
+            eprintln!("{}", Self::json(phase, bytes, total));
+        }
+    }
+    /// Writes the summary of a program run: as JSON if a format was
+    /// chosen, otherwise as text unless `quiet`.
+    pub fn summary(&self, summary: &Summary, quiet: bool) {
+        match self.format {
+            Some(ProgressFormat::Json) => eprintln!("{}", serde_json::to_string(summary).unwrap_or_default()),
+            None if !quiet => eprintln!("{}", summary),
+            None => (),
+        }
+    }
+    fn json(phase: &str, bytes: u64, total: u64) -> String {
+        serde_json::to_string(&Report { phase, bytes, total }).unwrap_or_default()
+    }
+}
+
+#[test]
+fn progress_json() {
+    assert_eq!(Progress::json("upload", 10, 20), r#"{"phase":"upload","bytes":10,"total":20}"#);
+    let summary = Summary { runtime: 12.5, bytes: 4096, status: Some(0) };
+    assert_eq!(serde_json::to_string(&summary).unwrap(), r#"{"runtime":12.5,"bytes":4096,"status":0}"#);
+    assert_eq!(summary.to_string(), "runtime 12.5s, 4096 bytes of output, exit status 0");
+}
@@ -0,0 +1,426 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+// Copyright (C) 2026 Brian Holdsworth
+
+//! Encoding of commands sent to the idun Lua interface.
+//!
+//! Commands are Lua calls such as `sys.shell(0, "copy a b", 0)`. The
+//! argument string is a Lua string literal, so it is escaped to survive
+//! any bytes. Inside it, shell.app splits arguments on spaces; an argument
+//! holding spaces or quotes is wrapped in double quotes, with any quote in
+//! it doubled, e.g. `copy "my file.seq" dest`.
+//!
+//! The daemon answers each command with a status byte, followed by a
+//! message when the status isn't 0. Released daemons only promise that
+//! a failure isn't 0. The values `ErrorCode` tells apart are proposed
+//! ones; any status idunsh doesn't know is taken as a plain failure.
+//!
+//! Commands 8 to 17, their transfer switches and `sys.batch` below are
+//! proposed additions to the protocol that no released daemon has yet.
+//! A daemon without them refuses them, and idunsh passes its answer on.
+//! `sys.features(proc)`, also proposed, redirects the names of those a
+//! daemon has, separated by spaces: `subdirectories` for commands 8 and
+//! 9, and `batch`. idunsh asks once per connection, and takes a daemon
+//! that refuses to say as having none of them.
+//!
+//! `mkdir` and `rmdir` (commands 8 and 9) take one `dev:path` argument,
+//! e.g. `c:games/arcade`, and create or remove a CMD-style subdirectory.
+//! On a 1581 image the path names a partition instead. Catalogs list
+//! subdirectories with type DIR and partitions with type CBM.
+//!
+//! Command 10 sends a DOS command to a drive: the device, then the
+//! command string as the drive's command channel takes it, e.g.
+//! `c: L0:GAME`. The command is PETSCII and passed on unchanged, so it
+//! may hold any byte (see `lua_bytes`). The redirected output is the
+//! drive's answer on its error channel. An empty command only reads
+//! the error channel.
+//!
+//! Commands 11 and 12 read and write a single block: the device, track
+//! and sector, e.g. `c: 18 1`, and for a write the 256 bytes as 512 hex
+//! digits. A read redirects the raw block.
+//!
+//! Command 13 reads a whole disk from a drive on the IEC bus: the device
+//! and the number of tracks, e.g. `8: 35`. For each sector in track
+//! order it redirects the DOS status of reading it as one byte (0 when
+//! the read succeeded), then the 256 bytes of the sector.
+//!
+//! Command 14 is the reverse: the device and the path of a D64 on this
+//! machine, which the daemon writes to the disk. It redirects the DOS
+//! status of writing each sector, one byte per sector. Both take the
+//! transfer switches described in `profile`.
+//!
+//! Commands 15 and 16 copy a single file off and onto a drive: the
+//! transfer switches, the device and the file name in PETSCII, quoted
+//! like a DOS command, e.g. `/retries=2 c: "MY FILE,P"`. `get` (15)
+//! redirects the file's contents unchanged. For `put` (16) the daemon
+//! connects to the redirect socket and reads the contents from it until
+//! idunsh shuts down its side; the file type is the one given after the
+//! comma.
+//!
+//! Command 17 sums up a whole disk instead of reading it: the device,
+//! its format and number of tracks, e.g. `d: d81 80`. For each sector in
+//! track order it redirects the DOS status of reading it as one byte,
+//! then the CRC-32 of the sector (see `util::crc32`), four bytes, low
+//! byte first. `image pull` and `push` compare these with a local image
+//! to send only the sectors that differ.
+//!
+//! A fourth argument of `"streams"` asks for the redirected output to be
+//! split into frames, so that a program's errors and status can be told
+//! from its output: a stream number (1 output, 2 errors, 3 status), the
+//! length as two bytes, low byte first, then that many bytes. Framed
+//! output starts with an empty frame for stream 0. A daemon that doesn't
+//! know streams ignores the argument and sends its output as it is, so
+//! redirected output that doesn't start that way is all taken as stream
+//! 1; see `Frames`. While a program runs, the daemon also sends an empty
+//! frame on stream 5 every few seconds, a heartbeat, so that a program
+//! that is quiet for a while can be told from one that has crashed.
+//!
+//! `sys.keys(keys)` types on the Commodore: the keys are PETSCII, passed
+//! on unchanged (see `lua_bytes`), as if typed on its keyboard.
+//!
+//! `sys.channel(path)` opens the cartridge's program channel: whenever
+//! a program on the Commodore opens it, the daemon connects to the Unix
+//! socket at `path` and passes on what the program sends, and what comes
+//! back to it, until the program closes it.
+//!
+//! `sys.clock(seconds)` sets the Commodore's clocks, TI$ and the CIA's
+//! time of day, to that many seconds after midnight.
+//!
+//! `"streams,crc"` also asks for a last frame on stream 4, holding the
+//! CRC-32 (see `util::crc32`) of the data of all frames before it, four
+//! bytes, low byte first. Output corrupted on the way is told by it.
+//!
+//! `sys.batch(cmds, proc)` runs several shell commands for one NMI and
+//! one answer, e.g. `sys.batch({{6, "d: demo.d64"}, {2, "demo"}}, 0)`
+//! mounts an image and loads from it. Each entry is a command number and
+//! its argument string, as `sys.shell` takes them, run in order with
+//! their output redirected to `proc`. The first command to fail stops
+//! the batch, and the answer is the status and message of the one that
+//! failed, the message starting with its place in the batch, e.g.
+//! `2: not found`. The commands before it stay done. To a daemon without
+//! `sys.batch`, idunsh sends the commands one by one instead.
+
+use std::fmt;
+use failure::Fail;
+
+/// Why the remote side refused or failed a command, by the proposed
+/// status values.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ErrorCode {
+    /// 1: failed for a reason without its own code
+    Failed,
+    /// 2: file or directory not found
+    NotFound,
+    /// 3: device busy, e.g. a program is already running
+    Busy,
+    /// 4: the command line couldn't be parsed
+    Syntax,
+    /// 5: out of memory on the Commodore
+    NoMemory,
+    /// A status this version of idunsh doesn't know
+    Unknown(u8),
+}
+
+impl ErrorCode {
+    pub fn from_status(status: u8) -> ErrorCode {
+        match status {
+            1 => ErrorCode::Failed,
+            2 => ErrorCode::NotFound,
+            3 => ErrorCode::Busy,
+            4 => ErrorCode::Syntax,
+            5 => ErrorCode::NoMemory,
+            n => ErrorCode::Unknown(n),
+        }
+    }
+    /// The idunsh exit status for this error, following sysexits.h.
+    pub fn exit_status(&self) -> i32 {
+        match self {
+            ErrorCode::NotFound => 66,  // EX_NOINPUT
+            ErrorCode::Busy => 75,      // EX_TEMPFAIL
+            ErrorCode::Syntax => 64,    // EX_USAGE
+            ErrorCode::NoMemory => 71,  // EX_OSERR
+            ErrorCode::Failed | ErrorCode::Unknown(_) => 1,
+        }
+    }
+}
+
+impl fmt::Display for ErrorCode {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ErrorCode::Failed => write!(f, "failed"),
+            ErrorCode::NotFound => write!(f, "not found"),
+            ErrorCode::Busy => write!(f, "device busy"),
+            ErrorCode::Syntax => write!(f, "syntax error"),
+            ErrorCode::NoMemory => write!(f, "out of memory"),
+            ErrorCode::Unknown(n) => write!(f, "error {}", n),
+        }
+    }
+}
+
+/// A command the daemon answered with a nonzero status.
+#[derive(Debug)]
+pub struct RemoteError {
+    pub code: ErrorCode,
+    pub message: String,
+}
+
+impl RemoteError {
+    /// Decodes a daemon reply, which is empty or starts with status 0
+    /// when the command was accepted.
+    pub fn from_reply(reply: &[u8]) -> Option<RemoteError> {
+        match reply.split_first() {
+            Some((&status, msg)) if status > 0 => Some(RemoteError {
+                code: ErrorCode::from_status(status),
+                message: String::from_utf8_lossy(msg).trim().to_string(),
+            }),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for RemoteError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.message.is_empty() {
+            write!(f, "{}", self.code)
+        } else {
+            write!(f, "{}: {}", self.code, self.message)
+        }
+    }
+}
+
+impl Fail for RemoteError {}
+
+/// Where a frame of redirected output belongs.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Stream {
+    Stdout,
+    Stderr,
+    Status,
+    /// The CRC of the output, at its end
+    Crc,
+    /// Empty frames that tell the program is still running
+    Heartbeat,
+    /// A stream this version of idunsh doesn't know
+    Other(u8),
+}
+
+impl Stream {
+    fn from_number(n: u8) -> Stream {
+        match n {
+            1 => Stream::Stdout,
+            2 => Stream::Stderr,
+            3 => Stream::Status,
+            4 => Stream::Crc,
+            5 => Stream::Heartbeat,
+            n => Stream::Other(n),
+        }
+    }
+}
+
+/// Splits redirected output into frames, as it arrives in pieces.
+/// Output that isn't framed comes out as it arrives, all on `Stdout`.
+#[derive(Debug, Default)]
+pub struct Frames {
+    buf: Vec<u8>,
+    // Known once the first three bytes are in
+    framed: Option<bool>,
+}
+
+impl Frames {
+    const START: [u8; 3] = [0, 0, 0];
+
+    /// The frames completed by `data`.
+    pub fn push(&mut self, data: &[u8]) -> Vec<(Stream, Vec<u8>)> {
+        self.buf.extend_from_slice(data);
+        if self.framed.is_none() {
+            if self.buf.len() < Self::START.len() && Self::START.starts_with(&self.buf) {
+                return vec![]
+            }
+            let framed = self.buf.starts_with(&Self::START);
+            if framed {
+                self.buf.drain(..Self::START.len());
+            }
+            self.framed = Some(framed);
+        }
+        let mut frames = vec![];
+        if self.framed == Some(false) {
+            if !self.buf.is_empty() {
+                frames.push((Stream::Stdout, std::mem::take(&mut self.buf)));
+            }
+            return frames
+        }
+        while let [n, lo, hi, ..] = self.buf[..] {
+            let len = u16::from_le_bytes([lo, hi]) as usize;
+            if self.buf.len() < 3 + len {
+                break
+            }
+            let data: Vec<u8> = self.buf.drain(..3 + len).skip(3).collect();
+            if n != 0 {
+                frames.push((Stream::from_number(n), data));
+            }
+        }
+        frames
+    }
+    /// Bytes of a frame that isn't complete yet, which output ending now
+    /// would cut short.
+    pub fn pending(&self) -> usize {
+        match self.framed {
+            Some(true) => self.buf.len(),
+            _ => 0,
+        }
+    }
+    /// What is left when the output ends: output too short to tell
+    /// whether it's framed, or a frame cut short.
+    pub fn finish(&mut self) -> Vec<(Stream, Vec<u8>)> {
+        match (self.framed, std::mem::take(&mut self.buf)) {
+            (_, rest) if rest.is_empty() => vec![],
+            (Some(true), rest) => vec![(Stream::from_number(rest[0]), rest[3.min(rest.len())..].to_vec())],
+            (_, rest) => vec![(Stream::Stdout, rest)],
+        }
+    }
+}
+
+/// Quotes text as a Lua string literal.
+pub fn lua_string(s: &str) -> String {
+    let mut lit = String::with_capacity(s.len() + 2);
+    lit.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => lit.push_str("\\\""),
+            '\\' => lit.push_str("\\\\"),
+            '\n' => lit.push_str("\\n"),
+            '\r' => lit.push_str("\\r"),
+            c if c.is_control() => lit.push_str(&format!("\\{:03}", c as u32)),
+            c => lit.push(c),
+        }
+    }
+    lit.push('"');
+    lit
+}
+
+/// Quotes bytes as a Lua string literal, escaping everything but
+/// printable ASCII, for arguments that aren't text.
+pub fn lua_bytes(b: &[u8]) -> String {
+    let mut lit = String::with_capacity(b.len() + 2);
+    lit.push('"');
+    for &c in b {
+        match c {
+            b'"' => lit.push_str("\\\""),
+            b'\\' => lit.push_str("\\\\"),
+            b' '..=b'~' => lit.push(c as char),
+            c => lit.push_str(&format!("\\{:03}", c)),
+        }
+    }
+    lit.push('"');
+    lit
+}
+
+/// Joins an argument vector into a shell.app command line.
+pub fn join_args<S: AsRef<str>>(args: &[S]) -> String {
+    let quoted: Vec<String> = args.iter()
+        .map(|a| {
+            let a = a.as_ref();
+            if a.is_empty() || a.contains([' ', '"']) {
+                format!("\"{}\"", a.replace('"', "\"\""))
+            } else {
+                a.to_string()
+            }
+        })
+        .collect();
+    quoted.join(" ")
+}
+
+/// Expands `-x` flags into shell.app switches. Plain letters give one
+/// switch each (`ab` is `/a /b`), `key=value` gives `/key=value` and a
+/// leading slash passes a long switch such as `/verbose` as it is. A later
+/// switch replaces an earlier one of the same name.
+pub fn xarg_switches<S: AsRef<str>>(specs: &[S]) -> Vec<String> {
+    let mut switches: Vec<(String, String)> = vec![];
+    for spec in specs {
+        let spec = spec.as_ref();
+        let named = if let Some((key, _)) = spec.split_once('=') {
+            vec![(key.trim_start_matches('/').to_string(), spec.trim_start_matches('/').to_string())]
+        } else if let Some(name) = spec.strip_prefix('/') {
+            vec![(name.to_string(), name.to_string())]
+        } else {
+            spec.chars().map(|c| (c.to_string(), c.to_string())).collect()
+        };
+        for (key, switch) in named {
+            switches.retain(|(k, _)| *k != key);
+            switches.push((key, format!("/{}", switch)));
+        }
+    }
+    switches.into_iter().map(|(_, s)| s).collect()
+}
+
+/// Splits a shell.app command line back into arguments, the inverse of
+/// `join_args`, decoding it as shell.app does.
+pub fn split_args(line: &str) -> Vec<String> {
+    let mut args = vec![];
+    let mut chars = line.chars().peekable();
+    loop {
+        while chars.next_if_eq(&' ').is_some() {}
+        if chars.peek().is_none() {
+            break;
+        }
+        let mut arg = String::new();
+        let mut quoted = false;
+        while let Some(c) = chars.next() {
+            match c {
+                '"' if quoted && chars.next_if_eq(&'"').is_some() => arg.push('"'),
+                '"' => quoted = !quoted,
+                ' ' if !quoted => break,
+                c => arg.push(c),
+            }
+        }
+        args.push(arg);
+    }
+    args
+}
+
+#[test]
+fn args_round_trip() {
+    let args = ["copy", "my file.seq", "say \"hi\"", "", "dest"];
+    let line = join_args(&args);
+    assert_eq!(line, r#"copy "my file.seq" "say ""hi""" "" dest"#);
+    assert_eq!(split_args(&line), args);
+    assert_eq!(lua_bytes(b"M-R\x00\x05\"\xff"), r#""M-R\000\005\"\255""#);
+    assert_eq!(lua_string(&line), r#""copy \"my file.seq\" \"say \"\"hi\"\"\" \"\" dest""#);
+}
+
+#[test]
+fn xarg_expansion() {
+    assert_eq!(xarg_switches(&["ab"]), ["/a", "/b"]);
+    assert_eq!(xarg_switches(&["device=8", "/verbose", "device=9"]), ["/verbose", "/device=9"]);
+    assert_eq!(join_args(&xarg_switches(&["name=my file"])), r#""/name=my file""#);
+    let line = join_args(&[vec!["dir".to_string()], xarg_switches(&["name=my file"]), vec!["8".into()]].concat());
+    assert_eq!(split_args(&line), ["dir", "/name=my file", "8"]);
+}
+
+#[test]
+fn remote_errors() {
+    assert!(RemoteError::from_reply(b"").is_none() && RemoteError::from_reply(b"\x00").is_none());
+    let e = RemoteError::from_reply(b"\x02game.prg").unwrap();
+    assert_eq!(e.code, ErrorCode::NotFound);
+    assert_eq!(e.to_string(), "not found: game.prg");
+    assert_eq!(e.code.exit_status(), 66);
+}
+
+#[test]
+fn output_frames() {
+    let mut frames = Frames::default();
+    assert!(frames.push(b"\x00\x00").is_empty());
+    assert_eq!(frames.push(b"\x00\x01\x02\x00HI\x02\x03"), [(Stream::Stdout, b"HI".to_vec())]);
+    assert_eq!(frames.push(b"\x00ERR\x03\x00\x00"), [(Stream::Stderr, b"ERR".to_vec()), (Stream::Status, vec![])]);
+    assert_eq!(frames.push(b"\x05\x00\x00"), [(Stream::Heartbeat, vec![])]);
+    assert!(frames.push(b"\x01\x05\x00ab").is_empty());
+    assert_eq!(frames.pending(), 5);
+    assert_eq!(frames.finish(), [(Stream::Stdout, b"ab".to_vec())]);
+    let mut plain = Frames::default();
+    assert!(plain.push(b"\x00").is_empty());
+    assert_eq!(plain.pending(), 0);
+    assert_eq!(plain.push(b"AB"), [(Stream::Stdout, b"\x00AB".to_vec())]);
+    assert_eq!(plain.push(b"C"), [(Stream::Stdout, b"C".to_vec())]);
+    let mut short = Frames::default();
+    assert!(short.push(b"\x00").is_empty());
+    assert_eq!(short.finish(), [(Stream::Stdout, b"\x00".to_vec())]);
+}
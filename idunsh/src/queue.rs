@@ -0,0 +1,252 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+// Copyright (C) 2026 Brian Holdsworth
+
+//! Commands kept for when the daemon is back.
+//!
+//! With `--queue`, a command that can't reach the daemon is appended to
+//! `~/.local/share/idunsh/queue`, one a line, and sent by the first run
+//! of idunsh that reaches the daemon again, or by `idunsh queue run`.
+//! Most are Lua calls, whose string literals escape line breaks, so
+//! every call fits on one line. A `put` can't be a single call, as the
+//! file's contents are sent when the daemon asks for them; it is kept as
+//! `put` and its arguments, quoted as `protocol::join_args` does, and
+//! the file is read when it's sent.
+use std::env;
+use std::fs;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::result;
+use std::thread;
+use std::time::Duration;
+use clap::Subcommand;
+use idun_client::client::{ASSIGN_CMD, MOUNT_CMD, shell_call};
+use idun_client::protocol::{self, RemoteError};
+use idun_client::util;
+use crate::Syscommands;
+use crate::confirm::confirm;
+use crate::daemon_reachable;
+use crate::files;
+use crate::lock::FileLock;
+use crate::luasend;
+use crate::profile::Profile;
+use crate::progress::Progress;
+use crate::state;
+use crate::store;
+
+// Simpler error handling
+type Result<T> = result::Result<T, failure::Error>;
+
+pub struct Queue {
+    path: PathBuf,
+}
+
+/// A queued command.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Entry {
+    /// A Lua call, sent as it is
+    Call(String),
+    /// A local file to copy to a drive
+    Put { file: String, dest: String },
+}
+
+impl Entry {
+    pub fn parse(line: &str) -> Entry {
+        match line.strip_prefix("put ").map(protocol::split_args).as_deref() {
+            Some([file, dest]) => Entry::Put { file: file.clone(), dest: dest.clone() },
+            _ => Entry::Call(line.to_string()),
+        }
+    }
+    /// The entry as its line in the queue.
+    pub fn line(&self) -> String {
+        match self {
+            Entry::Call(call) => call.clone(),
+            Entry::Put { file, dest } => format!("put {}", protocol::join_args(&[file, dest])),
+        }
+    }
+}
+
+impl Queue {
+    pub fn open() -> Result<Queue> {
+        Ok(Queue { path: store::dir()?.join("queue") })
+    }
+    fn lock(&self) -> Result<FileLock> {
+        FileLock::exclusive(&self.path.with_extension("lock"))
+    }
+    /// Adds a Lua call to the end of the queue.
+    pub fn push(&self, call: &str) -> Result<()> {
+        let _lock = self.lock()?;
+        let mut f = fs::File::options().create(true).append(true).open(&self.path)?;
+        writeln!(f, "{}", call)?;
+        Ok(())
+    }
+    /// The queued calls, oldest first.
+    pub fn list(&self) -> Result<Vec<String>> {
+        match fs::read_to_string(&self.path) {
+            Ok(text) => Ok(text.lines().map(String::from).collect()),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(vec![]),
+            Err(e) => Err(e.into()),
+        }
+    }
+    /// Sends the queued calls in order, removing each once sent. Stops at
+    /// the first failure, leaving it and the rest queued. Returns how many
+    /// were sent.
+    pub fn flush<F>(&self, mut send: F) -> Result<usize>
+    where F: FnMut(&str) -> Result<()> {
+        let _lock = self.lock()?;
+        let calls = self.list()?;
+        for (i, call) in calls.iter().enumerate() {
+            if let Err(e) = send(call) {
+                self.rewrite(&calls[i..])?;
+                return Err(e);
+            }
+        }
+        self.rewrite(&[])?;
+        Ok(calls.len())
+    }
+    pub fn clear(&self) -> Result<()> {
+        let _lock = self.lock()?;
+        self.rewrite(&[])
+    }
+    fn rewrite(&self, calls: &[String]) -> Result<()> {
+        let text: String = calls.iter().map(|c| format!("{}\n", c)).collect();
+        store::write(&self.path, text.as_bytes())
+    }
+}
+
+#[derive(Subcommand)]
+pub enum QueueCommands {
+    /// Show the queued commands, oldest first
+    Ls,
+    /// Send the queued commands to the daemon
+    Run {
+        #[arg(long)]
+        /// Keep running, sending commands whenever the daemon is reachable
+        watch: bool,
+        #[arg(long, default_value="5s", value_parser=util::parse_duration, value_name="time")]
+        /// How often to check for the daemon with --watch
+        interval: Duration,
+    },
+    /// Drop all queued commands
+    Clear,
+}
+
+// Sends a queued command. One the daemon refuses, or a put of a file
+// that's gone, is reported and dropped, so it isn't retried forever.
+fn send_entry(line: &str, profile: Profile, progress: Progress) -> Result<()> {
+    let sent = match Entry::parse(line) {
+        Entry::Call(call) => luasend(call),
+        Entry::Put { file, .. } if !Path::new(&file).exists() => {
+            eprintln!("Queued command {} failed: {} is gone", line, file);
+            return Ok(())
+        },
+        Entry::Put { file, dest } => files::transfer_settings(&files::split_device(&dest)?.0, profile)
+            .and_then(|settings| files::put(&file, &dest, settings, progress)),
+    };
+    match sent {
+        Err(e) if e.iter_chain().any(|f| f.downcast_ref::<RemoteError>().is_some()) => {
+            eprintln!("Queued command {} failed: {}", line, e);
+            Ok(())
+        },
+        r => r,
+    }
+}
+
+// Sends what was queued while the daemon was down, once it's back
+pub fn replay(profile: Profile, progress: Progress) -> Result<()> {
+    let queue = Queue::open()?;
+    if queue.list()?.is_empty() {
+        return Ok(())
+    }
+    let n = queue.flush(|line| send_entry(line, profile, progress))?;
+    eprintln!("Sent {} queued command(s)", n);
+    Ok(())
+}
+
+// Queues an assign, mount or put for later if the daemon can't be
+// reached. Gives false when it can, for the command to be sent now.
+pub fn defer(cmd: &Syscommands) -> Result<bool> {
+    if !matches!(cmd, Syscommands::Mount { .. } | Syscommands::Assign { .. } | Syscommands::Put { .. }) {
+        bail!("--queue only applies to assign, mount and put")
+    }
+    if daemon_reachable() {
+        return Ok(false)
+    }
+    // Relative paths are resolved now, as there's no daemon to chdir
+    let absolute = |path: &str| env::current_dir().map(|d| d.join(path).to_string_lossy().into_owned());
+    let entry = match cmd {
+        Syscommands::Mount { dev, dimage } =>
+            Entry::Call(shell_call(MOUNT_CMD, &protocol::join_args(&[dev.clone(), absolute(dimage)?]), 0)),
+        Syscommands::Assign { dev, path, protect, .. } => {
+            state::set_protected(dev, *protect)?;
+            Entry::Call(shell_call(ASSIGN_CMD, &protocol::join_args(&[dev.clone(), absolute(path)?]), 0))
+        },
+        Syscommands::Put { file, dest } => {
+            state::check_writable(&files::split_device(dest)?.0)?;
+            Entry::Put { file: absolute(file)?, dest: dest.clone() }
+        },
+        _ => unreachable!(),
+    };
+    Queue::open()?.push(&entry.line())?;
+    eprintln!("The daemon is not running; queued until it's reached again");
+    Ok(true)
+}
+
+pub fn run(cmd: QueueCommands, profile: Profile, progress: Progress, yes: bool) -> Result<()> {
+    let queue = Queue::open()?;
+    let send = |line: &str| send_entry(line, profile, progress);
+    match cmd {
+        QueueCommands::Ls => {
+            for call in queue.list()? {
+                println!("{}", call);
+            }
+            Ok(())
+        },
+        QueueCommands::Clear => {
+            confirm("Drop all queued commands?", yes)?;
+            queue.clear()
+        },
+        QueueCommands::Run { watch: false, .. } => {
+            let n = queue.flush(send)?;
+            eprintln!("Sent {} queued command(s)", n);
+            Ok(())
+        },
+        QueueCommands::Run { watch: true, interval } => loop {
+            if daemon_reachable() {
+                match queue.flush(send) {
+                    Ok(0) => (),
+                    Ok(n) => eprintln!("Sent {} queued command(s)", n),
+                    Err(e) => eprintln!("Queue not sent: {}", e),
+                }
+            }
+            thread::sleep(interval);
+        },
+    }
+}
+
+#[test]
+fn queue_flush() {
+    let dir = std::env::temp_dir().join(format!("idunsh-queue-{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    let queue = Queue { path: dir.join("queue") };
+    for call in ["one", "two", "three"] {
+        queue.push(call).unwrap();
+    }
+    let mut sent = vec![];
+    let failed = queue.flush(|c| if c == "two" { bail!("down") } else { sent.push(c.to_string()); Ok(()) });
+    assert!(failed.is_err());
+    assert_eq!(sent, ["one"]);
+    assert_eq!(queue.list().unwrap(), ["two", "three"]);
+    assert_eq!(queue.flush(|_| Ok(())).unwrap(), 2);
+    assert!(queue.list().unwrap().is_empty());
+    fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn queue_entries() {
+    let put = Entry::Put { file: "/home/me/my game.prg".into(), dest: "c:".into() };
+    assert_eq!(put.line(), r#"put "/home/me/my game.prg" c:"#);
+    assert_eq!(Entry::parse(&put.line()), put);
+    let call = r#"sys.shell(6, "d: /home/me/put it.d64", 0)"#;
+    assert_eq!(Entry::parse(call), Entry::Call(call.into()));
+    assert_eq!(Entry::parse("put one"), Entry::Call("put one".into()));
+}
@@ -0,0 +1,101 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+// Copyright (C) 2026 Brian Holdsworth
+
+//! Loading a program again whenever it is rebuilt, for `idunsh watch`.
+//!
+//! Assemblers and linkers often write a file in several steps, or write
+//! a new one and rename it over the old, so the directory holding the
+//! file is watched rather than the file itself, and a change is acted on
+//! once the file has been left alone for a moment.
+use std::env;
+use std::ffi::OsStr;
+use std::os::unix::io::AsRawFd;
+use std::path::{Path, PathBuf};
+use std::result;
+use std::thread;
+use std::time::Duration;
+use nix::errno::Errno;
+use nix::poll::{poll, PollFd, PollFlags};
+use nix::sys::inotify::{AddWatchFlags, InitFlags, Inotify};
+use idun_client::client::LOAD_CMD;
+use crate::c64ultimate::C64Ultimate;
+use crate::shell;
+use crate::stop_cmd;
+
+// Simpler error handling
+type Result<T> = result::Result<T, failure::Error>;
+
+/// Loads `prg` again whenever it changes, on the C64U if given, resetting
+/// first if asked.
+pub fn run(c64u: Option<&C64Ultimate>, prg: &str, reset_before: bool, settle: Duration) -> Result<()> {
+    // The remote shell has a current directory of its own
+    let path = match c64u {
+        Some(_) => PathBuf::from(prg),
+        None => env::current_dir()?.join(prg),
+    };
+    eprintln!("Watching {}, Ctrl-C to stop", prg);
+    watch_file(&path, settle, || {
+        match c64u {
+            Some(c64u) => {
+                if reset_before {
+                    c64u.reset()?;
+                }
+                c64u.load(prg, &[])?;
+            },
+            None => {
+                if reset_before {
+                    stop_cmd()?;
+                    // Give the program a moment to return to the shell
+                    thread::sleep(Duration::from_secs(1));
+                }
+                shell(LOAD_CMD, &path.to_string_lossy(), 0)?;
+            },
+        }
+        eprintln!("Loaded {}", prg);
+        Ok(())
+    })
+}
+
+/// Calls `changed` after every change to `file`, once it has been
+/// quiet for `settle`, until idunsh is stopped. An error from `changed`
+/// is shown and watching goes on.
+pub fn watch_file<F>(file: &Path, settle: Duration, mut changed: F) -> Result<()>
+where F: FnMut() -> Result<()> {
+    const MASK: AddWatchFlags = AddWatchFlags::IN_CLOSE_WRITE
+        .union(AddWatchFlags::IN_MOVED_TO)
+        .union(AddWatchFlags::IN_CREATE);
+    let name = file.file_name().ok_or_else(|| format_err!("{} is not a file", file.display()))?;
+    let dir = match file.parent() {
+        Some(d) if !d.as_os_str().is_empty() => d,
+        _ => Path::new("."),
+    };
+    let inotify = Inotify::init(InitFlags::IN_NONBLOCK)?;
+    inotify.add_watch(dir, MASK).map_err(|e| format_err!("{}: {}", dir.display(), e))?;
+    let mut fds = [PollFd::new(inotify.as_raw_fd(), PollFlags::POLLIN)];
+    let settle = settle.as_millis().min(i32::MAX as u128) as i32;
+    loop {
+        poll(&mut fds, -1)?;
+        if !drain(&inotify, name)? {
+            continue
+        }
+        while poll(&mut fds, settle)? > 0 {
+            drain(&inotify, name)?;
+        }
+        if let Err(e) = changed() {
+            eprintln!("{}: {}", file.display(), e);
+        }
+    }
+}
+
+// Reads the events waiting, true if any was for `name`
+fn drain(inotify: &Inotify, name: &OsStr) -> Result<bool> {
+    let mut seen = false;
+    loop {
+        match inotify.read_events() {
+            Ok(events) if events.is_empty() => return Ok(seen),
+            Ok(events) => seen |= events.iter().any(|ev| ev.name.as_deref() == Some(name)),
+            Err(nix::Error::Sys(Errno::EAGAIN)) => return Ok(seen),
+            Err(e) => return Err(e.into()),
+        }
+    }
+}
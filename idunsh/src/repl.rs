@@ -0,0 +1,159 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+// Copyright (C) 2026 Brian Holdsworth
+
+//! Line editing for the interactive mode (`-i`).
+//!
+//! Each line is an idunsh command line without the `idunsh`, e.g.
+//! `-o dir c:`. History is kept in `~/.local/share/idunsh/history`, and
+//! Tab completes the sub-command names, and file names after a drive,
+//! e.g. `c:ga`. Function keys bound under `keys` in the config file run
+//! their command line at once.
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+use std::result;
+use std::sync::{Arc, Mutex};
+use rustyline::completion::Completer;
+use rustyline::error::ReadlineError;
+use rustyline::highlight::Highlighter;
+use rustyline::hint::Hinter;
+use rustyline::history::DefaultHistory;
+use rustyline::validate::Validator;
+use rustyline::{Cmd, ConditionalEventHandler, Context, Editor, Event, EventContext, EventHandler,
+    Helper, KeyCode, KeyEvent, Modifiers, RepeatCount};
+use crate::catalogs;
+
+// Simpler error handling
+type Result<T> = result::Result<T, failure::Error>;
+
+// The names a directory such as "c:games/" holds
+type Files = Box<dyn Fn(&str) -> Vec<String>>;
+
+struct Commands {
+    names: Vec<String>,
+    files: Files,
+}
+
+impl Completer for Commands {
+    type Candidate = String;
+    fn complete(&self, line: &str, pos: usize, _: &Context<'_>) -> rustyline::Result<(usize, Vec<String>)> {
+        let start = line[..pos].rfind(' ').map_or(0, |i| i + 1);
+        let word = &line[start..pos];
+        // Only the first word that isn't a flag names the sub-command
+        let first = line[..start].split_whitespace().all(|w| w.starts_with('-'));
+        if word.starts_with('-') {
+            return Ok((pos, vec![]))
+        }
+        if first {
+            return Ok((start, self.names.iter().filter(|c| c.starts_with(word)).cloned().collect()))
+        }
+        if !word.contains(':') {
+            return Ok((pos, vec![]))
+        }
+        let (dir, name) = catalogs::split_dir(word);
+        let files = (self.files)(dir).into_iter()
+            .filter(|f| f.to_ascii_lowercase().starts_with(&name.to_ascii_lowercase()))
+            .map(|f| format!("{}{}", dir, f))
+            .collect();
+        Ok((start, files))
+    }
+}
+
+impl Hinter for Commands {
+    type Hint = String;
+}
+impl Highlighter for Commands {}
+impl Validator for Commands {}
+impl Helper for Commands {}
+
+// Ends the line being typed, leaving a function key's command line to
+// run instead
+struct Shortcut {
+    line: String,
+    pressed: Arc<Mutex<Option<String>>>,
+}
+
+impl ConditionalEventHandler for Shortcut {
+    fn handle(&self, _: &Event, _: RepeatCount, _: bool, _: &EventContext) -> Option<Cmd> {
+        *self.pressed.lock().ok()? = Some(self.line.clone());
+        Some(Cmd::AcceptLine)
+    }
+}
+
+// The function key named, e.g. "F5"
+fn function_key(name: &str) -> Option<KeyEvent> {
+    let n: u8 = name.strip_prefix(['F', 'f'])?.parse().ok()?;
+    (1..=12).contains(&n).then_some(KeyEvent(KeyCode::F(n), Modifiers::NONE))
+}
+
+pub struct Repl {
+    editor: Editor<Commands, DefaultHistory>,
+    history: Option<PathBuf>,
+    pressed: Arc<Mutex<Option<String>>>,
+}
+
+impl Repl {
+    /// A prompt that completes `commands`, and file names from `files`,
+    /// with function keys bound to the command lines in `keys`.
+    pub fn new<F>(commands: Vec<String>, keys: &BTreeMap<String, String>, files: F) -> Result<Repl>
+    where F: Fn(&str) -> Vec<String> + 'static {
+        let mut editor = Editor::new()?;
+        editor.set_helper(Some(Commands { names: commands, files: Box::new(files) }));
+        let pressed = Arc::new(Mutex::new(None));
+        for (name, line) in keys {
+            let key = function_key(name)
+                .ok_or_else(|| format_err!("{:?} in [keys] is not a function key F1 to F12", name))?;
+            let shortcut = Shortcut { line: line.clone(), pressed: pressed.clone() };
+            editor.bind_sequence(key, EventHandler::Conditional(Box::new(shortcut)));
+        }
+        let history = dirs::data_local_dir().map(|d| d.join("idunsh").join("history"));
+        if let Some(path) = &history {
+            // There's no history yet on the first run
+            let _ = editor.load_history(path);
+        }
+        Ok(Repl { editor, history, pressed })
+    }
+    /// Reads the next command line, or None at the end of input.
+    /// Ctrl-C drops the line being typed.
+    pub fn read(&mut self, prompt: &str) -> Result<Option<String>> {
+        loop {
+            let read = self.editor.readline(prompt);
+            if let Some(line) = self.pressed.lock().ok().and_then(|mut p| p.take()) {
+                eprintln!("{}", line);
+                self.editor.add_history_entry(line.as_str())?;
+                return Ok(Some(line))
+            }
+            match read {
+                Ok(line) if line.trim().is_empty() => continue,
+                Ok(line) => {
+                    self.editor.add_history_entry(line.as_str())?;
+                    return Ok(Some(line))
+                },
+                Err(ReadlineError::Interrupted) => continue,
+                Err(ReadlineError::Eof) => return Ok(None),
+                Err(e) => return Err(e.into()),
+            }
+        }
+    }
+}
+
+impl Drop for Repl {
+    fn drop(&mut self) {
+        if let Some(path) = &self.history {
+            let _ = path.parent().map(std::fs::create_dir_all);
+            let _ = self.editor.save_history(path);
+        }
+    }
+}
+
+#[test]
+fn complete_commands() {
+    let files = |dir: &str| if dir == "c:" { vec!["Game".into(), "games/".into(), "demo".into()] } else { vec![] };
+    let commands = Commands { names: vec!["dir".into(), "drives".into(), "mount".into()], files: Box::new(files) };
+    let history = DefaultHistory::new();
+    let ctx = Context::new(&history);
+    assert_eq!(commands.complete("-o d", 4, &ctx).unwrap(), (3, vec!["dir".into(), "drives".into()]));
+    assert_eq!(commands.complete("dir d", 5, &ctx).unwrap().1, Vec::<String>::new());
+    assert_eq!(commands.complete("get c:ga", 8, &ctx).unwrap(), (4, vec!["c:Game".into(), "c:games/".into()]));
+    assert_eq!(function_key("F5"), Some(KeyEvent(KeyCode::F(5), Modifiers::NONE)));
+    assert_eq!(function_key("F13"), None);
+}
@@ -0,0 +1,75 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+// Copyright (C) 2026 Brian Holdsworth
+
+//! The tokio runtime shared by the network code.
+//!
+//! Requests to the daemon, the redirect listeners and C64U discovery run
+//! as tasks on one runtime, so that they can overlap with each other and
+//! with the blocking work left on the calling thread. The rest of idunsh
+//! stays synchronous and waits for them with `block_on` or `join`. Never
+//! call these from inside a task: the runtime can't block on itself.
+use std::future::{self, Future};
+use std::pin::Pin;
+use std::result;
+use std::sync::OnceLock;
+use std::task::Poll;
+use tokio::runtime::{Builder, Runtime};
+use tokio::task::JoinHandle;
+use crate::cleanup;
+
+// Simpler error handling
+type Result<T> = result::Result<T, failure::Error>;
+
+/// The runtime, started on first use.
+pub fn get() -> &'static Runtime {
+    static RUNTIME: OnceLock<Runtime> = OnceLock::new();
+    RUNTIME.get_or_init(|| {
+        Builder::new_multi_thread()
+            .worker_threads(2)
+            .thread_name("idunsh-net")
+            .enable_all()
+            .build()
+            .expect("Failed to start the network runtime")
+    })
+}
+
+/// Runs `future` to completion on the calling thread.
+pub fn block_on<F: Future>(future: F) -> F::Output {
+    get().block_on(future)
+}
+
+/// Starts `future` in the background.
+pub fn spawn<F>(future: F) -> JoinHandle<F::Output>
+where F: Future + Send + 'static, F::Output: Send + 'static {
+    get().spawn(future)
+}
+
+/// Runs blocking `work` on the runtime's pool and waits for it, unless
+/// SIGINT comes first. The work is then left to finish on its own.
+pub fn interruptible<T, F>(work: F) -> Result<T>
+where F: FnOnce() -> T + Send + 'static, T: Send + 'static {
+    block_on(async {
+        let mut done = get().spawn_blocking(work);
+        let interrupt = cleanup::interrupted();
+        tokio::pin!(interrupt);
+        future::poll_fn(|cx| {
+            if let Poll::Ready(r) = Pin::new(&mut done).poll(cx) {
+                return Poll::Ready(r.map_err(|e| format_err!("Network task failed: {}", e)));
+            }
+            interrupt.as_mut().poll(cx).map(|_| Err(format_err!("Interrupted")))
+        }).await
+    })
+}
+
+/// Waits for a task started with `spawn`.
+pub fn join<T>(task: JoinHandle<Result<T>>) -> Result<T> {
+    block_on(task).map_err(|e| format_err!("Network task failed: {}", e))?
+}
+
+#[test]
+fn spawn_and_join() {
+    let task = spawn(async { Ok(6 * 7) });
+    assert_eq!(join(task).unwrap(), 42);
+    assert_eq!(block_on(async { "done" }), "done");
+    assert_eq!(interruptible(|| 6 * 7).unwrap(), 42);
+}
@@ -0,0 +1,293 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+// Copyright (C) 2026 Brian Holdsworth
+
+//! Snapshots of game saves, kept in `~/.local/share/idunsh/saves`.
+//!
+//! `saves backup` copies the files on a drive that match a pattern, or
+//! the whole disk image mounted on it, into a snapshot named by the UTC
+//! time it was taken, e.g. `saves/c/20261016-093000`. Files keep the
+//! name and type they have on the drive, e.g. `HISCORE,s`. An image is
+//! kept as `image`, with the path it was mounted from in `source`, so it
+//! can be put back in place. A snapshot is written under a temporary
+//! name and renamed when complete, so one cut short is never listed.
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process;
+use std::result;
+use std::time::{SystemTime, UNIX_EPOCH};
+use clap::Subcommand;
+use idun_client::client::{CATALOG_CMD, DRIVES_CMD, MOUNT_CMD};
+use idun_client::listing::{Entry, Listing, Mount};
+use idun_client::protocol;
+use crate::capture_shell;
+use crate::confirm::confirm;
+use crate::drive;
+use crate::files;
+use crate::journal::format_time;
+use crate::profile::Profile;
+use crate::progress::Progress;
+use crate::shell;
+use crate::state;
+use crate::store;
+
+// Simpler error handling
+type Result<T> = result::Result<T, failure::Error>;
+
+/// The snapshots of one drive.
+pub struct Saves {
+    dir: PathBuf,
+}
+
+/// What a snapshot holds.
+#[derive(Debug, PartialEq, Eq)]
+pub enum Contents {
+    /// Files, by the name and type they had on the drive
+    Files(Vec<String>),
+    /// A disk image, and where it was mounted from
+    Image(String),
+}
+
+/// One snapshot, named by when it was taken.
+#[derive(Debug)]
+pub struct Snapshot {
+    pub name: String,
+    path: PathBuf,
+}
+
+/// A snapshot being written; see `Saves::begin`.
+pub struct Pending {
+    tmp: PathBuf,
+    path: PathBuf,
+}
+
+impl Saves {
+    /// The snapshots of a drive such as "c:".
+    pub fn open(dev: &str) -> Result<Saves> {
+        let name: String = dev.trim_end_matches(':').chars()
+            .map(|c| if c.is_ascii_alphanumeric() { c.to_ascii_lowercase() } else { '_' })
+            .collect();
+        let dir = store::subdir("saves")?.join(name);
+        fs::create_dir_all(&dir)?;
+        Ok(Saves { dir })
+    }
+    /// Starts a snapshot taken now.
+    pub fn begin(&self) -> Result<Pending> {
+        let secs = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs();
+        let path = self.dir.join(snapshot_name(secs));
+        if path.exists() {
+            bail!("There is already a snapshot {}; try again in a second", path.display())
+        }
+        let tmp = self.dir.join(format!(".tmp-{}", process::id()));
+        let _ = fs::remove_dir_all(&tmp);
+        fs::create_dir(&tmp)?;
+        Ok(Pending { tmp, path })
+    }
+    /// The snapshots, oldest first.
+    pub fn list(&self) -> Result<Vec<Snapshot>> {
+        let mut snapshots: Vec<Snapshot> = fs::read_dir(&self.dir)?
+            .filter_map(|e| e.ok())
+            .map(|e| e.file_name().to_string_lossy().into_owned())
+            .filter(|name| !name.starts_with('.'))
+            .map(|name| Snapshot { path: self.dir.join(&name), name })
+            .collect();
+        snapshots.sort_by(|a, b| a.name.cmp(&b.name));
+        Ok(snapshots)
+    }
+    /// The snapshot called `name`, or the latest.
+    pub fn find(&self, name: Option<&str>) -> Result<Snapshot> {
+        let mut snapshots = self.list()?;
+        match name {
+            Some(name) => {
+                let i = snapshots.iter().position(|s| s.name == name)
+                    .ok_or_else(|| format_err!("There is no snapshot {}; see saves ls", name))?;
+                Ok(snapshots.swap_remove(i))
+            },
+            None => snapshots.pop().ok_or_else(|| format_err!("There are no snapshots yet")),
+        }
+    }
+}
+
+impl Pending {
+    /// Keeps a file from the drive, named as on the drive, e.g. "HISCORE,s".
+    pub fn add_file(&self, name: &str, data: &[u8]) -> Result<()> {
+        if name.contains('/') || name.starts_with('.') || name == "image" || name == "source" {
+            bail!("Can't keep a file named {:?}", name)
+        }
+        Ok(fs::write(self.tmp.join(name), data)?)
+    }
+    /// Keeps a whole disk image, mounted from `source`.
+    pub fn add_image(&self, source: &str) -> Result<()> {
+        fs::copy(source, self.tmp.join("image")).map_err(|e| format_err!("{}: {}", source, e))?;
+        Ok(fs::write(self.tmp.join("source"), source)?)
+    }
+    /// Makes the snapshot complete, giving its name.
+    pub fn finish(self) -> Result<String> {
+        fs::rename(&self.tmp, &self.path)?;
+        Ok(self.path.file_name().unwrap_or_default().to_string_lossy().into_owned())
+    }
+}
+
+impl Drop for Pending {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.tmp);
+    }
+}
+
+impl Snapshot {
+    pub fn contents(&self) -> Result<Contents> {
+        if let Ok(source) = fs::read_to_string(self.path.join("source")) {
+            return Ok(Contents::Image(source))
+        }
+        let mut files: Vec<String> = fs::read_dir(&self.path)?
+            .filter_map(|e| e.ok())
+            .map(|e| e.file_name().to_string_lossy().into_owned())
+            .collect();
+        files.sort();
+        Ok(Contents::Files(files))
+    }
+    /// The contents of one of its files, or of "image".
+    pub fn read(&self, name: &str) -> Result<Vec<u8>> {
+        Ok(fs::read(self.path.join(name))?)
+    }
+    pub fn image_path(&self) -> PathBuf {
+        self.path.join("image")
+    }
+    /// The snapshot as shown by `saves ls`.
+    pub fn display(&self) -> Result<String> {
+        Ok(match self.contents()? {
+            Contents::Image(source) => format!("{}  image {}", self.name, source),
+            Contents::Files(files) => format!("{}  {} file(s): {}", self.name, files.len(), files.join(" ")),
+        })
+    }
+}
+
+// Sortable UTC time, e.g. "20261016-093000"
+fn snapshot_name(secs: u64) -> String {
+    format_time(secs).replace(['-', ':'], "").replace(' ', "-")
+}
+
+/// True if `name` matches a CBM DOS pattern, where `?` stands for any
+/// character and `*` for any number of them.
+pub fn matches(pattern: &str, name: &str) -> bool {
+    let (p, n): (Vec<char>, Vec<char>) = (pattern.chars().collect(), name.chars().collect());
+    // Where to go on from after the last star: pattern and name positions
+    let mut star = None;
+    let (mut i, mut j) = (0, 0);
+    while j < n.len() {
+        match p.get(i) {
+            Some('*') => {
+                star = Some((i + 1, j));
+                i += 1;
+            },
+            Some(c) if *c == '?' || c.eq_ignore_ascii_case(&n[j]) => {
+                i += 1;
+                j += 1;
+            },
+            _ => match star {
+                Some((si, sj)) => {
+                    star = Some((si, sj + 1));
+                    i = si;
+                    j = sj + 1;
+                },
+                None => return false,
+            },
+        }
+    }
+    p[i..].iter().all(|c| *c == '*')
+}
+
+#[derive(Subcommand)]
+pub enum SavesCommands {
+    /// Copy the files matching a pattern, e.g. "save*", or else the whole
+    /// image mounted on the drive, to a new snapshot
+    Backup { dev: String, pattern: Option<String> },
+    /// List the snapshots of a drive, oldest first
+    Ls { dev: String },
+    /// Put a snapshot back on the drive, by default the latest
+    Restore { dev: String, snapshot: Option<String> },
+}
+
+// Backs up, lists or restores the save snapshots of a drive
+pub fn run(cmd: SavesCommands, profile: Profile, progress: Progress, yes: bool) -> Result<()> {
+    match cmd {
+        SavesCommands::Ls { dev } => {
+            for snapshot in Saves::open(&files::split_device(&dev)?.0)?.list()? {
+                println!("{}", snapshot.display()?);
+            }
+            Ok(())
+        },
+        SavesCommands::Backup { dev, pattern } => {
+            let (dev, _) = files::split_device(&dev)?;
+            let pending = Saves::open(&dev)?.begin()?;
+            match pattern {
+                Some(pattern) => {
+                    let listing = Listing::parse(&String::from(capture_shell(CATALOG_CMD, &dev)?));
+                    let files: Vec<&Entry> = listing.entries.iter()
+                        .filter(|e| !e.is_dir() && matches(&pattern, &e.name))
+                        .collect();
+                    if files.is_empty() {
+                        bail!("No files on {} match {}", dev, pattern)
+                    }
+                    let settings = files::transfer_settings(&dev, profile)?;
+                    for e in files {
+                        let data = files::fetch(&format!("{}{}", dev, e.name), settings)?;
+                        let ftype = e.ftype.chars().next().unwrap_or('p').to_ascii_lowercase();
+                        pending.add_file(&format!("{},{}", e.name, ftype), &data)?;
+                    }
+                },
+                None => pending.add_image(&mounted_image(&dev)?)?,
+            }
+            eprintln!("Saved snapshot {}", pending.finish()?);
+            Ok(())
+        },
+        SavesCommands::Restore { dev, snapshot } => {
+            let (dev, _) = files::split_device(&dev)?;
+            state::check_writable(&dev)?;
+            let snapshot = Saves::open(&dev)?.find(snapshot.as_deref())?;
+            match snapshot.contents()? {
+                Contents::Image(source) => {
+                    confirm(&format!("Replace {} with the image saved {}?", source, snapshot.name), yes)?;
+                    fs::copy(snapshot.image_path(), &source).map_err(|e| format_err!("{}: {}", source, e))?;
+                    // Mounting again makes the drive read the image afresh
+                    drive::with_status(&dev, shell(MOUNT_CMD, &protocol::join_args(&[&dev, &source]), 0))
+                },
+                Contents::Files(files) => {
+                    confirm(&format!("Replace {} file(s) on {} with those saved {}?", files.len(), dev, snapshot.name), yes)?;
+                    let settings = files::transfer_settings(&dev, profile)?;
+                    for stored in files {
+                        // DOS won't write over a file, so the old one goes first
+                        let name = stored.rsplit_once(',').map_or(stored.as_str(), |(name, _)| name);
+                        let scratch = format!("S0:{}", name).to_ascii_uppercase();
+                        let status = drive::dos_status(&dev, &drive::dos_send(&dev, scratch.as_bytes())?)?;
+                        if status.is_error() {
+                            return Err(status.into())
+                        }
+                        files::store(&dev, &stored, snapshot.read(&stored)?, settings, progress)?;
+                    }
+                    Ok(())
+                },
+            }
+        },
+    }
+}
+
+// The disk image on this machine mounted on `dev`
+fn mounted_image(dev: &str) -> Result<String> {
+    let mounts = Mount::parse_all(&String::from(capture_shell(DRIVES_CMD, "")?));
+    match mounts.into_iter().find(|m| m.device.eq_ignore_ascii_case(dev)) {
+        Some(m) if Path::new(&m.target).is_file() => Ok(m.target),
+        Some(_) => bail!("{} isn't a disk image; name the save files, e.g. saves backup {} \"save*\"", dev, dev),
+        None => bail!("Nothing is mounted on {}", dev),
+    }
+}
+
+#[test]
+fn save_patterns() {
+    assert!(matches("save*", "SAVE GAME 1"));
+    assert!(matches("*", "anything"));
+    assert!(matches("?iscore", "hiscore"));
+    assert!(matches("*game*", "my game.1"));
+    assert!(!matches("save*", "game"));
+    assert!(!matches("save", "saves"));
+    assert_eq!(snapshot_name(1792143000), "20261016-093000");
+}
@@ -0,0 +1,102 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+// Copyright (C) 2026 Brian Holdsworth
+
+//! Single sectors of a drive or of a local disk image, for `idunsh
+//! sector`.
+use std::fs;
+use std::io::{self, Read, Write, stdout};
+use std::result;
+use clap::Subcommand;
+use idun_client::client::{BLOCK_READ_CMD, BLOCK_WRITE_CMD};
+use idun_client::protocol;
+use crate::capture;
+use crate::confirm::confirm;
+use crate::drive;
+use crate::hexdump;
+use crate::image::{self, SECTOR_SIZE};
+use crate::is_device_path;
+use crate::labels::Labels;
+use crate::shell;
+use crate::state;
+
+// Simpler error handling
+type Result<T> = result::Result<T, failure::Error>;
+
+#[derive(Subcommand)]
+pub enum SectorCommands {
+    /// Show a sector as a hexdump
+    Read {
+        /// Drive (e.g. c:) or local disk image
+        disk: String,
+        track: u8,
+        sector: u8,
+        #[arg(long)]
+        /// Write the 256 bytes to stdout as they are
+        raw: bool,
+    },
+    /// Replace a sector with 256 bytes from a file, or - for stdin
+    Write {
+        /// Drive (e.g. c:) or local disk image
+        disk: String,
+        track: u8,
+        sector: u8,
+        file: String,
+    },
+}
+
+pub fn run(cmd: SectorCommands, yes: bool) -> Result<()> {
+    match cmd {
+        SectorCommands::Read { disk, track, sector, raw } => {
+            let data = match is_device_path(&disk) {
+                true => read_block(&disk, track, sector)?,
+                false => {
+                    let image = fs::read(&disk).map_err(|e| format_err!("{}: {}", disk, e))?;
+                    let offset = image::geometry(&disk, &image)?.offset(track, sector)?;
+                    image[offset..offset + SECTOR_SIZE].to_vec()
+                },
+            };
+            if raw {
+                stdout().write_all(&data)?;
+            } else {
+                hexdump::hexdump(&mut stdout(), 0, &data, 16, false, &Labels::default())?;
+            }
+        },
+        SectorCommands::Write { disk, track, sector, file } => {
+            confirm(&format!("Write track {} sector {} of {}? A wrong sector can make it unreadable", track, sector, disk), yes)?;
+            let mut data = vec![];
+            match file.as_str() {
+                "-" => io::stdin().read_to_end(&mut data)?,
+                _ => fs::File::open(&file).and_then(|mut f| f.read_to_end(&mut data))
+                    .map_err(|e| format_err!("{}: {}", file, e))?,
+            };
+            if data.len() != SECTOR_SIZE {
+                bail!("A sector is {} bytes, but {} holds {}", SECTOR_SIZE, file, data.len())
+            }
+            if is_device_path(&disk) {
+                state::check_writable(&disk)?;
+                write_block(&disk, track, sector, &data)?;
+            } else {
+                let mut image = fs::read(&disk).map_err(|e| format_err!("{}: {}", disk, e))?;
+                let offset = image::geometry(&disk, &image)?.offset(track, sector)?;
+                image[offset..offset + SECTOR_SIZE].copy_from_slice(&data);
+                fs::write(&disk, image).map_err(|e| format_err!("{}: {}", disk, e))?;
+            }
+        },
+    }
+    Ok(())
+}
+
+pub fn read_block(dev: &str, track: u8, sector: u8) -> Result<Vec<u8>> {
+    let args = protocol::join_args(&[dev.to_string(), track.to_string(), sector.to_string()]);
+    let data = capture(|id| drive::with_status(dev, shell(BLOCK_READ_CMD, &args, id)))?;
+    if data.len() != SECTOR_SIZE {
+        bail!("Reading track {} sector {} of {} returned {} bytes", track, sector, dev, data.len())
+    }
+    Ok(data)
+}
+
+pub fn write_block(dev: &str, track: u8, sector: u8, data: &[u8]) -> Result<()> {
+    let hex: String = data.iter().map(|b| format!("{:02x}", b)).collect();
+    let args = protocol::join_args(&[dev.to_string(), track.to_string(), sector.to_string(), hex]);
+    drive::with_status(dev, shell(BLOCK_WRITE_CMD, &args, 0))
+}
@@ -0,0 +1,271 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+// Copyright (C) 2026 Brian Holdsworth
+
+//! The daemon over the cartridge's UART, for when there's no network or
+//! USB link to reach its socket by.
+//!
+//! A socket given as `serial:///dev/ttyUSB0?baud=115200` names a serial
+//! line instead, 115200 baud unless given. Everything goes over the line
+//! as SLIP frames (see `encoding`), each a kind, the number of the
+//! redirect socket it's for as four bytes, low byte first, then the
+//! data:
+//!
+//! ```text
+//! c  0     a call, without its newline       to the daemon
+//! a  0     the answer to it                  from the daemon
+//! o  proc  output redirected to `proc`       from the daemon
+//! r  proc  a request for the data of `proc`  from the daemon
+//! i  proc  that data, as for `put`           to the daemon
+//! e  proc  the end of output or data         either way
+//! ```
+//!
+//! A `Relay` stands in for the daemon on this side: it takes calls on
+//! connections of its own, one at a time, and connects to the redirect
+//! sockets in the run directory the way the daemon would. The rest of
+//! idunsh works as it does with the daemon's socket.
+use std::collections::BTreeMap;
+use std::fs::{File, OpenOptions};
+use std::io::{BufRead, BufReader, Read, Write};
+use std::os::unix::fs::OpenOptionsExt;
+use std::os::unix::io::AsRawFd;
+use std::os::unix::net::UnixStream;
+use std::path::{Path, PathBuf};
+use std::result;
+use std::sync::{mpsc, Arc, Mutex};
+use std::thread;
+use nix::fcntl::OFlag;
+use nix::sys::termios::{self, BaudRate, SetArg};
+use crate::encoding::{Encoding, Slip, SlipFrames};
+
+// Simpler error handling
+type Result<T> = result::Result<T, failure::Error>;
+
+const SCHEME: &str = "serial://";
+const DEFAULT_BAUD: u32 = 115200;
+
+// The kinds of frame
+const CALL: u8 = b'c';
+const ANSWER: u8 = b'a';
+const OUTPUT: u8 = b'o';
+const READ: u8 = b'r';
+const INPUT: u8 = b'i';
+const END: u8 = b'e';
+
+/// A serial line, as named by a `serial://` socket.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Port {
+    pub path: PathBuf,
+    pub baud: u32,
+}
+
+impl Port {
+    /// The serial line `socket` names, or None if it names a socket.
+    pub fn parse(socket: &str) -> Result<Option<Port>> {
+        let Some(rest) = socket.strip_prefix(SCHEME) else { return Ok(None) };
+        let (path, query) = rest.split_once('?').unwrap_or((rest, ""));
+        if path.is_empty() {
+            bail!("{} names no serial device", socket)
+        }
+        let mut baud = DEFAULT_BAUD;
+        for (key, value) in query.split('&').filter(|q| !q.is_empty()).map(|q| q.split_once('=').unwrap_or((q, ""))) {
+            match key {
+                "baud" => baud = value.parse().map_err(|_| format_err!("{}: {:?} isn't a baud rate", socket, value))?,
+                _ => bail!("{}: unknown setting {:?}; only baud can be set", socket, key),
+            }
+        }
+        Ok(Some(Port { path: PathBuf::from(path), baud }))
+    }
+    // The line, opened raw at its baud rate
+    fn open(&self) -> Result<File> {
+        let line = OpenOptions::new().read(true).write(true)
+            .custom_flags(OFlag::O_NOCTTY.bits())
+            .open(&self.path)
+            .map_err(|e| format_err!("Can't open {}: {}", self.path.display(), e))?;
+        let mut settings = termios::tcgetattr(line.as_raw_fd())
+            .map_err(|e| format_err!("{} isn't a serial line: {}", self.path.display(), e))?;
+        termios::cfmakeraw(&mut settings);
+        termios::cfsetspeed(&mut settings, baud_rate(self.baud)?)?;
+        termios::tcsetattr(line.as_raw_fd(), SetArg::TCSANOW, &settings)?;
+        Ok(line)
+    }
+}
+
+fn baud_rate(baud: u32) -> Result<BaudRate> {
+    Ok(match baud {
+        9600 => BaudRate::B9600,
+        19200 => BaudRate::B19200,
+        38400 => BaudRate::B38400,
+        57600 => BaudRate::B57600,
+        115200 => BaudRate::B115200,
+        230400 => BaudRate::B230400,
+        460800 => BaudRate::B460800,
+        921600 => BaudRate::B921600,
+        _ => bail!("{} baud isn't supported; use 9600 to 921600", baud),
+    })
+}
+
+fn frame(kind: u8, proc: u32, data: &[u8]) -> Vec<u8> {
+    let mut frame = vec![kind];
+    frame.extend(proc.to_le_bytes());
+    frame.extend(data);
+    Slip.encode(&frame)
+}
+
+/// Stands in for the daemon, passing what goes to it over a line.
+pub struct Relay {
+    calls: mpsc::Sender<UnixStream>,
+}
+
+impl Relay {
+    /// Relays over `line`, connecting to redirect sockets in `run_dir`.
+    pub fn start<L>(line: L, writer: impl Write + Send + 'static, run_dir: &Path) -> Relay
+    where L: Read + Send + 'static {
+        let writer = Arc::new(Mutex::new(writer));
+        let (calls, incoming) = mpsc::channel::<UnixStream>();
+        let (answer, answers) = mpsc::channel();
+        let run_dir = run_dir.to_path_buf();
+        let line_writer = writer.clone();
+        thread::spawn(move || read_line(line, line_writer, answer, run_dir));
+        thread::spawn(move || {
+            for call in incoming {
+                let _ = relay_call(call, &writer, &answers);
+            }
+        });
+        Relay { calls }
+    }
+    /// A connection to send a call on, as to the daemon's socket.
+    pub fn connect(&self) -> Result<UnixStream> {
+        let (ours, theirs) = UnixStream::pair()?;
+        self.calls.send(theirs).map_err(|_| format_err!("The serial relay has stopped"))?;
+        Ok(ours)
+    }
+}
+
+/// The relay for `port`, started on first use.
+pub fn relay(port: &Port, run_dir: &Path) -> Result<Arc<Relay>> {
+    static RELAYS: Mutex<BTreeMap<PathBuf, Arc<Relay>>> = Mutex::new(BTreeMap::new());
+    let mut relays = RELAYS.lock().map_err(|_| format_err!("The serial relays are unusable"))?;
+    if let Some(relay) = relays.get(&port.path) {
+        return Ok(relay.clone())
+    }
+    let line = port.open()?;
+    let relay = Arc::new(Relay::start(line.try_clone()?, line, run_dir));
+    relays.insert(port.path.clone(), relay.clone());
+    Ok(relay)
+}
+
+// Sends one call and passes the answer back
+fn relay_call<W: Write>(call: UnixStream, line: &Mutex<W>, answers: &mpsc::Receiver<Vec<u8>>) -> Result<()> {
+    let mut text = vec![];
+    BufReader::new(&call).read_until(b'\n', &mut text)?;
+    if text.last() == Some(&b'\n') {
+        text.pop();
+    }
+    // Answers to calls given up on are dropped
+    while answers.try_recv().is_ok() {}
+    let sent = line.lock().map_err(|_| format_err!("Serial line unusable"))
+        .and_then(|mut l| Ok(l.write_all(&frame(CALL, 0, &text)).and_then(|_| l.flush())?));
+    let answer = match sent.map(|_| answers.recv()) {
+        Ok(Ok(answer)) => answer,
+        Ok(Err(_)) => [&[1u8][..], b"The serial line closed"].concat(),
+        Err(e) => [vec![1u8], e.to_string().into_bytes()].concat(),
+    };
+    (&call).write_all(&answer)?;
+    Ok(())
+}
+
+// Reads frames off the line until it closes, handing on answers and
+// serving redirect sockets
+fn read_line<W>(mut line: impl Read, writer: Arc<Mutex<W>>, answers: mpsc::Sender<Vec<u8>>, run_dir: PathBuf)
+where W: Write + Send + 'static {
+    let mut frames = SlipFrames::default();
+    let mut outputs: BTreeMap<u32, Option<UnixStream>> = BTreeMap::new();
+    let mut buf = [0u8; 4096];
+    while let Ok(n @ 1..) = line.read(&mut buf) {
+        // Frames spoiled by noise on the line are lost
+        for frame in frames.push(&buf[..n]).unwrap_or_default() {
+            let [kind, a, b, c, d, ..] = frame[..] else { continue };
+            let (proc, data) = (u32::from_le_bytes([a, b, c, d]), &frame[5..]);
+            match kind {
+                ANSWER => {
+                    let _ = answers.send(data.to_vec());
+                },
+                OUTPUT => {
+                    // A socket that can't be reached gets none of its output
+                    let out = outputs.entry(proc).or_insert_with(|| UnixStream::connect(run_dir.join(proc.to_string())).ok());
+                    if out.as_mut().is_some_and(|s| s.write_all(data).is_err()) {
+                        *out = None;
+                    }
+                },
+                END => {
+                    outputs.remove(&proc);
+                },
+                READ => {
+                    let (writer, path) = (writer.clone(), run_dir.join(proc.to_string()));
+                    thread::spawn(move || send_input(proc, &path, &writer));
+                },
+                _ => (),
+            }
+        }
+    }
+}
+
+// Sends what idunsh has for a redirect socket, then its end
+fn send_input<W: Write>(proc: u32, path: &Path, line: &Mutex<W>) {
+    let send = |kind, data: &[u8]| match line.lock() {
+        Ok(mut l) => l.write_all(&frame(kind, proc, data)).and_then(|_| l.flush()).is_ok(),
+        Err(_) => false,
+    };
+    if let Ok(mut s) = UnixStream::connect(path) {
+        let mut buf = [0u8; 1024];
+        while let Ok(n @ 1..) = s.read(&mut buf) {
+            if !send(INPUT, &buf[..n]) {
+                return
+            }
+        }
+    }
+    send(END, &[]);
+}
+
+#[test]
+fn serial_relay() {
+    use std::os::unix::net::UnixListener;
+    assert_eq!(Port::parse("/tmp/idunmm-lua").unwrap(), None);
+    let port = Port::parse("serial:///dev/ttyUSB0?baud=57600").unwrap().unwrap();
+    assert_eq!((port.path.to_str(), port.baud), (Some("/dev/ttyUSB0"), 57600));
+    assert_eq!(Port::parse("serial:///dev/ttyS0").unwrap().unwrap().baud, DEFAULT_BAUD);
+    assert!(Port::parse("serial://?baud=9600").is_err() && Port::parse("serial:///dev/ttyS0?parity=even").is_err());
+    assert!(baud_rate(12345).is_err());
+
+    let dir = std::env::temp_dir().join(format!("idunsh-serial-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let output = UnixListener::bind(dir.join("7")).unwrap();
+    // The daemon's end of the line
+    let (ours, daemon) = UnixStream::pair().unwrap();
+    let relay = Relay::start(ours.try_clone().unwrap(), ours, &dir);
+    let daemon_side = thread::spawn(move || {
+        let mut frames = SlipFrames::default();
+        let mut buf = [0u8; 256];
+        let call = loop {
+            let n = (&daemon).read(&mut buf).unwrap();
+            if let Some(f) = frames.push(&buf[..n]).unwrap().pop() {
+                break f
+            }
+        };
+        let mut reply = frame(OUTPUT, 7, b"C:=/home");
+        reply.extend(frame(END, 7, &[]));
+        reply.extend(frame(ANSWER, 0, &[0]));
+        (&daemon).write_all(&reply).unwrap();
+        call
+    });
+    let mut call = relay.connect().unwrap();
+    call.write_all(b"sys.shell(5, \"\", 7)\n").unwrap();
+    let mut answer = vec![];
+    call.read_to_end(&mut answer).unwrap();
+    assert_eq!(answer, [0]);
+    assert_eq!(daemon_side.join().unwrap(), [&[CALL, 0, 0, 0, 0][..], b"sys.shell(5, \"\", 7)"].concat());
+    let mut text = String::new();
+    output.accept().unwrap().0.read_to_string(&mut text).unwrap();
+    assert_eq!(text, "C:=/home");
+    std::fs::remove_dir_all(&dir).unwrap();
+}
@@ -0,0 +1,224 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+// Copyright (C) 2026 Brian Holdsworth
+
+//! Test plans for `idunsh test`, which runs a program on the C64
+//! Ultimate and checks what it shows on the text screen.
+//!
+//! ```toml
+//! prg = "build/game.prg"
+//!
+//! [[case]]
+//! name = "title screen"
+//! wait = "3s"
+//! expect = ["press fire"]
+//!
+//! [[case]]
+//! name = "game starts"
+//! keys = "{f1}"
+//! wait = "2s"
+//! expect = ["score 000000", "lives ?"]
+//! reject = ["?syntax*error"]
+//! golden = "shots/start.png"
+//! tolerance = 0.5
+//! ```
+//!
+//! Each case loads the program afresh, unless it has `reload = false`
+//! to go on from where the case before left off. Then its keys are
+//! typed, and after `wait` (1s by default) the screen is read. Each
+//! `expect` pattern must be found on some line of it, and no `reject`
+//! pattern on any. Patterns ignore case, with `?` for any character and
+//! `*` for any number of them. `screen` moves the screen from $0400 for
+//! programs that put it elsewhere.
+//!
+//! With `golden`, a frame of the video stream is compared with that PNG
+//! file too, found next to the plan; `tolerance` is the percentage of
+//! pixels that may differ, 0 by default. `test --update` writes the
+//! frames as the golden images instead.
+use std::fs;
+use std::path::Path;
+use std::result;
+use std::thread;
+use std::time::Duration;
+use serde::{de, Deserialize, Deserializer};
+use idun_client::petscii::{self, Charset, Layout};
+use idun_client::util;
+use crate::c64ultimate::{C64Ultimate, Stream};
+use crate::errors::ExitStatus;
+use crate::frame;
+use crate::saves;
+use crate::target::{self, Memory, Target};
+
+// Simpler error handling
+type Result<T> = result::Result<T, failure::Error>;
+
+/// The size of the text screen
+pub const COLUMNS: usize = 40;
+pub const ROWS: usize = 25;
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Plan {
+    pub prg: String,
+    #[serde(default = "default_screen", deserialize_with = "addr")]
+    pub screen: u16,
+    #[serde(rename = "case")]
+    pub cases: Vec<Case>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Case {
+    pub name: String,
+    /// Typed after loading, with names in braces for keys, e.g. {f1}
+    #[serde(default)]
+    pub keys: String,
+    #[serde(default = "default_wait", deserialize_with = "duration")]
+    pub wait: Duration,
+    #[serde(default)]
+    pub expect: Vec<String>,
+    #[serde(default)]
+    pub reject: Vec<String>,
+    #[serde(default = "default_reload")]
+    pub reload: bool,
+    /// PNG file the video frame must match
+    pub golden: Option<String>,
+    #[serde(default)]
+    pub tolerance: f64,
+}
+
+fn default_screen() -> u16 {
+    0x0400
+}
+fn default_wait() -> Duration {
+    Duration::from_secs(1)
+}
+fn default_reload() -> bool {
+    true
+}
+
+fn addr<'de, D: Deserializer<'de>>(d: D) -> result::Result<u16, D::Error> {
+    util::parse_addr(&String::deserialize(d)?).map_err(de::Error::custom)
+}
+fn duration<'de, D: Deserializer<'de>>(d: D) -> result::Result<Duration, D::Error> {
+    util::parse_duration(&String::deserialize(d)?).map_err(de::Error::custom)
+}
+
+impl Plan {
+    /// Reads a plan. A program named relative to it is found next to it,
+    /// and golden images are kept there.
+    pub fn load(file: &str) -> Result<Plan> {
+        let text = fs::read_to_string(file).map_err(|e| format_err!("{}: {}", file, e))?;
+        let mut plan = Self::parse(&text).map_err(|e| format_err!("{}: {}", file, e))?;
+        let dir = Path::new(file).parent().unwrap_or(Path::new(""));
+        let local = dir.join(&plan.prg);
+        if Path::new(&plan.prg).is_relative() && local.exists() {
+            plan.prg = local.to_string_lossy().into_owned();
+        }
+        for golden in plan.cases.iter_mut().filter_map(|c| c.golden.as_mut()) {
+            *golden = dir.join(&*golden).to_string_lossy().into_owned();
+        }
+        Ok(plan)
+    }
+    fn parse(text: &str) -> Result<Plan> {
+        let plan: Plan = toml::from_str(text)?;
+        if plan.cases.is_empty() {
+            bail!("The plan has no cases")
+        }
+        Ok(plan)
+    }
+}
+
+impl Case {
+    /// What's wrong with the screen, if anything.
+    pub fn check(&self, lines: &[String]) -> Vec<String> {
+        let found = |pattern: &str| lines.iter().any(|l| saves::matches(&format!("*{}*", pattern), l));
+        let missing = self.expect.iter().filter(|p| !found(p)).map(|p| format!("no {:?} on the screen", p));
+        let unwanted = self.reject.iter().filter(|p| found(p)).map(|p| format!("{:?} on the screen", p));
+        missing.chain(unwanted).collect()
+    }
+}
+
+/// The lines of text in screen memory, without trailing spaces, in
+/// capitals as the C64 shows them by default.
+pub fn screen_text(screen: &[u8]) -> Vec<String> {
+    screen.chunks(COLUMNS)
+        .map(|row| {
+            let pet: Vec<u8> = row.iter().map(|c| util::screen_to_pet(*c)).collect();
+            String::from_utf8_lossy(&util::pet_to_ascii(&pet)).trim_end().to_ascii_uppercase()
+        })
+        .collect()
+}
+
+// Runs each case of a test plan, reporting how it went
+pub fn run(c64u: &C64Ultimate, plan: &Plan, update: bool, port: u16, charset: Charset,
+        layout: Layout) -> Result<()> {
+    if !c64u.capabilities()?.memory_access {
+        return Err(target::unsupported(c64u, "Memory access"))
+    }
+    let video = match plan.cases.iter().any(|c| c.golden.is_some()) {
+        true => Some(std::net::UdpSocket::bind(("0.0.0.0", port)).map_err(|e| format_err!("UDP port {}: {}", port, e))?),
+        false => None,
+    };
+    let mut failed = 0;
+    for (i, case) in plan.cases.iter().enumerate() {
+        if case.reload || i == 0 {
+            c64u.load(&plan.prg, &[])?;
+        }
+        c64u.type_text(&petscii::keys(&case.keys, charset, layout)?)?;
+        thread::sleep(case.wait);
+        let lines = screen_text(&c64u.read(plan.screen, COLUMNS * ROWS)?);
+        let mut problems = case.check(&lines);
+        if let (Some(golden), Some(socket)) = (&case.golden, &video) {
+            c64u.stream_start(Stream::Video, &c64u.stream_dest(port)?)?;
+            let frame = frame::Image::receive(socket, Duration::from_secs(5));
+            c64u.stream_stop(Stream::Video)?;
+            let frame = frame?;
+            if update {
+                frame.save_png(golden)?;
+            } else if !Path::new(golden).exists() {
+                problems.push(format!("there is no {}; make it with --update", golden));
+            } else {
+                let differing = frame.differing(&frame::Image::load_png(golden)?)
+                    .map_err(|e| format_err!("{}: {}", golden, e))?;
+                if differing > case.tolerance {
+                    // The frame is kept next to the golden image to look at
+                    let actual = Path::new(golden).with_extension("actual.png").to_string_lossy().into_owned();
+                    frame.save_png(&actual)?;
+                    problems.push(format!("{:.2}% of the frame differs from {}; see {}", differing, golden, actual));
+                }
+            }
+        }
+        if problems.is_empty() {
+            println!("ok    {}", case.name);
+            continue
+        }
+        failed += 1;
+        println!("FAIL  {}: {}", case.name, problems.join(", "));
+        for line in &lines {
+            println!("      |{}", line);
+        }
+    }
+    println!("{} passed, {} failed", plan.cases.len() - failed, failed);
+    match failed {
+        0 => Ok(()),
+        _ => Err(ExitStatus(1).into()),
+    }
+}
+
+#[test]
+fn test_plans() {
+    let plan = Plan::parse("prg = \"a.prg\"\n[[case]]\nname = \"one\"\nexpect = [\"ready.\"]\nreject = [\"?syntax*\"]\n").unwrap();
+    let case = &plan.cases[0];
+    assert_eq!((plan.screen, case.wait, case.reload), (0x0400, Duration::from_secs(1), true));
+    // READY. and ?SYNTAX  ERROR in screen codes
+    let ready = [18, 5, 1, 4, 25, 46];
+    let error = [63, 19, 25, 14, 20, 1, 24, 32, 32, 5, 18, 18, 15, 18];
+    let mut screen = vec![32; COLUMNS * ROWS];
+    screen[..6].copy_from_slice(&ready);
+    let lines = screen_text(&screen);
+    assert_eq!((lines.len(), lines[0].as_str(), lines[1].as_str()), (ROWS, "READY.", ""));
+    assert!(case.check(&lines).is_empty());
+    screen[COLUMNS..COLUMNS + error.len()].copy_from_slice(&error);
+    assert_eq!(case.check(&screen_text(&screen)), ["\"?syntax*\" on the screen"]);
+    assert!(Plan::parse("prg = \"a.prg\"\ncase = []\n").is_err());
+}
@@ -0,0 +1,283 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+// Copyright (C) 2026 Brian Holdsworth
+
+//! Saved drive setups.
+//!
+//! A state file holds the assigns and mounts the daemon has, and the
+//! settings of the config file that change how idunsh behaves, so they
+//! can be applied again later:
+//!
+//! ```toml
+//! protected = ["e:"]
+//!
+//! [assigns]
+//! "e:" = "/home/idun/projects/goodies"
+//!
+//! [mounts]
+//! "d:" = "/home/idun/disks/work.d64"
+//!
+//! [config]
+//! yes = true
+//! charset = "lower"
+//! ```
+//!
+//! The drives are taken from the daemon's drives listing, leaving out
+//! those `~/.config/idunrc.toml` gives built-in paths, such as the home
+//! and system drives. A drive showing a disk image is a mount, any other
+//! an assign. `config` holds those of `TOGGLES` the config file sets;
+//! importing the state writes them to `config.d/state.toml` (see
+//! `config`).
+//!
+//! The daemon has no read-only assigns. idunsh keeps the drives assigned
+//! with `--protect` in `protected` in its state directory, one a line,
+//! and refuses to change them itself. That is advisory: the daemon isn't
+//! told, so the Commodore, and anything else using the daemon, still can.
+use std::collections::BTreeMap;
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+use std::result;
+use clap::Subcommand;
+use serde::{Deserialize, Serialize};
+use idun_client::client::Batch;
+use idun_client::listing::Mount;
+use crate::batch;
+use crate::config::Config;
+use crate::idun;
+use crate::store;
+
+// Simpler error handling
+type Result<T> = result::Result<T, failure::Error>;
+
+// idunrc device types and flags, as described in idunrc.toml
+const TYPE_FILESYSTEM: u8 = 4;
+const TYPE_VIRTUAL_DISK: u8 = 7;
+const FLAG_USER_PATH: u8 = 4;
+
+// The drives assigned with --protect, in the state directory
+const PROTECTED: &str = "protected";
+
+// What a drive showing a file with one of these extensions has mounted
+const IMAGE_TYPES: [&str; 8] = ["d64", "d71", "d81", "g64", "g71", "d80", "d82", "t64"];
+
+/// The settings of the config file a state keeps.
+pub const TOGGLES: [&str; 4] = ["yes", "charset", "keyboard", "output_timeout"];
+
+#[derive(Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct State {
+    /// Drives assigned to a directory
+    pub assigns: BTreeMap<String, String>,
+    /// Drives with a disk image mounted
+    pub mounts: BTreeMap<String, String>,
+    /// Assigned drives idunsh won't change
+    #[serde(skip_serializing_if = "Vec::is_empty", alias = "read_only")]
+    pub protected: Vec<String>,
+    /// Settings of the config file, as in `TOGGLES`
+    #[serde(skip_serializing_if = "toml::Table::is_empty")]
+    pub config: toml::Table,
+}
+
+#[derive(Deserialize)]
+struct Idunrc {
+    devices: Vec<DeviceConfig>,
+}
+
+#[derive(Deserialize)]
+struct DeviceConfig {
+    id: String,
+    #[serde(rename = "type")]
+    dtype: u8,
+    #[serde(default)]
+    flags: u8,
+    path: Option<String>,
+}
+
+impl State {
+    /// Where the cartridge keeps its configuration.
+    pub fn idunrc_path() -> Option<PathBuf> {
+        dirs::config_dir().map(|d| d.join("idunrc.toml"))
+    }
+    /// Reads the state the cartridge starts up with from its
+    /// configuration. Drives with built-in paths, such as the home and
+    /// system drives, are left out.
+    pub fn current() -> Result<State> {
+        let path = Self::idunrc_path().ok_or_else(|| format_err!("No config directory"))?;
+        let text = fs::read_to_string(&path)
+            .map_err(|e| format_err!("{}: {}", path.display(), e))?;
+        let mut state = Self::from_idunrc(&text).map_err(|e| format_err!("{}: {}", path.display(), e))?;
+        state.protected = protected()?.into_iter().filter(|d| state.assigns.contains_key(d)).collect();
+        Ok(state)
+    }
+    /// The state the daemon is in now, given its `drives`, with the
+    /// settings of the config file.
+    pub fn live(drives: &[Mount]) -> Result<State> {
+        let builtin = match Self::idunrc_path().map(fs::read_to_string) {
+            Some(Ok(text)) => Self::builtin(&text)?,
+            _ => vec![],
+        };
+        let mut state = Self::from_drives(drives, &builtin);
+        state.protected = protected()?.into_iter().filter(|d| state.assigns.contains_key(d)).collect();
+        if let Some(path) = Config::path().filter(|p| p.exists()) {
+            let text = fs::read_to_string(&path)?;
+            let table: toml::Table = toml::from_str(&text).map_err(|e| format_err!("{}: {}", path.display(), e))?;
+            state.config = table.into_iter().filter(|(k, _)| TOGGLES.contains(&k.as_str())).collect();
+        }
+        Ok(state)
+    }
+    fn from_drives(drives: &[Mount], builtin: &[String]) -> State {
+        let mut state = State::default();
+        for Mount { device, target } in drives.iter().filter(|m| !builtin.contains(&m.device)) {
+            let ext = PathBuf::from(target.to_lowercase()).extension().map(|e| e.to_string_lossy().into_owned());
+            match ext {
+                Some(ext) if IMAGE_TYPES.contains(&ext.as_str()) => state.mounts.insert(device.clone(), target.clone()),
+                _ => state.assigns.insert(device.clone(), target.clone()),
+            };
+        }
+        state
+    }
+    // The drives the cartridge configuration gives paths of its own
+    fn builtin(text: &str) -> Result<Vec<String>> {
+        let rc: Idunrc = toml::from_str(text)?;
+        Ok(rc.devices.into_iter()
+            .filter(|dev| match dev.dtype {
+                TYPE_VIRTUAL_DISK => false,
+                TYPE_FILESYSTEM => dev.flags & FLAG_USER_PATH == 0,
+                _ => true,
+            })
+            .map(|dev| format!("{}:", dev.id.to_lowercase()))
+            .collect())
+    }
+    fn from_idunrc(text: &str) -> Result<State> {
+        let rc: Idunrc = toml::from_str(text)?;
+        let mut state = State::default();
+        for dev in rc.devices {
+            let (Some(path), name) = (dev.path, format!("{}:", dev.id.to_lowercase())) else {
+                continue;
+            };
+            match dev.dtype {
+                TYPE_FILESYSTEM if dev.flags & FLAG_USER_PATH != 0 => {
+                    state.assigns.insert(name, path);
+                }
+                TYPE_VIRTUAL_DISK => {
+                    state.mounts.insert(name, path);
+                }
+                _ => (),
+            }
+        }
+        Ok(state)
+    }
+    pub fn load(file: &str) -> Result<State> {
+        let text = fs::read_to_string(file)?;
+        toml::from_str(&text).map_err(|e| format_err!("{}: {}", file, e))
+    }
+    pub fn save(&self, file: &str) -> Result<()> {
+        fs::write(file, toml::to_string(self)?)?;
+        Ok(())
+    }
+    /// Writes the settings in `config` to `config.d/state.toml`, where
+    /// they replace the config file's; without any, removes that file.
+    pub fn save_config(&self) -> Result<()> {
+        let dir = Config::fragment_dir().ok_or_else(|| format_err!("No config directory"))?;
+        let path = dir.join("state.toml");
+        if self.config.is_empty() {
+            return match fs::remove_file(&path) {
+                Err(e) if e.kind() != io::ErrorKind::NotFound => bail!("{}: {}", path.display(), e),
+                _ => Ok(()),
+            }
+        }
+        if let Some(key) = self.config.keys().find(|k| !TOGGLES.contains(&k.as_str())) {
+            bail!("{} can't be set by a state; only {} can", key, TOGGLES.join(", "))
+        }
+        fs::create_dir_all(&dir)?;
+        store::write(&path, toml::to_string(&self.config)?.as_bytes())
+    }
+}
+
+/// The drives assigned with `--protect`.
+pub fn protected() -> Result<Vec<String>> {
+    let path = store::dir()?.join(PROTECTED);
+    match fs::read_to_string(&path) {
+        Ok(text) => Ok(text.lines().map(String::from).collect()),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(vec![]),
+        Err(e) => bail!("{}: {}", path.display(), e),
+    }
+}
+
+/// Records whether `dev` was last assigned with `--protect`; a mount or a
+/// plain assign makes it writable again.
+pub fn set_protected(dev: &str, protect: bool) -> Result<()> {
+    let mut devs = protected()?;
+    if devs.iter().any(|d| d == dev) == protect {
+        return Ok(())
+    }
+    devs.retain(|d| d != dev);
+    if protect {
+        devs.push(dev.to_string());
+    }
+    let text: String = devs.iter().map(|d| format!("{}\n", d)).collect();
+    store::write(&store::dir()?.join(PROTECTED), text.as_bytes())
+}
+
+/// Refuses a change to `dev` if it was assigned with `--protect`.
+pub fn check_writable(dev: &str) -> Result<()> {
+    if protected()?.iter().any(|d| d.eq_ignore_ascii_case(dev)) {
+        bail!("{} is protected; assign it again without --protect to change it", dev)
+    }
+    Ok(())
+}
+
+#[derive(Subcommand)]
+pub enum StateCommands {
+    /// Write the daemon's assigns and mounts, and the config's toggles
+    /// such as yes and charset, to a file
+    Export { file: String },
+    /// Assign and mount the drives listed in a file, and set its toggles
+    Import { file: String },
+}
+
+pub fn run(cmd: StateCommands) -> Result<()> {
+    match cmd {
+        StateCommands::Export { file } => State::live(&idun().drives()?)?.save(&file),
+        StateCommands::Import { file } => {
+            let state = State::load(&file)?;
+            state.save_config()?;
+            apply(&state)
+        },
+    }
+}
+
+// Sets up the drives of a state, as one batch where the daemon can
+pub fn apply(state: &State) -> Result<()> {
+    let assigns = state.assigns.iter()
+        .fold(Batch::new(), |b, (dev, path)| b.assign(dev, path));
+    batch(state.mounts.iter().fold(assigns, |b, (dev, image)| b.mount(dev, image)))?;
+    for dev in state.assigns.keys().chain(state.mounts.keys()) {
+        set_protected(dev, state.protected.contains(dev))?;
+    }
+    Ok(())
+}
+
+#[test]
+fn state_from_idunrc() {
+    let rc = r#"devices = [
+        {id="C", type=4, addr=10, cmd=31, flags=57},
+        {id="D", type=7, addr=8, cmd=31, flags=56, path="/home/idun/work.d64"},
+        {id="E", type=4, addr=10, cmd=31, flags=60, path="/home/idun/apps"},
+        {id="Z", type=4, addr=0, cmd=31, flags=2, path="sys"},
+        {id="[", type=8, addr=0, cmd=0, flags=4, path="$XDG_RUNTIME_DIR"},
+    ]"#;
+    let state = State::from_idunrc(rc).unwrap();
+    assert_eq!(state.assigns.into_iter().collect::<Vec<_>>(), [("e:".to_string(), "/home/idun/apps".to_string())]);
+    assert_eq!(state.mounts["d:"], "/home/idun/work.d64");
+    assert!(state.protected.is_empty());
+    let builtin = State::builtin(rc).unwrap();
+    assert_eq!(builtin, ["c:", "z:", "[:"]);
+    let drives = Mount::parse_all("C:=/home/idun\rD:=/home/idun/Work.D64\rE:=/home/idun/apps.d\rF:=/tmp\r");
+    let live = State::from_drives(&drives, &builtin);
+    assert_eq!(live.mounts.keys().collect::<Vec<_>>(), ["d:"]);
+    assert_eq!(live.assigns.keys().collect::<Vec<_>>(), ["e:", "f:"]);
+    // State files from before --protect still load
+    let old: State = toml::from_str("read_only = [\"e:\"]").unwrap();
+    assert_eq!(old.protected, ["e:"]);
+}
@@ -0,0 +1,156 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+// Copyright (C) 2026 Brian Holdsworth
+
+//! One-line summaries for status bars such as polybar or tmux, from
+//! templates like `{backend} {running|idle} {drive8}`.
+//!
+//! Names in braces are replaced by their values. Text after a `|` is
+//! shown instead when the value is empty. `{{` and `}}` stand for the
+//! braces themselves.
+use std::io::{Write, stdout};
+use std::result;
+use std::thread;
+use std::time::Duration;
+use idun_client::client::DRIVES_CMD;
+use idun_client::listing::Mount;
+use crate::c64ultimate::{C64Ultimate, NamedDevice};
+use crate::capture_shell;
+use crate::daemon_reachable;
+use crate::running_program;
+
+// Simpler error handling
+type Result<T> = result::Result<T, failure::Error>;
+
+#[derive(Debug, PartialEq, Eq)]
+enum Part {
+    Text(String),
+    Field { name: String, fallback: String },
+}
+
+/// A parsed status line template.
+#[derive(Debug, PartialEq, Eq)]
+pub struct Template(Vec<Part>);
+
+impl Template {
+    pub fn parse(text: &str) -> Result<Template> {
+        let mut parts = vec![];
+        let mut literal = String::new();
+        let mut rest = text;
+        while let Some(i) = rest.find(['{', '}']) {
+            literal.push_str(&rest[..i]);
+            let after = &rest[i + 1..];
+            match (&rest[i..i + 1], after.chars().next()) {
+                ("{", Some('{')) | ("}", Some('}')) => {
+                    literal.push_str(&rest[i..i + 1]);
+                    rest = &after[1..];
+                },
+                ("{", _) => {
+                    let (field, after) = after.split_once('}')
+                        .ok_or_else(|| format_err!("{:?} has an unclosed {{", text))?;
+                    let (name, fallback) = field.split_once('|').unwrap_or((field, ""));
+                    if name.is_empty() {
+                        bail!("{:?} has a field without a name", text)
+                    }
+                    parts.push(Part::Text(std::mem::take(&mut literal)));
+                    parts.push(Part::Field { name: name.to_string(), fallback: fallback.to_string() });
+                    rest = after;
+                },
+                _ => bail!("{:?} has a }} without a {{; write }}}} for a brace", text),
+            }
+        }
+        literal.push_str(rest);
+        parts.push(Part::Text(literal));
+        parts.retain(|p| *p != Part::Text(String::new()));
+        Ok(Template(parts))
+    }
+    /// The line with each field replaced by what `value` gives for its
+    /// name.
+    pub fn render<F>(&self, mut value: F) -> Result<String>
+    where F: FnMut(&str) -> Result<String> {
+        let mut line = String::new();
+        for part in &self.0 {
+            match part {
+                Part::Text(text) => line.push_str(text),
+                Part::Field { name, fallback } => match value(name)? {
+                    v if v.is_empty() => line.push_str(fallback),
+                    v => line.push_str(&v),
+                },
+            }
+        }
+        Ok(line)
+    }
+}
+
+// Prints the status line `format` once, or every `interval`. A backend
+// that can't be reached is shown as offline rather than failing.
+pub fn run(format: &str, interval: Option<Duration>, c64u: Option<&C64Ultimate>) -> Result<()> {
+    let template = Template::parse(format)?;
+    loop {
+        println!("{}", line(&template, c64u)?);
+        stdout().flush()?;
+        match interval {
+            Some(interval) => thread::sleep(interval),
+            None => return Ok(()),
+        }
+    }
+}
+
+fn line(template: &Template, c64u: Option<&C64Ultimate>) -> Result<String> {
+    let online = match c64u {
+        Some(c64u) => c64u.version().is_ok(),
+        None => daemon_reachable(),
+    };
+    // The drives by device name, and by bus id on the C64U, with what
+    // each has mounted; only fetched if the template names one
+    let mut drives: Option<Vec<(String, String)>> = None;
+    template.render(|name| {
+        let dev = match name {
+            "backend" => return Ok(match (online, c64u) {
+                (false, _) => "offline",
+                (true, Some(_)) => "c64u",
+                (true, None) => "idun",
+            }.to_string()),
+            // The C64U doesn't tell what it runs
+            "running" if online && c64u.is_none() => return Ok(running_program().unwrap_or_default()),
+            "running" => return Ok(String::new()),
+            _ => name.strip_prefix("drive").filter(|d| !d.is_empty())
+                .ok_or_else(|| format_err!("Unknown status field {{{}}}; try backend, running or a drive, e.g. drive8", name))?,
+        };
+        if !online {
+            return Ok(String::new())
+        }
+        if drives.is_none() {
+            drives = Some(match c64u {
+                Some(c64u) => c64u.getdrv(&None)
+                    .map_err(|e| format_err!("C64 Ultimate drive settings Error: {}", e))?
+                    .drives.into_iter()
+                    .filter(|d| d.slot.is_floppy() && d.device.enabled)
+                    .flat_map(|NamedDevice { slot, device }| {
+                        let image = device.mounted_image().unwrap_or_default();
+                        [(slot.name().to_string(), image.clone()), (device.bus_id.to_string(), image)]
+                    })
+                    .collect(),
+                None => Mount::parse_all(&String::from(capture_shell(DRIVES_CMD, "")?)).into_iter()
+                    .map(|m| (m.device.trim_end_matches(':').to_string(), m.target))
+                    .collect(),
+            });
+        }
+        let target = drives.iter().flatten().find(|(d, _)| d.eq_ignore_ascii_case(dev)).map(|(_, t)| t.as_str());
+        Ok(target.unwrap_or_default().rsplit('/').next().unwrap_or_default().to_string())
+    })
+}
+
+#[test]
+fn status_templates() {
+    let template = Template::parse("{backend} {running|idle} {{8}}: {drive8}").unwrap();
+    let line = template.render(|name| Ok(match name {
+        "backend" => "idun".to_string(),
+        "drive8" => "game.d64".to_string(),
+        _ => String::new(),
+    })).unwrap();
+    assert_eq!(line, "idun idle {8}: game.d64");
+    assert!(template.render(|_| Err(format_err!("no"))).is_err());
+    assert!(Template::parse("{backend").is_err());
+    assert!(Template::parse("a } b").is_err());
+    assert!(Template::parse("{}").is_err());
+}
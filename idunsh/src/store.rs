@@ -0,0 +1,145 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+// Copyright (C) 2026 Brian Holdsworth
+
+//! The directory idunsh keeps its state in, `~/.local/share/idunsh`.
+//!
+//! ```text
+//! version          the number of the layout below
+//! queue            calls waiting for the daemon, one a line; see `queue`
+//! pkg.toml         the packages installed; see `pkg`
+//! journal/         changes to assigned directories, a log a drive
+//! saves/           snapshots of drives, a directory a drive
+//! transfers/       puts under way; see `transfers`
+//! packs/           packs unpacked
+//! collection.json  the index of a collection; see `collection`
+//! playing.json     what was loaded last; see `obs`
+//! protected        drives assigned with --protect; see `state`
+//! ```
+//!
+//! The first run of an idunsh with a newer layout brings the directory
+//! up to it, one version at a time, under a lock so two runs don't both
+//! do it. An idunsh older than the directory refuses it rather than
+//! spoil it. A directory without `version` is from before there was
+//! one, version 0.
+//!
+//! Files are replaced whole with `write`, which writes a temporary file
+//! next to the file and renames it over the file once it's on disk, so
+//! a run cut short leaves the old contents or the new, never a mix.
+use std::fs::{self, File};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::process;
+use std::result;
+use crate::lock::FileLock;
+
+// Simpler error handling
+type Result<T> = result::Result<T, failure::Error>;
+
+/// The layout this idunsh keeps its files in.
+pub const VERSION: u32 = 1;
+
+// What brings a directory from version n to n + 1, at MIGRATIONS[n]
+const MIGRATIONS: [fn(&Path) -> Result<()>; VERSION as usize] = [drop_temporaries];
+
+/// The state directory, created or brought up to date if need be.
+pub fn dir() -> Result<PathBuf> {
+    let dir = dirs::data_dir()
+        .ok_or_else(|| format_err!("No data directory"))?
+        .join("idunsh");
+    open(&dir)?;
+    Ok(dir)
+}
+
+/// A directory within the state directory, such as "journal".
+pub fn subdir(name: &str) -> Result<PathBuf> {
+    let dir = dir()?.join(name);
+    fs::create_dir_all(&dir)?;
+    Ok(dir)
+}
+
+fn open(dir: &Path) -> Result<()> {
+    let path = dir.join("version");
+    if read_version(&path)? == Some(VERSION) {
+        return Ok(())
+    }
+    fs::create_dir_all(dir)?;
+    let _lock = FileLock::exclusive(&dir.join("version.lock"))?;
+    // Another run may have done it while we waited
+    let mut version = read_version(&path)?.unwrap_or(0);
+    if version > VERSION {
+        bail!("{} is from a newer idunsh (version {}, this one knows {}); update idunsh",
+            dir.display(), version, VERSION)
+    }
+    while version < VERSION {
+        MIGRATIONS[version as usize](dir)
+            .map_err(|e| format_err!("Updating {} from version {}: {}", dir.display(), version, e))?;
+        version += 1;
+        write(&path, format!("{}\n", version).as_bytes())?;
+    }
+    Ok(())
+}
+
+fn read_version(path: &Path) -> Result<Option<u32>> {
+    match fs::read_to_string(path) {
+        Ok(text) => Ok(Some(text.trim().parse()
+            .map_err(|_| format_err!("{} doesn't hold a version: {:?}", path.display(), text.trim()))?)),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+        Err(e) => bail!("{}: {}", path.display(), e),
+    }
+}
+
+/// Replaces the file at `path` with `data`, all at once.
+pub fn write(path: &Path, data: &[u8]) -> Result<()> {
+    let name = path.file_name().ok_or_else(|| format_err!("{} isn't a file", path.display()))?;
+    let tmp = path.with_file_name(format!(".{}.tmp-{}", name.to_string_lossy(), process::id()));
+    let written = File::create(&tmp)
+        .and_then(|mut f| f.write_all(data).and_then(|_| f.sync_all()))
+        .and_then(|_| fs::rename(&tmp, path));
+    if let Err(e) = written {
+        let _ = fs::remove_file(&tmp);
+        bail!("{}: {}", path.display(), e)
+    }
+    Ok(())
+}
+
+// 0 to 1: the records of puts whose writing was cut short, left as
+// transfers/<name>.tmp before there was `write`; nothing else cleans
+// them up. saves/ is left alone: a snapshot's temporary directory may
+// belong to a backup still under way.
+fn drop_temporaries(dir: &Path) -> Result<()> {
+    let entries = match fs::read_dir(dir.join("transfers")) {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(()),
+        Err(e) => return Err(e.into()),
+    };
+    for entry in entries {
+        let entry = entry?;
+        let path = entry.path();
+        if entry.file_type()?.is_file() && path.extension().is_some_and(|e| e == "tmp") {
+            fs::remove_file(path)?;
+        }
+    }
+    Ok(())
+}
+
+#[test]
+fn state_versions() {
+    let dir = std::env::temp_dir().join(format!("idunsh-store-{}", process::id()));
+    fs::create_dir_all(dir.join("saves/c/.tmp-123")).unwrap();
+    fs::create_dir_all(dir.join("transfers")).unwrap();
+    fs::write(dir.join("queue"), "sys.keys(\"\")\n").unwrap();
+    fs::write(dir.join("transfers/1-2-0.tmp"), "{").unwrap();
+    open(&dir).unwrap();
+    assert_eq!(fs::read_to_string(dir.join("version")).unwrap(), "1\n");
+    assert!(dir.join("saves/c/.tmp-123").exists() && !dir.join("transfers/1-2-0.tmp").exists());
+    assert!(dir.join("queue").exists());
+    write(&dir.join("queue"), b"").unwrap();
+    assert_eq!(fs::read(dir.join("queue")).unwrap(), b"");
+    fs::write(dir.join("version"), "7\n").unwrap();
+    assert!(open(&dir).unwrap_err().to_string().contains("newer idunsh"));
+    // Only a missing version is version 0
+    fs::remove_file(dir.join("version")).unwrap();
+    fs::create_dir(dir.join("version")).unwrap();
+    assert!(open(&dir).is_err());
+    fs::remove_dir_all(&dir).unwrap();
+}
@@ -0,0 +1,222 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+// Copyright (C) 2026 Brian Holdsworth
+
+//! Conversion between TAP datasette images and WAV audio.
+//!
+//! A TAP holds the length of each pulse read from the tape, in units of
+//! 8 clock cycles. Version 1 files write pulses too long for a byte as a
+//! zero followed by the length in cycles in three bytes. As audio, each
+//! pulse is one period of a square wave.
+use std::fs;
+use std::result;
+use clap::Subcommand;
+use crate::formats::{self, FileInfo};
+
+// Simpler error handling
+type Result<T> = result::Result<T, failure::Error>;
+
+const TAP_MAGIC: &[u8] = b"C64-TAPE-RAW";
+const TAP_HEADER: usize = 20;
+/// PAL C64 clock, which TAP pulse lengths count
+const CLOCK: u64 = 985248;
+
+/// Pulse lengths in clock cycles.
+pub fn parse_tap(data: &[u8]) -> Result<Vec<u32>> {
+    if data.len() < TAP_HEADER || !data.starts_with(TAP_MAGIC) {
+        bail!("Not a TAP file")
+    }
+    let version = data[12];
+    let mut pulses = vec![];
+    let mut rest = &data[TAP_HEADER..];
+    while let Some((&b, r)) = rest.split_first() {
+        rest = r;
+        match (b, version) {
+            (0, 0) => pulses.push(256 * 8),
+            (0, _) if rest.len() >= 3 => {
+                pulses.push(u32::from_le_bytes([rest[0], rest[1], rest[2], 0]));
+                rest = &rest[3..];
+            },
+            (0, _) => bail!("TAP file ends inside a long pulse"),
+            (b, _) => pulses.push(b as u32 * 8),
+        }
+    }
+    Ok(pulses)
+}
+
+/// A version 1 TAP file of the pulses.
+pub fn write_tap(pulses: &[u32]) -> Vec<u8> {
+    let mut body = vec![];
+    for &p in pulses {
+        match (p + 4) / 8 {
+            0 => (),
+            n if n < 256 => body.push(n as u8),
+            _ => {
+                body.push(0);
+                body.extend_from_slice(&p.min(0xffffff).to_le_bytes()[..3]);
+            },
+        }
+    }
+    let mut tap = TAP_MAGIC.to_vec();
+    tap.extend_from_slice(&[1, 0, 0, 0]);
+    tap.extend_from_slice(&(body.len() as u32).to_le_bytes());
+    tap.extend(body);
+    tap
+}
+
+/// Renders pulses as 8 bit mono PCM: low for the first half of each
+/// pulse, high for the second. The datasette reads a pulse from one
+/// falling edge to the next, so the audio starts high and ends low to
+/// frame the first and last pulse.
+pub fn pulses_to_pcm(pulses: &[u32], rate: u32) -> Vec<u8> {
+    const LOW: u8 = 0x20;
+    const HIGH: u8 = 0xe0;
+    let mut pcm = vec![HIGH];
+    let mut cycles = 0u64;
+    for &p in pulses {
+        for (half, level) in [(p / 2, LOW), (p - p / 2, HIGH)] {
+            cycles += half as u64;
+            let end = 1 + (cycles * rate as u64 / CLOCK) as usize;
+            pcm.resize(end.max(pcm.len()), level);
+        }
+    }
+    pcm.push(LOW);
+    pcm
+}
+
+/// Measures pulses in audio samples scaled to -1.0..1.0: a pulse runs
+/// from one fall through `-threshold` to the next. The level must rise
+/// above `threshold` in between, which keeps noise from splitting a
+/// pulse.
+pub fn pcm_to_pulses(samples: &[f32], rate: u32, threshold: f32) -> Vec<u32> {
+    let mut pulses = vec![];
+    let mut last_fall: Option<usize> = None;
+    let mut high = false;
+    for (i, &s) in samples.iter().enumerate() {
+        if s > threshold {
+            high = true;
+        } else if high && s < -threshold {
+            high = false;
+            if let Some(start) = last_fall {
+                pulses.push(((i - start) as u64 * CLOCK / rate as u64) as u32);
+            }
+            last_fall = Some(i);
+        }
+    }
+    pulses
+}
+
+/// Audio read from a WAV file, as the first channel scaled to -1.0..1.0.
+pub struct Wav {
+    pub rate: u32,
+    pub samples: Vec<f32>,
+}
+
+impl Wav {
+    /// Reads 8 or 16 bit PCM.
+    pub fn parse(data: &[u8]) -> Result<Wav> {
+        if data.len() < 12 || &data[..4] != b"RIFF" || &data[8..12] != b"WAVE" {
+            bail!("Not a WAV file")
+        }
+        let mut format = None;
+        let mut rest = &data[12..];
+        while rest.len() >= 8 {
+            let id = &rest[..4];
+            let len = u32::from_le_bytes([rest[4], rest[5], rest[6], rest[7]]) as usize;
+            let body = &rest[8..(8 + len).min(rest.len())];
+            match id {
+                b"fmt " if body.len() >= 16 => {
+                    let tag = u16::from_le_bytes([body[0], body[1]]);
+                    let channels = u16::from_le_bytes([body[2], body[3]]) as usize;
+                    let rate = u32::from_le_bytes([body[4], body[5], body[6], body[7]]);
+                    let bits = u16::from_le_bytes([body[14], body[15]]);
+                    if tag != 1 || !(bits == 8 || bits == 16) || channels == 0 {
+                        bail!("Only 8 and 16 bit PCM WAV files can be read")
+                    }
+                    format = Some((channels, rate, bits));
+                },
+                b"data" => {
+                    let (channels, rate, bits) = format.ok_or_else(|| format_err!("WAV data before its format"))?;
+                    let width = bits as usize / 8;
+                    let samples = body.chunks_exact(width * channels)
+                        .map(|frame| match width {
+                            1 => (frame[0] as f32 - 128.0) / 128.0,
+                            _ => i16::from_le_bytes([frame[0], frame[1]]) as f32 / 32768.0,
+                        })
+                        .collect();
+                    return Ok(Wav { rate, samples })
+                },
+                _ => (),
+            }
+            // Chunks are padded to an even length
+            rest = &rest[(8 + len + len % 2).min(rest.len())..];
+        }
+        bail!("WAV file has no audio data")
+    }
+}
+
+/// An 8 bit mono WAV file of the samples.
+pub fn write_wav(pcm: &[u8], rate: u32) -> Vec<u8> {
+    let mut wav = b"RIFF\0\0\0\0WAVEfmt ".to_vec();
+    wav.extend_from_slice(&16u32.to_le_bytes());
+    wav.extend_from_slice(&1u16.to_le_bytes());     // PCM
+    wav.extend_from_slice(&1u16.to_le_bytes());     // mono
+    wav.extend_from_slice(&rate.to_le_bytes());
+    wav.extend_from_slice(&rate.to_le_bytes());     // bytes per second
+    wav.extend_from_slice(&1u16.to_le_bytes());     // bytes per frame
+    wav.extend_from_slice(&8u16.to_le_bytes());
+    wav.extend_from_slice(b"data");
+    wav.extend_from_slice(&(pcm.len() as u32).to_le_bytes());
+    wav.extend_from_slice(pcm);
+    if pcm.len() % 2 == 1 {
+        wav.push(0);
+    }
+    let riff_len = (wav.len() as u32 - 8).to_le_bytes();
+    wav[4..8].copy_from_slice(&riff_len);
+    wav
+}
+
+#[derive(Subcommand)]
+pub enum TapeCommands {
+    /// Convert in.tap to out.wav, or in.wav to out.tap
+    Convert {
+        input: String,
+        output: String,
+        #[arg(long, default_value_t=44100, value_name="hz")]
+        /// Sample rate of the WAV written
+        rate: u32,
+        #[arg(long, default_value_t=10, value_parser=clap::value_parser!(u8).range(0..100), value_name="percent")]
+        /// Level, as a percentage of full scale, the audio must cross to count as an edge
+        threshold: u8,
+    },
+}
+
+pub fn run(cmd: TapeCommands) -> Result<()> {
+    let TapeCommands::Convert { input, output, rate, threshold } = cmd;
+    let data = fs::read(&input).map_err(|e| format_err!("{}: {}", input, e))?;
+    let converted = match FileInfo::identify(&input, &data).format {
+        formats::Format::Tap => write_wav(&pulses_to_pcm(&parse_tap(&data)?, rate), rate),
+        _ if data.starts_with(b"RIFF") => {
+            let wav = Wav::parse(&data)?;
+            write_tap(&pcm_to_pulses(&wav.samples, wav.rate, threshold as f32 / 100.0))
+        },
+        _ => bail!("{} is neither a TAP nor a WAV file", input),
+    };
+    fs::write(&output, converted).map_err(|e| format_err!("{}: {}", output, e))?;
+    Ok(())
+}
+
+#[test]
+fn tap_wav_round_trip() {
+    let pulses = vec![384, 384, 528, 688, 3_000_000];
+    let tap = write_tap(&pulses);
+    assert_eq!(parse_tap(&tap).unwrap(), pulses);
+
+    let rate = 44100;
+    let wav = Wav::parse(&write_wav(&pulses_to_pcm(&pulses, rate), rate)).unwrap();
+    // Lengths are only as exact as the sample rate allows
+    let measured = pcm_to_pulses(&wav.samples, rate, 0.1);
+    assert_eq!(measured.len(), pulses.len());
+    for (m, p) in measured.iter().zip(&pulses) {
+        assert!(m.abs_diff(*p) <= (CLOCK / rate as u64) as u32, "{} vs {}", m, p);
+    }
+}
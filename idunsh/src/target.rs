@@ -0,0 +1,169 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+// Copyright (C) 2026 Brian Holdsworth
+
+//! What each backend can do.
+//!
+//! idunsh drives either the idun cartridge, through its daemon, or a
+//! C64 Ultimate, through its web service. Commands check the backend's
+//! capabilities first, so a missing feature gets a precise error instead
+//! of a failed request.
+use std::path::Path;
+use std::result;
+
+// Simpler error handling
+type Result<T> = result::Result<T, failure::Error>;
+
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Capabilities {
+    /// Disk image extensions that can be mounted, or None if the backend
+    /// leaves that to the drive
+    pub mount_types: Option<Vec<&'static str>>,
+    /// Largest file that can be sent, or None if there's no known limit
+    pub max_transfer: Option<u64>,
+    /// Reading and writing C64 memory
+    pub memory_access: bool,
+    /// Completion events, as used by `--wait`
+    pub events: bool,
+    /// CMD-style subdirectories and partitions, as made by `mkdir`
+    pub subdirectories: bool,
+}
+
+impl Capabilities {
+    pub fn can_mount(&self, image: &str) -> bool {
+        let ext = Path::new(image).extension()
+            .and_then(|e| e.to_str())
+            .map(|e| e.to_lowercase());
+        match (&self.mount_types, ext) {
+            (None, _) => true,
+            (Some(types), Some(ext)) => types.contains(&ext.as_str()),
+            (Some(_), None) => false,
+        }
+    }
+}
+
+pub trait Target {
+    /// The backend name used in messages
+    fn name(&self) -> &str;
+    fn capabilities(&self) -> Result<Capabilities>;
+}
+
+/// C64 memory, for backends with `memory_access`.
+pub trait Memory: Target {
+    fn read(&self, addr: u16, len: usize) -> Result<Vec<u8>>;
+    fn write(&self, addr: u16, data: &[u8]) -> Result<()>;
+    /// Starts the machine code at `addr`, the way SYS does.
+    fn call(&self, addr: u16) -> Result<()>;
+    /// Breakpoints and the CPU's registers, for backends that have them.
+    fn debugger(&self) -> Option<&dyn Debugger> {
+        None
+    }
+    /// Stops the CPU until `thaw`, so a program can't run with memory
+    /// half changed. Gives false for backends that can't.
+    fn freeze(&self) -> Result<bool> {
+        Ok(false)
+    }
+    fn thaw(&self) -> Result<()> {
+        Ok(())
+    }
+}
+
+/// The 6502's registers.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Registers {
+    pub pc: u16,
+    pub a: u8,
+    pub x: u8,
+    pub y: u8,
+    pub sp: u8,
+    pub flags: u8,
+}
+
+pub trait Debugger {
+    /// Stops the CPU before it runs `addr`, giving the breakpoint's number.
+    fn set_breakpoint(&self, addr: u16) -> Result<u32>;
+    /// Lets the CPU run until it stops again, giving its registers then.
+    fn resume(&self) -> Result<Registers>;
+    fn registers(&self) -> Result<Registers>;
+}
+
+/// The error for something `target` can't do, e.g. "Memory access is
+/// not supported by the C64 Ultimate backend".
+pub fn unsupported(target: &dyn Target, what: &str) -> failure::Error {
+    format_err!("{} is not supported by the {} backend", what, target.name())
+}
+
+/// The idun cartridge, reached through the daemon's Lua socket.
+pub struct Idun;
+
+impl Target for Idun {
+    fn name(&self) -> &str {
+        "idun"
+    }
+    /// What the daemon says it has, asked once per connection. Mounting
+    /// is checked by the daemon.
+    fn capabilities(&self) -> Result<Capabilities> {
+        Ok(Capabilities {
+            mount_types: None,
+            max_transfer: None,
+            memory_access: false,
+            // Not asked: a daemon without "events" has its state polled
+            // instead (see `events`), so every daemon has them
+            events: true,
+            subdirectories: crate::idun().has("subdirectories")?,
+        })
+    }
+}
+
+/// A C64 with nothing but its 64K of RAM, for testing what reads and
+/// writes memory.
+#[cfg(test)]
+pub struct Ram(pub std::sync::Mutex<Vec<u8>>);
+
+#[cfg(test)]
+impl Ram {
+    pub fn new() -> Ram {
+        Ram(std::sync::Mutex::new(vec![0; 0x10000]))
+    }
+}
+
+#[cfg(test)]
+impl Target for Ram {
+    fn name(&self) -> &str {
+        "RAM"
+    }
+    fn capabilities(&self) -> Result<Capabilities> {
+        Ok(Capabilities { memory_access: true, ..Default::default() })
+    }
+}
+
+#[cfg(test)]
+impl Memory for Ram {
+    fn read(&self, addr: u16, len: usize) -> Result<Vec<u8>> {
+        let ram = self.0.lock().unwrap();
+        match ram.get(addr as usize..addr as usize + len) {
+            Some(data) => Ok(data.to_vec()),
+            None => bail!("Reading {} bytes at ${:04x} runs past the end of memory", len, addr),
+        }
+    }
+    fn write(&self, addr: u16, data: &[u8]) -> Result<()> {
+        let mut ram = self.0.lock().unwrap();
+        match ram.get_mut(addr as usize..addr as usize + data.len()) {
+            Some(dest) => {
+                dest.copy_from_slice(data);
+                Ok(())
+            },
+            None => bail!("Writing {} bytes at ${:04x} runs past the end of memory", data.len(), addr),
+        }
+    }
+    fn call(&self, addr: u16) -> Result<()> {
+        bail!("There's no CPU to run ${:04x}", addr)
+    }
+}
+
+#[test]
+fn mount_types() {
+    let caps = Capabilities { mount_types: Some(vec!["d64", "d81"]), ..Default::default() };
+    assert!(caps.can_mount("Game.D64"));
+    assert!(!caps.can_mount("game.t64") && !caps.can_mount("game"));
+    assert!(Capabilities::default().can_mount("game.t64"));
+}
@@ -0,0 +1,160 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+// Copyright (C) 2026 Brian Holdsworth
+
+//! The Commodore's text screen in this terminal, for `idunsh term`.
+//!
+//! Screen memory is read a few times a second and the rows that changed
+//! are drawn again, reverse characters in reverse video. Keys pressed
+//! here go into the KERNAL's keyboard buffer at $0277, its count at
+//! $c6, once the C64 has taken those before them: letters, digits and
+//! signs as PETSCII, Return, Delete, Home, the cursor keys, and Ctrl-C
+//! as RUN/STOP. Ctrl-] ends the session.
+use std::collections::VecDeque;
+use std::io::{stdin, stdout, Read, Write};
+use std::os::unix::io::AsRawFd;
+use std::result;
+use std::sync::mpsc;
+use std::thread;
+use std::time::Duration;
+use nix::sys::termios::{self, SetArg, Termios};
+use idun_client::petscii::{self, Charset};
+use idun_client::util;
+use crate::smoke::{COLUMNS, ROWS};
+use crate::target::Memory;
+
+// Simpler error handling
+type Result<T> = result::Result<T, failure::Error>;
+
+const KEYBOARD_BUFFER: u16 = 0x0277;
+const KEYS_WAITING: u16 = 0x00c6;
+// The keyboard buffer holds ten keys
+const BUFFER_SIZE: usize = 10;
+// Ctrl-]
+const QUIT: u8 = 0x1d;
+
+/// One row of screen codes as text, reverse characters in reverse video.
+pub fn render_row(codes: &[u8], charset: Charset) -> String {
+    let mut row = String::new();
+    let mut reverse = false;
+    for &code in codes {
+        if (code >= 0x80) != reverse {
+            reverse = !reverse;
+            row.push_str(if reverse { "\x1b[7m" } else { "\x1b[27m" });
+        }
+        row.push(petscii::glyph(charset, util::screen_to_pet(code & 0x7f)).unwrap_or(' '));
+    }
+    if reverse {
+        row.push_str("\x1b[27m");
+    }
+    row
+}
+
+/// The PETSCII keys for what was typed here, and whether Ctrl-] was.
+pub fn keys(input: &[u8], charset: Charset) -> (Vec<u8>, bool) {
+    let mut keys = vec![];
+    let mut rest = input;
+    while let Some((&b, after)) = rest.split_first() {
+        rest = after;
+        let key = match b {
+            QUIT => return (keys, true),
+            b'\r' | b'\n' => 13,
+            0x7f | 0x08 => 20,
+            0x03 => 3,
+            0x1b => {
+                // Cursor keys and Home come as ESC [ and a letter
+                let (key, n) = match rest {
+                    [b'[', b'A', ..] => (145, 2),
+                    [b'[', b'B', ..] => (17, 2),
+                    [b'[', b'C', ..] => (29, 2),
+                    [b'[', b'D', ..] => (157, 2),
+                    [b'[', b'H', ..] => (19, 2),
+                    _ => (0, 0),
+                };
+                rest = &rest[n..];
+                key
+            },
+            b => std::str::from_utf8(&[b]).ok()
+                .and_then(|s| s.chars().next())
+                .and_then(|c| petscii::encode_char(charset, c))
+                .unwrap_or(0),
+        };
+        if key != 0 {
+            keys.push(key);
+        }
+    }
+    (keys, false)
+}
+
+// Puts the terminal back the way it was when dropped
+struct RawMode(Termios);
+
+impl RawMode {
+    fn enter() -> Result<RawMode> {
+        let fd = stdin().as_raw_fd();
+        let saved = termios::tcgetattr(fd).map_err(|e| format_err!("term needs a terminal: {}", e))?;
+        let mut raw = saved.clone();
+        termios::cfmakeraw(&mut raw);
+        termios::tcsetattr(fd, SetArg::TCSANOW, &raw)?;
+        Ok(RawMode(saved))
+    }
+}
+
+impl Drop for RawMode {
+    fn drop(&mut self) {
+        let _ = termios::tcsetattr(stdin().as_raw_fd(), SetArg::TCSANOW, &self.0);
+        print!("\x1b[{};1H\r\n", ROWS + 1);
+        let _ = stdout().flush();
+    }
+}
+
+/// Mirrors the screen at `screen` every `interval` and types what's
+/// typed here, until Ctrl-].
+pub fn run(memory: &dyn Memory, screen: u16, interval: Duration, charset: Charset) -> Result<()> {
+    let _raw = RawMode::enter()?;
+    let (tx, typed) = mpsc::channel();
+    thread::spawn(move || {
+        let mut buf = [0u8; 64];
+        while let Ok(n @ 1..) = stdin().read(&mut buf) {
+            if tx.send(buf[..n].to_vec()).is_err() {
+                break
+            }
+        }
+    });
+    print!("{}", crate::watch::CLEAR);
+    let mut shown: Vec<Vec<u8>> = vec![];
+    let mut pending = VecDeque::new();
+    loop {
+        while let Ok(input) = typed.try_recv() {
+            let (k, quit) = keys(&input, charset);
+            pending.extend(k);
+            if quit {
+                return Ok(())
+            }
+        }
+        if !pending.is_empty() && memory.read(KEYS_WAITING, 1)?.first() == Some(&0) {
+            let lot: Vec<u8> = pending.drain(..pending.len().min(BUFFER_SIZE)).collect();
+            memory.write(KEYBOARD_BUFFER, &lot)?;
+            memory.write(KEYS_WAITING, &[lot.len() as u8])?;
+        }
+        let rows: Vec<Vec<u8>> = memory.read(screen, COLUMNS * ROWS)?.chunks(COLUMNS).map(<[u8]>::to_vec).collect();
+        let mut out = String::new();
+        for (i, row) in rows.iter().enumerate().filter(|(i, row)| shown.get(*i) != Some(row)) {
+            out.push_str(&format!("\x1b[{};1H{}\x1b[K", i + 1, render_row(row, charset)));
+        }
+        if !out.is_empty() {
+            print!("{}", out);
+            stdout().flush()?;
+        }
+        shown = rows;
+        thread::sleep(interval);
+    }
+}
+
+#[test]
+fn term_screen_and_keys() {
+    // HI in screen codes, then a reverse space
+    assert_eq!(render_row(&[8, 9, 0xa0, 32], Charset::Upper), "HI\x1b[7m \x1b[27m ");
+    assert_eq!(render_row(&[8, 9], Charset::Lower), "hi");
+    assert_eq!(keys(b"ab\r\x7f\x1b[A", Charset::Upper), (vec![65, 66, 13, 20, 145], false));
+    assert_eq!(keys(b"x\x1dy", Charset::Upper), (vec![88], true));
+}
@@ -0,0 +1,181 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+// Copyright (C) 2026 Brian Holdsworth
+
+//! Colors for listings printed on a terminal.
+//!
+//! ```toml
+//! [theme]
+//! preset = "c64-blue"
+//! dir = "bright-yellow"
+//! ```
+//!
+//! A preset sets every color, and single colors can be changed after it.
+//! Colors are names such as `cyan`, `bright-blue`, `bold` or `reverse`,
+//! several joined with `+`, or raw SGR codes such as `38;5;75`. Without
+//! a `[theme]` section listings are printed as they come, and nothing is
+//! colored when output isn't a terminal or `NO_COLOR` is set.
+use std::env;
+use std::io::{self, IsTerminal};
+use std::result;
+use serde::Deserialize;
+use crate::listing::Listing;
+
+// Simpler error handling
+type Result<T> = result::Result<T, failure::Error>;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Preset {
+    Light,
+    Dark,
+    C64Blue,
+}
+
+/// The `[theme]` section of the config file.
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ThemeConfig {
+    pub preset: Option<Preset>,
+    pub header: Option<String>,
+    pub dir: Option<String>,
+    pub locked: Option<String>,
+    pub footer: Option<String>,
+    pub device: Option<String>,
+    pub changed: Option<String>,
+}
+
+/// SGR parameters for each part of a listing; empty for none.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Theme {
+    /// Disk name line of a catalog
+    pub header: String,
+    /// Subdirectory and partition entries
+    pub dir: String,
+    /// Write protected files
+    pub locked: String,
+    /// BLOCKS FREE line
+    pub footer: String,
+    /// Drive names in drives tables
+    pub device: String,
+    /// Lines that changed in `drives --watch`
+    pub changed: String,
+}
+
+impl Theme {
+    pub fn preset(preset: Preset) -> Theme {
+        let (header, dir, locked, footer, device, changed) = match preset {
+            Preset::Light => ("bold+blue", "magenta", "red", "bright-black", "blue", "reverse"),
+            Preset::Dark => ("bold+bright-white", "bright-cyan", "bright-red", "bright-black", "bright-green", "reverse"),
+            Preset::C64Blue => ("reverse+bright-blue+44", "bright-cyan+44", "bright-red+44", "bright-blue+44", "bright-blue+44", "reverse+bright-blue"),
+        };
+        Theme {
+            header: sgr(header).unwrap_or_default(),
+            dir: sgr(dir).unwrap_or_default(),
+            locked: sgr(locked).unwrap_or_default(),
+            footer: sgr(footer).unwrap_or_default(),
+            device: sgr(device).unwrap_or_default(),
+            changed: sgr(changed).unwrap_or_default(),
+        }
+    }
+    /// The configured theme, or no colors at all when stdout isn't a
+    /// terminal. Without a theme only changed lines stand out. The colors
+    /// are checked either way, so a mistake shows wherever it's run.
+    pub fn load(config: &ThemeConfig) -> Result<Theme> {
+        let mut theme = match config.preset {
+            Some(preset) => Theme::preset(preset),
+            None => Theme { changed: "7".to_string(), ..Default::default() },
+        };
+        for (field, color) in [
+            (&mut theme.header, &config.header), (&mut theme.dir, &config.dir),
+            (&mut theme.locked, &config.locked), (&mut theme.footer, &config.footer),
+            (&mut theme.device, &config.device), (&mut theme.changed, &config.changed),
+        ] {
+            if let Some(color) = color {
+                *field = sgr(color).ok_or_else(|| format_err!("Unknown color {:?} in [theme]", color))?;
+            }
+        }
+        if !io::stdout().is_terminal() || env::var_os("NO_COLOR").is_some() {
+            return Ok(Theme::default())
+        }
+        Ok(theme)
+    }
+    /// True if listings are colored, so they have to be collected
+    /// rather than streamed as they come
+    pub fn is_active(&self) -> bool {
+        [&self.header, &self.dir, &self.locked, &self.footer, &self.device].iter().any(|s| !s.is_empty())
+    }
+    /// `text` in the color `style`, one of the fields of the theme
+    pub fn paint(&self, style: &str, text: &str) -> String {
+        match style {
+            "" => text.to_string(),
+            _ => format!("\x1b[{}m{}\x1b[0m", style, text),
+        }
+    }
+    /// The lines of a catalog, colored by what each one is
+    pub fn listing(&self, listing: &Listing) -> Vec<String> {
+        let entries = listing.entries.iter().map(|e| {
+            let style = if e.is_dir() {
+                &self.dir
+            } else if e.locked {
+                &self.locked
+            } else {
+                ""
+            };
+            self.paint(style, &e.row())
+        });
+        listing.header.iter().map(|h| self.paint(&self.header, h))
+            .chain(entries)
+            .chain(listing.footer.iter().map(|f| self.paint(&self.footer, f)))
+            .collect()
+    }
+    /// A line of a drives table with its drive name, e.g. `a:`, colored
+    pub fn drive(&self, line: &str) -> String {
+        match line.split_once(':') {
+            Some((name, rest)) if !name.is_empty() && !name.contains(' ') =>
+                format!("{}{}", self.paint(&self.device, &format!("{}:", name)), rest),
+            _ => line.to_string(),
+        }
+    }
+}
+
+// SGR parameters for a color spec, e.g. "bold+bright-blue" is "1;94"
+fn sgr(spec: &str) -> Option<String> {
+    const COLORS: [&str; 8] = ["black", "red", "green", "yellow", "blue", "magenta", "cyan", "white"];
+    let codes: Option<Vec<String>> = spec.split('+')
+        .map(|part| {
+            let part = part.trim();
+            if let Some(i) = COLORS.iter().position(|c| *c == part) {
+                Some((30 + i).to_string())
+            } else if let Some(i) = part.strip_prefix("bright-").and_then(|p| COLORS.iter().position(|c| *c == p)) {
+                Some((90 + i).to_string())
+            } else {
+                match part {
+                    "none" => Some(String::new()),
+                    "bold" => Some("1".to_string()),
+                    "dim" => Some("2".to_string()),
+                    "underline" => Some("4".to_string()),
+                    "reverse" => Some("7".to_string()),
+                    p if !p.is_empty() && p.chars().all(|c| c.is_ascii_digit() || c == ';') => Some(p.to_string()),
+                    _ => None,
+                }
+            }
+        })
+        .collect();
+    Some(codes?.into_iter().filter(|c| !c.is_empty()).collect::<Vec<_>>().join(";"))
+}
+
+#[test]
+fn theme_colors() {
+    assert_eq!(sgr("bold+bright-blue").as_deref(), Some("1;94"));
+    assert_eq!(sgr("38;5;75+underline").as_deref(), Some("38;5;75;4"));
+    assert_eq!(sgr("none").as_deref(), Some(""));
+    assert_eq!(sgr("blurple"), None);
+    let theme = Theme::preset(Preset::Dark);
+    assert_eq!(theme.paint(&theme.dir, "games"), "\x1b[96mgames\x1b[0m");
+    assert_eq!(theme.paint("", "games"), "games");
+    assert_eq!(theme.drive("a:  8 1541"), "\x1b[92ma:\x1b[0m  8 1541");
+    assert!(theme.is_active() && !Theme::default().is_active());
+    // A bad color is found even where nothing is colored, as under test
+    let config = ThemeConfig { dir: Some("blurple".into()), ..Default::default() };
+    assert_eq!(Theme::load(&config).unwrap_err().to_string(), "Unknown color \"blurple\" in [theme]");
+}
@@ -0,0 +1,201 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+// Copyright (C) 2026 Brian Holdsworth
+
+//! A journal of files being put on drives, so a `put` cut short doesn't
+//! leave a truncated file behind.
+//!
+//! Before a file goes to a drive, a copy of it and a record go in
+//! `~/.local/share/idunsh/transfers`: the drive, the name, and the
+//! CRC-32 of each 256 byte chunk. The record counts the chunks sent as
+//! they go, and both are removed once the drive has taken the whole file.
+//! A record left by a run that's no longer alive marks a transfer that
+//! was cut short. The next run scratches the partly written file and puts
+//! it again from the copy, then reads it back to check it against the
+//! CRCs. Without the copy, the partly written file is only scratched.
+use std::fs;
+use std::path::PathBuf;
+use std::process;
+use std::result;
+use std::sync::atomic::{AtomicU32, AtomicUsize, Ordering};
+use std::time::{SystemTime, UNIX_EPOCH};
+use nix::sys::signal;
+use nix::unistd::Pid;
+use serde::{Deserialize, Serialize};
+use idun_client::util;
+use crate::files;
+use crate::profile::Profile;
+use crate::progress::Progress;
+use crate::store;
+
+// Simpler error handling
+type Result<T> = result::Result<T, failure::Error>;
+
+pub const CHUNK: usize = 256;
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Record {
+    pub dev: String,
+    /// The name stored, with its type after a comma
+    pub name: String,
+    /// The run doing the transfer
+    pub pid: u32,
+    pub size: usize,
+    pub crcs: Vec<u32>,
+    /// Chunks sent, counted every few chunks
+    pub sent: usize,
+}
+
+pub struct Transfer {
+    path: PathBuf,
+    pub record: Record,
+    sent: AtomicUsize,
+}
+
+fn dir() -> Result<PathBuf> {
+    store::subdir("transfers")
+}
+
+fn crcs(data: &[u8]) -> Vec<u32> {
+    data.chunks(CHUNK).map(util::crc32).collect()
+}
+
+impl Transfer {
+    /// Records the start of putting `data` on `dev` as `name`.
+    pub fn begin(dev: &str, name: &str, data: &[u8]) -> Result<Transfer> {
+        Self::begin_in(dir()?, dev, name, data)
+    }
+    fn begin_in(dir: PathBuf, dev: &str, name: &str, data: &[u8]) -> Result<Transfer> {
+        static NEXT: AtomicU32 = AtomicU32::new(0);
+        let secs = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs();
+        let id = format!("{}-{}-{}", secs, process::id(), NEXT.fetch_add(1, Ordering::Relaxed));
+        let record = Record {
+            dev: dev.to_string(),
+            name: name.to_string(),
+            pid: process::id(),
+            size: data.len(),
+            crcs: crcs(data),
+            sent: 0,
+        };
+        let transfer = Transfer { path: dir.join(format!("{}.json", id)), record, sent: AtomicUsize::new(0) };
+        fs::write(transfer.path.with_extension("data"), data)?;
+        transfer.save()?;
+        Ok(transfer)
+    }
+    fn save(&self) -> Result<()> {
+        let record = Record { sent: self.sent(), ..self.record.clone() };
+        store::write(&self.path, &serde_json::to_vec(&record)?)
+    }
+    /// Counts a chunk sent, writing the count down every 16 chunks.
+    pub fn chunk_sent(&self) -> Result<()> {
+        let n = self.sent.fetch_add(1, Ordering::Relaxed) + 1;
+        if n == 1 || n.is_multiple_of(16) {
+            self.save()?;
+        }
+        Ok(())
+    }
+    /// The chunks sent, as far as is known.
+    pub fn sent(&self) -> usize {
+        self.sent.load(Ordering::Relaxed).max(self.record.sent)
+    }
+    /// The copy of the file, if it's still whole.
+    pub fn data(&self) -> Option<Vec<u8>> {
+        let data = fs::read(self.path.with_extension("data")).ok()?;
+        self.check(&data).is_none().then_some(data)
+    }
+    /// Where `data` first differs from the file put, if it does.
+    pub fn check(&self, data: &[u8]) -> Option<usize> {
+        let theirs = crcs(data);
+        match self.record.crcs.iter().zip(&theirs).position(|(a, b)| a != b) {
+            Some(chunk) => Some(chunk * CHUNK),
+            None if data.len() != self.record.size => Some(data.len().min(self.record.size)),
+            None => None,
+        }
+    }
+    /// Removes the record and the copy.
+    pub fn finish(&self) -> Result<()> {
+        let _ = fs::remove_file(self.path.with_extension("data"));
+        fs::remove_file(&self.path)?;
+        Ok(())
+    }
+}
+
+/// The transfers cut short: those whose runs are gone.
+pub fn interrupted() -> Result<Vec<Transfer>> {
+    interrupted_in(dir()?)
+}
+fn interrupted_in(dir: PathBuf) -> Result<Vec<Transfer>> {
+    let mut found = vec![];
+    for f in fs::read_dir(dir)? {
+        let path = f?.path();
+        if path.extension().is_none_or(|e| e != "json") {
+            continue;
+        }
+        let Ok(record) = serde_json::from_slice::<Record>(&fs::read(&path)?) else {
+            continue;
+        };
+        if signal::kill(Pid::from_raw(record.pid as i32), None).is_err() {
+            found.push(Transfer { path, record, sent: AtomicUsize::new(0) });
+        }
+    }
+    found.sort_by(|a, b| a.path.cmp(&b.path));
+    Ok(found)
+}
+
+// Puts right the transfers an earlier run was cut short in
+pub fn repair(profile: Profile, progress: Progress) {
+    for transfer in interrupted().unwrap_or_default() {
+        let file = format!("{}{}", transfer.record.dev, transfer.record.name);
+        match repair_one(&transfer, profile, progress) {
+            Ok(()) => {
+                let _ = transfer.finish();
+            },
+            Err(e) => eprintln!("The put of {} was cut short, and it couldn't be put right: {}", file, e),
+        }
+    }
+}
+
+// Scratches the partly written file, then puts it again from the copy
+// kept and checks it
+fn repair_one(transfer: &Transfer, profile: Profile, progress: Progress) -> Result<()> {
+    let (dev, name) = (&transfer.record.dev, &transfer.record.name);
+    // Nothing was written if nothing was sent, and a file there is another's
+    if transfer.sent() == 0 {
+        eprintln!("The put of {}{} was cut short before it started", dev, name);
+        return Ok(())
+    }
+    files::scratch(dev, name)?;
+    let Some(data) = transfer.data() else {
+        eprintln!("Removed {}{}, which was cut short; the copy to complete it is gone", dev, name);
+        return Ok(())
+    };
+    eprintln!("Completing the put of {}{}, which was cut short", dev, name);
+    let settings = files::transfer_settings(dev, profile)?;
+    files::store(dev, name, data, settings, progress)?;
+    let base = name.rsplit_once(',').map_or(name.as_str(), |(name, _)| name);
+    if let Some(at) = transfer.check(&files::fetch(&format!("{}{}", dev, base), settings)?) {
+        bail!("{}{} differs from what was put from byte {}", dev, base, at)
+    }
+    Ok(())
+}
+
+#[test]
+fn transfer_journal() {
+    let dir = std::env::temp_dir().join(format!("idunsh-transfers-{}", process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    let data: Vec<u8> = (0..600).map(|i| i as u8).collect();
+    let transfer = Transfer::begin_in(dir.clone(), "c:", "test,p", &data).unwrap();
+    assert_eq!(transfer.record.crcs.len(), 3);
+    transfer.chunk_sent().unwrap();
+    let saved: Record = serde_json::from_slice(&fs::read(&transfer.path).unwrap()).unwrap();
+    assert_eq!((saved.sent, saved.pid), (1, process::id()));
+    assert_eq!(transfer.data(), Some(data.clone()));
+    assert_eq!(transfer.check(&data[..512]), Some(512));
+    let mut changed = data.clone();
+    changed[300] = 0;
+    assert_eq!(transfer.check(&changed), Some(256));
+    // This run is alive, so its transfer isn't one cut short
+    assert!(interrupted_in(dir.clone()).unwrap().is_empty());
+    transfer.finish().unwrap();
+    assert!(!transfer.path.exists() && !transfer.path.with_extension("data").exists());
+    fs::remove_dir_all(&dir).unwrap();
+}
@@ -0,0 +1,182 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+// Copyright (C) 2026 Brian Holdsworth
+
+//! The `ult` commands, the general commands that work differently on the
+//! C64 Ultimate, and the table of its floppy drives.
+use std::path::Path;
+use std::result;
+use std::thread;
+use std::time::{Duration, Instant};
+use clap::{Subcommand, ValueEnum};
+use idun_client::petscii::{Charset, Layout};
+use idun_client::util;
+use crate::PlayerOpts;
+use crate::TypedText;
+use crate::c64ultimate::{self, C64Ultimate, NamedDevice, Stream};
+use crate::config::Config;
+use crate::confirm::confirm;
+use crate::errors::{At, Context};
+use crate::hooks::{self, Hook};
+use crate::obs;
+use crate::target::{self, Target};
+
+// Simpler error handling
+type Result<T> = result::Result<T, failure::Error>;
+
+#[derive(Subcommand)]
+pub enum UltCommands {
+    /// Look for the C64U on the LAN and print its address, e.g.
+    /// export $(idunsh ult detect)
+    Detect,
+    /// Launch content, the same as run
+    Load { prg:String, #[command(flatten)] player: PlayerOpts },
+    /// Mount a disk image on drive a or b, e.g. ult mount a: disk.d64
+    Mount { dev:String, dimage:String },
+    /// Show the floppy drives and their images
+    Drives { dev:Option<String> },
+    /// Reset the C64, keeping the mounted images
+    Reset,
+    /// Start or stop one of the Ultimate's network data streams
+    Stream {
+        action: StreamAction,
+        stream: Stream,
+        #[arg(long, value_name="host:port", required_if_eq("action", "start"))]
+        /// Where the Ultimate should send the stream
+        dest: Option<String>,
+    },
+    /// Exchange the disk images mounted in drives a and b
+    Swap,
+    /// Type text on the C64 through its keyboard buffer, like keys
+    Type { #[command(flatten)] text: TypedText },
+    /// Upload a firmware update to the Ultimate and wait for it to be
+    /// installed, e.g. ult update update.u64 --sha256 <published sum>
+    Update {
+        file: String,
+        #[arg(long, value_name="hex")]
+        /// The SHA-256 the file must have, as published with it
+        sha256: Option<String>,
+        #[arg(long, default_value="/Temp", value_name="dir")]
+        /// Directory on the Ultimate to upload to
+        dir: String,
+    },
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+pub enum StreamAction {
+    Start,
+    Stop,
+}
+
+pub fn run(c64u: &C64Ultimate, cmd: UltCommands, charset: Charset, layout: Layout, yes: bool) -> Result<()> {
+    match cmd {
+        UltCommands::Stream { action: StreamAction::Start, stream, dest } =>
+            c64u.stream_start(stream, &dest.unwrap_or_default()),
+        UltCommands::Stream { action: StreamAction::Stop, stream, .. } =>
+            c64u.stream_stop(stream),
+        // Turned into the commands they stand for in run()
+        UltCommands::Load { .. } | UltCommands::Mount { .. } | UltCommands::Drives { .. } => Ok(()),
+        UltCommands::Reset => c64u.reset(),
+        UltCommands::Detect => {
+            println!("C64_ULTIMATE_IP={}", c64u.ip().as_deref().unwrap_or_default());
+            Ok(())
+        },
+        UltCommands::Swap => c64u.swap(),
+        UltCommands::Type { text } => c64u.type_text(&text.keys(charset, layout)?),
+        UltCommands::Update { file, sha256, dir } => {
+            let data = c64ultimate::read_update(&file, sha256.as_deref())?;
+            eprintln!("WARNING: an update cut short, by losing power or by the wrong file for this model,");
+            eprintln!("WARNING: can leave the Ultimate unable to start until it is recovered by cable.");
+            eprintln!("The Ultimate's API is at version {}", c64u.version()?);
+            confirm(&format!("Upload {} for a firmware update?", file), yes)?;
+            let name = Path::new(&file).file_name().unwrap_or_default().to_string_lossy().into_owned();
+            let path = format!("{}/{}", dir.trim_end_matches('/'), name);
+            c64u.ftp_put(&path, &data)?;
+            // The web service can't start an update, so it's done on the menu
+            eprintln!("Uploaded {}. Now open it in the Ultimate's file browser and run it;", path);
+            eprintln!("keep the power on until the Ultimate has restarted.");
+            let version = c64u.wait_restart(Duration::from_secs(900))?;
+            eprintln!("The Ultimate is back, with API version {}", version);
+            Ok(())
+        },
+    }
+}
+
+// Starts a program, or plays music with the player options
+pub fn load(c64u: &C64Ultimate, prg: &str, player: &PlayerOpts, config: &Config, name: &str) -> Result<()> {
+    if player.duration.is_some() && !prg.to_lowercase().ends_with(".sid") {
+        bail!("--duration only applies to SID playback")
+    }
+    c64u.load(prg, &player.mod_options(prg)?).at(Context::File(prg.to_string()))?;
+    if let Some(duration) = player.duration {
+        c64u.stop_after(duration, player.fade)?;
+    }
+    // The overlay only misses out if this can't be kept
+    let _ = obs::Playing::record(prg);
+    hooks::after(&config.hooks, Hook::PostLoad, name, &[("FILE", prg)]);
+    Ok(())
+}
+
+pub fn mount(c64u: &C64Ultimate, dev: &str, dimage: &str, config: &Config, name: &str) -> Result<()> {
+    if dev.is_empty() {
+        bail!("No device given, e.g. a:")
+    }
+    if !c64u.capabilities()?.can_mount(dimage) {
+        return Err(target::unsupported(c64u, &format!("Mounting {}", dimage)))
+    }
+    hooks::run(&config.hooks, Hook::PreMount, name, &[("DEV", dev), ("FILE", dimage)])?;
+    c64u.mount(dev, dimage).at(Context::File(dimage.to_string())).at(Context::Device(dev.to_string()))
+}
+
+// Table of the C64U floppy drives and their images
+pub fn drives(c64u: &C64Ultimate, dev: &Option<String>) -> Result<Vec<String>> {
+    let ultid = c64u.getdrv(dev)
+        .map_err(|e| format_err!("C64 Ultimate drive settings Error: {}", e))?;
+    let mut lines = vec![];
+    for NamedDevice { slot, device } in ultid.drives {
+        if slot.is_floppy() {     // Just listing a:, b:
+            let image = if device.enabled {
+                device.image_file.unwrap_or_default()
+            } else {
+                String::from("<Disabled>")
+            };
+            lines.push(format!("{} {} {} {}",
+                util::padded(&format!("{}:", slot.name()), 2),
+                util::right_aligned(device.bus_id, 2),
+                util::padded(device.device_type.as_deref().unwrap_or("-"), 6),
+                util::truncated(&image, 64)));
+        }
+    }
+    Ok(lines)
+}
+
+// Switches the C64 on with the smart plug's webhook, then waits up to
+// `boot` for the C64U to answer
+pub fn power_on(config: &Config, ip: Option<String>, boot: Duration) -> Result<()> {
+    let url = config.power.on.as_deref()
+        .ok_or_else(|| format_err!("Powering on needs a webhook, on under [power] in the config file"))?;
+    ureq::post(url).send_empty().map_err(|e| format_err!("{}: {}", url, e))?;
+    let started = Instant::now();
+    while !boot.is_zero() {
+        let c64u = C64Ultimate::discover(ip.clone(), config.timeouts("power")?).connect();
+        if c64u.ip().is_some() && c64u.version().is_ok() {
+            eprintln!("The C64 Ultimate is up after {}s", started.elapsed().as_secs());
+            break
+        }
+        if started.elapsed() >= boot {
+            bail!("The C64 Ultimate didn't answer within {}s of power on", boot.as_secs())
+        }
+        thread::sleep(Duration::from_secs(2));
+    }
+    Ok(())
+}
+
+// Switches the C64 off, then the smart plug if there's a webhook for it
+pub fn power_off(c64u: &C64Ultimate, config: &Config) -> Result<()> {
+    c64u.poweroff()?;
+    if let Some(url) = &config.power.off {
+        // The Ultimate takes a moment to shut down
+        thread::sleep(Duration::from_secs(3));
+        ureq::post(url).send_empty().map_err(|e| format_err!("{}: {}", url, e))?;
+    }
+    Ok(())
+}
@@ -1,7 +1,92 @@
 // SPDX-License-Identifier: GPL-3.0-or-later
 // Copyright (C) 2026 Brian Holdsworth
+use std::borrow::Cow;
 use std::ffi::CString;
+use std::fmt;
+use std::time::Duration;
 use bstr::{BStr, BString, ByteSlice};
+use clap::ValueEnum;
+use failure::Fail;
+use crate::petscii::{self, Charset, Controls};
+
+// Byte translation tables, built at compile time so conversion is a
+// single indexed load per byte.
+const fn asc2pet_table() -> [u8; 256] {
+    let mut t = [0u8; 256];
+    let mut i = 0;
+    while i < 256 {
+        let a = i as u8;
+        t[i] = match a {
+            0x41..=0x5A => a+0x80,
+            0x61..=0x7A => a-0x20,
+            0x7B..=0x7F => a+0x60,
+            _ => a
+        };
+        i += 1;
+    }
+    t
+}
+const fn pet2asc_table() -> [u8; 256] {
+    let mut t = [0u8; 256];
+    let mut i = 0;
+    while i < 256 {
+        let p = i as u8;
+        t[i] = match p {
+            0x61..=0x7A => p-0x20,  // 'a'..='z'
+            0x41..=0x5A => p+0x20,  // 'A'..='Z'
+            0xC1..=0xDA => p-0x80,  // 'Á'..='Ú'
+            0xDE => p-0x60,         // 'Þ'
+            _ => p
+        };
+        i += 1;
+    }
+    t
+}
+const fn scr2pet_table() -> [u8; 256] {
+    let mut t = [0u8; 256];
+    let mut i = 0;
+    while i < 256 {
+        // Reverse video codes show the same glyph as the normal ones
+        let c = (i & 0x7f) as u8;
+        t[i] = match c {
+            0x00..=0x1F => c+0x40,
+            0x40..=0x5F => c+0x80,
+            0x60..=0x7F => c+0x40,
+            _ => c
+        };
+        i += 1;
+    }
+    t
+}
+static ASC2PET: [u8; 256] = asc2pet_table();
+static PET2ASC: [u8; 256] = pet2asc_table();
+static SCR2PET: [u8; 256] = scr2pet_table();
+
+// Applies a translation table, only allocating if some byte changes.
+fn translate<'a>(b: &'a [u8], table: &[u8; 256]) -> Cow<'a, [u8]> {
+    match b.iter().position(|c| table[*c as usize] != *c) {
+        None => Cow::Borrowed(b),
+        Some(first) => {
+            let mut v = b.to_vec();
+            for c in &mut v[first..] {
+                *c = table[*c as usize];
+            }
+            Cow::Owned(v)
+        }
+    }
+}
+/// Bulk ASCII to PETSCII conversion.
+pub fn ascii_to_pet(b: &[u8]) -> Cow<'_, [u8]> {
+    translate(b, &ASC2PET)
+}
+/// Converts a screen code, as found in screen memory, to PETSCII.
+pub fn screen_to_pet(c: u8) -> u8 {
+    SCR2PET[c as usize]
+}
+/// Bulk PETSCII to ASCII conversion.
+pub fn pet_to_ascii(b: &[u8]) -> Cow<'_, [u8]> {
+    translate(b, &PET2ASC)
+}
 
 // Convertible PETSCII string type
 #[derive(PartialEq, Eq, PartialOrd, Ord, Debug)]
@@ -11,38 +96,16 @@ impl PetString {
     pub fn new(b: &BString) -> PetString {
         PetString(b.clone())
     }
-    fn asc2pet(a: u8) -> u8 {
-        match a {
-            0x41..=0x5A => a+0x80,
-            0x61..=0x7A => a-0x20,
-            0x7B..=0x7F => a+0x60,
-            _ => a
-        }
-    }
     fn to_pet(a: &str) -> BString {
-        let mut result = BString::new(vec![]);
-    
-        for c in a.chars() {
-            result.push(Self::asc2pet(c as u8));
-        }
-        result
-    }
-    fn pet2asc(p: u8) -> u8 {
-        match p as char {
-            'a'..='z' => p-0x20,
-            'A'..='Z' => p+0x20,
-            'Á'..='Ú' => p-0x80,
-            'Þ' => p-0x60,
-            _ => p
+        if a.is_ascii() {
+            return ascii_to_pet(a.as_bytes()).into_owned().into();
         }
+        a.chars().map(|c| petscii::encode_char(Charset::Lower, c).unwrap_or(b'?')).collect::<Vec<u8>>().into()
     }
-    fn from_pet(&self) -> Vec<u8> {
-        let mut result = Vec::<u8>::new();
-    
-        for c in self.0.as_slice() {
-            result.push(Self::pet2asc(*c));
-        }
-        result
+    /// Converts to ASCII bytes without any UTF-8 validation, so
+    /// nothing is lost when the data is not really text.
+    pub fn to_ascii(&self) -> BString {
+        pet_to_ascii(self.0.as_slice()).into_owned().into()
     }
     pub fn as_bstr(&self) -> &BStr {
         self.0.as_bstr()
@@ -61,17 +124,11 @@ impl From<&str> for PetString {
         PetString(Self::to_pet(value))
     }
 }
+/// Text in the shifted character set, as the Commodore shows file
+/// names. Control codes other than line ends are left out.
 impl From<PetString> for String {
     fn from(value: PetString) -> String {
-        match String::from_utf8(value.from_pet()) {
-            Ok(s) => s,
-            Err(e) => {
-                let l = e.utf8_error().valid_up_to();
-                let mut p = value.from_pet();
-                p.truncate(l);
-                String::from_utf8(p).unwrap()
-            }
-        }
+        petscii::decode(value.as_slice(), Charset::Lower, Controls::Drop)
     }
 }
 impl From<PetString> for BString {
@@ -79,16 +136,201 @@ impl From<PetString> for BString {
         value.0.to_owned()
     }
 }
-impl From<PetString> for CString {
-    fn from(value: PetString) -> CString {
-        CString::new(value.0.as_slice()).unwrap()
+/// A PETSCII string holding a zero byte, which can't be passed on as a
+/// C string. Zero is a legal PETSCII code, so this is data, not a bug.
+#[derive(Debug, PartialEq, Eq)]
+pub struct PetNulError {
+    pub position: usize,
+}
+impl fmt::Display for PetNulError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "PETSCII string has a NUL byte at position {}", self.position)
     }
 }
+impl Fail for PetNulError {}
+impl TryFrom<PetString> for CString {
+    type Error = PetNulError;
 
-pub fn _padded(s: &[u8], width: usize) -> BString {
-    let mut pad = BString::new(s.to_vec());
-    while pad.len()<width {
-        pad.push(b' ');
+    fn try_from(value: PetString) -> Result<CString, PetNulError> {
+        CString::new(value.0.as_slice())
+            .map_err(|e| PetNulError { position: e.nul_position() })
     }
-    pad
+}
+
+// Line ending written in place of the Commodore's CR
+#[derive(Clone, Copy, PartialEq, Eq, Debug, ValueEnum)]
+pub enum Newline {
+    /// Keep CR, and also turn any stray LF into CR
+    Cr,
+    /// Replace CR with LF
+    Lf,
+    /// Replace CR with CR LF
+    Crlf,
+    /// Leave the bytes exactly as received
+    None,
+}
+
+impl Newline {
+    pub fn translate(&self, s: &[u8]) -> BString {
+        match self {
+            Newline::Cr => s.replace(b"\n", b"\r").into(),
+            Newline::Lf => s.replace(b"\r", b"\n").into(),
+            Newline::Crlf => s.replace(b"\r", b"\r\n").into(),
+            Newline::None => BString::from(s),
+        }
+    }
+}
+
+// Text alignment within a fixed-width column
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Align {
+    Left,
+    Right,
+}
+
+/// Fits text into exactly `width` columns. Long text is cut on a character
+/// boundary (so a converted PETSCII glyph is never split) and marked with an
+/// ellipsis; short text is padded with spaces on the side given by `align`.
+pub fn fit(s: &str, width: usize, align: Align) -> String {
+    let len = s.chars().count();
+    if len > width {
+        return truncated(s, width);
+    }
+    let pad = " ".repeat(width - len);
+    match align {
+        Align::Left => format!("{}{}", s, pad),
+        Align::Right => format!("{}{}", pad, s),
+    }
+}
+/// Pads text with trailing spaces to `width` columns, truncating if longer.
+pub fn padded(s: &str, width: usize) -> String {
+    fit(s, width, Align::Left)
+}
+/// Shortens text to at most `width` columns, ending with an ellipsis
+/// when anything was cut off.
+pub fn truncated(s: &str, width: usize) -> String {
+    if s.chars().count() <= width {
+        return s.to_string();
+    }
+    if width == 0 {
+        return String::new();
+    }
+    let mut t: String = s.chars().take(width - 1).collect();
+    t.push('…');
+    t
+}
+/// Right-aligns a number (or anything displayable) in `width` columns.
+pub fn right_aligned<T: fmt::Display>(n: T, width: usize) -> String {
+    fit(&n.to_string(), width, Align::Right)
+}
+
+/// Parses a duration such as `90`, `1.5s`, `500ms`, `3m` or `2m30s`.
+/// A bare number is taken as seconds.
+pub fn parse_duration(s: &str) -> Result<Duration, String> {
+    let s = s.trim();
+    if let Ok(secs) = s.parse::<f64>() {
+        return Duration::try_from_secs_f64(secs).map_err(|e| e.to_string());
+    }
+    let mut total = 0.0;
+    let mut rest = s;
+    while !rest.is_empty() {
+        let split = rest.find(|c: char| !(c.is_ascii_digit() || c == '.'))
+            .ok_or(format!("missing unit in duration '{}'", s))?;
+        let (num, tail) = rest.split_at(split);
+        let unit_len = tail.find(|c: char| c.is_ascii_digit()).unwrap_or(tail.len());
+        let (unit, tail) = tail.split_at(unit_len);
+        let n: f64 = num.parse().map_err(|_| format!("bad number in duration '{}'", s))?;
+        total += n * match unit {
+            "ms" => 0.001,
+            "s" => 1.0,
+            "m" => 60.0,
+            "h" => 3600.0,
+            _ => return Err(format!("unknown unit '{}' in duration '{}'", unit, s)),
+        };
+        rest = tail;
+    }
+    Duration::try_from_secs_f64(total).map_err(|e| e.to_string())
+}
+
+const fn crc32_table() -> [u32; 256] {
+    let mut t = [0u32; 256];
+    let mut i = 0;
+    while i < 256 {
+        let mut c = i as u32;
+        let mut k = 0;
+        while k < 8 {
+            c = if c & 1 != 0 { 0xedb88320 ^ (c >> 1) } else { c >> 1 };
+            k += 1;
+        }
+        t[i] = c;
+        i += 1;
+    }
+    t
+}
+static CRC32: [u32; 256] = crc32_table();
+
+/// CRC-32 as used by zip and PNG, so values can be checked with
+/// common tools.
+pub fn crc32(data: &[u8]) -> u32 {
+    crc32_update(0, data)
+}
+/// The CRC-32 of data that `crc` is the CRC of, followed by `data`.
+pub fn crc32_update(crc: u32, data: &[u8]) -> u32 {
+    !data.iter().fold(!crc, |c, b| CRC32[((c ^ *b as u32) & 0xff) as usize] ^ (c >> 8))
+}
+
+/// Parses a count or size: decimal, or hex with a `$` or `0x` prefix.
+pub fn parse_number(s: &str) -> Result<u32, String> {
+    let r = if let Some(hex) = s.strip_prefix('$').or(s.strip_prefix("0x")) {
+        u32::from_str_radix(hex, 16)
+    } else {
+        s.parse()
+    };
+    r.map_err(|_| format!("invalid number '{}'", s))
+}
+/// Parses a C64 address. As in a machine-code monitor, addresses are hex
+/// with or without a `$` or `0x` prefix.
+pub fn parse_addr(s: &str) -> Result<u16, String> {
+    let hex = s.strip_prefix('$').or(s.strip_prefix("0x")).unwrap_or(s);
+    u16::from_str_radix(hex, 16).map_err(|_| format!("invalid address '{}'", s))
+}
+
+#[test]
+fn fit_text() {
+    assert_eq!(padded("abc", 5), "abc  ");
+    assert_eq!(right_aligned(42, 5), "   42");
+    assert_eq!(truncated("GAMEDISK", 5), "GAME…");
+    assert_eq!(fit("ÁBC", 2, Align::Left), "Á…");
+}
+
+#[test]
+fn petscii_tables() {
+    assert!(matches!(pet_to_ascii(b"123 ."), Cow::Borrowed(_)));
+    assert_eq!(&*pet_to_ascii(b"\xc8ELLO"), b"Hello");
+    assert_eq!(&*ascii_to_pet(b"Hello{"), b"\xc8ELLO\xdb");
+    assert_eq!(String::from(PetString::from("Load \"$\",8")), "Load \"$\",8");
+    assert_eq!(String::from(PetString::from("£▒")).as_str(), "£▒");
+    assert_eq!(String::from(PetString::new(&BString::from(&b"\x12\xd3\x41\x56\x45\r"[..]))), "Save\r");
+}
+
+#[test]
+fn petscii_cstring() {
+    let ok = CString::try_from(PetString::from("file"));
+    assert_eq!(ok.unwrap().as_bytes(), b"FILE");
+    let nul = CString::try_from(PetString::new(&BString::from(&b"AB\0C"[..])));
+    assert_eq!(nul, Err(PetNulError { position: 2 }));
+}
+
+#[test]
+fn durations() {
+    assert_eq!(parse_duration("90"), Ok(Duration::from_secs(90)));
+    assert_eq!(parse_duration("500ms"), Ok(Duration::from_millis(500)));
+    assert_eq!(parse_duration("2m30s"), Ok(Duration::from_secs(150)));
+    assert!(parse_duration("5 parsecs").is_err());
+}
+
+#[test]
+fn crc32_check() {
+    assert_eq!(crc32(b"123456789"), 0xcbf43926);
+    assert_eq!(crc32_update(crc32(b"1234"), b"56789"), 0xcbf43926);
 }
@@ -0,0 +1,278 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+// Copyright (C) 2026 Brian Holdsworth
+
+//! The VICE emulator as a backend, through its binary monitor protocol
+//! (`x64sc -binarymonitor`, port 6502 by default).
+//!
+//! Requests are a header of STX, the API version, the body's length, a
+//! request id and the command, then the body. Responses carry the same
+//! id, or 0xffffffff for events such as a checkpoint being hit. VICE
+//! stops while it handles a request, so it is sent on again afterwards
+//! unless a breakpoint stopped it. A breakpoint can be hit at any time
+//! while VICE runs, so events are looked for before each request too.
+use std::io::{self, Read, Write};
+use std::net::TcpStream;
+use std::result;
+use std::sync::Mutex;
+use crate::target::{Capabilities, Debugger, Memory, Registers, Target};
+
+// Simpler error handling
+type Result<T> = result::Result<T, failure::Error>;
+
+const STX: u8 = 0x02;
+const API_VERSION: u8 = 0x02;
+const EVENT: u32 = 0xffff_ffff;
+
+const MEMORY_GET: u8 = 0x01;
+const MEMORY_SET: u8 = 0x02;
+const CHECKPOINT_SET: u8 = 0x12;
+const REGISTERS_GET: u8 = 0x31;
+const REGISTERS_SET: u8 = 0x32;
+const EXIT: u8 = 0xaa;
+
+// A checkpoint, as the answer when one is set and as the event when
+// one is hit
+const CHECKPOINT_INFO: u8 = 0x11;
+
+// Register ids of the main CPU
+const REG_A: u8 = 0;
+const REG_X: u8 = 1;
+const REG_Y: u8 = 2;
+const REG_PC: u8 = 3;
+const REG_SP: u8 = 4;
+const REG_FLAGS: u8 = 5;
+
+pub struct Vice {
+    address: String,
+    state: Mutex<Connection>,
+}
+
+struct Connection {
+    stream: TcpStream,
+    next_id: u32,
+    // Stopped at a breakpoint, so left stopped between requests
+    halted: bool,
+    // A breakpoint was hit that `resume` hasn't reported yet
+    hit: bool,
+}
+
+impl Connection {
+    // Sends a request and gives the body of its response
+    fn request(&mut self, command: u8, body: &[u8]) -> Result<Vec<u8>> {
+        self.poll_events()?;
+        let id = self.next_id;
+        self.next_id = self.next_id.wrapping_add(1) % EVENT;
+        self.stream.write_all(&packet(id, command, body))?;
+        loop {
+            let (kind, error, rid, body) = self.response()?;
+            if rid != id {
+                continue
+            }
+            if error != 0 {
+                bail!("VICE refused command ${:02x} with error ${:02x}", command, error)
+            }
+            // Setting answers with what was set
+            let expected = match command {
+                CHECKPOINT_SET => CHECKPOINT_INFO,
+                REGISTERS_SET => REGISTERS_GET,
+                c => c,
+            };
+            if kind != expected {
+                bail!("VICE answered command ${:02x} with ${:02x}", command, kind)
+            }
+            return Ok(body)
+        }
+    }
+    // The next response or event: its type, error code, id and body
+    fn response(&mut self) -> Result<(u8, u8, u32, Vec<u8>)> {
+        let mut header = [0u8; 12];
+        self.stream.read_exact(&mut header)?;
+        if header[0] != STX {
+            bail!("VICE sent something other than a binary monitor response")
+        }
+        let len = u32::from_le_bytes([header[2], header[3], header[4], header[5]]) as usize;
+        let id = u32::from_le_bytes([header[8], header[9], header[10], header[11]]);
+        let mut body = vec![0u8; len];
+        self.stream.read_exact(&mut body)?;
+        if id == EVENT && header[6] == CHECKPOINT_INFO {
+            self.halted = true;
+            self.hit = true;
+        }
+        Ok((header[6], header[7], id, body))
+    }
+    // Reads the events that came while nothing was asked
+    fn poll_events(&mut self) -> Result<()> {
+        loop {
+            self.stream.set_nonblocking(true)?;
+            let waiting = self.stream.peek(&mut [0u8]);
+            self.stream.set_nonblocking(false)?;
+            match waiting {
+                Ok(0) => bail!("VICE closed the binary monitor connection"),
+                Ok(_) => drop(self.response()?),
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => return Ok(()),
+                Err(e) => return Err(e.into()),
+            }
+        }
+    }
+    // Sends a request, then lets VICE run on unless it's at a breakpoint
+    fn call(&mut self, command: u8, body: &[u8]) -> Result<Vec<u8>> {
+        let response = self.request(command, body)?;
+        if !self.halted {
+            self.request(EXIT, &[])?;
+        }
+        Ok(response)
+    }
+}
+
+// A request as sent
+fn packet(id: u32, command: u8, body: &[u8]) -> Vec<u8> {
+    let mut p = vec![STX, API_VERSION];
+    p.extend_from_slice(&(body.len() as u32).to_le_bytes());
+    p.extend_from_slice(&id.to_le_bytes());
+    p.push(command);
+    p.extend_from_slice(body);
+    p
+}
+
+// The registers in a register response
+fn registers(body: &[u8]) -> Result<Registers> {
+    let count = u16::from_le_bytes([*body.first().unwrap_or(&0), *body.get(1).unwrap_or(&0)]) as usize;
+    let mut regs = Registers::default();
+    let mut items = body.get(2..).unwrap_or_default();
+    for _ in 0..count {
+        let size = *items.first().ok_or_else(|| format_err!("VICE sent too few registers"))? as usize;
+        let item = items.get(1..=size).filter(|i| i.len() >= 3)
+            .ok_or_else(|| format_err!("VICE sent a short register"))?;
+        let value = u16::from_le_bytes([item[1], item[2]]);
+        match item[0] {
+            REG_A => regs.a = value as u8,
+            REG_X => regs.x = value as u8,
+            REG_Y => regs.y = value as u8,
+            REG_PC => regs.pc = value,
+            REG_SP => regs.sp = value as u8,
+            REG_FLAGS => regs.flags = value as u8,
+            _ => (),
+        }
+        items = &items[size + 1..];
+    }
+    Ok(regs)
+}
+
+impl Vice {
+    /// Connects to VICE's binary monitor at `address`, e.g. localhost:6502.
+    pub fn connect(address: &str) -> Result<Vice> {
+        let stream = TcpStream::connect(address)
+            .map_err(|e| format_err!("VICE binary monitor at {}: {}", address, e))?;
+        let state = Mutex::new(Connection { stream, next_id: 1, halted: false, hit: false });
+        Ok(Vice { address: address.to_string(), state })
+    }
+    fn connection(&self) -> Result<std::sync::MutexGuard<'_, Connection>> {
+        self.state.lock().map_err(|_| format_err!("The connection to VICE at {} was lost", self.address))
+    }
+}
+
+impl Target for Vice {
+    fn name(&self) -> &str {
+        "VICE"
+    }
+    fn capabilities(&self) -> Result<Capabilities> {
+        Ok(Capabilities {
+            mount_types: None,
+            max_transfer: None,
+            memory_access: true,
+            events: false,
+            subdirectories: false,
+        })
+    }
+}
+
+impl Memory for Vice {
+    fn read(&self, addr: u16, len: usize) -> Result<Vec<u8>> {
+        if len == 0 {
+            return Ok(vec![])
+        }
+        let end = (addr as usize + len - 1).min(0xffff) as u16;
+        let mut body = vec![0];
+        body.extend_from_slice(&addr.to_le_bytes());
+        body.extend_from_slice(&end.to_le_bytes());
+        body.extend_from_slice(&[0, 0, 0]);
+        let response = self.connection()?.call(MEMORY_GET, &body)?;
+        Ok(response.get(2..).unwrap_or_default().to_vec())
+    }
+    fn write(&self, addr: u16, data: &[u8]) -> Result<()> {
+        if data.is_empty() {
+            return Ok(())
+        }
+        let end = addr.wrapping_add(data.len() as u16 - 1);
+        let mut body = vec![0];
+        body.extend_from_slice(&addr.to_le_bytes());
+        body.extend_from_slice(&end.to_le_bytes());
+        body.extend_from_slice(&[0, 0, 0]);
+        body.extend_from_slice(data);
+        self.connection()?.call(MEMORY_SET, &body)?;
+        Ok(())
+    }
+    /// Sets the program counter and lets VICE run from there.
+    fn call(&self, addr: u16) -> Result<()> {
+        let mut conn = self.connection()?;
+        let mut body = vec![0, 1, 0, 3, REG_PC];
+        body.extend_from_slice(&addr.to_le_bytes());
+        conn.request(REGISTERS_SET, &body)?;
+        conn.halted = false;
+        conn.hit = false;
+        conn.request(EXIT, &[])?;
+        Ok(())
+    }
+    fn debugger(&self) -> Option<&dyn Debugger> {
+        Some(self)
+    }
+    /// VICE stops for each request; it is just not sent on until `thaw`.
+    fn freeze(&self) -> Result<bool> {
+        self.connection()?.halted = true;
+        Ok(true)
+    }
+    fn thaw(&self) -> Result<()> {
+        let mut conn = self.connection()?;
+        conn.halted = false;
+        conn.request(EXIT, &[])?;
+        Ok(())
+    }
+}
+
+impl Debugger for Vice {
+    fn set_breakpoint(&self, addr: u16) -> Result<u32> {
+        let mut body = addr.to_le_bytes().to_vec();
+        body.extend_from_slice(&addr.to_le_bytes());
+        // Stop when hit, enabled, on execution, not temporary
+        body.extend_from_slice(&[1, 1, 4, 0]);
+        let response = self.connection()?.call(CHECKPOINT_SET, &body)?;
+        let number = response.get(..4).ok_or_else(|| format_err!("VICE sent no checkpoint number"))?;
+        Ok(u32::from_le_bytes([number[0], number[1], number[2], number[3]]))
+    }
+    /// A breakpoint hit since the last time counts, e.g. after `g`.
+    fn resume(&self) -> Result<Registers> {
+        let mut conn = self.connection()?;
+        conn.poll_events()?;
+        if !conn.hit {
+            conn.halted = false;
+            conn.request(EXIT, &[])?;
+            while !conn.hit {
+                conn.response()?;
+            }
+        }
+        conn.hit = false;
+        registers(&conn.request(REGISTERS_GET, &[0])?)
+    }
+    fn registers(&self) -> Result<Registers> {
+        registers(&self.connection()?.call(REGISTERS_GET, &[0])?)
+    }
+}
+
+#[test]
+fn binary_monitor_packets() {
+    assert_eq!(packet(7, MEMORY_GET, &[0, 1]), [STX, API_VERSION, 2, 0, 0, 0, 7, 0, 0, 0, MEMORY_GET, 0, 1]);
+    let body = [3, 0, 3, REG_A, 0x41, 0, 3, REG_PC, 0x00, 0xc0, 3, 0x26, 1, 0];
+    let regs = registers(&body).unwrap();
+    assert_eq!((regs.a, regs.pc), (0x41, 0xc000));
+    assert!(registers(&[2, 0, 3, REG_A, 0x41, 0]).is_err());
+}
@@ -0,0 +1,38 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+// Copyright (C) 2026 Brian Holdsworth
+use std::io::{stdout, Write};
+use std::result;
+use std::thread;
+use std::time::Duration;
+use crate::theme::Theme;
+
+// Simpler error handling
+type Result<T> = result::Result<T, failure::Error>;
+
+/// Clears the terminal and puts the cursor at the top.
+pub const CLEAR: &str = "\x1b[H\x1b[2J";
+
+/// Redraws the lines returned by `refresh` in place every `interval`,
+/// until interrupted. Lines that changed since the previous refresh are
+/// shown in the theme's `changed` color, reverse video by default.
+pub fn watch<F>(interval: Duration, theme: &Theme, mut refresh: F) -> Result<()>
+where F: FnMut() -> Result<Vec<String>> {
+    let mut last: Option<Vec<String>> = None;
+    loop {
+        let lines = refresh()?;
+        let mut screen = String::from(CLEAR);
+        for (i, line) in lines.iter().enumerate() {
+            let changed = last.as_ref().is_some_and(|l| l.get(i) != Some(line));
+            if changed {
+                screen.push_str(&format!("{}\n", theme.paint(&theme.changed, line)));
+            } else {
+                screen.push_str(&format!("{}\n", line));
+            }
+        }
+        let mut out = stdout();
+        out.write_all(screen.as_bytes())?;
+        out.flush()?;
+        last = Some(lines);
+        thread::sleep(interval);
+    }
+}
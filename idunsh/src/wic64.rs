@@ -0,0 +1,260 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+// Copyright (C) 2026 Brian Holdsworth
+
+//! A stand-in for the WiC64, so programs written for it run on idun.
+//!
+//! `wic64-bridge` opens the cartridge's program channel (see
+//! `sys.channel` in `protocol`) and answers what a program sends on it
+//! the way a WiC64 would, from this machine. Requests come in the
+//! WiC64's revised form, `R`, the command, the size of the payload as
+//! two bytes, low byte first, then the payload, and are answered with a
+//! status (0 when it went well), the size as two bytes and the payload.
+//! The older form, `W`, the size counting the header's four bytes, then
+//! the command and the payload, is answered with the size, high byte
+//! first, and the payload, or an error message in its place.
+//!
+//! ```text
+//! $00  the version                  $0c  the WLANs seen, by nmcli
+//! $01  an HTTP GET of the URL       $0f  the same, with data in the URL
+//! $06  this machine's IP address    $15  the local time and date
+//! ```
+//!
+//! In a `$0f` URL, `<$` is followed by the size of some data as two
+//! bytes, low byte first, and the data, which goes in the URL as hex.
+//! Other commands are answered with a client error.
+use std::io::{Read, Write};
+use std::net::UdpSocket;
+use std::os::unix::net::UnixListener;
+use std::process::{self, Command};
+use std::result;
+use std::thread;
+use std::time::Duration;
+use idun_client::cleanup;
+use idun_client::protocol::{self, RemoteError};
+use crate::idun;
+use crate::luasend;
+use crate::target::{self, Idun};
+
+// Simpler error handling
+type Result<T> = result::Result<T, failure::Error>;
+
+pub const VERSION: u8 = 0x00;
+pub const HTTP_GET: u8 = 0x01;
+pub const GET_IP: u8 = 0x06;
+pub const SCAN_WLANS: u8 = 0x0c;
+pub const HTTP_GET_ENCODED: u8 = 0x0f;
+pub const LOCAL_TIME: u8 = 0x15;
+
+/// How a request went, as the WiC64 tells it.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Status {
+    Success = 0,
+    Internal = 1,
+    Client = 2,
+    Connection = 3,
+    Network = 4,
+    Server = 5,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Request {
+    pub cmd: u8,
+    pub payload: Vec<u8>,
+    // Sent in the older form, to be answered in it
+    legacy: bool,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Response {
+    pub status: Status,
+    pub payload: Vec<u8>,
+}
+
+impl Request {
+    /// The next request on the channel, or None once it's closed.
+    pub fn read(r: &mut impl Read) -> Result<Option<Request>> {
+        let mut header = [0u8; 4];
+        match r.read_exact(&mut header[..1]) {
+            Err(e) if e.kind() == std::io::ErrorKind::UnexpectedEof => return Ok(None),
+            r => r?,
+        }
+        r.read_exact(&mut header[1..])?;
+        let (cmd, size, legacy) = match header {
+            [b'R', cmd, lo, hi] => (cmd, u16::from_le_bytes([lo, hi]) as usize, false),
+            [b'W', lo, hi, cmd] => (cmd, (u16::from_le_bytes([lo, hi]) as usize).saturating_sub(4), true),
+            _ => bail!("Not a WiC64 request: {:02x?}", header),
+        };
+        let mut payload = vec![0; size];
+        r.read_exact(&mut payload)?;
+        Ok(Some(Request { cmd, payload, legacy }))
+    }
+    /// Sends the answer to this request in the form it came in.
+    pub fn answer(&self, w: &mut impl Write, response: &Response) -> Result<()> {
+        let size = u16::try_from(response.payload.len())
+            .map_err(|_| format_err!("{} bytes are too many for a WiC64 answer", response.payload.len()))?;
+        let mut answer = match self.legacy {
+            true => size.to_be_bytes().to_vec(),
+            false => [&[response.status as u8][..], &size.to_le_bytes()].concat(),
+        };
+        answer.extend(&response.payload);
+        w.write_all(&answer)?;
+        Ok(w.flush()?)
+    }
+}
+
+impl Response {
+    fn ok(payload: impl Into<Vec<u8>>) -> Response {
+        Response { status: Status::Success, payload: payload.into() }
+    }
+    fn error(status: Status, message: impl Into<String>) -> Response {
+        Response { status, payload: message.into().into_bytes() }
+    }
+}
+
+/// What a WiC64 would answer to `request`.
+pub fn serve(request: &Request) -> Response {
+    match request.cmd {
+        VERSION => Response::ok(format!("WiC64 bridge, idunsh {}", env!("CARGO_PKG_VERSION"))),
+        HTTP_GET => http_get(&String::from_utf8_lossy(&request.payload)),
+        HTTP_GET_ENCODED => match expand_url(&request.payload) {
+            Ok(url) => http_get(&url),
+            Err(e) => Response::error(Status::Client, e.to_string()),
+        },
+        GET_IP => match local_ip() {
+            Some(ip) => Response::ok(ip),
+            None => Response::error(Status::Network, "No network"),
+        },
+        LOCAL_TIME => match output_of("date", &["+%H:%M:%S %d-%m-%Y"]) {
+            Ok(time) => Response::ok(time.trim()),
+            Err(e) => Response::error(Status::Internal, e.to_string()),
+        },
+        SCAN_WLANS => match output_of("nmcli", &["-t", "-f", "SSID,SIGNAL", "dev", "wifi", "list"]) {
+            Ok(list) => Response::ok(wlans(&list)),
+            Err(e) => Response::error(Status::Network, e.to_string()),
+        },
+        cmd => Response::error(Status::Client, format!("Command ${:02x} isn't served by the bridge", cmd)),
+    }
+}
+
+fn http_get(url: &str) -> Response {
+    let agent = ureq::Agent::config_builder()
+        .timeout_global(Some(Duration::from_secs(30)))
+        .build()
+        .new_agent();
+    match agent.get(url).call() {
+        Ok(resp) => match resp.into_body().with_config().limit(u16::MAX as u64).read_to_vec() {
+            Ok(body) => Response::ok(body),
+            Err(e) => Response::error(Status::Server, e.to_string()),
+        },
+        Err(e @ ureq::Error::StatusCode(_)) => Response::error(Status::Server, e.to_string()),
+        Err(e) => Response::error(Status::Connection, e.to_string()),
+    }
+}
+
+// A $0f URL with the data after each `<$` put in as hex
+fn expand_url(encoded: &[u8]) -> Result<String> {
+    let mut url = String::new();
+    let mut rest = encoded;
+    while let Some(at) = rest.windows(2).position(|w| w == b"<$") {
+        url.push_str(&String::from_utf8_lossy(&rest[..at]));
+        let [lo, hi, ..] = rest[at + 2..] else { bail!("The URL ends in the size of its data") };
+        let size = u16::from_le_bytes([lo, hi]) as usize;
+        let data = rest.get(at + 4..at + 4 + size).ok_or_else(|| format_err!("The URL ends before its data"))?;
+        url.extend(data.iter().map(|b| format!("{:02x}", b)));
+        rest = &rest[at + 4 + size..];
+    }
+    url.push_str(&String::from_utf8_lossy(rest));
+    Ok(url)
+}
+
+// The address this machine reaches the internet from; nothing is sent
+fn local_ip() -> Option<String> {
+    let socket = UdpSocket::bind("0.0.0.0:0").ok()?;
+    socket.connect("192.0.2.1:80").ok()?;
+    Some(socket.local_addr().ok()?.ip().to_string())
+}
+
+fn output_of(program: &str, args: &[&str]) -> Result<String> {
+    let out = Command::new(program).args(args).output().map_err(|e| format_err!("{}: {}", program, e))?;
+    if !out.status.success() {
+        bail!("{}: {}", program, String::from_utf8_lossy(&out.stderr).trim())
+    }
+    Ok(String::from_utf8_lossy(&out.stdout).into_owned())
+}
+
+// nmcli's `SSID:SIGNAL` lines as the WiC64 lists them: the number, the
+// name and the signal strength of each, each ended by $01
+fn wlans(list: &str) -> Vec<u8> {
+    let mut out = vec![];
+    let named = list.lines().filter_map(|l| l.rsplit_once(':')).filter(|(ssid, _)| !ssid.is_empty());
+    for (i, (ssid, signal)) in named.enumerate() {
+        for field in [i.to_string().as_str(), &ssid.replace("\\:", ":"), signal] {
+            out.extend(field.as_bytes());
+            out.push(1);
+        }
+    }
+    out
+}
+
+// Opens the program channel and answers WiC64 requests on it, each
+// program that opens the channel on a thread of its own
+pub fn run() -> Result<()> {
+    let path = idun().run_dir().join(format!("{}.channel", process::id()));
+    let listener = UnixListener::bind(&path)
+        .map_err(|e| format_err!("Can't listen for output at {}: {}", path.display(), e))?;
+    let path = cleanup::TempPath::new(path);
+    let opened = luasend(format!("sys.channel({})", protocol::lua_string(&path.path().to_string_lossy())));
+    if let Err(e) = opened {
+        if e.iter_chain().any(|f| f.downcast_ref::<RemoteError>().is_some()) {
+            return Err(target::unsupported(&Idun, "The program channel of this daemon version"))
+        }
+        return Err(e)
+    }
+    eprintln!("WiC64 bridge running (Ctrl-C to stop)");
+    for conn in listener.incoming() {
+        let mut conn = conn?;
+        thread::spawn(move || {
+            let mut out = match conn.try_clone() {
+                Ok(out) => out,
+                Err(_) => return,
+            };
+            loop {
+                let request = match Request::read(&mut conn) {
+                    Ok(Some(request)) => request,
+                    Ok(None) => break,
+                    Err(e) => {
+                        eprintln!("WiC64: {}", e);
+                        break
+                    },
+                };
+                let response = serve(&request);
+                eprintln!("WiC64: command ${:02x}: {:?}", request.cmd, response.status);
+                if request.answer(&mut out, &response).is_err() {
+                    break
+                }
+            }
+        });
+    }
+    Ok(())
+}
+
+#[test]
+fn wic64_requests() {
+    let mut input: &[u8] = b"R\x00\x00\x00W\x07\x00\x01abc";
+    let request = Request::read(&mut input).unwrap().unwrap();
+    assert_eq!((request.cmd, request.payload.len()), (VERSION, 0));
+    let mut answer = vec![];
+    request.answer(&mut answer, &Response::ok("v2")).unwrap();
+    assert_eq!(answer, b"\x00\x02\x00v2");
+    let legacy = Request::read(&mut input).unwrap().unwrap();
+    assert_eq!((legacy.cmd, legacy.payload.as_slice()), (HTTP_GET, &b"abc"[..]));
+    let mut answer = vec![];
+    legacy.answer(&mut answer, &Response::ok("hi")).unwrap();
+    assert_eq!(answer, b"\x00\x02hi");
+    assert_eq!(Request::read(&mut input).unwrap(), None);
+    assert!(Request::read(&mut &b"X\x00\x00\x00"[..]).is_err());
+    assert_eq!(expand_url(b"http://h/?d=<$\x02\x00\xca\xfe&x=1").unwrap(), "http://h/?d=cafe&x=1");
+    assert!(expand_url(b"http://h/?d=<$\x05\x00ab").is_err());
+    assert_eq!(serve(&Request { cmd: 0x7f, payload: vec![], legacy: false }).status, Status::Client);
+    assert_eq!(wlans("home:80\nmy\\:net:40\n:10\n"), b"0\x01home\x0180\x011\x01my:net\x0140\x01");
+}